@@ -5,6 +5,10 @@ use clap::Parser;
 use regex::Regex;
 
 use crate::dezoomer::Dezoomer;
+use crate::dry_run::DryRunFormat;
+use crate::encoder::EncoderOptions;
+use crate::iiif::IiifOptions;
+use crate::region::RegionSpec;
 
 use super::{Vec2d, ZoomError, auto, stdin_line};
 
@@ -55,6 +59,26 @@ pub struct Arguments {
     #[arg(long = "image-index")]
     pub image_index: Option<usize>,
 
+    /// Download only a rectangular part of the chosen zoom level, given as 'x,y,width,height'.
+    /// Each value is either in pixels, or in percents of the size of the level, such as
+    /// '25%,25%,50%,50%' for the center of the image. Only the tiles that intersect
+    /// the region are downloaded.
+    #[arg(long, value_name = "X,Y,W,H")]
+    pub region: Option<RegionSpec>,
+
+    /// Do not download the image. Instead, print its zoom levels with their size, their number of tiles,
+    /// the URLs of their first tiles, the HTTP headers the tiles are requested with,
+    /// and an estimation of the size of the uncompressed image. The level that would be downloaded is marked.
+    /// Use `--dry-run=json` to print this as JSON. With --bulk, every image of the bulk input is described.
+    #[arg(
+        long,
+        value_name = "FORMAT",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "text"
+    )]
+    pub dry_run: Option<DryRunFormat>,
+
     /// Degree of parallelism to use. At most this number of
     /// tiles will be downloaded at the same time.
     #[arg(short = 'n', long = "parallelism", default_value = "16")]
@@ -78,10 +102,48 @@ pub struct Arguments {
     /// A number between 0 and 100 expressing how much to compress the output image.
     /// For lossy output formats such as jpeg, this affects the quality of the resulting image.
     /// 0 means less compression, 100 means more compression.
-    /// Currently affects only the JPEG and PNG encoders.
+    /// Currently affects only the JPEG, PNG, TIFF, WebP and AVIF encoders.
+    /// With WebP, 0 means lossless compression.
     #[arg(long, default_value = "5")]
     pub compression: u8,
 
+    /// Width and height of the tiles when saving to a Deep Zoom image (.dzi)
+    #[arg(long, default_value = "254")]
+    pub dzi_tile_size: u32,
+
+    /// Number of pixels each tile shares with its neighbours when saving to a Deep Zoom image (.dzi)
+    #[arg(long, default_value = "1")]
+    pub dzi_overlap: u32,
+
+    /// Save a JSON file describing the download next to each output image,
+    /// named after the image followed by `.json`. It contains the source of the image,
+    /// the metadata URLs that were fetched, the chosen size, the tiles that could not be downloaded,
+    /// and the time spent on each step.
+    #[arg(long)]
+    pub sidecar: bool,
+
+    /// Complete an image left incomplete by a previous run of the same command.
+    /// When some tiles cannot be downloaded, their list is saved next to the image,
+    /// in a file ending with `.partial.json`. With this option, only these tiles are requested again,
+    /// the other ones are read from the existing image, and the image is rewritten.
+    /// Not available in bulk mode, where --job continues interrupted downloads.
+    #[arg(long)]
+    pub resume: bool,
+
+    /// Save the progress of the download to this file, and keep the downloaded tiles on disk
+    /// (in the tile cache, or in a folder named after the file followed by `.tiles`).
+    /// If the download is interrupted, running the same command again continues it where it stopped.
+    /// In bulk mode, the images that were already downloaded are skipped.
+    /// The file and the tiles are removed once all the images are downloaded.
+    #[arg(long = "job", value_name = "JOB_FILE")]
+    pub job_file: Option<PathBuf>,
+
+    /// When some tiles cannot be downloaded, fill the holes they leave with the same part
+    /// of the image taken from the next lower zoom level, and upscaled.
+    /// The filled parts are logged, and listed in the sidecar file.
+    #[arg(long)]
+    pub fill_missing: bool,
+
     /// Sets an HTTP header to use on requests.
     /// This option can be repeated in order to set multiple headers.
     /// You can use `-H "Referer: URL"` where URL is the URL of the website's
@@ -137,6 +199,52 @@ pub struct Arguments {
     /// In bulk mode, if no level-specifying argument is defined (such as --max-width), then --largest is implied.
     #[arg(long = "bulk")]
     pub bulk: Option<String>,
+
+    /// In bulk mode, also assemble all the successfully downloaded images into a single PDF file
+    /// at the given path, with one page per image.
+    /// Without --bulk, the input URI is downloaded in bulk mode, as if it were given to --bulk.
+    /// When the images come from a IIIF manifest, the pages have the size of the IIIF canvases,
+    /// and the document is titled after the manifest.
+    #[arg(long = "pdf")]
+    pub pdf: Option<PathBuf>,
+
+    /// In bulk mode, store all the successfully downloaded images in a single comic book archive
+    /// (.cbz) at the given path, instead of separate files.
+    /// Without --bulk, the input URI is downloaded in bulk mode, as if it were given to --bulk.
+    /// The archive contains a ComicInfo.xml file describing the document,
+    /// filled from the metadata of the IIIF manifest.
+    #[arg(long = "cbz")]
+    pub cbz: Option<PathBuf>,
+
+    /// When the input is a IIIF collection, maximum number of levels of nested collections
+    /// to traverse. Manifests listed directly in the input collection are always processed.
+    #[arg(long = "collection-depth", default_value = "5")]
+    pub collection_depth: usize,
+
+    /// When the input is a IIIF collection, only process the manifests whose label or URL
+    /// matches this regular expression
+    #[arg(long = "collection-filter")]
+    pub collection_filter: Option<Regex>,
+
+    /// Download IIIF images in a single request each instead of tile by tile,
+    /// at one of the sizes listed by the server.
+    /// This is done automatically for servers that do not support requesting image regions.
+    #[arg(long = "iiif-full-images")]
+    pub iiif_full_images: bool,
+
+    /// Access token for IIIF images protected by the IIIF Authentication API.
+    /// It is sent as a bearer token with the requests for image information and tiles
+    /// on the same server as the input URL.
+    /// When it is not set, dezoomify-rs requests one from the access token service of the image.
+    #[arg(long = "iiif-token")]
+    pub iiif_token: Option<String>,
+
+    /// Cookie to send to the IIIF Authentication API access token service and with image requests
+    /// on the same server as the input URL,
+    /// such as the session cookie set after logging in with a browser.
+    /// Example: --iiif-cookie 'session=abc123'
+    #[arg(long = "iiif-cookie")]
+    pub iiif_cookie: Option<String>,
 }
 
 impl Default for Arguments {
@@ -151,9 +259,17 @@ impl Default for Arguments {
             max_height: None,
             zoom_level: None,
             image_index: None,
+            region: None,
+            dry_run: None,
             parallelism: 16,
             retries: 1,
             compression: 5,
+            dzi_tile_size: 254,
+            dzi_overlap: 1,
+            sidecar: false,
+            resume: false,
+            job_file: None,
+            fill_missing: false,
             retry_delay: Duration::from_secs(2),
             headers: vec![],
             max_idle_per_host: 32,
@@ -164,6 +280,13 @@ impl Default for Arguments {
             logging: "info".to_string(),
             tile_storage_folder: None,
             bulk: None,
+            pdf: None,
+            cbz: None,
+            collection_depth: 5,
+            collection_filter: None,
+            iiif_full_images: false,
+            iiif_token: None,
+            iiif_cookie: None,
         }
     }
 }
@@ -180,7 +303,19 @@ impl Arguments {
     }
 
     pub fn is_bulk_mode(&self) -> bool {
-        self.bulk.is_some()
+        self.bulk.is_some() || self.pdf.is_some() || self.cbz.is_some()
+    }
+
+    /// The list of images to download in bulk mode: the --bulk argument,
+    /// or the input URI when its images are assembled into a single document
+    pub fn bulk_source(&self) -> Result<String, ZoomError> {
+        match &self.bulk {
+            Some(bulk) => Ok(bulk.clone()),
+            None if self.is_bulk_mode() => self.choose_input_uri(),
+            None => Err(ZoomError::NoBulkUrl {
+                bulk_file_path: "No bulk source specified".to_string(),
+            }),
+        }
     }
 
     pub fn should_use_largest(&self) -> bool {
@@ -195,13 +330,32 @@ impl Arguments {
         self.max_width.is_some() || self.max_height.is_some() || self.zoom_level.is_some()
     }
     pub fn find_dezoomer(&self) -> Result<Box<dyn Dezoomer>, ZoomError> {
-        auto::all_dezoomers(true)
+        auto::all_dezoomers_with_options(true, &self.iiif_options())
             .into_iter()
             .find(|d| d.name() == self.dezoomer)
             .ok_or_else(|| ZoomError::NoSuchDezoomer {
                 name: self.dezoomer.clone(),
             })
     }
+    pub(crate) fn encoder_options(&self) -> EncoderOptions {
+        EncoderOptions {
+            compression: self.compression,
+            dzi_tile_size: self.dzi_tile_size,
+            dzi_overlap: self.dzi_overlap,
+            provenance: None,
+        }
+    }
+    pub fn iiif_options(&self) -> IiifOptions {
+        IiifOptions {
+            collection_depth: self.collection_depth,
+            collection_filter: self.collection_filter.clone(),
+            full_images: self.iiif_full_images,
+            access_token: self.iiif_token.clone(),
+            token_origin: None,
+            cookie: self.iiif_cookie.clone(),
+            credentials_origin: None,
+        }
+    }
     pub fn best_size<I: Iterator<Item = Vec2d>>(&self, sizes: I) -> Option<Vec2d> {
         if self.should_use_largest() {
             sizes.max_by_key(|s| s.area())
@@ -304,6 +458,25 @@ fn test_bulk_url_reading() {
     assert!(!args.should_use_largest());
 }
 
+#[test]
+fn test_documents_imply_bulk_mode() {
+    let args = Arguments {
+        input_uri: Some("manifest.json".into()),
+        pdf: Some("book.pdf".into()),
+        ..Default::default()
+    };
+    assert!(args.is_bulk_mode());
+    assert_eq!(args.bulk_source().unwrap(), "manifest.json");
+    assert!(Arguments::default().bulk_source().is_err());
+
+    let args = Arguments {
+        input_uri: Some("manifest.json".into()),
+        cbz: Some("book.cbz".into()),
+        ..Default::default()
+    };
+    assert!(args.is_bulk_mode());
+}
+
 #[test]
 fn test_should_use_largest() {
     let mut args = Arguments::default();
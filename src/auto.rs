@@ -1,9 +1,12 @@
+use std::collections::HashMap;
+
 use log::debug;
 
 use crate::dezoomer::{
     Dezoomer, DezoomerError, DezoomerInput, DezoomerResult, ZoomLevel, ZoomLevels,
 };
 use crate::errors::DezoomerError::NeedsData;
+use crate::iiif::IiifOptions;
 
 /// Reorder dezoomers to prioritize those most likely to handle the given URL
 pub fn prioritize_dezoomers_for_url(
@@ -22,6 +25,14 @@ pub fn prioritize_dezoomers_for_url(
         ("ImageProperties.xml", "zoomify"),
         ("TileGroup", "zoomify"),
         ("digitalcollections.nypl.org", "nypl"),
+        ("WMTSCapabilities", "wmts"),
+        ("GetCapabilities", "wmts"),
+        ("{{Z}}", "xyz"),
+        ("{{z}}", "xyz"),
+        ("{{Z=", "xyz"),
+        ("{{z=", "xyz"),
+        ("{{QUADKEY", "xyz"),
+        ("{{quadkey", "xyz"),
         ("{{", "generic"),
     ];
 
@@ -49,21 +60,36 @@ pub fn prioritize_dezoomers_for_url(
 }
 
 pub fn all_dezoomers(include_generic: bool) -> Vec<Box<dyn Dezoomer>> {
+    all_dezoomers_with_options(include_generic, &IiifOptions::default())
+}
+
+/// Same as [all_dezoomers], with user-provided options for the dezoomers that accept some
+pub fn all_dezoomers_with_options(
+    include_generic: bool,
+    iiif_options: &IiifOptions,
+) -> Vec<Box<dyn Dezoomer>> {
     let mut dezoomers: Vec<Box<dyn Dezoomer>> = vec![
         Box::<crate::custom_yaml::CustomDezoomer>::default(),
         Box::<crate::google_arts_and_culture::GAPDezoomer>::default(),
         Box::<crate::zoomify::ZoomifyDezoomer>::default(),
-        Box::<crate::iiif::IIIF>::default(),
+        Box::new(crate::iiif::IIIF::new(iiif_options.clone())),
         Box::<crate::dzi::DziDezoomer>::default(),
+        Box::<crate::openseadragon::OpenSeadragonDezoomer>::default(),
+        Box::<crate::wmts::WmtsDezoomer>::default(),
+        Box::<crate::xyz::XyzDezoomer>::default(),
         Box::<crate::generic::GenericDezoomer>::default(),
         Box::<crate::pff::PFF>::default(),
-        Box::<crate::krpano::KrpanoDezoomer>::default(),
         Box::<crate::iipimage::IIPImage>::default(),
         Box::<crate::nypl::NYPLImage>::default(),
         Box::<crate::bulk_text::BulkTextDezoomer>::default(),
+        // Web pages are scanned after the dezoomers that read the pages of their own websites,
+        // and before krpano, which accepts any xml-like file, even when it contains no image
+        Box::<crate::html_page::HtmlPageDezoomer>::default(),
+        Box::<crate::krpano::KrpanoDezoomer>::default(),
     ];
     if include_generic {
-        dezoomers.push(Box::<AutoDezoomer>::default())
+        let auto_dezoomers = all_dezoomers_with_options(false, iiif_options);
+        dezoomers.push(Box::new(AutoDezoomer::new(auto_dezoomers)))
     }
     dezoomers
 }
@@ -73,22 +99,29 @@ pub struct AutoDezoomer {
     errors: Vec<(&'static str, DezoomerError)>,
     successes: Vec<ZoomLevel>,
     needs_uris: Vec<String>,
+    /// The last file each dezoomer requested, by dezoomer name
+    requested_uris: HashMap<&'static str, String>,
     prioritized_for_url: Option<String>,
 }
 
 impl Default for AutoDezoomer {
     fn default() -> Self {
+        AutoDezoomer::new(all_dezoomers(false))
+    }
+}
+
+impl AutoDezoomer {
+    pub fn new(dezoomers: Vec<Box<dyn Dezoomer>>) -> Self {
         AutoDezoomer {
-            dezoomers: all_dezoomers(false),
+            dezoomers,
             errors: vec![],
             successes: vec![],
             needs_uris: vec![],
+            requested_uris: HashMap::new(),
             prioritized_for_url: None,
         }
     }
-}
 
-impl AutoDezoomer {
     /// Prioritize dezoomers for a specific URL if not already done
     fn prioritize_for_url_if_needed(&mut self, url: &str) {
         if self.prioritized_for_url.as_ref() != Some(&url.to_string()) {
@@ -105,6 +138,18 @@ impl Dezoomer for AutoDezoomer {
         "auto"
     }
 
+    /// Only the dezoomers that requested the file can add headers to the request,
+    /// because they can contain credentials
+    fn http_headers(&self, uri: &str) -> HashMap<String, String> {
+        self.dezoomers
+            .iter()
+            .filter(|dezoomer| {
+                self.requested_uris.get(dezoomer.name()).map(String::as_str) == Some(uri)
+            })
+            .flat_map(|dezoomer| dezoomer.http_headers(uri))
+            .collect()
+    }
+
     fn zoom_levels(&mut self, data: &DezoomerInput) -> Result<ZoomLevels, DezoomerError> {
         // Prioritize dezoomers based on the URL pattern
         self.prioritize_for_url_if_needed(&data.uri);
@@ -125,6 +170,7 @@ impl Dezoomer for AutoDezoomer {
                 }
                 Err(DezoomerError::NeedsData { uri }) => {
                     debug!("dezoomer '{}' requested to load {}", dezoomer.name(), &uri);
+                    self.requested_uris.insert(dezoomer.name(), uri.clone());
                     if !self.needs_uris.contains(&uri) {
                         self.needs_uris.push(uri);
                     }
@@ -172,6 +218,7 @@ impl Dezoomer for AutoDezoomer {
                 }
                 Err(DezoomerError::NeedsData { uri }) => {
                     debug!("dezoomer '{}' requested to load {}", dezoomer.name(), &uri);
+                    self.requested_uris.insert(dezoomer.name(), uri.clone());
                     if !self.needs_uris.contains(&uri) {
                         self.needs_uris.push(uri);
                     }
@@ -292,4 +339,45 @@ mod tests {
         // Current implementation is case-sensitive, so uppercase won't match
         assert_eq!(prioritized[0].name(), original_first);
     }
+
+    #[test]
+    fn test_headers_of_requesting_dezoomer_only() {
+        let options = IiifOptions {
+            cookie: Some("session=secret".into()),
+            ..Default::default()
+        };
+        let mut auto = AutoDezoomer::new(all_dezoomers_with_options(false, &options));
+        let info = "https://iiif.example.com/img/info.json";
+        let input = DezoomerInput {
+            uri: info.into(),
+            contents: crate::dezoomer::PageContents::Unknown,
+        };
+        assert!(matches!(auto.zoom_levels(&input), Err(NeedsData { .. })));
+        assert_eq!(
+            auto.http_headers(info).get("Cookie").map(String::as_str),
+            Some("session=secret")
+        );
+        // The credentials of the IIIF dezoomer are not sent with the requests of other dezoomers
+        assert!(
+            auto.http_headers("https://example.org/page.html")
+                .is_empty()
+        );
+    }
+    #[test]
+    fn test_html_page_before_krpano() {
+        // krpano parses html pages without finding any image in them
+        let page = r#"<html><body><script>
+            OpenSeadragon({id: "viewer", tileSources: "/images/painting.dzi"});
+            </script></body></html>"#;
+        let input = DezoomerInput {
+            uri: "https://example.com/viewer.html".into(),
+            contents: crate::dezoomer::PageContents::Success(page.as_bytes().to_vec()),
+        };
+        let images = AutoDezoomer::default().dezoomer_result(&input).unwrap();
+        assert_eq!(images.len(), 1);
+        assert_eq!(
+            images[0].url(),
+            Some("https://example.com/images/painting.dzi")
+        );
+    }
 }
@@ -1,4 +1,6 @@
 use crate::dezoomer::*;
+use crate::generic::is_generic_template;
+use crate::xyz::is_xyz_template;
 use custom_error::custom_error;
 
 custom_error! {pub BulkTextError
@@ -72,8 +74,8 @@ fn validate_url_or_path(input: &str, line_number: usize) -> Result<(), BulkTextE
         return Ok(());
     }
 
-    // If it is an URL template, check if it is valid
-    if input.contains("{{X}}") || input.contains("{{Y}}") {
+    // Tile URL templates, for the generic and xyz dezoomers
+    if is_generic_template(input) || is_xyz_template(input) {
         return Ok(());
     }
 
@@ -116,6 +118,8 @@ fn parse_text_urls(content: &str) -> Result<Vec<ZoomableImageUrl>, BulkTextError
         urls.push(ZoomableImageUrl {
             url: url_part.to_string(),
             title,
+            document: None,
+            page_size: None,
         });
     }
 
@@ -210,6 +214,18 @@ mod tests {
         assert!(error_msg.contains("not_a_valid_url"));
     }
 
+    #[test]
+    fn test_parse_url_templates() {
+        let content = "tiles/map_{{x:02}}_{{y:02}}.jpg\n\
+            tiles/{{Z}}/{{X}}/{{-Y}}.png\n\
+            tiles/a{{QUADKEY}}.jpeg Aerial view";
+        let urls = parse_text_urls(content).unwrap();
+        assert_eq!(urls.len(), 3);
+        assert_eq!(urls[1].url, "tiles/{{Z}}/{{X}}/{{-Y}}.png");
+        assert_eq!(urls[2].title, Some("Aerial view".to_string()));
+        assert!(parse_text_urls("tiles/{{Z}}.png").is_err());
+    }
+
     #[test]
     fn test_extract_title_from_url() {
         assert_eq!(
@@ -1,4 +1,7 @@
-use evalexpr::{ContextWithMutableVariables, DefaultNumericTypes, HashMapContext};
+use evalexpr::{
+    ContextWithMutableFunctions, ContextWithMutableVariables, DefaultNumericTypes, EvalexprError,
+    EvalexprResult, Function, HashMapContext, Value,
+};
 use itertools::Itertools;
 use regex::Regex;
 use serde::Deserialize;
@@ -170,8 +173,29 @@ impl Variables {
 }
 
 fn build_context() -> HashMapContext<DefaultNumericTypes> {
-    HashMapContext::new()
+    let mut context = HashMapContext::new();
     // Add custom variables and functions here
+    context
+        .set_function("quadkey".into(), Function::new(quadkey))
+        .expect("a HashMapContext accepts functions");
+    context
+}
+
+/// `quadkey(zoom, x, y)`, for the tile servers that address their tiles by quadkey
+fn quadkey(argument: &Value) -> EvalexprResult<Value> {
+    let numbers = argument
+        .as_fixed_len_tuple(3)?
+        .iter()
+        .map(|n| n.as_int().map(u32::try_from))
+        .collect::<Result<Vec<_>, _>>()?;
+    match numbers[..] {
+        [Ok(zoom), Ok(x), Ok(y)] if zoom <= 30 && x >> zoom == 0 && y >> zoom == 0 => {
+            Ok(Value::String(crate::xyz::quadkey(zoom, x, y)))
+        }
+        _ => Err(EvalexprError::CustomMessage(format!(
+            "quadkey{argument}: there is no such tile"
+        ))),
+    }
 }
 
 custom_error! {pub BadVariableError
@@ -216,6 +240,16 @@ mod tests {
         )
     }
 
+    #[test]
+    fn quadkey_function() {
+        let vars = Variables(vec![VarOrConst::var("x", 3, 3, 1).unwrap()]);
+        let ctx = vars.iter_contexts().next().unwrap().unwrap();
+        let quadkey = |expression| evalexpr::eval_string_with_context(expression, &ctx);
+        assert_eq!(quadkey("quadkey(3, x, 5)").unwrap(), "213");
+        assert!(quadkey("quadkey(1, x, 0)").is_err());
+        assert!(quadkey("quadkey(x, 0)").is_err());
+    }
+
     #[test]
     fn iter_contexts() {
         let vars = Variables(vec![
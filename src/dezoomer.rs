@@ -4,6 +4,8 @@ use std::error::Error;
 use std::fmt::Debug;
 use std::str::FromStr;
 
+use serde::{Deserialize, Serialize};
+
 pub use crate::errors::DezoomerError;
 
 pub use super::Vec2d;
@@ -79,6 +81,22 @@ pub trait ZoomableImageWithLevels: Send + Sync + std::fmt::Debug {
 pub struct ZoomableImageUrl {
     pub url: String,
     pub title: Option<String>,
+    /// The document the image is a part of, such as a IIIF manifest
+    pub document: Option<DocumentInfo>,
+    /// Size of the page the image is displayed on in its document, such as a IIIF canvas
+    pub page_size: Option<Vec2d>,
+}
+
+/// Information about a document containing multiple images, such as a book
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct DocumentInfo {
+    pub title: Option<String>,
+    /// Descriptive metadata, as label and value pairs
+    pub metadata: Vec<(String, String)>,
+    /// The license of the document, usually as a URL
+    pub rights: Option<String>,
+    /// Text that must be displayed along with the document, such as an attribution
+    pub required_statement: Option<String>,
 }
 
 /// Result type for dezoomer operations - a vector of ZoomableImages
@@ -102,23 +120,52 @@ impl ZoomableImage {
         }
     }
 
+    /// The URL of the image, if it still needs to be resolved
+    pub fn url(&self) -> Option<&str> {
+        match self {
+            ZoomableImage::Image(_) => None,
+            ZoomableImage::ImageUrl(url) => Some(&url.url),
+        }
+    }
+
+    pub fn document(&self) -> Option<&DocumentInfo> {
+        match self {
+            ZoomableImage::Image(_) => None,
+            ZoomableImage::ImageUrl(url) => url.document.as_ref(),
+        }
+    }
+
+    pub fn page_size(&self) -> Option<Vec2d> {
+        match self {
+            ZoomableImage::Image(_) => None,
+            ZoomableImage::ImageUrl(url) => url.page_size,
+        }
+    }
+
+    /// Finds the zoom levels of the image.
+    /// The URLs of the metadata files fetched to find them are added to `metadata_urls`.
     pub async fn into_zoom_levels(
         self,
         http: &reqwest::Client,
+        iiif_options: &crate::iiif::IiifOptions,
+        metadata_urls: &mut Vec<String>,
     ) -> Result<ZoomLevels, DezoomerError> {
         match self {
             ZoomableImage::Image(image) => image.into_zoom_levels(),
             ZoomableImage::ImageUrl(url) => {
                 // Import at the top of the function rather than globally
-                use crate::auto::{all_dezoomers, prioritize_dezoomers_for_url};
-                use crate::network::fetch_uri;
+                use crate::auto::{all_dezoomers_with_options, prioritize_dezoomers_for_url};
+                use crate::network::fetch_uri_with_headers;
                 use log::debug;
 
                 debug!("Resolving ZoomableImageUrl: {}", url.url);
 
                 // Try each dezoomer on this URL to find one that can process it
                 // Prioritize dezoomers based on URL patterns for better performance
-                let dezoomers = prioritize_dezoomers_for_url(&url.url, all_dezoomers(false));
+                let dezoomers = prioritize_dezoomers_for_url(
+                    &url.url,
+                    all_dezoomers_with_options(false, iiif_options),
+                );
 
                 for mut dezoomer in dezoomers {
                     debug!("Trying dezoomer '{}' on URL: {}", dezoomer.name(), url.url);
@@ -146,7 +193,11 @@ impl ZoomableImage {
                                     dezoomer.name(),
                                     needed_uri
                                 );
-                                let contents = fetch_uri(&needed_uri, http).await.into();
+                                metadata_urls.push(needed_uri.clone());
+                                let headers = dezoomer.http_headers(&needed_uri);
+                                let contents = fetch_uri_with_headers(&needed_uri, http, &headers)
+                                    .await
+                                    .into();
                                 input.uri = needed_uri;
                                 input.contents = contents;
                             }
@@ -217,6 +268,11 @@ pub trait Dezoomer {
         Ok(dezoomer_result_from_single_image(image))
     }
 
+    /// Additional http headers to use when requesting a file this dezoomer needs
+    fn http_headers(&self, _uri: &str) -> HashMap<String, String> {
+        HashMap::new()
+    }
+
     fn assert(&self, c: bool) -> Result<(), DezoomerError> {
         if c {
             Ok(())
@@ -280,6 +336,16 @@ pub trait TileProvider: Debug {
         None
     }
 
+    /// The width and height of the tiles, when they are known before downloading them
+    fn tile_size_hint(&self) -> Option<Vec2d> {
+        None
+    }
+
+    /// The number of tiles, when it is known before downloading them
+    fn tile_count_hint(&self) -> Option<u32> {
+        None
+    }
+
     /// A collection of http headers to use when requesting the tiles
     fn http_headers(&self) -> HashMap<String, String> {
         HashMap::new()
@@ -340,6 +406,12 @@ pub trait TilesRect: Debug {
     fn post_process_fn(&self) -> PostProcessFn {
         PostProcessFn::None
     }
+    fn http_headers(&self) -> HashMap<String, String> {
+        let mut headers = HashMap::new();
+        // By default, use the first tile as the referer, so that it is on the same domain
+        headers.insert("Referer".into(), self.tile_url(Vec2d::default()));
+        headers
+    }
 
     fn tile_count(&self) -> u32 {
         let Vec2d { x, y } = self.size().ceil_div(self.tile_size());
@@ -386,15 +458,20 @@ impl<T: TilesRect> TileProvider for T {
         Some(self.size())
     }
 
+    fn tile_size_hint(&self) -> Option<Vec2d> {
+        Some(self.tile_size())
+    }
+
+    fn tile_count_hint(&self) -> Option<u32> {
+        Some(self.tile_count())
+    }
+
     fn http_headers(&self) -> HashMap<String, String> {
-        let mut headers = HashMap::new();
-        // By default, use the first tile as the referer, so that it is on the same domain
-        headers.insert("Referer".into(), self.tile_url(Vec2d::default()));
-        headers
+        TilesRect::http_headers(self)
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
 pub struct TileReference {
     pub url: String,
     pub position: Vec2d,
@@ -3,15 +3,18 @@ use crate::arguments::Arguments;
 use crate::dezoomer::{TileFetchResult, TileReference, ZoomLevel, ZoomLevelIter};
 use crate::encoder::tile_buffer::TileBuffer;
 use crate::errors::{self, ZoomError}; // `self` imports the errors module itself
+use crate::job::JobFile;
 use crate::max_size_in_rect;
 use crate::network::{TileDownloader, client as network_client};
+use crate::region::{Region, grid_cells};
+use crate::resume::PreviousDownload;
 use crate::throttler::Throttler;
 use crate::tile::Tile;
 use crate::vec2d::Vec2d; // This is a public function from lib.rs
 
 use futures::stream::StreamExt;
 use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
-use log::debug;
+use log::{debug, info};
 use std::default::Default;
 
 // --- DownloadState ---
@@ -21,7 +24,17 @@ pub(crate) struct DownloadState {
     pub(crate) successful_tiles: u64,
     pub(crate) last_batch_count: u64,
     pub(crate) last_batch_successes: u64,
-    tile_size: Option<Vec2d>,
+    pub(crate) tile_size: Option<Vec2d>,
+    /// Size of the largest tile that was received
+    pub(crate) largest_tile_size: Option<Vec2d>,
+    /// Size of the image, once it is known
+    pub(crate) image_size: Option<Vec2d>,
+    /// The part of the image that is downloaded, with `--region`
+    pub(crate) region: Option<Region>,
+    /// The tiles that could not be downloaded
+    pub(crate) failed_tiles: Vec<TileReference>,
+    /// The parts of the image that were filled from a lower zoom level
+    pub(crate) upscaled_regions: Vec<Region>,
 }
 
 impl DownloadState {
@@ -40,8 +53,22 @@ impl DownloadState {
         self.successful_tiles += 1;
     }
 
+    fn record_failure(&mut self, tile: TileReference) {
+        self.failed_tiles.push(tile);
+    }
+
     fn set_tile_size(&mut self, size: Vec2d) {
         self.tile_size = Some(size);
+        self.largest_tile_size = Some(self.largest_tile_size.map_or(size, |s| s.max(size)));
+    }
+
+    /// The part of a tile that is inside the downloaded region,
+    /// positioned in the output image
+    pub(crate) fn visible_part(&self, tile: Tile) -> Option<Tile> {
+        match &self.region {
+            Some(region) => region.crop(tile),
+            None => Some(tile),
+        }
     }
 
     pub(crate) fn create_fetch_result(&self) -> TileFetchResult {
@@ -133,6 +160,10 @@ pub(crate) struct TileDownloadCoordinator<'a> {
     downloader: TileDownloader,
     throttler: Throttler,
     args: &'a Arguments,
+    /// An incomplete image, from which the tiles that were already downloaded are taken
+    previous: Option<PreviousDownload>,
+    /// The job in which the downloaded tiles are recorded
+    job: Option<&'a mut JobFile>,
 }
 
 impl<'a> TileDownloadCoordinator<'a> {
@@ -144,9 +175,29 @@ impl<'a> TileDownloadCoordinator<'a> {
             downloader,
             throttler,
             args,
+            previous: None,
+            job: None,
         })
     }
 
+    /// Downloads only the tiles that are missing from a previous download
+    pub(crate) fn resuming(mut self, previous: Option<PreviousDownload>) -> Self {
+        self.previous = previous;
+        self
+    }
+
+    /// Records the progress of the download in a job, and keeps the tiles on disk
+    /// (in the tile cache if there is one, or else in the folder of the job)
+    pub(crate) fn recording_to(mut self, job: Option<&'a mut JobFile>) -> Result<Self, ZoomError> {
+        if let Some(job) = &job
+            && self.downloader.tile_storage_folder.is_none()
+        {
+            self.downloader.tile_storage_folder = Some(job.tile_folder()?);
+        }
+        self.job = job;
+        Ok(self)
+    }
+
     pub(crate) async fn download_batch(
         &mut self,
         tile_refs: Vec<TileReference>,
@@ -155,11 +206,51 @@ impl<'a> TileDownloadCoordinator<'a> {
         progress: &ProgressManager,
         zoom_level_iter: &ZoomLevelIter<'_>,
     ) -> Result<(), ZoomError> {
+        // With --region, only the tiles that intersect the region are downloaded.
+        // Tiles cannot be filtered before the size of the image is known,
+        // because some dezoomers find it by requesting tiles one by one.
+        let tile_refs = match (state.region, zoom_level_iter.size_hint()) {
+            (Some(region), Some(size)) => grid_cells(tile_refs, size)
+                .filter(|(_, cell)| cell.intersects(&region))
+                .map(|(tile_ref, _)| tile_ref)
+                .collect(),
+            _ => tile_refs,
+        };
         state.add_batch(tile_refs.len() as u64);
         progress.set_total_tiles(state.total_tiles); // Update progress bar length with cumulative total
         progress.set_requesting_tiles();
 
-        prepare_canvas_size(canvas, zoom_level_iter).await?;
+        if let Some(job) = &mut self.job {
+            let completed = job.add_tiles(&tile_refs);
+            if completed > 0 {
+                info!("{completed} tiles were already downloaded by a previous run");
+            }
+        }
+
+        prepare_canvas_size(canvas, zoom_level_iter, state.region).await?;
+        state.image_size = zoom_level_iter.size_hint().or(state.image_size);
+
+        // Tiles that were already downloaded are added first,
+        // so that new tiles are drawn over the holes of the previous image
+        let tile_refs = match &self.previous {
+            Some(previous) => {
+                let (to_download, reused) = previous.reuse_tiles(tile_refs);
+                debug!("Reusing {} tiles from the previous download", reused.len());
+                for tile in reused {
+                    progress.increment();
+                    state.record_success();
+                    if let Some(job) = &mut self.job {
+                        job.record_success(tile.position());
+                    }
+                    state.set_tile_size(tile.size());
+                    if let Some(tile) = state.visible_part(tile) {
+                        canvas.add_tile(tile).await;
+                    }
+                }
+                to_download
+            }
+            None => tile_refs,
+        };
 
         let mut stream = futures::stream::iter(tile_refs)
             .map(|tile_ref: TileReference| self.downloader.download_tile(tile_ref))
@@ -168,6 +259,9 @@ impl<'a> TileDownloadCoordinator<'a> {
         while let Some(tile_result) = stream.next().await {
             debug!("Received tile result: {:?}", tile_result); // Tile and TileDownloadError need Debug
             progress.increment();
+            if let Err(e) = &tile_result {
+                state.record_failure(e.tile_reference.clone());
+            }
 
             let (tile, success) = process_tile_result(
                 tile_result,
@@ -181,20 +275,29 @@ impl<'a> TileDownloadCoordinator<'a> {
                 state.record_success();
                 if let Some(ref tile) = tile {
                     state.set_tile_size(tile.size());
+                    if let Some(job) = &mut self.job {
+                        job.record_success(tile.position());
+                    }
                 }
             }
 
-            if let Some(tile) = tile {
+            // With --fill-missing, the holes are filled once all the tiles are downloaded
+            if let Some(tile) = tile
+                && (success || !self.args.fill_missing)
+                && let Some(tile) = state.visible_part(tile)
+            {
                 canvas.add_tile(tile).await;
             }
             self.throttler.wait().await;
         }
+        if let Some(job) = &mut self.job {
+            job.checkpoint();
+        }
         Ok(())
     }
 }
 
-// Helper function, private to this module
-fn create_tile_downloader(
+pub(crate) fn create_tile_downloader(
     zoom_level: &ZoomLevel,
     args: &Arguments,
 ) -> Result<TileDownloader, ZoomError> {
@@ -212,8 +315,13 @@ fn create_tile_downloader(
 async fn prepare_canvas_size(
     canvas: &mut TileBuffer,
     zoom_level_iter: &ZoomLevelIter<'_>,
+    region: Option<Region>,
 ) -> Result<(), ZoomError> {
     if let Some(size) = zoom_level_iter.size_hint() {
+        let size = match region {
+            Some(region) => region.size.min(size - region.position),
+            None => size,
+        };
         canvas.set_size(size).await?;
     }
     Ok(())
@@ -0,0 +1,305 @@
+//! Describing an image and its zoom levels without downloading it, with `--dry-run`.
+//! In bulk mode, every image of the bulk input is described.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use serde::Serialize;
+
+use crate::arguments::Arguments;
+use crate::dezoomer::ZoomLevel;
+use crate::network::client;
+use crate::{FoundImage, Vec2d, ZoomError, find_image, get_dezoomer_result, select_level_index};
+
+/// Number of tile URLs listed for each level
+const SAMPLE_TILES: usize = 3;
+
+/// How the description of the image is printed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum DryRunFormat {
+    /// Human-readable text
+    Text,
+    /// A JSON object, for scripts
+    Json,
+}
+
+/// What would be downloaded
+#[derive(Debug, Serialize)]
+pub struct ImageDescription {
+    /// The URI given by the user
+    pub input_uri: String,
+    /// The URL of the image, or of the page it was found in
+    pub source_url: String,
+    pub title: Option<String>,
+    /// The metadata files that were fetched to find the image, in order
+    pub metadata_urls: Vec<String>,
+    pub levels: Vec<LevelDescription>,
+    /// The index of the level that would be downloaded,
+    /// if the arguments are enough to choose it without asking
+    pub chosen_level: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LevelDescription {
+    /// The format of the zoomable image, such as "Zoomify Image"
+    pub dezoomer: String,
+    /// The level, as it is described to the user
+    pub name: String,
+    pub size: Option<Vec2d>,
+    pub tile_size: Option<Vec2d>,
+    pub tile_count: Option<u32>,
+    /// The URLs of the first tiles of the level
+    pub sample_tile_urls: Vec<String>,
+    /// The HTTP headers the tiles are requested with
+    pub http_headers: BTreeMap<String, String>,
+    /// Estimated size of the uncompressed image, at 3 bytes per pixel.
+    /// The tiles to download are compressed, and usually much smaller.
+    pub uncompressed_bytes: Option<u64>,
+}
+
+impl LevelDescription {
+    fn new(level: &mut ZoomLevel) -> Self {
+        // Listing the tiles of a level does not download anything
+        let sample_tile_urls = level
+            .next_tiles(None)
+            .into_iter()
+            .take(SAMPLE_TILES)
+            .map(|tile_ref| tile_ref.url)
+            .collect();
+        let size = level.size_hint();
+        LevelDescription {
+            dezoomer: format!("{level:?}"),
+            name: level.name(),
+            size,
+            tile_size: level.tile_size_hint(),
+            tile_count: level.tile_count_hint(),
+            sample_tile_urls,
+            http_headers: level.http_headers().into_iter().collect(),
+            uncompressed_bytes: size.map(|s| s.area() * 3),
+        }
+    }
+}
+
+/// Finds the image and its zoom levels, without downloading any tile
+pub async fn describe_image(args: &Arguments) -> Result<ImageDescription, ZoomError> {
+    let mut metadata_urls = vec![];
+    let FoundImage {
+        uri,
+        source_url,
+        title,
+        zoom_levels,
+        ..
+    } = find_image(args, &mut metadata_urls).await?;
+    ImageDescription::new(args, uri, source_url, title, metadata_urls, zoom_levels)
+}
+
+/// Describes every image of the bulk input, without downloading any tile.
+/// The images whose zoom levels cannot be found are skipped.
+pub async fn describe_bulk(args: &Arguments) -> Result<Vec<ImageDescription>, ZoomError> {
+    let bulk_uri = &args.bulk_source()?;
+    let http = client(std::iter::empty(), args, None)?;
+    let mut dezoomer = args.find_dezoomer()?;
+    let mut bulk_metadata_urls = vec![];
+    let images =
+        get_dezoomer_result(dezoomer.as_mut(), &http, bulk_uri, &mut bulk_metadata_urls).await?;
+    let mut descriptions = Vec::with_capacity(images.len());
+    for (index, image) in images.into_iter().enumerate() {
+        let source_url = image.url().unwrap_or(bulk_uri).to_string();
+        let title = image.title().map(String::from);
+        let mut metadata_urls = bulk_metadata_urls.clone();
+        let description = match image
+            .into_zoom_levels(&http, &args.iiif_options(), &mut metadata_urls)
+            .await
+        {
+            Ok(zoom_levels) => ImageDescription::new(
+                args,
+                bulk_uri.clone(),
+                source_url,
+                title,
+                metadata_urls,
+                zoom_levels,
+            ),
+            Err(e) => Err(e.into()),
+        };
+        match description {
+            Ok(description) => descriptions.push(description),
+            Err(e) => log::warn!("Unable to describe image {}: {}", index + 1, e),
+        }
+    }
+    Ok(descriptions)
+}
+
+/// Prints what would be downloaded, in the given format
+pub async fn dry_run(args: &Arguments, format: DryRunFormat) -> Result<(), ZoomError> {
+    if args.is_bulk_mode() {
+        let descriptions = describe_bulk(args).await?;
+        match format {
+            DryRunFormat::Text => {
+                let texts: Vec<String> = descriptions.iter().map(|d| d.to_string()).collect();
+                print!("{}", texts.join("\n"));
+            }
+            DryRunFormat::Json => print_json(&descriptions)?,
+        }
+        return Ok(());
+    }
+    let description = describe_image(args).await?;
+    match format {
+        DryRunFormat::Text => print!("{description}"),
+        DryRunFormat::Json => print_json(&description)?,
+    }
+    Ok(())
+}
+
+fn print_json<T: Serialize>(value: &T) -> Result<(), ZoomError> {
+    let json = serde_json::to_string_pretty(value).map_err(std::io::Error::from)?;
+    println!("{json}");
+    Ok(())
+}
+
+impl ImageDescription {
+    fn new(
+        args: &Arguments,
+        input_uri: String,
+        source_url: String,
+        title: Option<String>,
+        metadata_urls: Vec<String>,
+        mut zoom_levels: Vec<ZoomLevel>,
+    ) -> Result<Self, ZoomError> {
+        if zoom_levels.is_empty() {
+            return Err(ZoomError::NoLevels);
+        }
+        let chosen_level = select_level_index(&zoom_levels, args);
+        let title = title.or_else(|| zoom_levels.iter().find_map(|l| l.title()));
+        Ok(ImageDescription {
+            input_uri,
+            source_url,
+            title,
+            metadata_urls,
+            levels: zoom_levels.iter_mut().map(LevelDescription::new).collect(),
+            chosen_level,
+        })
+    }
+}
+
+impl fmt::Display for ImageDescription {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(title) = &self.title {
+            writeln!(f, "Image: {title}")?;
+        }
+        writeln!(f, "Source: {}", self.source_url)?;
+        for url in &self.metadata_urls {
+            writeln!(f, "Metadata: {url}")?;
+        }
+        writeln!(f, "Found {} zoom levels:", self.levels.len())?;
+        for (i, level) in self.levels.iter().enumerate() {
+            let marker = if self.chosen_level == Some(i) {
+                '*'
+            } else {
+                ' '
+            };
+            writeln!(f, "{marker}{i: >2}. {}", level.name)?;
+            write!(f, "{level}")?;
+        }
+        match self.chosen_level {
+            Some(i) => writeln!(f, "Level {i} would be downloaded."),
+            None => writeln!(
+                f,
+                "Use --largest, --max-width, --max-height or --zoom-level to choose the level to download."
+            ),
+        }
+    }
+}
+
+impl fmt::Display for LevelDescription {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let indent = "      ";
+        if let Some(Vec2d { x, y }) = self.size {
+            write!(f, "{indent}Size: {x} x {y} pixels")?;
+            if let Some(count) = self.tile_count {
+                write!(f, ", {count} tiles")?;
+            }
+            if let Some(Vec2d { x, y }) = self.tile_size {
+                write!(f, " of {x} x {y} pixels")?;
+            }
+            writeln!(f)?;
+        }
+        if let Some(bytes) = self.uncompressed_bytes {
+            writeln!(f, "{indent}Uncompressed size: about {}", human_bytes(bytes))?;
+        }
+        for (i, url) in self.sample_tile_urls.iter().enumerate() {
+            let label = if i == 0 { "Tiles:" } else { "" };
+            writeln!(f, "{indent}{label: <8} {url}")?;
+        }
+        for (i, (name, value)) in self.http_headers.iter().enumerate() {
+            let label = if i == 0 { "Headers:" } else { "" };
+            writeln!(f, "{indent}{label: <8} {name}: {value}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Formats a number of bytes with a decimal unit, such as "1.5 MB"
+fn human_bytes(bytes: u64) -> String {
+    if bytes < 1000 {
+        return format!("{bytes} B");
+    }
+    let mut value = bytes as f64 / 1000.;
+    for unit in ["kB", "MB", "GB"] {
+        if value < 1000. {
+            return format!("{value:.1} {unit}");
+        }
+        value /= 1000.;
+    }
+    format!("{value:.1} TB")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dezoomer::TilesRect;
+
+    #[derive(Debug)]
+    struct FakeLvl;
+
+    impl TilesRect for FakeLvl {
+        fn size(&self) -> Vec2d {
+            Vec2d { x: 1000, y: 500 }
+        }
+
+        fn tile_size(&self) -> Vec2d {
+            Vec2d::square(256)
+        }
+
+        fn tile_url(&self, pos: Vec2d) -> String {
+            format!("https://example.com/{}_{}.jpg", pos.x, pos.y)
+        }
+    }
+
+    #[test]
+    fn test_human_bytes() {
+        assert_eq!(human_bytes(999), "999 B");
+        assert_eq!(human_bytes(1_500_000), "1.5 MB");
+        assert_eq!(human_bytes(3_000_000_000), "3.0 GB");
+    }
+
+    #[test]
+    fn test_level_description() {
+        let mut level: ZoomLevel = Box::new(FakeLvl);
+        let description = LevelDescription::new(&mut level);
+        assert_eq!(description.tile_count, Some(8));
+        assert_eq!(description.tile_size, Some(Vec2d::square(256)));
+        assert_eq!(description.uncompressed_bytes, Some(1_500_000));
+        assert_eq!(
+            description.sample_tile_urls,
+            [
+                "https://example.com/0_0.jpg",
+                "https://example.com/1_0.jpg",
+                "https://example.com/2_0.jpg"
+            ]
+        );
+        let text = description.to_string();
+        assert!(text.contains("Size: 1000 x 500 pixels, 8 tiles of 256 x 256 pixels"));
+        assert!(text.contains("Uncompressed size: about 1.5 MB"));
+        assert!(text.contains("Headers: Referer: https://example.com/0_0.jpg"));
+    }
+}
@@ -3,7 +3,7 @@ use std::sync::Arc;
 use custom_error::custom_error;
 use log::debug;
 
-use dzi_file::DziFile;
+pub(crate) use dzi_file::DziFile;
 
 use crate::dezoomer::*;
 use crate::json_utils::all_json;
@@ -66,7 +66,7 @@ fn load_from_properties(url: &str, contents: &[u8]) -> Result<ZoomLevels, DziErr
         })
 }
 
-fn load_from_dzi(url: &str, image_properties: DziFile) -> Result<ZoomLevels, DziError> {
+pub(crate) fn load_from_dzi(url: &str, image_properties: DziFile) -> Result<ZoomLevels, DziError> {
     debug!("Found dzi meta-information: {image_properties:?}");
 
     if image_properties.tile_size == 0 {
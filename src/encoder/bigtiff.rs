@@ -0,0 +1,399 @@
+//! Minimal writer for little-endian BigTIFF files.
+//! BigTIFF uses 64-bit offsets, so files can be larger than 4 GiB.
+//! Image data is written as it comes, and image file directories (IFDs) are written
+//! after the data they describe, so that nothing has to be kept in memory.
+
+use std::io::{self, Seek, SeekFrom, Write};
+
+pub const BYTE: u16 = 1;
+pub const SHORT: u16 = 3;
+pub const LONG: u16 = 4;
+pub const UNDEFINED: u16 = 7;
+pub const LONG8: u16 = 16;
+pub const IFD8: u16 = 18;
+
+pub const TAG_NEW_SUBFILE_TYPE: u16 = 254;
+pub const TAG_IMAGE_WIDTH: u16 = 256;
+pub const TAG_IMAGE_LENGTH: u16 = 257;
+pub const TAG_BITS_PER_SAMPLE: u16 = 258;
+pub const TAG_COMPRESSION: u16 = 259;
+pub const TAG_PHOTOMETRIC_INTERPRETATION: u16 = 262;
+pub const TAG_SAMPLES_PER_PIXEL: u16 = 277;
+pub const TAG_PLANAR_CONFIGURATION: u16 = 284;
+pub const TAG_TILE_WIDTH: u16 = 322;
+pub const TAG_TILE_LENGTH: u16 = 323;
+pub const TAG_TILE_OFFSETS: u16 = 324;
+pub const TAG_TILE_BYTE_COUNTS: u16 = 325;
+pub const TAG_SUB_IFDS: u16 = 330;
+pub const TAG_YCBCR_SUBSAMPLING: u16 = 530;
+pub const TAG_XMP: u16 = 700;
+pub const TAG_EXIF_IFD: u16 = 34665;
+pub const TAG_GPS_IFD: u16 = 34853;
+pub const TAG_ICC_PROFILE: u16 = 34675;
+
+pub const COMPRESSION_NONE: u16 = 1;
+pub const COMPRESSION_JPEG: u16 = 7;
+pub const COMPRESSION_DEFLATE: u16 = 8;
+pub const PHOTOMETRIC_RGB: u16 = 2;
+pub const PHOTOMETRIC_YCBCR: u16 = 6;
+/// Value of the new subfile type field for reduced-resolution versions of an image
+pub const SUBFILE_REDUCED_IMAGE: u32 = 1;
+
+/// A single field of an image file directory. The data is stored in little-endian order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IfdEntry {
+    pub tag: u16,
+    pub field_type: u16,
+    pub count: u64,
+    pub data: Vec<u8>,
+}
+
+impl IfdEntry {
+    pub fn byte(tag: u16, data: Vec<u8>) -> Self {
+        IfdEntry {
+            tag,
+            field_type: BYTE,
+            count: data.len() as u64,
+            data,
+        }
+    }
+
+    pub fn short(tag: u16, values: &[u16]) -> Self {
+        let data = values.iter().flat_map(|v| v.to_le_bytes()).collect();
+        IfdEntry {
+            tag,
+            field_type: SHORT,
+            count: values.len() as u64,
+            data,
+        }
+    }
+
+    pub fn long(tag: u16, value: u32) -> Self {
+        IfdEntry {
+            tag,
+            field_type: LONG,
+            count: 1,
+            data: value.to_le_bytes().to_vec(),
+        }
+    }
+
+    pub fn long8(tag: u16, values: &[u64]) -> Self {
+        let data = values.iter().flat_map(|v| v.to_le_bytes()).collect();
+        IfdEntry {
+            tag,
+            field_type: LONG8,
+            count: values.len() as u64,
+            data,
+        }
+    }
+
+    pub fn ifd8(tag: u16, offsets: &[u64]) -> Self {
+        IfdEntry {
+            field_type: IFD8,
+            ..IfdEntry::long8(tag, offsets)
+        }
+    }
+
+    pub fn undefined(tag: u16, data: Vec<u8>) -> Self {
+        IfdEntry {
+            tag,
+            field_type: UNDEFINED,
+            count: data.len() as u64,
+            data,
+        }
+    }
+}
+
+pub struct BigTiffWriter<W: Write + Seek> {
+    writer: W,
+    position: u64,
+    /// Where to write the offset of the next IFD in the main chain
+    next_ifd_pointer: u64,
+}
+
+impl<W: Write + Seek> BigTiffWriter<W> {
+    pub fn new(mut writer: W) -> io::Result<Self> {
+        // Byte order, version 43 (BigTIFF), offset size, and the offset of the first IFD
+        writer.write_all(b"II")?;
+        writer.write_all(&43u16.to_le_bytes())?;
+        writer.write_all(&8u16.to_le_bytes())?;
+        writer.write_all(&0u16.to_le_bytes())?;
+        writer.write_all(&0u64.to_le_bytes())?;
+        Ok(BigTiffWriter {
+            writer,
+            position: 16,
+            next_ifd_pointer: 8,
+        })
+    }
+
+    /// Writes a block of data, and returns its offset in the file
+    pub fn write_data(&mut self, data: &[u8]) -> io::Result<u64> {
+        // Offsets must be on a word boundary
+        if self.position % 2 == 1 {
+            self.writer.write_all(&[0])?;
+            self.position += 1;
+        }
+        let offset = self.position;
+        self.writer.write_all(data)?;
+        self.position += data.len() as u64;
+        Ok(offset)
+    }
+
+    /// Writes an IFD that is not part of the main chain (such as an EXIF IFD or a sub-IFD),
+    /// and returns its offset
+    pub fn write_ifd(&mut self, mut entries: Vec<IfdEntry>) -> io::Result<u64> {
+        entries.sort_by_key(|e| e.tag);
+        let mut ifd = Vec::with_capacity(8 + 20 * entries.len() + 8);
+        ifd.extend((entries.len() as u64).to_le_bytes());
+        for entry in entries {
+            ifd.extend(entry.tag.to_le_bytes());
+            ifd.extend(entry.field_type.to_le_bytes());
+            ifd.extend(entry.count.to_le_bytes());
+            if entry.data.len() <= 8 {
+                let mut value = entry.data;
+                value.resize(8, 0);
+                ifd.extend(value);
+            } else {
+                ifd.extend(self.write_data(&entry.data)?.to_le_bytes());
+            }
+        }
+        ifd.extend(0u64.to_le_bytes());
+        self.write_data(&ifd)
+    }
+
+    /// Writes an IFD, and links it from the previous IFD of the main chain
+    pub fn append_ifd(&mut self, entries: Vec<IfdEntry>) -> io::Result<u64> {
+        let count = entries.len() as u64;
+        let offset = self.write_ifd(entries)?;
+        self.writer.seek(SeekFrom::Start(self.next_ifd_pointer))?;
+        self.writer.write_all(&offset.to_le_bytes())?;
+        self.writer.seek(SeekFrom::Start(self.position))?;
+        self.next_ifd_pointer = offset + 8 + 20 * count;
+        Ok(offset)
+    }
+
+    pub fn into_inner(mut self) -> io::Result<W> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+/// Fields found in EXIF metadata, converted so that they can be written to a BigTIFF file
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ExifFields {
+    pub image: Vec<IfdEntry>,
+    pub exif: Vec<IfdEntry>,
+    pub gps: Vec<IfdEntry>,
+}
+
+impl ExifFields {
+    /// Parses raw EXIF metadata, which is itself a small TIFF file.
+    /// The fields that describe the layout of the original image are dropped.
+    pub fn parse(exif: &[u8]) -> Option<Self> {
+        let exif = exif.strip_prefix(b"Exif\0\0").unwrap_or(exif);
+        let reader = ExifReader {
+            data: exif,
+            big_endian: match exif.get(..2)? {
+                b"II" => false,
+                b"MM" => true,
+                _ => return None,
+            },
+        };
+        if reader.u16(2)? != 42 {
+            return None;
+        }
+        let mut fields = ExifFields::default();
+        for (entry, pointer) in reader.ifd(reader.u32(4)?)? {
+            match (entry.tag, pointer) {
+                (TAG_EXIF_IFD, Some(offset)) => fields.exif = sub_ifd(&reader, offset),
+                (TAG_GPS_IFD, Some(offset)) => fields.gps = sub_ifd(&reader, offset),
+                (tag, None) if !is_layout_tag(tag) => fields.image.push(entry),
+                _ => {}
+            }
+        }
+        Some(fields)
+    }
+
+    /// Writes the EXIF and GPS IFDs, and returns the fields to add to the image IFD
+    pub fn write<W: Write + Seek>(
+        &self,
+        writer: &mut BigTiffWriter<W>,
+    ) -> io::Result<Vec<IfdEntry>> {
+        let mut entries = self.image.clone();
+        if !self.exif.is_empty() {
+            let offset = writer.write_ifd(self.exif.clone())?;
+            entries.push(IfdEntry::ifd8(TAG_EXIF_IFD, &[offset]));
+        }
+        if !self.gps.is_empty() {
+            let offset = writer.write_ifd(self.gps.clone())?;
+            entries.push(IfdEntry::ifd8(TAG_GPS_IFD, &[offset]));
+        }
+        Ok(entries)
+    }
+}
+
+fn sub_ifd(reader: &ExifReader, offset: u32) -> Vec<IfdEntry> {
+    // Nested pointers, such as the interoperability IFD, are dropped
+    reader
+        .ifd(offset)
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|(entry, pointer)| pointer.is_none().then_some(entry))
+        .collect()
+}
+
+/// Fields that describe how the pixels of the original image are stored
+fn is_layout_tag(tag: u16) -> bool {
+    matches!(
+        tag,
+        254..=259 | 262 | 266 | 273 | 274 | 277..=279 | 284 | 317 | 322..=325 | 330 | 338 | 339
+            | 513 | 514 | 530..=532 | TAG_ICC_PROFILE
+    )
+}
+
+struct ExifReader<'a> {
+    data: &'a [u8],
+    big_endian: bool,
+}
+
+impl ExifReader<'_> {
+    fn bytes<const N: usize>(&self, offset: usize) -> Option<[u8; N]> {
+        let mut bytes: [u8; N] = self.data.get(offset..offset + N)?.try_into().ok()?;
+        if self.big_endian {
+            bytes.reverse();
+        }
+        Some(bytes)
+    }
+
+    fn u16(&self, offset: usize) -> Option<u16> {
+        self.bytes(offset).map(u16::from_le_bytes)
+    }
+
+    fn u32(&self, offset: usize) -> Option<u32> {
+        self.bytes(offset).map(u32::from_le_bytes)
+    }
+
+    /// Reads the entries of an IFD. Entries that point to another IFD are returned with its offset.
+    fn ifd(&self, offset: u32) -> Option<Vec<(IfdEntry, Option<u32>)>> {
+        let offset = offset as usize;
+        let count = self.u16(offset)?;
+        let entries = (0..usize::from(count))
+            .filter_map(|i| self.entry(offset + 2 + 12 * i))
+            .collect();
+        Some(entries)
+    }
+
+    fn entry(&self, offset: usize) -> Option<(IfdEntry, Option<u32>)> {
+        let tag = self.u16(offset)?;
+        let field_type = self.u16(offset + 2)?;
+        let count = self.u32(offset + 4)? as usize;
+        let component_size = match field_type {
+            1 | 2 | 6 | 7 => 1,
+            3 | 8 => 2,
+            // Rationals are pairs of 32-bit integers
+            4 | 5 | 9 | 10 | 11 | 13 => 4,
+            12 => 8,
+            _ => return None,
+        };
+        let components = if matches!(field_type, 5 | 10) {
+            count * 2
+        } else {
+            count
+        };
+        let length = components.checked_mul(component_size)?;
+        let start = if length <= 4 {
+            offset + 8
+        } else {
+            self.u32(offset + 8)? as usize
+        };
+        let raw = self.data.get(start..start.checked_add(length)?)?;
+        let mut data = raw.to_vec();
+        if self.big_endian {
+            data.chunks_mut(component_size).for_each(|c| c.reverse());
+        }
+        let pointer = match (tag, field_type) {
+            (TAG_EXIF_IFD | TAG_GPS_IFD | 40965, 4 | 13) => self.u32(offset + 8),
+            _ => None,
+        };
+        let entry = IfdEntry {
+            tag,
+            // IFD pointers are stored as plain integers in classic TIFF files
+            field_type: if field_type == 13 { LONG } else { field_type },
+            count: count as u64,
+            data,
+        };
+        Some((entry, pointer))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn test_ifd_chain() {
+        let mut writer = BigTiffWriter::new(Cursor::new(Vec::new())).unwrap();
+        let data_offset = writer.write_data(&[1, 2, 3]).unwrap();
+        assert_eq!(data_offset, 16);
+        let first = writer
+            .append_ifd(vec![
+                IfdEntry::long(TAG_IMAGE_WIDTH, 7),
+                IfdEntry::long8(TAG_TILE_OFFSETS, &[1, 2]),
+            ])
+            .unwrap();
+        let second = writer.append_ifd(vec![]).unwrap();
+        let bytes = writer.into_inner().unwrap().into_inner();
+        let u64_at =
+            |o: u64| u64::from_le_bytes(bytes[o as usize..o as usize + 8].try_into().unwrap());
+        assert_eq!(&bytes[..4], b"II\x2b\x00");
+        assert_eq!(u64_at(8), first);
+        // Entries are sorted, values that do not fit in 8 bytes are written before the IFD
+        assert_eq!(first % 2, 0);
+        assert_eq!(u64_at(first), 2);
+        assert_eq!(u64_at(first + 8 + 4), 1);
+        assert_eq!(u64_at(first + 8 + 12), 7);
+        assert_eq!(u64_at(u64_at(first + 8 + 20 + 12)), 1);
+        assert_eq!(u64_at(first + 8 + 40), second);
+    }
+
+    #[test]
+    fn test_parse_exif() {
+        // Big-endian EXIF data with a camera make, an orientation and an EXIF IFD with an exposure time
+        let exif = [
+            b"Exif\0\0MM\0\x2a\0\0\0\x08".as_slice(),
+            &[0, 3],
+            &[0x01, 0x0f, 0, 2, 0, 0, 0, 4, b'A', b'B', b'C', 0],
+            &[0x01, 0x12, 0, 3, 0, 0, 0, 1, 0, 6, 0, 0],
+            &[0x87, 0x69, 0, 4, 0, 0, 0, 1, 0, 0, 0, 0x32],
+            &[0, 0, 0, 0],
+            &[0, 1],
+            &[0x82, 0x9a, 0, 5, 0, 0, 0, 1, 0, 0, 0, 0x44],
+            &[0, 0, 0, 0],
+            &[0, 0, 0, 1, 0, 0, 0, 100],
+        ]
+        .concat();
+        let fields = ExifFields::parse(&exif).unwrap();
+        assert_eq!(
+            fields.image,
+            vec![IfdEntry {
+                tag: 0x010f,
+                field_type: 2, // ASCII
+                count: 4,
+                data: b"ABC\0".to_vec()
+            }]
+        );
+        assert_eq!(
+            fields.exif,
+            vec![IfdEntry {
+                tag: 0x829a,
+                field_type: 5,
+                count: 1,
+                data: vec![1, 0, 0, 0, 100, 0, 0, 0]
+            }]
+        );
+        assert!(fields.gps.is_empty());
+        assert_eq!(ExifFields::parse(b"not exif"), None);
+    }
+}
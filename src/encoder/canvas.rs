@@ -1,14 +1,13 @@
 use image::{
-    ExtendedColorType, GenericImageView, ImageBuffer, ImageEncoder, ImageResult, Pixel,
-    PixelWithColorType, Rgb, Rgba,
+    GenericImageView, ImageBuffer, ImageEncoder, ImageResult, Pixel, PixelWithColorType, Rgb, Rgba,
 };
-use log::debug;
+use log::{debug, warn};
 use std::io;
 use std::path::{Path, PathBuf};
 
 use crate::Vec2d;
 use crate::ZoomError;
-use crate::encoder::Encoder;
+use crate::encoder::{Encoder, check_output_size, icc_embedding};
 use crate::tile::Tile;
 use std::fs::File;
 use std::io::BufWriter;
@@ -31,16 +30,40 @@ impl<Pix: Pixel> Canvas<Pix> {
             icc_profile: None,
         })
     }
+}
 
-    pub fn new_jpeg(
+impl Canvas<Rgb<u8>> {
+    /// A WebP image, lossless if the compression is 0
+    pub fn new_webp(
         destination: PathBuf,
         size: Vec2d,
-        quality: u8,
-    ) -> Result<Canvas<Rgb<u8>>, ZoomError> {
-        Ok(Canvas::<Rgb<u8>> {
+        compression: u8,
+        xmp_metadata: Option<String>,
+    ) -> Result<Self, ZoomError> {
+        check_output_size(&destination, size)?;
+        let quality = (compression > 0).then(|| 100u8.saturating_sub(compression));
+        Ok(Canvas {
+            image: ImageBuffer::new(size.x, size.y),
+            destination,
+            image_writer: ImageWriter::WebP {
+                quality,
+                xmp_metadata,
+            },
+            icc_profile: None,
+        })
+    }
+
+    /// An AVIF image. More compression also makes the encoder slower.
+    pub fn new_avif(destination: PathBuf, size: Vec2d, compression: u8) -> Result<Self, ZoomError> {
+        check_output_size(&destination, size)?;
+        let compression = compression.min(100);
+        Ok(Canvas {
             image: ImageBuffer::new(size.x, size.y),
             destination,
-            image_writer: ImageWriter::Jpeg { quality },
+            image_writer: ImageWriter::Avif {
+                quality: 100 - compression,
+                speed: 10 - compression / 11,
+            },
             icc_profile: None,
         })
     }
@@ -116,7 +139,15 @@ impl<Pix: Pixel<Subpixel = u8> + PixelWithColorType + Send + FromRgba + 'static>
 
 pub enum ImageWriter {
     Generic,
-    Jpeg { quality: u8 },
+    /// Lossy with the given quality, or lossless
+    WebP {
+        quality: Option<u8>,
+        xmp_metadata: Option<String>,
+    },
+    Avif {
+        quality: u8,
+        speed: u8,
+    },
 }
 
 impl ImageWriter {
@@ -127,26 +158,60 @@ impl ImageWriter {
         icc_profile: &Option<Vec<u8>>,
     ) -> ImageResult<()> {
         match *self {
-            ImageWriter::Jpeg { quality } => {
-                let file = File::create(destination)?;
-                let fout = &mut BufWriter::new(file);
-                let mut encoder = image::codecs::jpeg::JpegEncoder::new_with_quality(fout, quality);
-
-                // Set ICC profile if available
-                if let Some(profile) = icc_profile {
-                    if let Err(e) = encoder.set_icc_profile(profile.clone()) {
-                        debug!("Failed to set ICC profile for JPEG: {}", e);
+            ImageWriter::WebP {
+                quality,
+                ref xmp_metadata,
+            } => {
+                let (width, height) = image.dimensions();
+                let webp = if let Some(quality) = quality {
+                    let layout = if Pix::CHANNEL_COUNT == 4 {
+                        webp::PixelLayout::Rgba
                     } else {
-                        debug!("Applied ICC profile to JPEG output");
+                        webp::PixelLayout::Rgb
+                    };
+                    webp::Encoder::new(image.as_raw(), layout, width, height)
+                        .encode(f32::from(quality))
+                        .to_vec()
+                } else {
+                    let mut webp = Vec::new();
+                    image::codecs::webp::WebPEncoder::new_lossless(&mut webp).write_image(
+                        image.as_raw(),
+                        width,
+                        height,
+                        Pix::COLOR_TYPE,
+                    )?;
+                    webp
+                };
+                let data = if icc_profile.is_some() || xmp_metadata.is_some() {
+                    icc_embedding::webp_with_metadata(
+                        &webp,
+                        width,
+                        height,
+                        icc_profile.as_deref(),
+                        xmp_metadata.as_ref().map(String::as_bytes),
+                    )
+                } else {
+                    webp
+                };
+                std::fs::write(destination, data)?;
+            }
+            ImageWriter::Avif { quality, speed } => {
+                debug!("Encoding avif with quality {quality} and speed {speed}");
+                let mut avif = Vec::new();
+                image::codecs::avif::AvifEncoder::new_with_speed_quality(&mut avif, speed, quality)
+                    .write_image(
+                        image.as_raw(),
+                        image.width(),
+                        image.height(),
+                        Pix::COLOR_TYPE,
+                    )?;
+                if let Some(profile) = icc_profile {
+                    match icc_embedding::avif_with_icc(&avif, profile) {
+                        Ok(with_icc) => avif = with_icc,
+                        Err(e) => warn!("Writing the AVIF image without its color profile: {e}"),
                     }
                 }
-
-                encoder.encode(
-                    image.as_raw(),
-                    image.width(),
-                    image.height(),
-                    ExtendedColorType::Rgb8,
-                )?;
+                std::fs::write(destination, avif)?;
             }
             ImageWriter::Generic => {
                 // For generic format, we need to handle ICC profiles based on the file extension
@@ -191,16 +256,6 @@ impl ImageWriter {
                 image::codecs::tiff::TiffEncoder::new,
                 "TIFF",
             ),
-            "webp" => Self::encode_with_icc_profile::<
-                Pix,
-                image::codecs::webp::WebPEncoder<BufWriter<File>>,
-            >(
-                image,
-                destination,
-                icc_profile,
-                image::codecs::webp::WebPEncoder::new_lossless,
-                "WebP",
-            ),
             _ => {
                 // For other formats, fall back to the standard save method
                 debug!("ICC profile not supported for format: {}", extension);
@@ -303,4 +358,47 @@ mod tests {
         assert_eq!(canvas.icc_profile, first_profile);
         assert_eq!(canvas.icc_profile.unwrap().len(), 3);
     }
+
+    #[test]
+    fn test_webp_keeps_icc_profile() {
+        use image::ImageDecoder;
+        use image::codecs::webp::WebPDecoder;
+
+        let size = Vec2d { x: 8, y: 8 };
+        for (compression, name) in [(0, "lossless"), (20, "lossy")] {
+            let destination = temp_dir().join(format!("test_icc_canvas_{name}.webp"));
+            let mut canvas =
+                Canvas::new_webp(destination.clone(), size, compression, None).unwrap();
+            let tile = Tile::builder()
+                .with_image(image::DynamicImage::ImageRgb8(ImageBuffer::from_pixel(
+                    8,
+                    8,
+                    Rgb([0, 0, 255]),
+                )))
+                .at_position(Vec2d { x: 0, y: 0 })
+                .with_icc_profile(vec![1, 2, 3])
+                .build();
+            canvas.add_tile(tile).unwrap();
+            canvas.finalize().unwrap();
+
+            let file = io::BufReader::new(File::open(&destination).unwrap());
+            let mut decoder = WebPDecoder::new(file).unwrap();
+            assert_eq!(
+                decoder.icc_profile().unwrap(),
+                Some(vec![1, 2, 3]),
+                "{name}"
+            );
+            let image = image::DynamicImage::from_decoder(decoder).unwrap();
+            let pixel = image.get_pixel(4, 4).0;
+            assert!(pixel[0] < 10 && pixel[2] > 245, "{name}: {pixel:?}");
+        }
+    }
+
+    #[test]
+    fn test_webp_too_large() {
+        let destination = temp_dir().join("test_too_large.webp");
+        let size = Vec2d { x: 20_000, y: 10 };
+        let error = Canvas::new_webp(destination, size, 10, None).err().unwrap();
+        assert!(matches!(error, ZoomError::ImageTooLarge { .. }), "{error}");
+    }
 }
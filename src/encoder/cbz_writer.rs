@@ -0,0 +1,224 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use lazy_static::lazy_static;
+use log::debug;
+use regex::Regex;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+use crate::sidecar::sidecar_path;
+use crate::xml_utils::escape_xml;
+
+/// Stores already saved images in a comic book archive (a zip file with a `.cbz` extension),
+/// with zero-padded page file names so that readers display them in order,
+/// and a `ComicInfo.xml` file describing the document.
+pub struct CbzWriter {
+    zip: ZipWriter<BufWriter<File>>,
+    page_count: usize,
+    /// Number of digits in page file names
+    digits: usize,
+}
+
+impl CbzWriter {
+    /// Creates an archive that can contain up to `max_page_count` pages
+    pub fn new(path: &Path, max_page_count: usize) -> io::Result<Self> {
+        let file = BufWriter::new(File::create(path)?);
+        Ok(CbzWriter {
+            zip: ZipWriter::new(file),
+            page_count: 0,
+            digits: max_page_count.to_string().len().max(3),
+        })
+    }
+
+    /// Copies the image saved at the given path to the next page of the archive,
+    /// with its JSON sidecar file if there is one
+    pub fn add_page(&mut self, image_path: &Path) -> io::Result<()> {
+        let extension = image_path.extension().unwrap_or_default().to_string_lossy();
+        let name = format!(
+            "{:0width$}.{}",
+            self.page_count + 1,
+            extension.to_lowercase(),
+            width = self.digits
+        );
+        debug!("Adding {image_path:?} to the archive as {name}");
+        // Images are already compressed
+        let options = SimpleFileOptions::default()
+            .compression_method(CompressionMethod::Stored)
+            .large_file(true);
+        self.zip.start_file(name.as_str(), options)?;
+        io::copy(&mut File::open(image_path)?, &mut self.zip)?;
+        // The description of the download goes next to its image
+        let sidecar = sidecar_path(image_path);
+        if sidecar.exists() {
+            let options =
+                SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+            self.zip.start_file(format!("{name}.json"), options)?;
+            io::copy(&mut File::open(sidecar)?, &mut self.zip)?;
+        }
+        self.page_count += 1;
+        Ok(())
+    }
+
+    pub fn page_count(&self) -> usize {
+        self.page_count
+    }
+
+    /// Writes the `ComicInfo.xml` file and the zip directory
+    pub fn finish(mut self, info: &ComicInfo) -> io::Result<()> {
+        let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+        self.zip.start_file("ComicInfo.xml", options)?;
+        self.zip
+            .write_all(info.to_xml(self.page_count).as_bytes())?;
+        self.zip.finish()?.flush()
+    }
+}
+
+/// The description of a document in the format used by comic book readers.
+/// See https://anansi-project.github.io/docs/comicinfo/documentation
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ComicInfo {
+    pub title: Option<String>,
+    pub summary: Option<String>,
+    /// All the metadata of the document, one `label: value` line per entry
+    pub notes: Option<String>,
+    pub year: Option<String>,
+    pub writer: Option<String>,
+    pub publisher: Option<String>,
+}
+
+lazy_static! {
+    static ref HTML_TAG: Regex = Regex::new("<[^>]*>").unwrap();
+    static ref YEAR: Regex = Regex::new(r"\b\d{4}\b").unwrap();
+}
+
+impl ComicInfo {
+    /// Maps the usual metadata labels of IIIF manifests to ComicInfo fields
+    pub fn from_metadata(title: Option<&str>, metadata: &[(String, String)]) -> Self {
+        let mut info = ComicInfo {
+            title: title.map(str::to_string),
+            ..Default::default()
+        };
+        let mut notes = vec![];
+        for (label, value) in metadata {
+            // Metadata values are allowed to contain some HTML
+            let value = HTML_TAG.replace_all(value, "").trim().to_string();
+            if value.is_empty() {
+                continue;
+            }
+            notes.push(format!("{label}: {value}"));
+            let label = label.to_lowercase();
+            let field = match label.as_str() {
+                "title" => &mut info.title,
+                "description" | "summary" | "abstract" => &mut info.summary,
+                "author" | "creator" | "writer" | "contributor" => &mut info.writer,
+                "publisher" | "repository" | "holding institution" => &mut info.publisher,
+                _ if label.contains("date") => {
+                    if info.year.is_none() {
+                        info.year = YEAR.find(&value).map(|m| m.as_str().to_string());
+                    }
+                    continue;
+                }
+                _ => continue,
+            };
+            match field {
+                Some(existing) if label == "title" => debug!("Keeping the title {existing:?}"),
+                Some(existing) => *existing = format!("{existing}, {value}"),
+                None => *field = Some(value),
+            }
+        }
+        if !notes.is_empty() {
+            info.notes = Some(notes.join("\n"));
+        }
+        info
+    }
+
+    fn to_xml(&self, page_count: usize) -> String {
+        let mut xml = String::from(
+            "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+            <ComicInfo xmlns:xsi=\"http://www.w3.org/2001/XMLSchema-instance\" \
+            xmlns:xsd=\"http://www.w3.org/2001/XMLSchema\">\n",
+        );
+        // The elements must be in the order of the ComicInfo schema
+        let fields = [
+            ("Title", &self.title),
+            ("Summary", &self.summary),
+            ("Notes", &self.notes),
+            ("Year", &self.year),
+            ("Writer", &self.writer),
+            ("Publisher", &self.publisher),
+        ];
+        for (name, value) in fields {
+            if let Some(value) = value {
+                xml.push_str(&format!("  <{name}>{}</{name}>\n", escape_xml(value)));
+            }
+        }
+        xml.push_str(&format!(
+            "  <PageCount>{page_count}</PageCount>\n</ComicInfo>\n"
+        ));
+        xml
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env::temp_dir;
+    use std::io::Read;
+
+    use super::*;
+
+    #[test]
+    fn test_comic_info_from_metadata() {
+        let metadata = [
+            ("Title", "Gospel-book"),
+            ("Date", "c. 715-720"),
+            ("Creator", "Eadfrith"),
+            ("Description", "<p>A <b>decorated</b> manuscript</p>"),
+            ("Shelfmark", "Cotton MS Nero D IV"),
+        ]
+        .map(|(label, value)| (label.to_string(), value.to_string()));
+        let info = ComicInfo::from_metadata(Some("Lindisfarne Gospels"), &metadata);
+        assert_eq!(
+            info,
+            ComicInfo {
+                title: Some("Lindisfarne Gospels".into()),
+                summary: Some("A decorated manuscript".into()),
+                notes: Some(
+                    "Title: Gospel-book\nDate: c. 715-720\nCreator: Eadfrith\n\
+                    Description: A decorated manuscript\nShelfmark: Cotton MS Nero D IV"
+                        .into()
+                ),
+                year: None,
+                writer: Some("Eadfrith".into()),
+                publisher: None,
+            }
+        );
+        let with_year = ComicInfo::from_metadata(None, &[("Date".into(), "1892-03".into())]);
+        assert_eq!(with_year.year.as_deref(), Some("1892"));
+    }
+
+    #[test]
+    fn test_cbz() {
+        let image_path = temp_dir().join("dezoomify-rs-cbz-test.JPG");
+        std::fs::write(&image_path, b"not really a jpeg").unwrap();
+        let cbz_path = temp_dir().join("dezoomify-rs-cbz-test.cbz");
+        let mut cbz = CbzWriter::new(&cbz_path, 1200).unwrap();
+        cbz.add_page(&image_path).unwrap();
+        cbz.add_page(&image_path).unwrap();
+        let info = ComicInfo {
+            title: Some("Tom & Jerry".into()),
+            ..Default::default()
+        };
+        cbz.finish(&info).unwrap();
+
+        let mut archive = zip::ZipArchive::new(File::open(&cbz_path).unwrap()).unwrap();
+        let names: Vec<String> = archive.file_names().map(|n| n.unwrap().into()).collect();
+        assert_eq!(names, vec!["0001.jpg", "0002.jpg", "ComicInfo.xml"]);
+        let mut xml = String::new();
+        let mut comic_info = archive.by_name("ComicInfo.xml").unwrap();
+        comic_info.read_to_string(&mut xml).unwrap();
+        assert!(xml.contains("<Title>Tom &amp; Jerry</Title>"), "{xml}");
+        assert!(xml.contains("<PageCount>2</PageCount>"), "{xml}");
+    }
+}
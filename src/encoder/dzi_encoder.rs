@@ -0,0 +1,242 @@
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use image::DynamicImage;
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::FilterType;
+use log::debug;
+
+use crate::encoder::retiler::{Retiler, TileSaver};
+use crate::errors::image_error_to_io_error;
+use crate::tile::Tile;
+use crate::{Vec2d, ZoomError};
+
+use super::Encoder;
+
+const DZI_NAMESPACE: &str = "http://schemas.microsoft.com/deepzoom/2008";
+
+/// Writes a Deep Zoom image: an xml file describing the image,
+/// and a `_files` directory with one subdirectory of jpeg tiles per zoom level.
+/// Level 0 is a single pixel, and each level is twice as large as the previous one.
+pub struct DziEncoder {
+    retiler: Retiler<DziTileSaver>,
+    tile_saver: Arc<DziTileSaver>,
+    destination: PathBuf,
+    overlap: u32,
+}
+
+impl DziEncoder {
+    pub fn new(
+        destination: PathBuf,
+        size: Vec2d,
+        tile_size: u32,
+        overlap: u32,
+        quality: u8,
+    ) -> Result<Self, ZoomError> {
+        if tile_size == 0 {
+            let msg = "The tile size of a deep zoom image cannot be zero";
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, msg).into());
+        }
+        let files_path = files_path(&destination);
+        debug!("Creating deep zoom tiles directory at {files_path:?}");
+        std::fs::create_dir_all(&files_path)?;
+        let tile_saver = Arc::new(DziTileSaver {
+            files_path,
+            tile_size,
+            max_level: log2(size.x.max(size.y)),
+            quality,
+            smallest_level: Mutex::new(None),
+        });
+        let tile_size = Vec2d::square(tile_size);
+        let retiler = Retiler::new(size, tile_size, overlap, Arc::clone(&tile_saver), 1);
+        Ok(DziEncoder {
+            retiler,
+            tile_saver,
+            destination,
+            overlap,
+        })
+    }
+
+    fn tile_size(&self) -> u32 {
+        self.tile_saver.tile_size
+    }
+
+    /// The levels below the smallest level of the retiler fit in a single tile.
+    /// They are generated by downscaling the smallest level.
+    fn write_small_levels(&self) -> io::Result<()> {
+        let smallest = self.tile_saver.smallest_level.lock().unwrap().take();
+        let Some((scale_factor, mut image)) = smallest else {
+            return Ok(());
+        };
+        let mut size = self.size().ceil_div(scale_factor);
+        let first_level = self.tile_saver.max_level - scale_factor.trailing_zeros();
+        for level in (0..first_level).rev() {
+            size = size.ceil_div(2);
+            image = image.resize_exact(size.x, size.y, FilterType::Gaussian);
+            self.tile_saver
+                .write_tile(level, Vec2d::default(), &image)?;
+        }
+        Ok(())
+    }
+
+    fn write_dzi_file(&self) -> io::Result<()> {
+        let size = self.size();
+        let dzi = format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+            <Image xmlns=\"{DZI_NAMESPACE}\" Format=\"jpg\" Overlap=\"{}\" TileSize=\"{}\">\n  \
+            <Size Width=\"{}\" Height=\"{}\"/>\n\
+            </Image>\n",
+            self.overlap,
+            self.tile_size(),
+            size.x,
+            size.y
+        );
+        debug!("Writing deep zoom metadata to {:?}", self.destination);
+        std::fs::write(&self.destination, dzi)
+    }
+
+    fn write_viewer(&self) -> io::Result<()> {
+        let size = self.size();
+        let files_dir = self.tile_saver.files_path.file_name().unwrap_or_default();
+        let tile_source = serde_json::json!({
+            "Image": {
+                "xmlns": DZI_NAMESPACE,
+                "Url": format!("{}/", files_dir.to_string_lossy()),
+                "Format": "jpg",
+                "Overlap": self.overlap,
+                "TileSize": self.tile_size(),
+                "Size": { "Width": size.x, "Height": size.y }
+            }
+        });
+        let viewer_path = sibling_path(&self.destination, "_viewer.html");
+        debug!("Writing viewer page to {viewer_path:?}");
+        let viewer_buf = include_str!("./viewer_files/viewer.html")
+            .replace(
+                "/*DEZOOMIFY_SEADRAGON*/",
+                include_str!("./viewer_files/openseadragon.min.js"),
+            )
+            .replace("{/*DEZOOMIFY_TILE_SOURCE*/}", &tile_source.to_string());
+        File::create(viewer_path)?.write_all(viewer_buf.as_bytes())
+    }
+}
+
+impl Encoder for DziEncoder {
+    fn add_tile(&mut self, tile: Tile) -> io::Result<()> {
+        self.retiler.add_tile(&tile)
+    }
+
+    fn finalize(&mut self) -> io::Result<()> {
+        self.retiler.finalize();
+        self.write_small_levels()?;
+        self.write_dzi_file()?;
+        self.write_viewer()
+    }
+
+    fn size(&self) -> Vec2d {
+        self.retiler.size()
+    }
+}
+
+struct DziTileSaver {
+    files_path: PathBuf,
+    tile_size: u32,
+    /// The level of the full-resolution image
+    max_level: u32,
+    quality: u8,
+    /// The most downscaled image saved so far, with its scale factor
+    smallest_level: Mutex<Option<(u32, DynamicImage)>>,
+}
+
+impl DziTileSaver {
+    fn write_tile(&self, level: u32, position: Vec2d, image: &DynamicImage) -> io::Result<()> {
+        let level_path = self.files_path.join(level.to_string());
+        std::fs::create_dir_all(&level_path)?;
+        let image_path = level_path.join(format!("{}_{}.jpg", position.x, position.y));
+        debug!("Writing tile to {image_path:?}");
+        let file = &mut BufWriter::new(File::create(&image_path)?);
+        let jpeg_writer = JpegEncoder::new_with_quality(file, self.quality);
+        image
+            .write_with_encoder(jpeg_writer)
+            .map_err(image_error_to_io_error)
+    }
+}
+
+impl TileSaver for DziTileSaver {
+    fn save_tile(&self, _size: Vec2d, scale_factor: u32, tile: Tile) -> io::Result<()> {
+        let level = self.max_level - scale_factor.trailing_zeros();
+        let position = tile.position / (self.tile_size * scale_factor);
+        self.write_tile(level, position, &tile.image)?;
+        let mut smallest = self.smallest_level.lock().unwrap();
+        if smallest.as_ref().is_none_or(|(s, _)| *s < scale_factor) {
+            *smallest = Some((scale_factor, tile.image));
+        }
+        Ok(())
+    }
+}
+
+/// The directory containing the tiles of the deep zoom image at the given path
+fn files_path(destination: &Path) -> PathBuf {
+    sibling_path(destination, "_files")
+}
+
+/// A path in the same directory as the given file, with the same name but a different suffix
+fn sibling_path(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_stem().unwrap_or_default().to_os_string();
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
+/// The number of times a dimension has to be halved to reach a single pixel
+fn log2(n: u32) -> u32 {
+    32 - n.saturating_sub(1).leading_zeros()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env::temp_dir;
+
+    use image::{GenericImageView, RgbImage};
+
+    use super::*;
+
+    #[test]
+    fn test_dzi_pyramid() {
+        let destination = temp_dir().join("dezoomify-rs-dzi-test.dzi");
+        let size = Vec2d { x: 10, y: 5 };
+        let mut encoder = DziEncoder::new(destination.clone(), size, 4, 1, 90).unwrap();
+        let image = RgbImage::from_fn(10, 5, |x, _| image::Rgb([x as u8 * 25, 0, 0]));
+        encoder
+            .add_tile(
+                Tile::builder()
+                    .at_position(Vec2d::default())
+                    .with_image(DynamicImage::ImageRgb8(image))
+                    .build(),
+            )
+            .unwrap();
+        encoder.finalize().unwrap();
+
+        let dzi = std::fs::read_to_string(&destination).unwrap();
+        assert!(dzi.contains(r#"Overlap="1" TileSize="4""#), "{dzi}");
+        assert!(dzi.contains(r#"<Size Width="10" Height="5"/>"#), "{dzi}");
+
+        let files = temp_dir().join("dezoomify-rs-dzi-test_files");
+        let dimensions = |path: &str| image::open(files.join(path)).unwrap().dimensions();
+        // Tiles overlap their neighbours, except at the borders of the image
+        assert_eq!(dimensions("4/0_0.jpg"), (5, 5));
+        assert_eq!(dimensions("4/1_0.jpg"), (6, 5));
+        assert_eq!(dimensions("4/2_0.jpg"), (3, 5));
+        assert_eq!(dimensions("3/1_0.jpg"), (2, 3));
+        // Levels smaller than a tile are generated down to a single pixel
+        assert_eq!(dimensions("2/0_0.jpg"), (3, 2));
+        assert_eq!(dimensions("1/0_0.jpg"), (2, 1));
+        assert_eq!(dimensions("0/0_0.jpg"), (1, 1));
+        assert!(!files.join("4/3_0.jpg").exists());
+
+        let viewer = temp_dir().join("dezoomify-rs-dzi-test_viewer.html");
+        let viewer = std::fs::read_to_string(viewer).unwrap();
+        assert!(viewer.contains(r#""Url":"dezoomify-rs-dzi-test_files/""#));
+    }
+}
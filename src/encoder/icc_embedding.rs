@@ -0,0 +1,417 @@
+//! Adds ICC color profiles and XMP metadata to already encoded WebP and AVIF files,
+//! for the encoders that cannot embed them themselves.
+
+use std::io;
+
+/// Converts a WebP file to the extended format, with ICCP and XMP chunks.
+/// See https://developers.google.com/speed/webp/docs/riff_container
+pub fn webp_with_metadata(
+    webp: &[u8],
+    width: u32,
+    height: u32,
+    icc_profile: Option<&[u8]>,
+    xmp_metadata: Option<&[u8]>,
+) -> Vec<u8> {
+    let mut flags = 0u8;
+    // Keep the image chunks, and the flags of the file if it already is in the extended format
+    let mut image_chunks = Vec::with_capacity(webp.len());
+    let mut position = 12;
+    while position + 8 <= webp.len() {
+        let fourcc = &webp[position..position + 4];
+        let size = u32::from_le_bytes(webp[position + 4..position + 8].try_into().unwrap());
+        let end = (position + 8 + size as usize + size as usize % 2).min(webp.len());
+        match fourcc {
+            b"VP8X" => flags = webp[position + 8] & !(ICC_FLAG | XMP_FLAG),
+            b"ICCP" | b"XMP " => {}
+            _ => image_chunks.extend_from_slice(&webp[position..end]),
+        }
+        position = end;
+    }
+    if icc_profile.is_some() {
+        flags |= ICC_FLAG;
+    }
+    if xmp_metadata.is_some() {
+        flags |= XMP_FLAG;
+    }
+    let mut vp8x = vec![flags, 0, 0, 0];
+    vp8x.extend_from_slice(&(width - 1).to_le_bytes()[..3]);
+    vp8x.extend_from_slice(&(height - 1).to_le_bytes()[..3]);
+
+    let metadata_size = icc_profile.map_or(0, <[u8]>::len) + xmp_metadata.map_or(0, <[u8]>::len);
+    let mut chunks = Vec::with_capacity(webp.len() + metadata_size + 48);
+    write_riff_chunk(&mut chunks, b"VP8X", &vp8x);
+    if let Some(profile) = icc_profile {
+        write_riff_chunk(&mut chunks, b"ICCP", profile);
+    }
+    chunks.extend_from_slice(&image_chunks);
+    if let Some(xmp) = xmp_metadata {
+        write_riff_chunk(&mut chunks, b"XMP ", xmp);
+    }
+
+    let mut result = Vec::with_capacity(chunks.len() + 12);
+    result.extend_from_slice(b"RIFF");
+    result.extend_from_slice(&(chunks.len() as u32 + 4).to_le_bytes());
+    result.extend_from_slice(b"WEBP");
+    result.extend_from_slice(&chunks);
+    result
+}
+
+const ICC_FLAG: u8 = 0x20;
+const XMP_FLAG: u8 = 0x04;
+
+fn write_riff_chunk(out: &mut Vec<u8>, fourcc: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(fourcc);
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    out.extend_from_slice(data);
+    if data.len() % 2 == 1 {
+        out.push(0);
+    }
+}
+
+/// A box of an ISO base media file, such as AVIF files
+struct IsoBox {
+    /// Position of the start of the box header in the file
+    start: usize,
+    /// Position of the start of the box contents
+    content: usize,
+    end: usize,
+    name: [u8; 4],
+    /// Position and length of the size field, if the box does not extend to the end of the file
+    size_field: Option<(usize, usize)>,
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("invalid AVIF file: {message}"),
+    )
+}
+
+/// The boxes found between `start` and `end`
+fn iso_boxes(data: &[u8], mut start: usize, end: usize) -> io::Result<Vec<IsoBox>> {
+    let mut boxes = vec![];
+    while start + 8 <= end {
+        let name = data[start + 4..start + 8].try_into().unwrap();
+        let (size, content, size_field) = match read_be(data, start, 4) {
+            // The box extends to the end of the file
+            0 => ((end - start) as u64, start + 8, None),
+            // The size is a 64-bit integer, after the name
+            1 if start + 16 <= end => (
+                read_be(data, start + 8, 8),
+                start + 16,
+                Some((start + 8, 8)),
+            ),
+            size => (size, start + 8, Some((start, 4))),
+        };
+        if size < (content - start) as u64 || size > (end - start) as u64 {
+            return Err(invalid("wrong box size"));
+        }
+        let size = size as usize;
+        boxes.push(IsoBox {
+            start,
+            content,
+            end: start + size,
+            name,
+            size_field,
+        });
+        start += size;
+    }
+    Ok(boxes)
+}
+
+fn find_box<'a>(boxes: &'a [IsoBox], name: &[u8; 4]) -> io::Result<&'a IsoBox> {
+    boxes
+        .iter()
+        .find(|b| &b.name == name)
+        .ok_or_else(|| invalid(&format!("no {} box", String::from_utf8_lossy(name))))
+}
+
+fn read_be(data: &[u8], position: usize, size: usize) -> u64 {
+    data[position..position + size]
+        .iter()
+        .fold(0, |acc, &b| (acc << 8) | u64::from(b))
+}
+
+fn write_be(data: &mut [u8], position: usize, size: usize, value: u64) {
+    for (i, byte) in data[position..position + size].iter_mut().enumerate() {
+        *byte = (value >> (8 * (size - 1 - i))) as u8;
+    }
+}
+
+/// Grows a box, that has moved by `moved_by` bytes, by `added` bytes
+fn add_to_box_size(data: &mut [u8], b: &IsoBox, moved_by: usize, added: usize) {
+    if let Some((position, length)) = b.size_field {
+        let size = read_be(data, position + moved_by, length);
+        write_be(data, position + moved_by, length, size + added as u64);
+    }
+}
+
+/// Adds a `colr` property with the given ICC profile to the primary image of an AVIF file.
+/// The file must have its `meta` box before its `mdat` box, as AVIF encoders usually write it.
+pub fn avif_with_icc(avif: &[u8], icc_profile: &[u8]) -> io::Result<Vec<u8>> {
+    let top = iso_boxes(avif, 0, avif.len())?;
+    let meta = find_box(&top, b"meta")?;
+    // meta is a full box: its children start after its version and flags
+    let meta_children = iso_boxes(avif, meta.content + 4, meta.end)?;
+    let pitm = find_box(&meta_children, b"pitm")?;
+    let primary_item_size = if avif[pitm.content] == 0 { 2 } else { 4 };
+    let primary_item = read_be(avif, pitm.content + 4, primary_item_size);
+    let iloc = find_box(&meta_children, b"iloc")?;
+    let iprp = find_box(&meta_children, b"iprp")?;
+    let iprp_children = iso_boxes(avif, iprp.content, iprp.end)?;
+    let ipco = find_box(&iprp_children, b"ipco")?;
+    let ipma = find_box(&iprp_children, b"ipma")?;
+    if ipma.start < ipco.end || iloc.start > ipco.end {
+        return Err(invalid("unexpected order of the boxes"));
+    }
+    let property_index = iso_boxes(avif, ipco.content, ipco.end)?.len() + 1;
+
+    // The new property box
+    let mut colr = Vec::with_capacity(icc_profile.len() + 12);
+    colr.extend_from_slice(&(icc_profile.len() as u32 + 12).to_be_bytes());
+    colr.extend_from_slice(b"colrprof");
+    colr.extend_from_slice(icc_profile);
+
+    // Find the association list of the primary item
+    let ipma_version = avif[ipma.content];
+    let large_indices = avif[ipma.content + 3] & 1 == 1;
+    let item_id_size = if ipma_version < 1 { 2 } else { 4 };
+    let association_size = if large_indices { 2 } else { 1 };
+    if !large_indices && property_index > 0x7F {
+        return Err(invalid("too many properties"));
+    }
+    let entry_count = read_be(avif, ipma.content + 4, 4);
+    let mut position = ipma.content + 8;
+    let mut primary_association = None;
+    for _ in 0..entry_count {
+        let item_id = read_be(avif, position, item_id_size);
+        let count_position = position + item_id_size;
+        let count = avif[count_position] as usize;
+        if item_id == primary_item {
+            primary_association = Some(count_position);
+        }
+        position = count_position + 1 + count * association_size;
+    }
+    let count_position = primary_association.ok_or_else(|| invalid("no primary item"))?;
+    let insert_association_at =
+        count_position + 1 + avif[count_position] as usize * association_size;
+    let association = if large_indices {
+        (property_index as u16).to_be_bytes().to_vec()
+    } else {
+        vec![property_index as u8]
+    };
+    let added = colr.len() + association.len();
+
+    let mut result = Vec::with_capacity(avif.len() + added);
+    result.extend_from_slice(&avif[..ipco.end]);
+    result.extend_from_slice(&colr);
+    result.extend_from_slice(&avif[ipco.end..insert_association_at]);
+    result.extend_from_slice(&association);
+    result.extend_from_slice(&avif[insert_association_at..]);
+
+    // The positions of the boxes before the insertions did not change
+    for b in [meta, iprp] {
+        add_to_box_size(&mut result, b, 0, added);
+    }
+    add_to_box_size(&mut result, ipco, 0, colr.len());
+    add_to_box_size(&mut result, ipma, colr.len(), association.len());
+    result[count_position + colr.len()] += 1;
+
+    // The image data comes after the meta box, so its offset changed
+    if meta.end <= find_box(&top, b"mdat")?.start {
+        shift_item_locations(&mut result, iloc, added)?;
+    }
+    Ok(result)
+}
+
+/// Adds `shift` to the file offsets of all the items in an `iloc` box
+fn shift_item_locations(data: &mut [u8], iloc: &IsoBox, shift: usize) -> io::Result<()> {
+    let version = data[iloc.content];
+    let mut position = iloc.content + 4;
+    let offset_size = (data[position] >> 4) as usize;
+    let length_size = (data[position] & 0xF) as usize;
+    let base_offset_size = (data[position + 1] >> 4) as usize;
+    let index_size = if version >= 1 {
+        (data[position + 1] & 0xF) as usize
+    } else {
+        0
+    };
+    position += 2;
+    let id_size = if version < 2 { 2 } else { 4 };
+    let item_count = read_be(data, position, id_size);
+    position += id_size;
+    for _ in 0..item_count {
+        position += id_size;
+        let construction_method = if version >= 1 {
+            position += 2;
+            read_be(data, position - 2, 2) & 0xF
+        } else {
+            0
+        };
+        position += 2; // data reference index
+        let base_offset_position = position;
+        position += base_offset_size;
+        let extent_count = read_be(data, position, 2);
+        position += 2;
+        // Only the items stored in the file itself have offsets that depend on its layout
+        let shift_item = construction_method == 0;
+        if shift_item && base_offset_size > 0 {
+            let base = read_be(data, base_offset_position, base_offset_size);
+            write_be(
+                data,
+                base_offset_position,
+                base_offset_size,
+                base + shift as u64,
+            );
+        }
+        for _ in 0..extent_count {
+            position += index_size;
+            if shift_item && base_offset_size == 0 {
+                if offset_size == 0 {
+                    return Err(invalid("item without offset"));
+                }
+                let offset = read_be(data, position, offset_size);
+                write_be(data, position, offset_size, offset + shift as u64);
+            }
+            position += offset_size + length_size;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use image::codecs::avif::AvifEncoder;
+    use image::codecs::webp::WebPDecoder;
+    use image::{ExtendedColorType, ImageDecoder, ImageEncoder};
+
+    use super::*;
+
+    #[test]
+    fn test_webp_with_metadata() {
+        let rgb = [255u8, 0, 0].repeat(4);
+        let webp = webp::Encoder::from_rgb(&rgb, 2, 2).encode(90.);
+        let icc_profile = vec![1, 2, 3];
+        let xmp = b"<x:xmpmeta/>".to_vec();
+        let data = webp_with_metadata(&webp, 2, 2, Some(&icc_profile), Some(&xmp));
+        let mut decoder = WebPDecoder::new(io::Cursor::new(&data)).unwrap();
+        assert_eq!(decoder.icc_profile().unwrap(), Some(icc_profile));
+        assert_eq!(decoder.xmp_metadata().unwrap(), Some(xmp.clone()));
+        assert_eq!(decoder.dimensions(), (2, 2));
+
+        // Adding metadata again replaces it
+        let data = webp_with_metadata(&data, 2, 2, None, Some(b"<x:xmpmeta />"));
+        let mut decoder = WebPDecoder::new(io::Cursor::new(&data)).unwrap();
+        assert_eq!(decoder.icc_profile().unwrap(), None);
+        assert_eq!(
+            decoder.xmp_metadata().unwrap(),
+            Some(b"<x:xmpmeta />".to_vec())
+        );
+    }
+
+    /// The position of the data of the first item of a file with a version 0 iloc box
+    fn first_item_offset(data: &[u8]) -> usize {
+        let top = iso_boxes(data, 0, data.len()).unwrap();
+        let meta = find_box(&top, b"meta").unwrap();
+        let children = iso_boxes(data, meta.content + 4, meta.end).unwrap();
+        let iloc = find_box(&children, b"iloc").unwrap();
+        assert_eq!(data[iloc.content], 0);
+        assert_eq!(data[iloc.content + 4] >> 4, 4);
+        assert_eq!(data[iloc.content + 5] >> 4, 0);
+        // sizes, item count, item id, data reference index and extent count
+        read_be(data, iloc.content + 4 + 2 + 2 + 2 + 2 + 2, 4) as usize
+    }
+
+    fn small_avif() -> Vec<u8> {
+        let rgb = [0u8, 128, 255].repeat(16 * 16);
+        let mut avif = Vec::new();
+        AvifEncoder::new_with_speed_quality(&mut avif, 10, 80)
+            .write_image(&rgb, 16, 16, ExtendedColorType::Rgb8)
+            .unwrap();
+        avif
+    }
+
+    #[test]
+    fn test_iso_box_sizes() {
+        let mut data = vec![];
+        data.extend_from_slice(&[0, 0, 0, 1]);
+        data.extend_from_slice(b"larg");
+        data.extend_from_slice(&18u64.to_be_bytes());
+        data.extend_from_slice(&[1, 2]);
+        data.extend_from_slice(&[0, 0, 0, 0]);
+        data.extend_from_slice(b"last");
+        data.extend_from_slice(&[3, 4, 5]);
+        let boxes = iso_boxes(&data, 0, data.len()).unwrap();
+        let summary: Vec<_> = boxes
+            .iter()
+            .map(|b| (&b.name, b.content, b.end, b.size_field))
+            .collect();
+        assert_eq!(
+            summary,
+            [(b"larg", 16, 18, Some((8, 8))), (b"last", 26, 29, None)]
+        );
+
+        // A large size that does not fit in the file
+        data[15] = 19;
+        assert!(iso_boxes(&data, 0, data.len()).is_err());
+    }
+
+    #[test]
+    fn test_avif_with_icc_and_large_boxes() {
+        let avif = small_avif();
+        // Rewrite the meta box with a 64-bit size, and the last box with no size
+        let top = iso_boxes(&avif, 0, avif.len()).unwrap();
+        let meta = find_box(&top, b"meta").unwrap();
+        let last = top.last().unwrap();
+        assert!(meta.end <= last.start);
+        let mut large = avif[..meta.start].to_vec();
+        large.extend_from_slice(&[0, 0, 0, 1]);
+        large.extend_from_slice(b"meta");
+        large.extend_from_slice(&(meta.end - meta.start + 8).to_be_bytes());
+        large.extend_from_slice(&avif[meta.content..last.start]);
+        large.extend_from_slice(&[0, 0, 0, 0]);
+        large.extend_from_slice(&avif[last.start + 4..]);
+        let top = iso_boxes(&large, 0, large.len()).unwrap();
+        let meta = find_box(&top, b"meta").unwrap();
+        let large_meta_size = meta.end - meta.start;
+        let children = iso_boxes(&large, meta.content + 4, meta.end).unwrap();
+        shift_item_locations(&mut large, find_box(&children, b"iloc").unwrap(), 8).unwrap();
+
+        let icc_profile = b"not really an icc profile".to_vec();
+        let data = avif_with_icc(&large, &icc_profile).unwrap();
+        let added = icc_profile.len() + 12 + 1;
+        assert_eq!(data.len(), large.len() + added);
+        let top = iso_boxes(&data, 0, data.len()).unwrap();
+        let meta = find_box(&top, b"meta").unwrap();
+        assert_eq!(meta.end - meta.start, large_meta_size + added);
+        let (old_offset, new_offset) = (first_item_offset(&large), first_item_offset(&data));
+        assert_eq!(new_offset - old_offset, added);
+        assert_eq!(&data[new_offset..], &large[old_offset..]);
+    }
+
+    #[test]
+    fn test_avif_with_icc() {
+        let avif = small_avif();
+        let icc_profile = b"not really an icc profile".to_vec();
+        let data = avif_with_icc(&avif, &icc_profile).unwrap();
+        assert_eq!(data.len(), avif.len() + icc_profile.len() + 12 + 1);
+
+        let top = iso_boxes(&data, 0, data.len()).unwrap();
+        let meta = find_box(&top, b"meta").unwrap();
+        let children = iso_boxes(&data, meta.content + 4, meta.end).unwrap();
+        let iprp = find_box(&children, b"iprp").unwrap();
+        let properties = iso_boxes(&data, iprp.content, iprp.end).unwrap();
+        let ipco = find_box(&properties, b"ipco").unwrap();
+        let colr = iso_boxes(&data, ipco.content, ipco.end)
+            .unwrap()
+            .pop()
+            .unwrap();
+        assert_eq!(&data[colr.content..colr.content + 4], b"prof");
+        assert_eq!(&data[colr.content + 4..colr.end], &icc_profile[..]);
+
+        // The image data did not change, and the item location points to it
+        let (old_offset, new_offset) = (first_item_offset(&avif), first_item_offset(&data));
+        assert_eq!(new_offset - old_offset, icc_profile.len() + 12 + 1);
+        assert_eq!(&data[new_offset..], &avif[old_offset..]);
+    }
+}
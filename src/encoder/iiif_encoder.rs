@@ -32,7 +32,7 @@ impl IiifEncoder {
         };
         let tile_size = Vec2d::square(512);
         Ok(IiifEncoder {
-            retiler: Retiler::new(size, tile_size, Arc::new(tile_saver), 1),
+            retiler: Retiler::new(size, tile_size, 0, Arc::new(tile_saver), 1),
             root_path: destination,
         })
     }
@@ -104,7 +104,7 @@ struct IIIFTileSaver {
 }
 
 impl TileSaver for IIIFTileSaver {
-    fn save_tile(&self, size: Vec2d, tile: Tile) -> io::Result<()> {
+    fn save_tile(&self, size: Vec2d, _scale_factor: u32, tile: Tile) -> io::Result<()> {
         let tile_size = tile.size();
         let region = format!(
             "{},{},{},{}",
@@ -0,0 +1,138 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter};
+use std::path::PathBuf;
+
+use log::debug;
+
+use crate::tile::Tile;
+use crate::{Vec2d, ZoomError};
+
+use super::jpeg_writer::JpegStreamWriter;
+use super::pixel_streamer::PixelStreamer;
+use super::{Encoder, check_output_size};
+
+/// Writes a JPEG file while the tiles are being downloaded.
+/// Like the PNG encoder, only the rows of pixels that cannot be written yet are kept in memory.
+pub struct JpegEncoder {
+    pixel_streamer: Option<PixelStreamer<JpegStreamWriter<BufWriter<File>>>>,
+    file: Option<File>,
+    quality: u8,
+    size: Vec2d,
+    xmp_metadata: Option<String>,
+}
+
+impl JpegEncoder {
+    pub fn new(
+        destination: PathBuf,
+        size: Vec2d,
+        quality: u8,
+        xmp_metadata: Option<String>,
+    ) -> Result<Self, ZoomError> {
+        check_output_size(&destination, size)?;
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(destination)?;
+        Ok(JpegEncoder {
+            pixel_streamer: None,
+            file: Some(file),
+            quality,
+            size,
+            xmp_metadata,
+        })
+    }
+
+    /// Writes the headers of the file, with the metadata of the first tile
+    fn write_header(
+        &mut self,
+        icc_profile: Option<&[u8]>,
+        exif_metadata: Option<&[u8]>,
+    ) -> io::Result<()> {
+        let file = self
+            .file
+            .take()
+            .expect("File should be available when writing header");
+        debug!(
+            "Writing jpeg header (ICC profile: {}, EXIF metadata: {})",
+            icc_profile.is_some(),
+            exif_metadata.is_some()
+        );
+        let writer = JpegStreamWriter::new(
+            BufWriter::new(file),
+            self.size,
+            self.quality,
+            icc_profile,
+            exif_metadata,
+            self.xmp_metadata.as_deref(),
+        )?;
+        self.pixel_streamer = Some(PixelStreamer::new(writer, self.size));
+        Ok(())
+    }
+}
+
+impl Encoder for JpegEncoder {
+    fn add_tile(&mut self, tile: Tile) -> io::Result<()> {
+        if self.pixel_streamer.is_none() {
+            self.write_header(tile.icc_profile.as_deref(), tile.exif_metadata.as_deref())?;
+        }
+        self.pixel_streamer
+            .as_mut()
+            .expect("tried to add a tile in a finalized image")
+            .add_tile(tile)
+    }
+
+    fn finalize(&mut self) -> io::Result<()> {
+        if self.file.is_some() {
+            self.write_header(None, None)?;
+        }
+        let mut pixel_streamer = self
+            .pixel_streamer
+            .take()
+            .expect("Tried to finalize an image twice");
+        pixel_streamer.finalize()?;
+        pixel_streamer.into_writer().finish()?;
+        Ok(())
+    }
+
+    fn size(&self) -> Vec2d {
+        self.size
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env::temp_dir;
+
+    use image::{DynamicImage, GenericImageView, ImageBuffer, Rgb};
+
+    use super::*;
+
+    #[test]
+    fn test_jpeg_create() {
+        let destination = temp_dir().join("dezoomify-rs-jpeg-test.jpg");
+        let size = Vec2d { x: 20, y: 20 };
+        let mut encoder = JpegEncoder::new(destination.clone(), size, 90, None).unwrap();
+        // Tiles arrive out of order, and the bottom of the image is missing
+        for (x, y, color) in [(10, 0, [0, 0, 255]), (0, 0, [255, 0, 0])] {
+            let image = ImageBuffer::from_pixel(10, 10, Rgb(color));
+            encoder
+                .add_tile(
+                    Tile::builder()
+                        .at_position(Vec2d { x, y })
+                        .with_image(DynamicImage::ImageRgb8(image))
+                        .build(),
+                )
+                .unwrap();
+        }
+        encoder.finalize().unwrap();
+
+        let image = image::open(&destination).unwrap();
+        assert_eq!(image.dimensions(), (20, 20));
+        let red = image.get_pixel(2, 2).0;
+        assert!(red[0] > 240 && red[2] < 15, "{red:?}");
+        let blue = image.get_pixel(17, 2).0;
+        assert!(blue[0] < 15 && blue[2] > 240, "{blue:?}");
+        assert!(image.get_pixel(10, 17).0[..3].iter().all(|&c| c < 15));
+    }
+}
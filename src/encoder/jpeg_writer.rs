@@ -0,0 +1,470 @@
+//! A baseline JPEG writer that receives the pixels of the image row by row.
+//! Only one band of 8 rows of pixels is kept in memory at any time.
+//! The image is stored in YCbCr without chroma subsampling,
+//! using the standard quantization and huffman tables from Annex K of the JPEG specification.
+
+use std::io::{self, Write};
+
+use log::warn;
+
+use crate::Vec2d;
+
+const BYTES_PER_PIXEL: usize = 3;
+const BLOCK: usize = 8;
+
+/// Natural (row-major) index of each coefficient, in zigzag order
+const ZIGZAG: [usize; 64] = [
+    0, 1, 8, 16, 9, 2, 3, 10, 17, 24, 32, 25, 18, 11, 4, 5, 12, 19, 26, 33, 40, 48, 41, 34, 27, 20,
+    13, 6, 7, 14, 21, 28, 35, 42, 49, 56, 57, 50, 43, 36, 29, 22, 15, 23, 30, 37, 44, 51, 58, 59,
+    52, 45, 38, 31, 39, 46, 53, 60, 61, 54, 47, 55, 62, 63,
+];
+
+const LUMA_QUANTIZATION: [u8; 64] = [
+    16, 11, 10, 16, 24, 40, 51, 61, 12, 12, 14, 19, 26, 58, 60, 55, 14, 13, 16, 24, 40, 57, 69, 56,
+    14, 17, 22, 29, 51, 87, 80, 62, 18, 22, 37, 56, 68, 109, 103, 77, 24, 35, 55, 64, 81, 104, 113,
+    92, 49, 64, 78, 87, 103, 121, 120, 101, 72, 92, 95, 98, 112, 100, 103, 99,
+];
+
+const CHROMA_QUANTIZATION: [u8; 64] = [
+    17, 18, 24, 47, 99, 99, 99, 99, 18, 21, 26, 66, 99, 99, 99, 99, 24, 26, 56, 99, 99, 99, 99, 99,
+    47, 66, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99,
+    99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99,
+];
+
+const DC_LUMA_BITS: [u8; 16] = [0, 1, 5, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0];
+const DC_CHROMA_BITS: [u8; 16] = [0, 3, 1, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0];
+const DC_VALUES: [u8; 12] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11];
+
+const AC_LUMA_BITS: [u8; 16] = [0, 2, 1, 3, 3, 2, 4, 3, 5, 5, 4, 4, 0, 0, 1, 0x7d];
+const AC_LUMA_VALUES: [u8; 162] = [
+    0x01, 0x02, 0x03, 0x00, 0x04, 0x11, 0x05, 0x12, 0x21, 0x31, 0x41, 0x06, 0x13, 0x51, 0x61, 0x07,
+    0x22, 0x71, 0x14, 0x32, 0x81, 0x91, 0xa1, 0x08, 0x23, 0x42, 0xb1, 0xc1, 0x15, 0x52, 0xd1, 0xf0,
+    0x24, 0x33, 0x62, 0x72, 0x82, 0x09, 0x0a, 0x16, 0x17, 0x18, 0x19, 0x1a, 0x25, 0x26, 0x27, 0x28,
+    0x29, 0x2a, 0x34, 0x35, 0x36, 0x37, 0x38, 0x39, 0x3a, 0x43, 0x44, 0x45, 0x46, 0x47, 0x48, 0x49,
+    0x4a, 0x53, 0x54, 0x55, 0x56, 0x57, 0x58, 0x59, 0x5a, 0x63, 0x64, 0x65, 0x66, 0x67, 0x68, 0x69,
+    0x6a, 0x73, 0x74, 0x75, 0x76, 0x77, 0x78, 0x79, 0x7a, 0x83, 0x84, 0x85, 0x86, 0x87, 0x88, 0x89,
+    0x8a, 0x92, 0x93, 0x94, 0x95, 0x96, 0x97, 0x98, 0x99, 0x9a, 0xa2, 0xa3, 0xa4, 0xa5, 0xa6, 0xa7,
+    0xa8, 0xa9, 0xaa, 0xb2, 0xb3, 0xb4, 0xb5, 0xb6, 0xb7, 0xb8, 0xb9, 0xba, 0xc2, 0xc3, 0xc4, 0xc5,
+    0xc6, 0xc7, 0xc8, 0xc9, 0xca, 0xd2, 0xd3, 0xd4, 0xd5, 0xd6, 0xd7, 0xd8, 0xd9, 0xda, 0xe1, 0xe2,
+    0xe3, 0xe4, 0xe5, 0xe6, 0xe7, 0xe8, 0xe9, 0xea, 0xf1, 0xf2, 0xf3, 0xf4, 0xf5, 0xf6, 0xf7, 0xf8,
+    0xf9, 0xfa,
+];
+
+const AC_CHROMA_BITS: [u8; 16] = [0, 2, 1, 2, 4, 4, 3, 4, 7, 5, 4, 4, 0, 1, 2, 0x77];
+const AC_CHROMA_VALUES: [u8; 162] = [
+    0x00, 0x01, 0x02, 0x03, 0x11, 0x04, 0x05, 0x21, 0x31, 0x06, 0x12, 0x41, 0x51, 0x07, 0x61, 0x71,
+    0x13, 0x22, 0x32, 0x81, 0x08, 0x14, 0x42, 0x91, 0xa1, 0xb1, 0xc1, 0x09, 0x23, 0x33, 0x52, 0xf0,
+    0x15, 0x62, 0x72, 0xd1, 0x0a, 0x16, 0x24, 0x34, 0xe1, 0x25, 0xf1, 0x17, 0x18, 0x19, 0x1a, 0x26,
+    0x27, 0x28, 0x29, 0x2a, 0x35, 0x36, 0x37, 0x38, 0x39, 0x3a, 0x43, 0x44, 0x45, 0x46, 0x47, 0x48,
+    0x49, 0x4a, 0x53, 0x54, 0x55, 0x56, 0x57, 0x58, 0x59, 0x5a, 0x63, 0x64, 0x65, 0x66, 0x67, 0x68,
+    0x69, 0x6a, 0x73, 0x74, 0x75, 0x76, 0x77, 0x78, 0x79, 0x7a, 0x82, 0x83, 0x84, 0x85, 0x86, 0x87,
+    0x88, 0x89, 0x8a, 0x92, 0x93, 0x94, 0x95, 0x96, 0x97, 0x98, 0x99, 0x9a, 0xa2, 0xa3, 0xa4, 0xa5,
+    0xa6, 0xa7, 0xa8, 0xa9, 0xaa, 0xb2, 0xb3, 0xb4, 0xb5, 0xb6, 0xb7, 0xb8, 0xb9, 0xba, 0xc2, 0xc3,
+    0xc4, 0xc5, 0xc6, 0xc7, 0xc8, 0xc9, 0xca, 0xd2, 0xd3, 0xd4, 0xd5, 0xd6, 0xd7, 0xd8, 0xd9, 0xda,
+    0xe2, 0xe3, 0xe4, 0xe5, 0xe6, 0xe7, 0xe8, 0xe9, 0xea, 0xf2, 0xf3, 0xf4, 0xf5, 0xf6, 0xf7, 0xf8,
+    0xf9, 0xfa,
+];
+
+/// Maximum size of the ICC profile data in a single APP2 segment
+const ICC_CHUNK_SIZE: usize = 65519;
+/// Maximum size of the data of a segment, without its length
+const MAX_SEGMENT_SIZE: usize = 65533;
+/// The start of the APP1 segment that contains XMP metadata
+const XMP_NAMESPACE: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+
+/// Huffman codes and their lengths, indexed by symbol
+struct HuffmanTable {
+    codes: [(u16, u8); 256],
+}
+
+impl HuffmanTable {
+    fn new(bits: &[u8; 16], values: &[u8]) -> Self {
+        let mut codes = [(0, 0); 256];
+        let mut code = 0u16;
+        let mut values = values.iter();
+        for (length, &count) in (1..=16).zip(bits) {
+            for &value in values.by_ref().take(count.into()) {
+                codes[usize::from(value)] = (code, length);
+                code += 1;
+            }
+            code <<= 1;
+        }
+        HuffmanTable { codes }
+    }
+}
+
+/// The tables used to encode one color component
+struct Component {
+    quantization: [u16; 64],
+    dc: HuffmanTable,
+    ac: HuffmanTable,
+}
+
+/// Writes the entropy-coded data, inserting a zero byte after each 0xFF byte
+struct BitWriter<W: Write> {
+    writer: W,
+    accumulator: u32,
+    bit_count: u8,
+}
+
+impl<W: Write> BitWriter<W> {
+    fn write_bits(&mut self, bits: u16, count: u8) -> io::Result<()> {
+        self.accumulator = (self.accumulator << count) | u32::from(bits);
+        self.bit_count += count;
+        while self.bit_count >= 8 {
+            self.bit_count -= 8;
+            let byte = (self.accumulator >> self.bit_count) as u8;
+            if byte == 0xFF {
+                self.writer.write_all(&[0xFF, 0])?;
+            } else {
+                self.writer.write_all(&[byte])?;
+            }
+        }
+        self.accumulator &= (1 << self.bit_count) - 1;
+        Ok(())
+    }
+
+    /// Pads the last byte with ones
+    fn flush_bits(&mut self) -> io::Result<()> {
+        if self.bit_count > 0 {
+            let padding = 8 - self.bit_count;
+            self.write_bits((1 << padding) - 1, padding)?;
+        }
+        Ok(())
+    }
+}
+
+/// Receives RGB pixels, from top left to bottom right, and writes them as a baseline JPEG image
+pub struct JpegStreamWriter<W: Write> {
+    writer: BitWriter<W>,
+    size: Vec2d,
+    components: [Component; 3],
+    /// The pixels of the band of rows currently being received
+    band: Vec<u8>,
+    /// The DC coefficient of the previous block of each component
+    previous_dc: [i32; 3],
+    cosines: [[f32; BLOCK]; BLOCK],
+}
+
+impl<W: Write> JpegStreamWriter<W> {
+    /// Writes the headers of the image. The EXIF metadata may include the "Exif\0\0" prefix.
+    pub fn new(
+        mut writer: W,
+        size: Vec2d,
+        quality: u8,
+        icc_profile: Option<&[u8]>,
+        exif_metadata: Option<&[u8]>,
+        xmp_metadata: Option<&str>,
+    ) -> io::Result<Self> {
+        let (Ok(width), Ok(height)) = (u16::try_from(size.x), u16::try_from(size.y)) else {
+            let msg = format!("A {size} image is too large to be saved as JPEG");
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+        };
+        let luma = scaled_quantization(&LUMA_QUANTIZATION, quality);
+        let chroma = scaled_quantization(&CHROMA_QUANTIZATION, quality);
+
+        writer.write_all(&[0xFF, 0xD8])?;
+        write_segment(
+            &mut writer,
+            0xE0,
+            &[b"JFIF\0", &[1, 1, 0, 0, 1, 0, 1, 0, 0]],
+        )?;
+        if let Some(exif) = exif_metadata {
+            let prefix: &[u8] = if exif.starts_with(b"Exif\0\0") {
+                b""
+            } else {
+                b"Exif\0\0"
+            };
+            write_segment(&mut writer, 0xE1, &[prefix, exif])?;
+        }
+        if let Some(xmp) = xmp_metadata {
+            if XMP_NAMESPACE.len() + xmp.len() <= MAX_SEGMENT_SIZE {
+                write_segment(&mut writer, 0xE1, &[XMP_NAMESPACE, xmp.as_bytes()])?;
+            } else {
+                // Larger packets would have to be split in extended XMP segments
+                warn!("The XMP metadata is too large to be saved in the JPEG file");
+            }
+        }
+        if let Some(profile) = icc_profile {
+            let chunks: Vec<&[u8]> = profile.chunks(ICC_CHUNK_SIZE).collect();
+            for (i, chunk) in chunks.iter().enumerate() {
+                let header = [(i + 1) as u8, chunks.len() as u8];
+                write_segment(&mut writer, 0xE2, &[b"ICC_PROFILE\0", &header, chunk])?;
+            }
+        }
+        for (id, table) in [luma, chroma].iter().enumerate() {
+            let zigzag: Vec<u8> = ZIGZAG.iter().map(|&i| table[i] as u8).collect();
+            write_segment(&mut writer, 0xDB, &[&[id as u8], &zigzag])?;
+        }
+        let [w1, w2] = width.to_be_bytes();
+        let [h1, h2] = height.to_be_bytes();
+        #[rustfmt::skip]
+        let frame = [8, h1, h2, w1, w2, 3, 1, 0x11, 0, 2, 0x11, 1, 3, 0x11, 1];
+        write_segment(&mut writer, 0xC0, &[&frame])?;
+        let tables: [(u8, &[u8; 16], &[u8]); 4] = [
+            (0x00, &DC_LUMA_BITS, &DC_VALUES),
+            (0x10, &AC_LUMA_BITS, &AC_LUMA_VALUES),
+            (0x01, &DC_CHROMA_BITS, &DC_VALUES),
+            (0x11, &AC_CHROMA_BITS, &AC_CHROMA_VALUES),
+        ];
+        for (class_and_id, bits, values) in tables {
+            write_segment(&mut writer, 0xC4, &[&[class_and_id], bits, values])?;
+        }
+        write_segment(
+            &mut writer,
+            0xDA,
+            &[&[3, 1, 0x00, 2, 0x11, 3, 0x11, 0, 63, 0]],
+        )?;
+
+        let component = |quantization| Component {
+            quantization,
+            dc: HuffmanTable::new(&DC_LUMA_BITS, &DC_VALUES),
+            ac: HuffmanTable::new(&AC_LUMA_BITS, &AC_LUMA_VALUES),
+        };
+        let chroma_component = || Component {
+            quantization: chroma,
+            dc: HuffmanTable::new(&DC_CHROMA_BITS, &DC_VALUES),
+            ac: HuffmanTable::new(&AC_CHROMA_BITS, &AC_CHROMA_VALUES),
+        };
+        let mut cosines = [[0f32; BLOCK]; BLOCK];
+        for (u, row) in cosines.iter_mut().enumerate() {
+            let scale = if u == 0 { 0.5f32.sqrt() } else { 1. } / 2.;
+            for (x, c) in row.iter_mut().enumerate() {
+                let angle = ((2 * x + 1) * u) as f32 * std::f32::consts::PI / 16.;
+                *c = scale * angle.cos();
+            }
+        }
+        Ok(JpegStreamWriter {
+            writer: BitWriter {
+                writer,
+                accumulator: 0,
+                bit_count: 0,
+            },
+            size,
+            components: [component(luma), chroma_component(), chroma_component()],
+            band: Vec::with_capacity(band_length(size)),
+            previous_dc: [0; 3],
+            cosines,
+        })
+    }
+
+    /// Encodes the last rows of the image and writes the end of the file.
+    /// All the pixels of the image must have been written.
+    pub fn finish(mut self) -> io::Result<W> {
+        if !self.band.is_empty() {
+            self.encode_band()?;
+        }
+        self.writer.flush_bits()?;
+        let mut writer = self.writer.writer;
+        writer.write_all(&[0xFF, 0xD9])?;
+        writer.flush()?;
+        Ok(writer)
+    }
+
+    /// Encodes the current band of rows, repeating its last row and column
+    /// to fill the blocks that go over the edges of the image
+    fn encode_band(&mut self) -> io::Result<()> {
+        let width = self.size.x as usize;
+        let row_length = width * BYTES_PER_PIXEL;
+        let rows = self.band.len() / row_length;
+        let mut blocks = [[0f32; 64]; 3];
+        for block_x in (0..width).step_by(BLOCK) {
+            for y in 0..BLOCK {
+                let row = &self.band[y.min(rows - 1) * row_length..];
+                for x in 0..BLOCK {
+                    let offset = (block_x + x).min(width - 1) * BYTES_PER_PIXEL;
+                    let [r, g, b] = [0, 1, 2].map(|i| f32::from(row[offset + i]));
+                    let i = y * BLOCK + x;
+                    blocks[0][i] = 0.299 * r + 0.587 * g + 0.114 * b - 128.;
+                    blocks[1][i] = -0.168_736 * r - 0.331_264 * g + 0.5 * b;
+                    blocks[2][i] = 0.5 * r - 0.418_688 * g - 0.081_312 * b;
+                }
+            }
+            for (c, block) in blocks.iter().enumerate() {
+                self.encode_block(c, block)?;
+            }
+        }
+        self.band.clear();
+        Ok(())
+    }
+
+    fn encode_block(&mut self, component: usize, block: &[f32; 64]) -> io::Result<()> {
+        let coefficients = self.quantized_dct(component, block);
+        let Component { dc, ac, .. } = &self.components[component];
+        let writer = &mut self.writer;
+
+        let dc_value = coefficients[0];
+        let (bits, size) = magnitude(dc_value - self.previous_dc[component]);
+        self.previous_dc[component] = dc_value;
+        let (code, length) = dc.codes[usize::from(size)];
+        writer.write_bits(code, length)?;
+        writer.write_bits(bits, size)?;
+
+        let mut zeros = 0;
+        for &value in &coefficients[1..] {
+            if value == 0 {
+                zeros += 1;
+                continue;
+            }
+            while zeros > 15 {
+                let (code, length) = ac.codes[0xF0];
+                writer.write_bits(code, length)?;
+                zeros -= 16;
+            }
+            let (bits, size) = magnitude(value);
+            let (code, length) = ac.codes[(zeros << 4) | usize::from(size)];
+            writer.write_bits(code, length)?;
+            writer.write_bits(bits, size)?;
+            zeros = 0;
+        }
+        if zeros > 0 {
+            let (code, length) = ac.codes[0x00];
+            writer.write_bits(code, length)?;
+        }
+        Ok(())
+    }
+
+    /// The discrete cosine transform of the block, quantized, in zigzag order
+    fn quantized_dct(&self, component: usize, block: &[f32; 64]) -> [i32; 64] {
+        let c = &self.cosines;
+        let mut rows = [0f32; 64];
+        for y in 0..BLOCK {
+            for u in 0..BLOCK {
+                rows[y * BLOCK + u] = (0..BLOCK).map(|x| c[u][x] * block[y * BLOCK + x]).sum();
+            }
+        }
+        let quantization = &self.components[component].quantization;
+        let mut coefficients = [0; 64];
+        for (z, &i) in ZIGZAG.iter().enumerate() {
+            let (v, u) = (i / BLOCK, i % BLOCK);
+            let value: f32 = (0..BLOCK).map(|y| c[v][y] * rows[y * BLOCK + u]).sum();
+            coefficients[z] = (value / f32::from(quantization[i])).round() as i32;
+        }
+        coefficients
+    }
+}
+
+impl<W: Write> Write for JpegStreamWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let band_length = band_length(self.size);
+        let length = buf.len().min(band_length - self.band.len());
+        self.band.extend_from_slice(&buf[..length]);
+        if self.band.len() == band_length {
+            self.encode_band()?;
+        }
+        Ok(length)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.writer.flush()
+    }
+}
+
+/// The number of bytes in a full band of rows
+fn band_length(size: Vec2d) -> usize {
+    size.x as usize * BYTES_PER_PIXEL * BLOCK
+}
+
+/// Scales a quantization table the same way as libjpeg
+fn scaled_quantization(table: &[u8; 64], quality: u8) -> [u16; 64] {
+    let quality = u32::from(quality.clamp(1, 100));
+    let scale = if quality < 50 {
+        5000 / quality
+    } else {
+        200 - quality * 2
+    };
+    table.map(|q| ((u32::from(q) * scale + 50) / 100).clamp(1, 255) as u16)
+}
+
+/// The category of a coefficient, and the bits that represent it in that category
+fn magnitude(value: i32) -> (u16, u8) {
+    let size = (32 - value.unsigned_abs().leading_zeros()) as u8;
+    let bits = if value < 0 {
+        value - 1 + (1 << size)
+    } else {
+        value
+    };
+    (bits as u16, size)
+}
+
+fn write_segment<W: Write>(writer: &mut W, marker: u8, parts: &[&[u8]]) -> io::Result<()> {
+    let length: usize = 2 + parts.iter().map(|p| p.len()).sum::<usize>();
+    let length = u16::try_from(length)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "JPEG segment too large"))?;
+    writer.write_all(&[0xFF, marker])?;
+    writer.write_all(&length.to_be_bytes())?;
+    for part in parts {
+        writer.write_all(part)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{ImageDecoder, Rgb, RgbImage};
+
+    use super::*;
+
+    #[test]
+    fn test_huffman_tables() {
+        for (bits, values) in [
+            (&DC_LUMA_BITS, &DC_VALUES[..]),
+            (&DC_CHROMA_BITS, &DC_VALUES[..]),
+            (&AC_LUMA_BITS, &AC_LUMA_VALUES[..]),
+            (&AC_CHROMA_BITS, &AC_CHROMA_VALUES[..]),
+        ] {
+            let count: usize = bits.iter().map(|&b| usize::from(b)).sum();
+            assert_eq!(count, values.len());
+        }
+        assert_eq!(magnitude(0), (0, 0));
+        assert_eq!(magnitude(5), (0b101, 3));
+        assert_eq!(magnitude(-5), (0b010, 3));
+    }
+
+    #[test]
+    fn test_stream_jpeg() {
+        // A size that is not a multiple of the block size
+        let (width, height) = (21, 13);
+        let image = RgbImage::from_fn(width, height, |x, y| {
+            Rgb([
+                (x * 12) as u8,
+                (y * 19) as u8,
+                if x < 10 { 50 } else { 200 },
+            ])
+        });
+        let icc_profile = vec![1, 2, 3, 4];
+        let mut writer = JpegStreamWriter::new(
+            Vec::new(),
+            Vec2d {
+                x: width,
+                y: height,
+            },
+            95,
+            Some(&icc_profile),
+            None,
+            Some("<x:xmpmeta/>"),
+        )
+        .unwrap();
+        // Rows do not need to be written all at once
+        for pixel in image.as_raw().chunks(7) {
+            writer.write_all(pixel).unwrap();
+        }
+        let data = writer.finish().unwrap();
+
+        let mut decoder =
+            image::codecs::jpeg::JpegDecoder::new(std::io::Cursor::new(&data)).unwrap();
+        assert_eq!(decoder.icc_profile().unwrap(), Some(icc_profile));
+        assert_eq!(
+            decoder.xmp_metadata().unwrap(),
+            Some(b"<x:xmpmeta/>".to_vec())
+        );
+        let decoded = image::load_from_memory(&data).unwrap().to_rgb8();
+        assert_eq!(decoded.dimensions(), (width, height));
+        for (x, y, expected) in image.enumerate_pixels() {
+            let actual = decoded.get_pixel(x, y);
+            for (a, e) in actual.0.iter().zip(expected.0) {
+                assert!(a.abs_diff(e) < 12, "{actual:?} != {expected:?} at {x},{y}");
+            }
+        }
+    }
+}
@@ -0,0 +1,246 @@
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use image::DynamicImage;
+use image::codecs::jpeg::JpegEncoder;
+use log::debug;
+use rusqlite::{Connection, params};
+
+use crate::encoder::retiler::{Retiler, TileSaver};
+use crate::errors::{image_error_to_io_error, make_io_err};
+use crate::tile::Tile;
+use crate::{Vec2d, ZoomError};
+
+use super::Encoder;
+
+/// Width and height of the tiles in the database
+pub const MBTILES_TILE_SIZE: u32 = 256;
+
+/// Stores the tiles of all the zoom levels in a single SQLite database,
+/// following the MBTiles specification. See https://github.com/mapbox/mbtiles-spec
+/// Zoom level 0 is the level where the image fits in a single tile,
+/// and rows are numbered from the bottom of the image, as in the TMS scheme.
+/// The image is placed at the top left corner of the world map, in the web mercator projection.
+pub struct MbtilesEncoder {
+    retiler: Retiler<MbtilesTileSaver>,
+    tile_saver: Arc<MbtilesTileSaver>,
+    name: String,
+}
+
+impl MbtilesEncoder {
+    pub fn new(destination: PathBuf, size: Vec2d, quality: u8) -> Result<Self, ZoomError> {
+        let _ = std::fs::remove_file(&destination);
+        debug!("Creating mbtiles database at {destination:?}");
+        let connection = Connection::open(&destination).map_err(make_io_err)?;
+        connection
+            .execute_batch(
+                "CREATE TABLE metadata (name TEXT, value TEXT);
+                CREATE TABLE tiles (
+                    zoom_level INTEGER,
+                    tile_column INTEGER,
+                    tile_row INTEGER,
+                    tile_data BLOB
+                );
+                CREATE UNIQUE INDEX tile_index ON tiles (zoom_level, tile_column, tile_row);
+                BEGIN;",
+            )
+            .map_err(make_io_err)?;
+        let tile_size = MBTILES_TILE_SIZE;
+        let max_tiles = size.x.max(size.y).div_ceil(tile_size).max(1);
+        let tile_saver = Arc::new(MbtilesTileSaver {
+            connection: Mutex::new(connection),
+            max_zoom: max_tiles.next_power_of_two().trailing_zeros(),
+            quality,
+        });
+        let retiler = Retiler::new(
+            size,
+            Vec2d::square(tile_size),
+            0,
+            Arc::clone(&tile_saver),
+            1,
+        );
+        let name = destination
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned();
+        Ok(MbtilesEncoder {
+            retiler,
+            tile_saver,
+            name,
+        })
+    }
+}
+
+impl Encoder for MbtilesEncoder {
+    fn add_tile(&mut self, tile: Tile) -> io::Result<()> {
+        self.retiler.add_tile(&tile)
+    }
+
+    fn finalize(&mut self) -> io::Result<()> {
+        self.retiler.finalize();
+        let max_zoom = self.tile_saver.max_zoom;
+        let min_zoom = max_zoom + 1 - self.retiler.level_count();
+        let size = self.size();
+        // The fraction of the world map the image covers, horizontally and vertically
+        let world_size = f64::from(MBTILES_TILE_SIZE) * f64::from(1u32 << max_zoom);
+        let right = f64::from(size.x) / world_size;
+        let bottom = f64::from(size.y) / world_size;
+        let bounds = format!(
+            "{:.6},{:.6},{:.6},{:.6}",
+            longitude(0.),
+            latitude(bottom),
+            longitude(right),
+            latitude(0.)
+        );
+        let center = format!(
+            "{:.6},{:.6},{min_zoom}",
+            longitude(right / 2.),
+            latitude(bottom / 2.)
+        );
+        let metadata = [
+            ("name", self.name.clone()),
+            ("format", "jpg".into()),
+            ("type", "baselayer".into()),
+            ("version", "1.3".into()),
+            ("description", format!("{}x{} image", size.x, size.y)),
+            ("minzoom", min_zoom.to_string()),
+            ("maxzoom", max_zoom.to_string()),
+            ("bounds", bounds),
+            ("center", center),
+        ];
+        let connection = self.tile_saver.connection.lock().unwrap();
+        for (name, value) in metadata {
+            connection
+                .execute(
+                    "INSERT INTO metadata (name, value) VALUES (?1, ?2)",
+                    params![name, value],
+                )
+                .map_err(make_io_err)?;
+        }
+        debug!("Committing the tiles to the mbtiles database");
+        connection.execute_batch("COMMIT;").map_err(make_io_err)
+    }
+
+    fn size(&self) -> Vec2d {
+        self.retiler.size()
+    }
+}
+
+/// The longitude of a point of the web mercator world map, from its horizontal position in the map
+fn longitude(x: f64) -> f64 {
+    x * 360. - 180.
+}
+
+/// The latitude of a point of the web mercator world map, from its vertical position in the map
+fn latitude(y: f64) -> f64 {
+    (std::f64::consts::PI * (1. - 2. * y))
+        .sinh()
+        .atan()
+        .to_degrees()
+}
+
+struct MbtilesTileSaver {
+    connection: Mutex<Connection>,
+    /// The zoom level of the full-resolution image
+    max_zoom: u32,
+    quality: u8,
+}
+
+impl TileSaver for MbtilesTileSaver {
+    fn save_tile(&self, _size: Vec2d, scale_factor: u32, tile: Tile) -> io::Result<()> {
+        let zoom = self.max_zoom - scale_factor.trailing_zeros();
+        let pos = tile.position / (MBTILES_TILE_SIZE * scale_factor);
+        let row = (1u32 << zoom) - 1 - pos.y;
+        // Map viewers expect all tiles to be square, so the tiles at the edges are padded
+        let mut image = DynamicImage::new_rgb8(MBTILES_TILE_SIZE, MBTILES_TILE_SIZE);
+        image::imageops::replace(&mut image, &tile.image, 0, 0);
+        let mut data = Vec::new();
+        let jpeg_writer = JpegEncoder::new_with_quality(&mut data, self.quality);
+        image
+            .write_with_encoder(jpeg_writer)
+            .map_err(image_error_to_io_error)?;
+        debug!("Inserting tile {pos} of zoom level {zoom} in the mbtiles database");
+        self.connection
+            .lock()
+            .unwrap()
+            .execute(
+                "INSERT OR REPLACE INTO tiles (zoom_level, tile_column, tile_row, tile_data) \
+                VALUES (?1, ?2, ?3, ?4)",
+                params![zoom, pos.x, row, data],
+            )
+            .map_err(make_io_err)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env::temp_dir;
+
+    use image::{DynamicImage, GenericImageView, RgbImage};
+
+    use super::*;
+
+    #[test]
+    fn test_mbtiles() {
+        let destination = temp_dir().join("dezoomify-rs-mbtiles-test.mbtiles");
+        let size = Vec2d { x: 600, y: 300 };
+        let mut encoder = MbtilesEncoder::new(destination.clone(), size, 90).unwrap();
+        let image = RgbImage::from_pixel(size.x, size.y, image::Rgb([255, 255, 255]));
+        encoder
+            .add_tile(
+                Tile::builder()
+                    .at_position(Vec2d::default())
+                    .with_image(DynamicImage::ImageRgb8(image))
+                    .build(),
+            )
+            .unwrap();
+        encoder.finalize().unwrap();
+
+        let connection = Connection::open(&destination).unwrap();
+        let metadata = |name: &str| -> String {
+            connection
+                .query_row("SELECT value FROM metadata WHERE name=?1", [name], |r| {
+                    r.get(0)
+                })
+                .unwrap()
+        };
+        assert_eq!(metadata("maxzoom"), "2");
+        // The image covers 600/1024 of the width of the world map, and 300/1024 of its height
+        assert_eq!(
+            metadata("bounds"),
+            "-180.000000,59.534318,30.937500,85.051129"
+        );
+        assert_eq!(metadata("center"), "-74.531250,77.617709,0");
+        let mut statement = connection
+            .prepare("SELECT zoom_level, tile_column, tile_row FROM tiles ORDER BY 1, 2, 3")
+            .unwrap();
+        let tiles: Vec<(u32, u32, u32)> = statement
+            .query_map([], |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?)))
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        #[rustfmt::skip]
+        assert_eq!(tiles, vec![
+            (0, 0, 0),
+            (1, 0, 1), (1, 1, 1),
+            (2, 0, 2), (2, 0, 3), (2, 1, 2), (2, 1, 3), (2, 2, 2), (2, 2, 3),
+        ]);
+
+        // The bottom-right tile of the full-resolution image
+        let data: Vec<u8> = connection
+            .query_row(
+                "SELECT tile_data FROM tiles WHERE zoom_level=2 AND tile_column=2 AND tile_row=2",
+                [],
+                |r| r.get(0),
+            )
+            .unwrap();
+        let tile = image::load_from_memory(&data).unwrap();
+        assert_eq!(tile.dimensions(), (256, 256));
+        // The part of the tile outside of the image is padded
+        assert!(tile.get_pixel(600 - 512 - 2, 300 - 256 - 2)[0] > 200);
+        assert!(tile.get_pixel(600 - 512 + 2, 300 - 256 + 2)[0] < 50);
+    }
+}
@@ -1,17 +1,29 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use image::{DynamicImage, GenericImageView, Rgb, Rgba, SubImage};
 use log::debug;
 
+use crate::provenance::Provenance;
 use crate::tile::Tile;
 use crate::{Vec2d, ZoomError, max_size_in_rect};
 
+mod bigtiff;
 pub mod canvas;
+pub mod cbz_writer;
+pub mod dzi_encoder;
+mod icc_embedding;
 pub mod iiif_encoder;
+pub mod jpeg_encoder;
+mod jpeg_writer;
+pub mod mbtiles_encoder;
+pub mod pdf_writer;
 pub mod pixel_streamer;
 pub mod png_encoder;
+pub mod pyramid_tiff_encoder;
 mod retiler;
+pub mod tiff_encoder;
 pub mod tile_buffer;
+pub mod zoomify_encoder;
 
 pub trait Encoder: Send + 'static {
     /// Add a tile to the image
@@ -22,13 +34,64 @@ pub trait Encoder: Send + 'static {
     fn size(&self) -> Vec2d;
 }
 
+/// User-provided settings for the encoders
+#[derive(Debug, Clone)]
+pub struct EncoderOptions {
+    /// Between 0 (less compression) and 100 (more compression)
+    pub compression: u8,
+    /// Width and height of the tiles of Deep Zoom images, without the overlap
+    pub dzi_tile_size: u32,
+    /// Number of pixels each tile of a Deep Zoom image shares with its neighbours
+    pub dzi_overlap: u32,
+    /// Where the image comes from, saved as XMP metadata by the encoders that support it
+    pub provenance: Option<Provenance>,
+}
+
+impl Default for EncoderOptions {
+    fn default() -> Self {
+        EncoderOptions {
+            compression: 5,
+            dzi_tile_size: 254,
+            dzi_overlap: 1,
+            provenance: None,
+        }
+    }
+}
+
+/// The largest width and height of the images that can be saved in the given format
+fn max_dimension(extension: &str) -> Option<(&'static str, u32)> {
+    match extension.to_ascii_lowercase().as_str() {
+        "webp" => Some(("WebP", 16383)),
+        "avif" => Some(("AVIF", 65535)),
+        "jpg" | "jpeg" => Some(("JPEG", u32::from(u16::MAX))),
+        _ => None,
+    }
+}
+
+/// Fails if an image of the given size cannot be saved to the destination
+pub fn check_output_size(destination: &Path, size: Vec2d) -> Result<(), ZoomError> {
+    let extension = destination.extension().unwrap_or_default();
+    if let Some((format, max_dimension)) = max_dimension(&extension.to_string_lossy())
+        && size.x.max(size.y) > max_dimension
+    {
+        return Err(ZoomError::ImageTooLarge {
+            size,
+            format: format.into(),
+            max_dimension,
+        });
+    }
+    Ok(())
+}
+
 fn encoder_for_name(
     destination: PathBuf,
     size: Vec2d,
-    compression: u8,
+    options: &EncoderOptions,
 ) -> Result<Box<dyn Encoder>, ZoomError> {
     let extension = destination.extension().unwrap_or_default();
+    let compression = options.compression;
     let quality = 100u8.saturating_sub(compression);
+    let xmp_metadata = options.provenance.as_ref().map(Provenance::to_xmp);
 
     if extension == "png" {
         debug!("Using the streaming png encoder");
@@ -36,6 +99,7 @@ fn encoder_for_name(
             destination,
             size,
             compression,
+            xmp_metadata,
         )?))
     } else if extension == "iiif" {
         debug!("Using the iiif tiling encoder");
@@ -44,12 +108,68 @@ fn encoder_for_name(
             size,
             quality,
         )?))
+    } else if extension == "ptif" || extension == "ptiff" {
+        debug!("Using the pyramidal tiff encoder with a quality of {quality}");
+        Ok(Box::new(pyramid_tiff_encoder::PyramidTiffEncoder::new(
+            destination,
+            size,
+            quality,
+            xmp_metadata,
+        )?))
+    } else if extension == "tif" || extension == "tiff" {
+        debug!("Using the streaming tiled tiff encoder");
+        Ok(Box::new(tiff_encoder::TiffEncoder::new(
+            destination,
+            size,
+            compression,
+            xmp_metadata,
+        )?))
+    } else if extension == "mbtiles" {
+        debug!("Using the mbtiles encoder");
+        Ok(Box::new(mbtiles_encoder::MbtilesEncoder::new(
+            destination,
+            size,
+            quality,
+        )?))
+    } else if extension == "zoomify" {
+        debug!("Using the zoomify tiling encoder");
+        Ok(Box::new(zoomify_encoder::ZoomifyEncoder::new(
+            destination,
+            size,
+            zoomify_encoder::ZOOMIFY_TILE_SIZE,
+            quality,
+        )?))
+    } else if extension == "dzi" {
+        debug!("Using the deep zoom tiling encoder");
+        Ok(Box::new(dzi_encoder::DziEncoder::new(
+            destination,
+            size,
+            options.dzi_tile_size,
+            options.dzi_overlap,
+            quality,
+        )?))
     } else if extension == "jpeg" || extension == "jpg" {
-        debug!("Using the jpeg encoder with a quality of {quality}");
-        Ok(Box::new(canvas::Canvas::<Rgb<u8>>::new_jpeg(
+        debug!("Using the streaming jpeg encoder with a quality of {quality}");
+        Ok(Box::new(jpeg_encoder::JpegEncoder::new(
             destination,
             size,
             quality,
+            xmp_metadata,
+        )?))
+    } else if extension == "webp" {
+        debug!("Using the webp encoder with a compression of {compression}");
+        Ok(Box::new(canvas::Canvas::<Rgb<u8>>::new_webp(
+            destination,
+            size,
+            compression,
+            xmp_metadata,
+        )?))
+    } else if extension == "avif" {
+        debug!("Using the avif encoder with a compression of {compression}");
+        Ok(Box::new(canvas::Canvas::<Rgb<u8>>::new_avif(
+            destination,
+            size,
+            compression,
         )?))
     } else {
         debug!(
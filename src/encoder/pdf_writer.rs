@@ -0,0 +1,319 @@
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+use flate2::Compression;
+use flate2::write::ZlibEncoder;
+use log::debug;
+
+use crate::Vec2d;
+use crate::errors::image_error_to_io_error;
+
+/// Largest width and height of a page, in points, that PDF readers accept
+const MAX_PAGE_DIMENSION: f64 = 14400.;
+
+/// Assembles already saved images into a PDF document, one page per image.
+/// Pages are written as soon as they are added, so only one image is in memory at a time.
+/// JPEG files are copied into the document as they are, without being decoded;
+/// other images are decoded and compressed with deflate.
+pub struct PdfWriter<W: Write> {
+    writer: W,
+    position: u64,
+    /// Position in the file of each object, indexed by object number minus one
+    offsets: Vec<u64>,
+    pages: Vec<u32>,
+}
+
+/// The object numbers of the document catalog and of the page tree,
+/// which are written last, when all the pages are known
+const CATALOG_ID: u32 = 1;
+const PAGE_TREE_ID: u32 = 2;
+
+impl<W: Write> PdfWriter<W> {
+    pub fn new(writer: W) -> io::Result<Self> {
+        let mut pdf = PdfWriter {
+            writer,
+            position: 0,
+            offsets: vec![0; 2],
+            pages: vec![],
+        };
+        // The binary comment tells file transfer programs that the file is not text
+        pdf.write(b"%PDF-1.4\n%\xE2\xE3\xCF\xD3\n")?;
+        Ok(pdf)
+    }
+
+    fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        self.writer.write_all(data)?;
+        self.position += data.len() as u64;
+        Ok(())
+    }
+
+    fn new_object_id(&mut self) -> u32 {
+        self.offsets.push(0);
+        self.offsets.len() as u32
+    }
+
+    fn write_object(&mut self, id: u32, dictionary: &str) -> io::Result<()> {
+        self.offsets[id as usize - 1] = self.position;
+        self.write(format!("{id} 0 obj\n{dictionary}\nendobj\n").as_bytes())
+    }
+
+    fn write_stream_object(&mut self, id: u32, dictionary: &str, data: &[u8]) -> io::Result<()> {
+        self.offsets[id as usize - 1] = self.position;
+        let length = data.len();
+        let header = format!("{id} 0 obj\n<< {dictionary} /Length {length} >>\nstream\n");
+        self.write(header.as_bytes())?;
+        self.write(data)?;
+        self.write(b"\nendstream\nendobj\n")
+    }
+
+    /// Adds a page containing the image saved at the given path.
+    /// The page has the given size in points if there is one, or the size of the image otherwise.
+    /// The image is read entirely before anything is written,
+    /// so that the document stays valid when it cannot be read.
+    pub fn add_page(&mut self, image_path: &Path, page_size: Option<Vec2d>) -> io::Result<()> {
+        let file_data = std::fs::read(image_path)?;
+        let (image_size, dictionary, data) = if file_data.starts_with(&[0xFF, 0xD8]) {
+            let (size, components) = jpeg_info(&mut &file_data[..])?;
+            debug!("Copying the jpeg data of {image_path:?} to the pdf document");
+            let color_space = match components {
+                1 => "/DeviceGray",
+                // CMYK JPEG files are usually written inverted, by Adobe software
+                4 => "/DeviceCMYK /Decode [1 0 1 0 1 0 1 0]",
+                _ => "/DeviceRGB",
+            };
+            let dictionary = image_dictionary(size, color_space, "/DCTDecode");
+            (size, dictionary, file_data)
+        } else {
+            debug!("Compressing {image_path:?} to add it to the pdf document");
+            let image = image::load_from_memory(&file_data)
+                .map_err(image_error_to_io_error)?
+                .into_rgb8();
+            let size = Vec2d::from(image.dimensions());
+            let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(image.as_raw())?;
+            let dictionary = image_dictionary(size, "/DeviceRGB", "/FlateDecode");
+            (size, dictionary, encoder.finish()?)
+        };
+
+        let image_id = self.new_object_id();
+        self.write_stream_object(image_id, &dictionary, &data)?;
+
+        let (width, height) = page_dimensions(page_size.unwrap_or(image_size));
+        let contents = format!("q {width:.2} 0 0 {height:.2} 0 0 cm /Im Do Q");
+        let contents_id = self.new_object_id();
+        self.write_stream_object(contents_id, "", contents.as_bytes())?;
+
+        let page_id = self.new_object_id();
+        self.write_object(
+            page_id,
+            &format!(
+                "<< /Type /Page /Parent {PAGE_TREE_ID} 0 R /MediaBox [0 0 {width:.2} {height:.2}] \
+                /Resources << /XObject << /Im {image_id} 0 R >> >> /Contents {contents_id} 0 R >>"
+            ),
+        )?;
+        self.pages.push(page_id);
+        Ok(())
+    }
+
+    pub fn page_count(&self) -> usize {
+        self.pages.len()
+    }
+
+    /// Writes the page tree, the document information and the cross-reference table
+    pub fn finish(mut self, title: Option<&str>) -> io::Result<W> {
+        let kids: Vec<String> = self.pages.iter().map(|id| format!("{id} 0 R")).collect();
+        let page_tree = format!(
+            "<< /Type /Pages /Kids [{}] /Count {} >>",
+            kids.join(" "),
+            kids.len()
+        );
+        self.write_object(PAGE_TREE_ID, &page_tree)?;
+        self.write_object(
+            CATALOG_ID,
+            &format!("<< /Type /Catalog /Pages {PAGE_TREE_ID} 0 R >>"),
+        )?;
+        let info_id = self.new_object_id();
+        let title = title
+            .map(|title| format!("/Title {} ", text_string(title)))
+            .unwrap_or_default();
+        self.write_object(info_id, &format!("<< {title}/Producer (dezoomify-rs) >>"))?;
+
+        let xref_position = self.position;
+        let mut xref = format!("xref\n0 {}\n0000000000 65535 f \n", self.offsets.len() + 1);
+        for offset in &self.offsets {
+            xref.push_str(&format!("{offset:010} 00000 n \n"));
+        }
+        xref.push_str(&format!(
+            "trailer\n<< /Size {} /Root {CATALOG_ID} 0 R /Info {info_id} 0 R >>\n\
+            startxref\n{xref_position}\n%%EOF\n",
+            self.offsets.len() + 1
+        ));
+        self.write(xref.as_bytes())?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+fn image_dictionary(size: Vec2d, color_space: &str, filter: &str) -> String {
+    format!(
+        "/Type /XObject /Subtype /Image /Width {} /Height {} \
+        /ColorSpace {color_space} /BitsPerComponent 8 /Filter {filter}",
+        size.x, size.y
+    )
+}
+
+/// The width and height of a page in points, scaled down if needed to fit in the PDF limits
+fn page_dimensions(size: Vec2d) -> (f64, f64) {
+    let (width, height) = (f64::from(size.x.max(1)), f64::from(size.y.max(1)));
+    let scale = (MAX_PAGE_DIMENSION / width.max(height)).min(1.);
+    (width * scale, height * scale)
+}
+
+/// A PDF string in UTF-16, so that it can contain any character
+fn text_string(text: &str) -> String {
+    let mut hex = String::from("<FEFF");
+    for unit in text.encode_utf16() {
+        hex.push_str(&format!("{unit:04X}"));
+    }
+    hex.push('>');
+    hex
+}
+
+/// Reads the size and the number of color components of a JPEG image from its headers
+fn jpeg_info(reader: &mut impl Read) -> io::Result<(Vec2d, u8)> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "invalid jpeg file");
+    let mut byte = [0u8; 1];
+    let mut read_byte = |reader: &mut dyn Read| -> io::Result<u8> {
+        reader.read_exact(&mut byte)?;
+        Ok(byte[0])
+    };
+    if read_byte(reader)? != 0xFF || read_byte(reader)? != 0xD8 {
+        return Err(invalid());
+    }
+    loop {
+        if read_byte(reader)? != 0xFF {
+            return Err(invalid());
+        }
+        let mut marker = read_byte(reader)?;
+        while marker == 0xFF {
+            marker = read_byte(reader)?;
+        }
+        // Markers without a segment
+        if marker == 0x01 || (0xD0..=0xD7).contains(&marker) {
+            continue;
+        }
+        let mut length = [0u8; 2];
+        reader.read_exact(&mut length)?;
+        let length = u64::from(u16::from_be_bytes(length)).checked_sub(2);
+        let length = length.ok_or_else(invalid)?;
+        let is_start_of_frame =
+            (0xC0..=0xCF).contains(&marker) && ![0xC4, 0xC8, 0xCC].contains(&marker);
+        if is_start_of_frame {
+            let mut frame = [0u8; 6];
+            reader.read_exact(&mut frame)?;
+            let height = u16::from_be_bytes([frame[1], frame[2]]);
+            let width = u16::from_be_bytes([frame[3], frame[4]]);
+            let size = Vec2d::from((u32::from(width), u32::from(height)));
+            return Ok((size, frame[5]));
+        }
+        if marker == 0xD9 || marker == 0xDA {
+            return Err(invalid());
+        }
+        io::copy(&mut reader.take(length), &mut io::sink())?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env::temp_dir;
+
+    use image::{Rgb, RgbImage};
+
+    use super::*;
+
+    /// Checks that the cross-reference table points to the objects
+    fn check_xref(pdf: &[u8]) {
+        let trailer_start = pdf.len() - 64;
+        let trailer = String::from_utf8_lossy(&pdf[trailer_start..]);
+        let start = trailer.split("startxref\n").nth(1).unwrap().lines().next();
+        let start: usize = start.unwrap().parse().unwrap();
+        let xref = String::from_utf8_lossy(&pdf[start..]);
+        assert!(xref.starts_with("xref\n"));
+        let entries = xref
+            .lines()
+            .skip(3)
+            .take_while(|l| !l.starts_with("trailer"));
+        for (i, entry) in entries.enumerate() {
+            let offset: usize = entry[..10].parse().unwrap();
+            let expected = format!("{} 0 obj", i + 1);
+            assert!(
+                pdf[offset..].starts_with(expected.as_bytes()),
+                "{expected} at {offset}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_pdf_pages() {
+        let jpeg_path = temp_dir().join("dezoomify-rs-pdf-test.jpg");
+        let png_path = temp_dir().join("dezoomify-rs-pdf-test.png");
+        RgbImage::from_pixel(30, 20, Rgb([255, 0, 0]))
+            .save(&jpeg_path)
+            .unwrap();
+        RgbImage::from_pixel(10, 10, Rgb([0, 0, 255]))
+            .save(&png_path)
+            .unwrap();
+
+        let mut pdf = PdfWriter::new(Vec::new()).unwrap();
+        pdf.add_page(&jpeg_path, None).unwrap();
+        pdf.add_page(
+            &png_path,
+            Some(Vec2d {
+                x: 20_000,
+                y: 10_000,
+            }),
+        )
+        .unwrap();
+        assert_eq!(pdf.page_count(), 2);
+        let data = pdf.finish(Some("Livre d'heures")).unwrap();
+        let text = String::from_utf8_lossy(&data);
+
+        // The jpeg file is copied as it is
+        let jpeg = std::fs::read(&jpeg_path).unwrap();
+        assert!(data.windows(jpeg.len()).any(|w| w == jpeg));
+        assert_eq!(
+            jpeg_info(&mut &jpeg[..]).unwrap(),
+            (Vec2d { x: 30, y: 20 }, 3)
+        );
+        assert!(text.contains("/Width 30 /Height 20 /ColorSpace /DeviceRGB"));
+        assert!(text.contains("/MediaBox [0 0 30.00 20.00]"));
+        assert!(text.contains("/MediaBox [0 0 14400.00 7200.00]"));
+        assert!(text.contains("/Filter /FlateDecode"));
+        assert!(text.contains("/Count 2"));
+        assert!(text.contains(&text_string("Livre d'heures")));
+        assert!(text.ends_with("%%EOF\n"));
+        check_xref(&data);
+    }
+
+    #[test]
+    fn test_pdf_skips_invalid_image() {
+        let png_path = temp_dir().join("dezoomify-rs-pdf-valid-test.png");
+        let invalid_path = temp_dir().join("dezoomify-rs-pdf-invalid-test.jpg");
+        RgbImage::from_pixel(10, 10, Rgb([0, 255, 0]))
+            .save(&png_path)
+            .unwrap();
+        // Starts like a jpeg file, but has no frame header
+        std::fs::write(&invalid_path, [0xFF, 0xD8, 0xFF, 0xD9]).unwrap();
+
+        let mut pdf = PdfWriter::new(Vec::new()).unwrap();
+        pdf.add_page(&png_path, None).unwrap();
+        assert!(pdf.add_page(&invalid_path, None).is_err());
+        pdf.add_page(&png_path, None).unwrap();
+        assert_eq!(pdf.page_count(), 2);
+        let data = pdf.finish(None).unwrap();
+        let text = String::from_utf8_lossy(&data);
+        assert!(text.contains("/Count 2"));
+        assert!(text.contains("/Size 10 "));
+        check_xref(&data);
+    }
+}
@@ -84,8 +84,9 @@ impl<W: Write> PixelStreamer<W> {
         Ok(())
     }
 
-    // https://github.com/image-rs/image-png/issues/307
-    // pub fn into_writer(self) -> W { self.writer }
+    pub fn into_writer(self) -> W {
+        self.writer
+    }
 }
 
 struct ImageStrip {
@@ -9,16 +9,25 @@ use crate::{Vec2d, ZoomError};
 use super::Encoder;
 use super::pixel_streamer::PixelStreamer;
 
+/// The keyword of the iTXt chunk that contains XMP metadata
+const XMP_KEYWORD: &str = "XML:com.adobe.xmp";
+
 pub struct PngEncoder {
     pixel_streamer: Option<PixelStreamer<png::StreamWriter<'static, File>>>,
     file: Option<File>,
     compression: png::Compression,
     size: Vec2d,
     first_tile: bool,
+    xmp_metadata: Option<String>,
 }
 
 impl PngEncoder {
-    pub fn new(destination: PathBuf, size: Vec2d, compression: u8) -> Result<Self, ZoomError> {
+    pub fn new(
+        destination: PathBuf,
+        size: Vec2d,
+        compression: u8,
+        xmp_metadata: Option<String>,
+    ) -> Result<Self, ZoomError> {
         let file = OpenOptions::new()
             .write(true)
             .create(true)
@@ -37,6 +46,7 @@ impl PngEncoder {
             compression: compression_level,
             size,
             first_tile: true,
+            xmp_metadata,
         })
     }
 
@@ -50,7 +60,9 @@ impl PngEncoder {
             .take()
             .expect("File should be available when writing header");
 
-        let writer = if icc_profile.is_some() || exif_metadata.is_some() {
+        let has_metadata =
+            icc_profile.is_some() || exif_metadata.is_some() || self.xmp_metadata.is_some();
+        let writer = if has_metadata {
             let mut info = png::Info::with_size(self.size.x, self.size.y);
             info.color_type = png::ColorType::Rgb;
             info.bit_depth = png::BitDepth::Eight;
@@ -73,6 +85,10 @@ impl PngEncoder {
 
             let mut encoder = png::Encoder::with_info(file, info)?;
             encoder.set_compression(self.compression);
+            if let Some(xmp) = &self.xmp_metadata {
+                log::debug!("Adding XMP metadata to the PNG file");
+                encoder.add_itxt_chunk(XMP_KEYWORD.to_string(), xmp.clone())?;
+            }
             encoder
                 .write_header()?
                 .into_stream_writer_with_size(128 * 1024)?
@@ -157,7 +173,7 @@ mod tests {
     fn test_png_create() {
         let destination = temp_dir().join("dezoomify-rs-png-test.png");
         let size = Vec2d { x: 2, y: 2 };
-        let mut encoder = PngEncoder::new(destination.clone(), size, 1).unwrap();
+        let mut encoder = PngEncoder::new(destination.clone(), size, 1, None).unwrap();
 
         encoder
             .add_tile(
@@ -183,7 +199,7 @@ mod tests {
     fn test_png_create_with_icc_profile() {
         let destination = temp_dir().join("dezoomify-rs-png-icc-test.png");
         let size = Vec2d { x: 1, y: 1 };
-        let mut encoder = PngEncoder::new(destination.clone(), size, 1).unwrap();
+        let mut encoder = PngEncoder::new(destination.clone(), size, 1, None).unwrap();
 
         // Create a dummy ICC profile (simplified sRGB profile header)
         let icc_profile = vec![
@@ -222,11 +238,29 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_png_create_with_xmp_metadata() {
+        let destination = temp_dir().join("dezoomify-rs-png-xmp-test.png");
+        let size = Vec2d { x: 1, y: 1 };
+        let xmp = "<x:xmpmeta xmlns:x=\"adobe:ns:meta/\"/>".to_string();
+        let mut encoder = PngEncoder::new(destination.clone(), size, 1, Some(xmp.clone())).unwrap();
+        encoder.finalize().unwrap();
+
+        let file = std::fs::File::open(&destination).unwrap();
+        let reader = png::Decoder::new(std::io::BufReader::new(file))
+            .read_info()
+            .unwrap();
+        let chunks = &reader.info().utf8_text;
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(chunks[0].keyword, XMP_KEYWORD);
+        assert_eq!(chunks[0].get_text().unwrap(), xmp);
+    }
+
     #[test]
     fn test_png_create_with_exif_metadata() {
         let destination = temp_dir().join("dezoomify-rs-png-exif-test.png");
         let size = Vec2d { x: 1, y: 1 };
-        let mut encoder = PngEncoder::new(destination.clone(), size, 1).unwrap();
+        let mut encoder = PngEncoder::new(destination.clone(), size, 1, None).unwrap();
 
         // Create dummy EXIF metadata (simplified EXIF header)
         let exif_metadata = vec![
@@ -270,7 +304,7 @@ mod tests {
     fn test_png_create_with_both_icc_and_exif() {
         let destination = temp_dir().join("dezoomify-rs-png-both-test.png");
         let size = Vec2d { x: 1, y: 1 };
-        let mut encoder = PngEncoder::new(destination.clone(), size, 1).unwrap();
+        let mut encoder = PngEncoder::new(destination.clone(), size, 1, None).unwrap();
 
         let icc_profile = vec![0x61, 0x64, 0x73, 0x70]; // Simplified
         let exif_metadata = vec![0x45, 0x78, 0x69, 0x66]; // Simplified
@@ -0,0 +1,307 @@
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use image::codecs::jpeg::JpegEncoder;
+use image::{ExtendedColorType, GenericImage, ImageEncoder, RgbImage};
+use log::debug;
+
+use crate::encoder::retiler::{Retiler, TileSaver};
+use crate::errors::image_error_to_io_error;
+use crate::tile::Tile;
+use crate::{Vec2d, ZoomError};
+
+use super::Encoder;
+use super::bigtiff::*;
+use super::tiff_encoder::TIFF_TILE_SIZE;
+
+/// Writes a pyramidal tiled BigTIFF file, that image servers such as IIPImage or Cantaloupe
+/// can serve directly. The full-resolution image is stored in the main image file directory,
+/// and each reduced-resolution level, half the size of the previous one, in a sub-IFD.
+/// All tiles are JPEG-compressed.
+pub struct PyramidTiffEncoder {
+    retiler: Retiler<PyramidTileSaver>,
+    tile_saver: Arc<PyramidTileSaver>,
+}
+
+impl PyramidTiffEncoder {
+    pub fn new(
+        destination: PathBuf,
+        size: Vec2d,
+        quality: u8,
+        xmp_metadata: Option<String>,
+    ) -> Result<Self, ZoomError> {
+        let file = BufWriter::new(File::create(&destination)?);
+        debug!("Creating a pyramidal tiff file at {destination:?}");
+        let tile_size = Vec2d::square(TIFF_TILE_SIZE);
+        let tile_saver = Arc::new(PyramidTileSaver {
+            quality,
+            state: Mutex::new(PyramidState {
+                writer: Some(BigTiffWriter::new(file)?),
+                levels: vec![],
+                icc_profile: None,
+                exif_metadata: None,
+                xmp_metadata,
+            }),
+        });
+        let retiler = Retiler::new(size, tile_size, 0, Arc::clone(&tile_saver), 1);
+        tile_saver.state.lock().unwrap().levels = (0..retiler.level_count())
+            .map(|level| LevelTiles::new(size.ceil_div(1 << level)))
+            .collect();
+        Ok(PyramidTiffEncoder {
+            retiler,
+            tile_saver,
+        })
+    }
+}
+
+impl Encoder for PyramidTiffEncoder {
+    fn add_tile(&mut self, tile: Tile) -> io::Result<()> {
+        {
+            let mut state = self.tile_saver.state.lock().unwrap();
+            if state.icc_profile.is_none() && tile.icc_profile.is_some() {
+                state.icc_profile = tile.icc_profile.clone();
+            }
+            if state.exif_metadata.is_none() && tile.exif_metadata.is_some() {
+                state.exif_metadata = tile.exif_metadata.clone();
+            }
+        }
+        self.retiler.add_tile(&tile)
+    }
+
+    fn finalize(&mut self) -> io::Result<()> {
+        self.retiler.finalize();
+        let blank_tile = self
+            .tile_saver
+            .encode(&RgbImage::new(TIFF_TILE_SIZE, TIFF_TILE_SIZE))?;
+        let mut state = self.tile_saver.state.lock().unwrap();
+        let PyramidState {
+            writer,
+            levels,
+            icc_profile,
+            exif_metadata,
+            xmp_metadata,
+        } = &mut *state;
+        let mut writer = writer.take().expect("Tried to finalize an image twice");
+
+        // Tiles that were never downloaded all share the same blank data
+        if levels.iter().any(LevelTiles::has_missing_tiles) {
+            let offset = writer.write_data(&blank_tile)?;
+            for level in levels.iter_mut() {
+                level.fill_missing_tiles(offset, blank_tile.len() as u64);
+            }
+        }
+        let mut sub_ifds = vec![];
+        for level in &levels[1..] {
+            let mut entries = level.ifd_entries();
+            entries.push(IfdEntry::long(TAG_NEW_SUBFILE_TYPE, SUBFILE_REDUCED_IMAGE));
+            sub_ifds.push(writer.write_ifd(entries)?);
+        }
+        debug!("Wrote {} reduced-resolution levels", sub_ifds.len());
+
+        let mut entries = levels[0].ifd_entries();
+        if !sub_ifds.is_empty() {
+            entries.push(IfdEntry::ifd8(TAG_SUB_IFDS, &sub_ifds));
+        }
+        if let Some(profile) = icc_profile {
+            entries.push(IfdEntry::undefined(TAG_ICC_PROFILE, profile.clone()));
+        }
+        if let Some(xmp) = xmp_metadata {
+            entries.push(IfdEntry::byte(TAG_XMP, xmp.clone().into_bytes()));
+        }
+        if let Some(exif) = exif_metadata.as_deref().and_then(ExifFields::parse) {
+            entries.extend(exif.write(&mut writer)?);
+        }
+        writer.append_ifd(entries)?;
+        writer.into_inner()?;
+        Ok(())
+    }
+
+    fn size(&self) -> Vec2d {
+        self.retiler.size()
+    }
+}
+
+struct PyramidState {
+    writer: Option<BigTiffWriter<BufWriter<File>>>,
+    /// The tiles of each level, starting with the full-resolution image
+    levels: Vec<LevelTiles>,
+    icc_profile: Option<Vec<u8>>,
+    exif_metadata: Option<Vec<u8>>,
+    xmp_metadata: Option<String>,
+}
+
+struct LevelTiles {
+    size: Vec2d,
+    /// Offsets and byte counts of the written tiles, in row-major order
+    offsets: Vec<u64>,
+    byte_counts: Vec<u64>,
+}
+
+impl LevelTiles {
+    fn new(size: Vec2d) -> Self {
+        let count = size.ceil_div(Vec2d::square(TIFF_TILE_SIZE)).area() as usize;
+        LevelTiles {
+            size,
+            offsets: vec![0; count],
+            byte_counts: vec![0; count],
+        }
+    }
+
+    fn has_missing_tiles(&self) -> bool {
+        self.byte_counts.contains(&0)
+    }
+
+    fn fill_missing_tiles(&mut self, offset: u64, byte_count: u64) {
+        for (o, c) in self.offsets.iter_mut().zip(self.byte_counts.iter_mut()) {
+            if *c == 0 {
+                *o = offset;
+                *c = byte_count;
+            }
+        }
+    }
+
+    fn ifd_entries(&self) -> Vec<IfdEntry> {
+        vec![
+            IfdEntry::long(TAG_IMAGE_WIDTH, self.size.x),
+            IfdEntry::long(TAG_IMAGE_LENGTH, self.size.y),
+            IfdEntry::short(TAG_BITS_PER_SAMPLE, &[8, 8, 8]),
+            IfdEntry::short(TAG_COMPRESSION, &[COMPRESSION_JPEG]),
+            // The JPEG encoder stores the colors as YCbCr, without chroma subsampling
+            IfdEntry::short(TAG_PHOTOMETRIC_INTERPRETATION, &[PHOTOMETRIC_YCBCR]),
+            IfdEntry::short(TAG_YCBCR_SUBSAMPLING, &[1, 1]),
+            IfdEntry::short(TAG_SAMPLES_PER_PIXEL, &[3]),
+            IfdEntry::short(TAG_PLANAR_CONFIGURATION, &[1]),
+            IfdEntry::long(TAG_TILE_WIDTH, TIFF_TILE_SIZE),
+            IfdEntry::long(TAG_TILE_LENGTH, TIFF_TILE_SIZE),
+            IfdEntry::long8(TAG_TILE_OFFSETS, &self.offsets),
+            IfdEntry::long8(TAG_TILE_BYTE_COUNTS, &self.byte_counts),
+        ]
+    }
+}
+
+struct PyramidTileSaver {
+    quality: u8,
+    state: Mutex<PyramidState>,
+}
+
+impl PyramidTileSaver {
+    /// Encodes a tile to JPEG. Tiff tiles always have the same size, even at the edges of the image.
+    fn encode(&self, image: &RgbImage) -> io::Result<Vec<u8>> {
+        let mut padded = RgbImage::new(TIFF_TILE_SIZE, TIFF_TILE_SIZE);
+        padded
+            .copy_from(image, 0, 0)
+            .map_err(image_error_to_io_error)?;
+        let mut data = Vec::new();
+        JpegEncoder::new_with_quality(&mut data, self.quality)
+            .write_image(
+                padded.as_raw(),
+                TIFF_TILE_SIZE,
+                TIFF_TILE_SIZE,
+                ExtendedColorType::Rgb8,
+            )
+            .map_err(image_error_to_io_error)?;
+        Ok(data)
+    }
+}
+
+impl TileSaver for PyramidTileSaver {
+    fn save_tile(&self, _size: Vec2d, scale_factor: u32, tile: Tile) -> io::Result<()> {
+        let data = self.encode(&tile.image.to_rgb8())?;
+        let mut state = self.state.lock().unwrap();
+        let PyramidState { writer, levels, .. } = &mut *state;
+        let level = &mut levels[scale_factor.trailing_zeros() as usize];
+        let position = tile.position / (TIFF_TILE_SIZE * scale_factor);
+        let index = (position.y * level.size.x.div_ceil(TIFF_TILE_SIZE) + position.x) as usize;
+        let writer = writer
+            .as_mut()
+            .expect("tried to add a tile in a finalized image");
+        level.offsets[index] = writer.write_data(&data)?;
+        level.byte_counts[index] = data.len() as u64;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::env::temp_dir;
+
+    use image::{DynamicImage, GenericImageView};
+
+    use super::*;
+
+    /// Reads the numeric fields of an IFD of a little-endian BigTIFF file
+    fn read_ifd(bytes: &[u8], offset: u64) -> HashMap<u16, Vec<u64>> {
+        let read = |o: usize, n: usize| {
+            let mut buf = [0u8; 8];
+            buf[..n].copy_from_slice(&bytes[o..o + n]);
+            u64::from_le_bytes(buf)
+        };
+        let offset = offset as usize;
+        (0..read(offset, 8) as usize)
+            .map(|i| {
+                let entry = offset + 8 + 20 * i;
+                let (tag, field_type) = (read(entry, 2) as u16, read(entry + 2, 2));
+                let count = read(entry + 4, 8) as usize;
+                let size = match field_type {
+                    3 => 2,
+                    4 => 4,
+                    16 | 18 => 8,
+                    _ => 1,
+                };
+                let start = if count * size <= 8 {
+                    entry + 12
+                } else {
+                    read(entry + 12, 8) as usize
+                };
+                let values = (0..count).map(|j| read(start + j * size, size)).collect();
+                (tag, values)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_pyramid_tiff() {
+        let destination = temp_dir().join("dezoomify-rs-pyramid-test.ptif");
+        let size = Vec2d { x: 600, y: 300 };
+        let mut encoder = PyramidTiffEncoder::new(destination.clone(), size, 90, None).unwrap();
+        let image = RgbImage::from_pixel(600, 300, image::Rgb([255, 255, 255]));
+        encoder
+            .add_tile(
+                Tile::builder()
+                    .at_position(Vec2d::default())
+                    .with_image(DynamicImage::ImageRgb8(image))
+                    .build(),
+            )
+            .unwrap();
+        encoder.finalize().unwrap();
+
+        let bytes = std::fs::read(&destination).unwrap();
+        assert_eq!(&bytes[..4], b"II\x2b\x00");
+        let main = read_ifd(&bytes, u64::from_le_bytes(bytes[8..16].try_into().unwrap()));
+        assert_eq!(main[&TAG_IMAGE_WIDTH], vec![600]);
+        assert_eq!(main[&TAG_COMPRESSION], vec![u64::from(COMPRESSION_JPEG)]);
+        assert_eq!(main[&TAG_TILE_OFFSETS].len(), 3 * 2);
+        let levels: Vec<_> = main[&TAG_SUB_IFDS]
+            .iter()
+            .map(|&offset| read_ifd(&bytes, offset))
+            .collect();
+        let level_sizes: Vec<_> = levels
+            .iter()
+            .map(|l| (l[&TAG_IMAGE_WIDTH][0], l[&TAG_IMAGE_LENGTH][0]))
+            .collect();
+        assert_eq!(level_sizes, vec![(300, 150), (150, 75)]);
+        assert!(levels.iter().all(|l| l[&TAG_NEW_SUBFILE_TYPE] == vec![1]));
+
+        // Each tile is a complete JPEG image
+        let smallest = &levels[1];
+        let offset = smallest[&TAG_TILE_OFFSETS][0] as usize;
+        let length = smallest[&TAG_TILE_BYTE_COUNTS][0] as usize;
+        let tile = image::load_from_memory(&bytes[offset..offset + length]).unwrap();
+        assert_eq!(tile.dimensions(), (TIFF_TILE_SIZE, TIFF_TILE_SIZE));
+        assert!(tile.get_pixel(10, 10).0[0] > 250);
+        assert!(tile.get_pixel(200, 200).0[0] < 5);
+    }
+}
@@ -15,7 +15,9 @@ use crate::errors::image_error_to_io_error;
 use crate::{Tile, max_size_in_rect};
 
 pub trait TileSaver {
-    fn save_tile(&self, size: Vec2d, tile: Tile) -> io::Result<()>;
+    /// Save a finished target tile. `size` is the area it covers in the original image,
+    /// and `scale_factor` is the zoom level it belongs to.
+    fn save_tile(&self, size: Vec2d, scale_factor: u32, tile: Tile) -> io::Result<()>;
 }
 
 /**
@@ -28,6 +30,7 @@ The retiler receives tiles from the original image.
 The received tiles can have any size, so they can cover partially or entirely any number of tiles
 in the target image.
 It computes the list of tiles covered by the current image,
+(target tiles can overlap their neighbours by a fixed number of pixels, as in Deep Zoom images)
 and pastes the correct resized and cropped source tile into temporary target tiles in the user's temporary folder.
 
 When a target tile has been entirely covered by source tiles,
@@ -38,6 +41,8 @@ Every level passes the source tile to it's child when it is done with it.
 pub struct Retiler<T: TileSaver> {
     original_size: Vec2d,
     pub tile_size: Vec2d,
+    /// Number of pixels each target tile shares with each of its neighbours, in the scaled image
+    overlap: u32,
     scale_factor: u32,
     next_level: Option<Box<Retiler<T>>>,
     /// This hash map contains target tiles that are being written.
@@ -51,17 +56,24 @@ struct TmpTile {
 }
 
 impl<T: TileSaver> Retiler<T> {
-    pub fn new(size: Vec2d, tile_size: Vec2d, tile_saver: Arc<T>, scale_factor: u32) -> Retiler<T> {
+    pub fn new(
+        size: Vec2d,
+        tile_size: Vec2d,
+        overlap: u32,
+        tile_saver: Arc<T>,
+        scale_factor: u32,
+    ) -> Retiler<T> {
         let next_level = if (size / scale_factor).fits_inside(tile_size) {
             None
         } else {
             let tile_saver = Arc::clone(&tile_saver);
-            let level = Retiler::new(size, tile_size, tile_saver, scale_factor * 2);
+            let level = Retiler::new(size, tile_size, overlap, tile_saver, scale_factor * 2);
             Some(Box::new(level))
         };
         Retiler {
             original_size: size,
             tile_size: tile_size * scale_factor,
+            overlap,
             next_level,
             tiles: HashMap::new(),
             tile_saver,
@@ -74,8 +86,11 @@ impl<T: TileSaver> Retiler<T> {
     }
 
     fn tile_positions(&self, position: Vec2d, size: Vec2d) -> impl Iterator<Item = Vec2d> + use<T> {
-        let top_left = (position / self.tile_size) * self.tile_size;
-        let bottom_right = ((position + size).ceil_div(self.tile_size)) * self.tile_size;
+        // Source pixels can also belong to the overlapping borders of the neighbouring tiles
+        let margin = Vec2d::square(self.overlap * self.scale_factor);
+        let top_left = ((position - margin) / self.tile_size) * self.tile_size;
+        let bottom_right = ((position + size + margin).ceil_div(self.tile_size)) * self.tile_size;
+        let bottom_right = bottom_right.min(self.original_size);
         let dy = self.tile_size.y as usize;
         let dx = self.tile_size.x as usize;
         (top_left.y..bottom_right.y).step_by(dy).flat_map(move |y| {
@@ -85,6 +100,17 @@ impl<T: TileSaver> Retiler<T> {
         })
     }
 
+    /// The position and size, in the scaled image, of the target tile at the given position
+    fn scaled_region(&self, position: Vec2d) -> (Vec2d, Vec2d) {
+        let level_size = self.original_size.ceil_div(self.scale_factor);
+        let overlap = Vec2d::square(self.overlap);
+        let scaled_position = position / self.scale_factor;
+        let top_left = scaled_position - overlap;
+        let bottom_right =
+            (scaled_position + self.tile_size / self.scale_factor + overlap).min(level_size);
+        (top_left, bottom_right - top_left)
+    }
+
     pub fn add_tile(&mut self, tile: &Tile) -> io::Result<()> {
         let tile_size = self.tile_size;
         let scale_factor = self.scale_factor;
@@ -110,19 +136,19 @@ impl<T: TileSaver> Retiler<T> {
         let scaled_tile = scaled_tile.as_ref().unwrap_or(tile);
         for cur_pos in covered_tiles_positions {
             let cur_tile_size = max_size_in_rect(cur_pos, tile_size, self.original_size);
-            let scaled_tile_size = cur_tile_size.ceil_div(scale_factor);
+            let (scaled_position, scaled_size) = self.scaled_region(cur_pos);
 
             let tmp_tile_entry = self.tiles.entry(cur_pos).or_insert_with(|| {
                 debug!(
                     "Creating a new partial tile at scale factor {scale_factor} position {cur_pos} size {cur_tile_size}"
                 );
-                Some(TmpTile::new(scaled_tile_size))
+                Some(TmpTile::new(scaled_size))
             });
             if let Some(tmp_tile) = tmp_tile_entry {
                 let finished = tmp_tile.add_tile(
                     cur_pos,
-                    cur_tile_size,
-                    self.original_size,
+                    scaled_position,
+                    scaled_size,
                     scale_factor,
                     scaled_tile,
                 )?;
@@ -180,6 +206,7 @@ impl<T: TileSaver> Retiler<T> {
     pub fn tile_save(&self, position: Vec2d, size: Vec2d, image: DynamicImage) -> io::Result<()> {
         self.tile_saver.save_tile(
             size,
+            self.scale_factor,
             Tile::builder()
                 .with_image(image)
                 .at_position(position)
@@ -219,19 +246,14 @@ impl TmpTile {
     fn add_tile(
         &mut self,
         self_position: Vec2d,
-        self_size: Vec2d,
-        level_size: Vec2d,
+        scaled_self_position: Vec2d,
+        scaled_size: Vec2d,
         scale_factor: u32,
         tile: &Tile,
     ) -> io::Result<Option<DynamicImage>> {
-        let scaled_self_position = self_position / scale_factor;
         let top_left = tile.position() - scaled_self_position;
-        let scaled_level_size = level_size.ceil_div(scale_factor);
-        let self_bottom_right = (self_position + self_size)
-            .ceil_div(scale_factor)
-            .min(scaled_level_size);
+        let self_bottom_right = scaled_self_position + scaled_size;
         let bottom_right = tile.bottom_right().min(self_bottom_right) - scaled_self_position;
-        let scaled_size = self_size.ceil_div(scale_factor);
 
         let tmp_tile_path = Self::path(self_position, scale_factor);
         debug!(
@@ -252,13 +274,15 @@ impl TmpTile {
         if self.missing_pixels() == 0 {
             // The tile has been fully covered by pixels
             debug!(
-                "Removing completed tile of level {} at position {}: {:?}",
-                level_size, self_position, &tmp_tile_path
+                "Removing completed tile of scale factor {} at position {}: {:?}",
+                scale_factor, self_position, &tmp_tile_path
             );
             let _ = std::fs::remove_file(&tmp_tile_path);
             Ok(Some(tile_img))
         } else {
-            debug!("Writing partly-filled tile of level {level_size} at position {self_position}");
+            debug!(
+                "Writing partly-filled tile of scale factor {scale_factor} at position {self_position}"
+            );
             tile_img
                 .save(&tmp_tile_path)
                 .map_err(image_error_to_io_error)?;
@@ -316,7 +340,7 @@ mod tests {
     }
 
     impl TileSaver for TestTileSaver {
-        fn save_tile(&self, size: Vec2d, tile: Tile) -> io::Result<()> {
+        fn save_tile(&self, size: Vec2d, _scale_factor: u32, tile: Tile) -> io::Result<()> {
             self.added.lock().unwrap().push((size, tile));
             Ok(())
         }
@@ -335,7 +359,7 @@ mod tests {
         let tile_size = Vec2d { x: 2, y: 2 };
 
         let tile_saver = Arc::new(TestTileSaver::default());
-        let mut retiler = Retiler::new(image_size, tile_size, Arc::clone(&tile_saver), 1);
+        let mut retiler = Retiler::new(image_size, tile_size, 0, Arc::clone(&tile_saver), 1);
         retiler
             .add_tile(
                 &Tile::builder()
@@ -402,4 +426,35 @@ mod tests {
             ]
         );
     }
+
+    #[test]
+    fn test_retiler_overlap() {
+        init();
+        let image_size = Vec2d { x: 4, y: 1 };
+        let tile_saver = Arc::new(TestTileSaver::default());
+        let mut retiler = Retiler::new(image_size, Vec2d::square(2), 1, Arc::clone(&tile_saver), 1);
+        let image = ImageBuffer::from_raw(4, 1, vec![10, 20, 30, 40]).unwrap();
+        retiler
+            .add_tile(
+                &Tile::builder()
+                    .with_image(DynamicImage::ImageLuma8(image))
+                    .at_position(Vec2d::default())
+                    .build(),
+            )
+            .unwrap();
+        retiler.finalize();
+        let added = tile_saver.get_added();
+        // Each tile contains one column of its neighbour
+        let pixels: Vec<_> = added[..2]
+            .iter()
+            .map(|(_, tile)| (tile.position, tile.image.to_luma8().into_raw()))
+            .collect();
+        assert_eq!(
+            pixels,
+            vec![
+                (Vec2d { x: 0, y: 0 }, vec![10, 20, 30]),
+                (Vec2d { x: 2, y: 0 }, vec![20, 30, 40]),
+            ]
+        );
+    }
 }
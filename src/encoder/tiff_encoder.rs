@@ -0,0 +1,301 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+
+use fixedbitset::FixedBitSet;
+use flate2::write::ZlibEncoder;
+use image::RgbImage;
+use log::debug;
+
+use crate::tile::Tile;
+use crate::{Vec2d, ZoomError};
+
+use super::Encoder;
+use super::bigtiff::*;
+
+/// Width and height of the tiles in the output file
+pub const TIFF_TILE_SIZE: u32 = 256;
+
+/// Writes tiles to a tiled BigTIFF file as they arrive.
+/// The tiles of the output file do not match the downloaded tiles, so only the output tiles
+/// that are partially covered by the downloaded tiles are kept in memory.
+pub struct TiffEncoder {
+    writer: Option<BigTiffWriter<BufWriter<File>>>,
+    size: Vec2d,
+    compression: Option<flate2::Compression>,
+    partial_tiles: HashMap<Vec2d, PartialTile>,
+    /// Offsets and byte counts of the written tiles, in row-major order
+    tile_offsets: Vec<u64>,
+    tile_byte_counts: Vec<u64>,
+    icc_profile: Option<Vec<u8>>,
+    exif_metadata: Option<Vec<u8>>,
+    xmp_metadata: Option<String>,
+}
+
+struct PartialTile {
+    image: RgbImage,
+    covered: FixedBitSet,
+    /// Number of pixels of the tile that are inside the image but not yet covered
+    remaining: usize,
+}
+
+impl TiffEncoder {
+    pub fn new(
+        destination: PathBuf,
+        size: Vec2d,
+        compression: u8,
+        xmp_metadata: Option<String>,
+    ) -> Result<Self, ZoomError> {
+        let file = BufWriter::new(File::create(destination)?);
+        let tile_count = size.ceil_div(Vec2d::square(TIFF_TILE_SIZE)).area() as usize;
+        let compression = (compression > 0).then(|| {
+            flate2::Compression::new((u32::from(compression) * 9).div_ceil(100).clamp(1, 9))
+        });
+        Ok(TiffEncoder {
+            writer: Some(BigTiffWriter::new(file)?),
+            size,
+            compression,
+            partial_tiles: HashMap::new(),
+            tile_offsets: vec![0; tile_count],
+            tile_byte_counts: vec![0; tile_count],
+            icc_profile: None,
+            exif_metadata: None,
+            xmp_metadata,
+        })
+    }
+
+    fn tiles_across(&self) -> u32 {
+        self.size.x.div_ceil(TIFF_TILE_SIZE)
+    }
+
+    fn tile_index(&self, tile_pos: Vec2d) -> usize {
+        (tile_pos.y * self.tiles_across() + tile_pos.x) as usize
+    }
+
+    fn is_written(&self, tile_pos: Vec2d) -> bool {
+        self.tile_byte_counts[self.tile_index(tile_pos)] != 0
+    }
+
+    fn new_partial_tile(&self, tile_pos: Vec2d) -> PartialTile {
+        let visible = self
+            .size
+            .min(tile_pos * TIFF_TILE_SIZE + Vec2d::square(TIFF_TILE_SIZE))
+            - tile_pos * TIFF_TILE_SIZE;
+        PartialTile {
+            image: RgbImage::new(TIFF_TILE_SIZE, TIFF_TILE_SIZE),
+            covered: FixedBitSet::with_capacity((TIFF_TILE_SIZE * TIFF_TILE_SIZE) as usize),
+            remaining: visible.area() as usize,
+        }
+    }
+
+    fn write_tile(&mut self, tile_pos: Vec2d, image: &RgbImage) -> io::Result<()> {
+        let data = match self.compression {
+            Some(level) => {
+                let mut encoder = ZlibEncoder::new(Vec::new(), level);
+                encoder.write_all(image.as_raw())?;
+                encoder.finish()?
+            }
+            None => image.as_raw().clone(),
+        };
+        let writer = self
+            .writer
+            .as_mut()
+            .expect("tried to add a tile in a finalized image");
+        let offset = writer.write_data(&data)?;
+        let index = self.tile_index(tile_pos);
+        self.tile_offsets[index] = offset;
+        self.tile_byte_counts[index] = data.len() as u64;
+        Ok(())
+    }
+
+    fn ifd_entries(&self) -> Vec<IfdEntry> {
+        let compression = if self.compression.is_some() {
+            COMPRESSION_DEFLATE
+        } else {
+            COMPRESSION_NONE
+        };
+        let mut entries = vec![
+            IfdEntry::long(TAG_IMAGE_WIDTH, self.size.x),
+            IfdEntry::long(TAG_IMAGE_LENGTH, self.size.y),
+            IfdEntry::short(TAG_BITS_PER_SAMPLE, &[8, 8, 8]),
+            IfdEntry::short(TAG_COMPRESSION, &[compression]),
+            IfdEntry::short(TAG_PHOTOMETRIC_INTERPRETATION, &[PHOTOMETRIC_RGB]),
+            IfdEntry::short(TAG_SAMPLES_PER_PIXEL, &[3]),
+            IfdEntry::short(TAG_PLANAR_CONFIGURATION, &[1]),
+            IfdEntry::long(TAG_TILE_WIDTH, TIFF_TILE_SIZE),
+            IfdEntry::long(TAG_TILE_LENGTH, TIFF_TILE_SIZE),
+            IfdEntry::long8(TAG_TILE_OFFSETS, &self.tile_offsets),
+            IfdEntry::long8(TAG_TILE_BYTE_COUNTS, &self.tile_byte_counts),
+        ];
+        if let Some(profile) = &self.icc_profile {
+            entries.push(IfdEntry::undefined(TAG_ICC_PROFILE, profile.clone()));
+        }
+        if let Some(xmp) = &self.xmp_metadata {
+            entries.push(IfdEntry::byte(TAG_XMP, xmp.clone().into_bytes()));
+        }
+        entries
+    }
+}
+
+impl Encoder for TiffEncoder {
+    fn add_tile(&mut self, tile: Tile) -> io::Result<()> {
+        if self.icc_profile.is_none() && tile.icc_profile.is_some() {
+            self.icc_profile = tile.icc_profile.clone();
+        }
+        if self.exif_metadata.is_none() && tile.exif_metadata.is_some() {
+            self.exif_metadata = tile.exif_metadata.clone();
+        }
+        let min_pos = tile.position();
+        let max_pos = tile.bottom_right().min(self.size);
+        if !min_pos.fits_inside(self.size) || max_pos == min_pos {
+            return Ok(());
+        }
+        let image = tile.image.to_rgb8();
+        let first_tile = min_pos / TIFF_TILE_SIZE;
+        let last_tile = (max_pos - Vec2d::square(1)) / TIFF_TILE_SIZE;
+        for tile_y in first_tile.y..=last_tile.y {
+            for tile_x in first_tile.x..=last_tile.x {
+                let tile_pos = Vec2d {
+                    x: tile_x,
+                    y: tile_y,
+                };
+                if self.is_written(tile_pos) {
+                    continue;
+                }
+                let mut partial = self
+                    .partial_tiles
+                    .remove(&tile_pos)
+                    .unwrap_or_else(|| self.new_partial_tile(tile_pos));
+                let tile_origin = tile_pos * TIFF_TILE_SIZE;
+                let start = min_pos.max(tile_origin);
+                let end = max_pos.min(tile_origin + Vec2d::square(TIFF_TILE_SIZE));
+                for y in start.y..end.y {
+                    for x in start.x..end.x {
+                        let local = Vec2d { x, y } - tile_origin;
+                        let bit = (local.y * TIFF_TILE_SIZE + local.x) as usize;
+                        if !partial.covered.put(bit) {
+                            partial.remaining -= 1;
+                        }
+                        let pixel = *image.get_pixel(x - min_pos.x, y - min_pos.y);
+                        partial.image.put_pixel(local.x, local.y, pixel);
+                    }
+                }
+                if partial.remaining == 0 {
+                    self.write_tile(tile_pos, &partial.image)?;
+                } else {
+                    self.partial_tiles.insert(tile_pos, partial);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn finalize(&mut self) -> io::Result<()> {
+        let mut partial_tiles: Vec<_> = self.partial_tiles.drain().collect();
+        partial_tiles.sort_by_key(|(pos, _)| (pos.y, pos.x));
+        debug!(
+            "Writing {} incomplete tiles to the tiff file",
+            partial_tiles.len()
+        );
+        for (tile_pos, partial) in partial_tiles {
+            self.write_tile(tile_pos, &partial.image)?;
+        }
+        // Tiles that were never downloaded all share the same blank data
+        let across = self.tiles_across();
+        let missing: Vec<Vec2d> = (0..self.tile_offsets.len() as u32)
+            .map(|i| Vec2d {
+                x: i % across,
+                y: i / across,
+            })
+            .filter(|&pos| !self.is_written(pos))
+            .collect();
+        if let Some(&first) = missing.first() {
+            self.write_tile(first, &RgbImage::new(TIFF_TILE_SIZE, TIFF_TILE_SIZE))?;
+            let first_index = self.tile_index(first);
+            for pos in missing {
+                let index = self.tile_index(pos);
+                self.tile_offsets[index] = self.tile_offsets[first_index];
+                self.tile_byte_counts[index] = self.tile_byte_counts[first_index];
+            }
+        }
+        let mut entries = self.ifd_entries();
+        let mut writer = self
+            .writer
+            .take()
+            .expect("Tried to finalize an image twice");
+        if let Some(exif) = self.exif_metadata.as_deref().and_then(ExifFields::parse) {
+            entries.extend(exif.write(&mut writer)?);
+        }
+        writer.append_ifd(entries)?;
+        writer.into_inner()?;
+        Ok(())
+    }
+
+    fn size(&self) -> Vec2d {
+        self.size
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env::temp_dir;
+
+    use image::{DynamicImage, GenericImageView, ImageDecoder, Rgb};
+
+    use super::*;
+
+    fn add_tile(encoder: &mut TiffEncoder, x: u32, y: u32, size: Vec2d, color: [u8; 3]) {
+        let image = RgbImage::from_pixel(size.x, size.y, Rgb(color));
+        encoder
+            .add_tile(
+                Tile::builder()
+                    .at_position(Vec2d { x, y })
+                    .with_image(DynamicImage::ImageRgb8(image))
+                    .build(),
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn test_tiff_create() {
+        let destination = temp_dir().join("dezoomify-rs-tiff-test.tif");
+        let size = Vec2d { x: 600, y: 300 };
+        let mut encoder = TiffEncoder::new(destination.clone(), size, 5, None).unwrap();
+        // Downloaded tiles that do not match the tiff tiles, the last one overflowing the image
+        add_tile(&mut encoder, 0, 0, Vec2d { x: 300, y: 300 }, [255, 0, 0]);
+        add_tile(&mut encoder, 300, 0, Vec2d { x: 310, y: 200 }, [0, 0, 255]);
+        // The first tiff tile is complete, so it is not kept in memory
+        assert!(encoder.is_written(Vec2d { x: 0, y: 0 }));
+        assert!(!encoder.is_written(Vec2d { x: 1, y: 0 }));
+        encoder.finalize().unwrap();
+
+        let image = image::open(&destination).unwrap();
+        assert_eq!(image.dimensions(), (600, 300));
+        assert_eq!(image.get_pixel(299, 299).0, [255, 0, 0, 255]);
+        assert_eq!(image.get_pixel(599, 0).0, [0, 0, 255, 255]);
+        assert_eq!(image.get_pixel(400, 250).0, [0, 0, 0, 255]);
+    }
+
+    #[test]
+    fn test_tiff_metadata() {
+        let destination = temp_dir().join("dezoomify-rs-tiff-icc-test.tiff");
+        let mut encoder =
+            TiffEncoder::new(destination.clone(), Vec2d::square(10), 0, None).unwrap();
+        let icc_profile = vec![0x61, 0x64, 0x73, 0x70, 0x00, 0x00, 0x02, 0x0C, 0x6D];
+        encoder
+            .add_tile(
+                Tile::builder()
+                    .at_position(Vec2d::default())
+                    .with_image(DynamicImage::ImageRgb8(RgbImage::new(10, 10)))
+                    .with_icc_profile(icc_profile.clone())
+                    .build(),
+            )
+            .unwrap();
+        encoder.finalize().unwrap();
+
+        let file = std::io::BufReader::new(File::open(&destination).unwrap());
+        let mut decoder = image::codecs::tiff::TiffDecoder::new(file).unwrap();
+        assert_eq!(decoder.icc_profile().unwrap(), Some(icc_profile));
+    }
+}
@@ -6,7 +6,7 @@ Used to receive tiles asynchronously and provide them to the encoder
 use log::debug;
 use tokio::sync::mpsc;
 
-use crate::encoder::{Encoder, encoder_for_name};
+use crate::encoder::{Encoder, EncoderOptions, encoder_for_name};
 use crate::tile::Tile;
 use crate::{Vec2d, ZoomError};
 use log::warn;
@@ -16,7 +16,7 @@ pub enum TileBuffer {
     Buffering {
         destination: PathBuf,
         buffer: Vec<Tile>,
-        compression: u8,
+        options: Box<EncoderOptions>,
     },
     Writing {
         destination: PathBuf,
@@ -29,11 +29,11 @@ impl TileBuffer {
     /// Create an encoder for an image of the given size at the path
     /// Errors out if the encoder cannot create files with the given extension
     /// or at the given size
-    pub async fn new(destination: PathBuf, compression: u8) -> Result<Self, ZoomError> {
+    pub async fn new(destination: PathBuf, options: EncoderOptions) -> Result<Self, ZoomError> {
         Ok(TileBuffer::Buffering {
             destination,
             buffer: vec![],
-            compression,
+            options: Box::new(options),
         })
     }
 
@@ -42,11 +42,11 @@ impl TileBuffer {
             TileBuffer::Buffering {
                 buffer,
                 destination,
-                compression,
+                options,
             } => {
                 let destination = std::mem::take(destination);
                 debug!("Creating a tile writer for an image of size {size}");
-                let mut encoder = encoder_for_name(destination.clone(), size, *compression)?;
+                let mut encoder = encoder_for_name(destination.clone(), size, options)?;
                 debug!("Adding buffered tiles: {buffer:?}");
                 for tile in buffer.drain(..) {
                     encoder.add_tile(tile)?;
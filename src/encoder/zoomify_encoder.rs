@@ -0,0 +1,201 @@
+use std::fs::File;
+use std::io;
+use std::io::BufWriter;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use image::codecs::jpeg::JpegEncoder;
+use log::debug;
+
+use crate::encoder::retiler::{Retiler, TileSaver};
+use crate::errors::image_error_to_io_error;
+use crate::tile::Tile;
+use crate::zoomify::image_properties::{ImageProperties, ZoomLevelInfo};
+use crate::{Vec2d, ZoomError};
+
+use super::Encoder;
+
+/// Width and height of the tiles of zoomify images
+pub const ZOOMIFY_TILE_SIZE: u32 = 256;
+
+/// Writes a zoomify image: a folder containing an `ImageProperties.xml` file,
+/// and the tiles of all the zoom levels, in `TileGroupN` folders of 256 tiles each.
+pub struct ZoomifyEncoder {
+    retiler: Retiler<ZoomifyTileSaver>,
+    root_path: PathBuf,
+    properties: ImageProperties,
+}
+
+impl ZoomifyEncoder {
+    pub fn new(
+        destination: PathBuf,
+        size: Vec2d,
+        tile_size: u32,
+        quality: u8,
+    ) -> Result<Self, ZoomError> {
+        let _ = std::fs::remove_file(&destination);
+        debug!("Creating zoomify directory at {destination:?}");
+        std::fs::create_dir_all(&destination)?;
+        let properties = ImageProperties::new(size, tile_size);
+        let tile_saver = ZoomifyTileSaver {
+            root_path: destination.clone(),
+            levels: properties.levels(),
+            quality,
+        };
+        let tile_size = Vec2d::square(tile_size);
+        Ok(ZoomifyEncoder {
+            retiler: Retiler::new(size, tile_size, 0, Arc::new(tile_saver), 1),
+            root_path: destination,
+            properties,
+        })
+    }
+}
+
+impl Encoder for ZoomifyEncoder {
+    fn add_tile(&mut self, tile: Tile) -> io::Result<()> {
+        self.retiler.add_tile(&tile)
+    }
+
+    fn finalize(&mut self) -> io::Result<()> {
+        self.retiler.finalize();
+        let ImageProperties {
+            width,
+            height,
+            tile_size,
+            num_tiles,
+        } = self.properties;
+        let xml = format!(
+            "<IMAGE_PROPERTIES WIDTH=\"{width}\" HEIGHT=\"{height}\" NUMTILES=\"{num_tiles}\" \
+            NUMIMAGES=\"1\" VERSION=\"1.8\" TILESIZE=\"{tile_size}\" />"
+        );
+        let properties_path = self.root_path.join("ImageProperties.xml");
+        debug!("Writing zoomify metadata to {properties_path:?}");
+        std::fs::write(properties_path, xml)
+    }
+
+    fn size(&self) -> Vec2d {
+        self.retiler.size()
+    }
+}
+
+struct ZoomifyTileSaver {
+    root_path: PathBuf,
+    /// The zoom levels, as the zoomify dezoomer reads them, starting with the smallest one
+    levels: Vec<ZoomLevelInfo>,
+    quality: u8,
+}
+
+impl TileSaver for ZoomifyTileSaver {
+    fn save_tile(&self, _size: Vec2d, scale_factor: u32, tile: Tile) -> io::Result<()> {
+        let scale_level = scale_factor.trailing_zeros() as usize;
+        let Some(z) = (self.levels.len()).checked_sub(scale_level + 1) else {
+            debug!("Ignoring a tile at scale factor {scale_factor}, below the smallest level");
+            return Ok(());
+        };
+        let level = &self.levels[z];
+        let pos = tile.position / (level.tile_size * scale_factor);
+        if !(pos * level.tile_size).fits_inside(level.size - Vec2d::square(1)) {
+            debug!("Ignoring tile {pos} outside of zoomify level {z}");
+            return Ok(());
+        }
+        // The retiler rounds the sizes of the levels up, zoomify rounds them down
+        let tile_size =
+            level.size.min((pos + Vec2d::square(1)) * level.tile_size) - pos * level.tile_size;
+        let image = tile.image.crop_imm(0, 0, tile_size.x, tile_size.y);
+        let group_path = self
+            .root_path
+            .join(format!("TileGroup{}", level.tile_group(pos)));
+        std::fs::create_dir_all(&group_path)?;
+        let image_path = group_path.join(format!("{z}-{}-{}.jpg", pos.x, pos.y));
+        debug!("Writing tile to {image_path:?}");
+        let file = &mut BufWriter::new(File::create(&image_path)?);
+        let jpeg_writer = JpegEncoder::new_with_quality(file, self.quality);
+        image
+            .write_with_encoder(jpeg_writer)
+            .map_err(image_error_to_io_error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env::temp_dir;
+
+    use image::{DynamicImage, GenericImageView, RgbImage};
+
+    use crate::dezoomer::{Dezoomer, DezoomerInput, PageContents};
+    use crate::zoomify::ZoomifyDezoomer;
+
+    use super::*;
+
+    #[test]
+    fn test_zoomify_round_trip() {
+        let destination = temp_dir().join("dezoomify-rs-zoomify-test.zoomify");
+        let _ = std::fs::remove_dir_all(&destination);
+        // Small tiles, so that the image has more than one tile group,
+        // and odd sizes, so that the levels are rounded
+        let size = Vec2d { x: 70, y: 66 };
+        let mut encoder = ZoomifyEncoder::new(destination.clone(), size, 4, 95).unwrap();
+        let image = RgbImage::from_fn(size.x, size.y, |x, y| image::Rgb([(2 * x + y) as u8, 0, 0]));
+        encoder
+            .add_tile(
+                Tile::builder()
+                    .at_position(Vec2d::default())
+                    .with_image(DynamicImage::ImageRgb8(image))
+                    .build(),
+            )
+            .unwrap();
+        encoder.finalize().unwrap();
+
+        let properties_path = destination.join("ImageProperties.xml");
+        let input = DezoomerInput {
+            uri: properties_path.to_string_lossy().into(),
+            contents: PageContents::Success(std::fs::read(&properties_path).unwrap()),
+        };
+        let mut levels = ZoomifyDezoomer.zoom_levels(&input).unwrap();
+        let sizes: Vec<_> = levels.iter().map(|l| l.size_hint().unwrap()).collect();
+        assert_eq!(
+            sizes,
+            [(4, 4), (8, 8), (17, 16), (35, 33), (70, 66)].map(Vec2d::from)
+        );
+        let mut tile_count = 0;
+        for (level, scale) in levels.iter_mut().zip([16, 8, 4, 2, 1]) {
+            let level_size = level.size_hint().unwrap();
+            for tile in level.next_tiles(None) {
+                let image = image::open(&tile.url).unwrap();
+                let expected_size =
+                    level_size.min(tile.position + Vec2d::square(4)) - tile.position;
+                assert_eq!(
+                    Vec2d::from(image.dimensions()),
+                    expected_size,
+                    "{}",
+                    tile.url
+                );
+                for (x, y, pixel) in image.pixels() {
+                    // Each level is the source image resized to its size rounded up,
+                    // and cropped to the size rounded down
+                    let resized_size = size.ceil_div(scale);
+                    let center = |pos: u32, source_size: u32, resized_size: u32| {
+                        let ratio = f64::from(source_size) / f64::from(resized_size);
+                        (f64::from(pos) + 0.5) * ratio - 0.5
+                    };
+                    let cx = center(tile.position.x + x, size.x, resized_size.x);
+                    let cy = center(tile.position.y + y, size.y, resized_size.y);
+                    let expected = 2. * cx + cy;
+                    let actual = f64::from(pixel[0]);
+                    // Resizing blurs the image, and more so on the smaller levels
+                    assert!(
+                        (actual - expected).abs() <= 8. + f64::from(scale) / 2.,
+                        "pixel ({x}, {y}) of {} is {actual}, expected {expected}",
+                        tile.url
+                    );
+                }
+                tile_count += 1;
+            }
+        }
+        assert_eq!(tile_count, 18 * 17 + 9 * 9 + 5 * 4 + 2 * 2 + 1);
+        // The smaller levels take the first 119 tile numbers, so the first tile group
+        // ends with the 137th tile of the largest level
+        assert!(destination.join("TileGroup0/4-10-7.jpg").exists());
+        assert!(destination.join("TileGroup1/4-11-7.jpg").exists());
+    }
+}
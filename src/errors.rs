@@ -1,6 +1,7 @@
 use std::error::Error;
 use std::fmt;
 
+use crate::Vec2d;
 use crate::dezoomer::TileReference;
 use crate::encoder::tile_buffer::TileBufferMsg;
 use custom_error::custom_error;
@@ -34,6 +35,14 @@ custom_error! {
     BufferToImage{source: BufferToImageError} = "{source}",
     WriteError{source: SendError<TileBufferMsg>} = "Unable to write tile {source:?}",
     PngError{source: png::EncodingError} = "PNG encoding error: {source}",
+    ImageTooLarge{size: Vec2d, format: String, max_dimension: u32} =
+        "The image is {size} pixels, but {format} images cannot be wider or higher than \
+        {max_dimension} pixels. Choose another output format, such as png.",
+    CannotResume{path: String, reason: String} =
+        "Unable to resume the download of '{path}': {reason}",
+    InvalidRegion{reason: String} = "Invalid region: {reason}",
+    UnsupportedInBulkMode{option: String, reason: String} =
+        "The {option} option cannot be used with --bulk: {reason}",
 }
 
 custom_error! {
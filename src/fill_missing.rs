@@ -0,0 +1,137 @@
+//! Filling the holes left by the tiles that could not be downloaded
+//! with the same part of the image, taken from a lower zoom level and upscaled.
+
+use futures::stream::StreamExt;
+use image::imageops::{self, FilterType};
+use image::{DynamicImage, RgbaImage};
+use log::debug;
+
+use crate::arguments::Arguments;
+use crate::dezoomer::{TileFetchResult, TileReference, ZoomLevel, ZoomLevelIter};
+use crate::download_state::{DownloadState, create_tile_downloader};
+use crate::region::{Region, grid_cells};
+use crate::tile::Tile;
+use crate::{Vec2d, ZoomError, max_size_in_rect};
+
+/// The parts of the image that the tiles that could not be downloaded should have covered
+pub(crate) fn missing_regions(state: &DownloadState) -> Vec<Region> {
+    let (Some(image_size), Some(tile_size)) = (state.image_size, state.largest_tile_size) else {
+        return vec![];
+    };
+    state
+        .failed_tiles
+        .iter()
+        .map(|tile_ref| tile_ref.position)
+        .filter(|p| p.x < image_size.x && p.y < image_size.y)
+        .map(|position| Region {
+            position,
+            size: max_size_in_rect(position, tile_size, image_size),
+        })
+        .collect()
+}
+
+/// Creates tiles covering the given regions of an image of size `image_size`,
+/// from the tiles of a lower zoom level of the same image.
+/// Regions for which no tile of the lower level could be downloaded are `None`.
+pub(crate) async fn upscale_from_level(
+    mut level: ZoomLevel,
+    args: &Arguments,
+    regions: &[Region],
+    image_size: Vec2d,
+) -> Result<Vec<Option<Tile>>, ZoomError> {
+    let Some(level_size) = level.size_hint() else {
+        return Ok(vec![None; regions.len()]);
+    };
+    let sources: Vec<Region> = regions
+        .iter()
+        .map(|region| region.scale(image_size, level_size))
+        .collect();
+    let downloader = create_tile_downloader(&level, args)?;
+    let mut level_tiles = vec![];
+    let mut zoom_level_iter = ZoomLevelIter::new(&mut level);
+    while let Some(tile_refs) = zoom_level_iter.next_tile_references() {
+        let needed: Vec<TileReference> = grid_cells(tile_refs, level_size)
+            .filter(|(_, cell)| sources.iter().any(|source| source.intersects(cell)))
+            .map(|(tile_ref, _)| tile_ref)
+            .collect();
+        let count = needed.len() as u64;
+        let downloaded: Vec<Tile> = futures::stream::iter(needed)
+            .map(|tile_ref| downloader.download_tile(tile_ref))
+            .buffer_unordered(args.parallelism)
+            .filter_map(|result| async {
+                result
+                    .map_err(|e| debug!("Unable to download a tile of the lower level: {e}"))
+                    .ok()
+            })
+            .collect()
+            .await;
+        zoom_level_iter.set_fetch_result(TileFetchResult {
+            count,
+            successes: downloaded.len() as u64,
+            tile_size: downloaded.first().map(Tile::size),
+        });
+        level_tiles.extend(downloaded);
+    }
+    Ok(regions
+        .iter()
+        .zip(&sources)
+        .map(|(region, source)| upscale(*region, *source, &level_tiles))
+        .collect())
+}
+
+/// Assembles the `source` region from the tiles of the lower level,
+/// and resizes it to the size of the missing `region`
+fn upscale(region: Region, source: Region, tiles: &[Tile]) -> Option<Tile> {
+    let mut image = RgbaImage::new(source.size.x.max(1), source.size.y.max(1));
+    let mut covered = false;
+    for tile in tiles {
+        let tile_region = Region {
+            position: tile.position(),
+            size: tile.size(),
+        };
+        if tile_region.intersects(&source) {
+            let x = i64::from(tile.position.x) - i64::from(source.position.x);
+            let y = i64::from(tile.position.y) - i64::from(source.position.y);
+            imageops::replace(&mut image, &tile.image.to_rgba8(), x, y);
+            covered = true;
+        }
+    }
+    covered.then(|| {
+        let upscaled = imageops::resize(&image, region.size.x, region.size.y, FilterType::Triangle);
+        Tile::builder()
+            .with_image(DynamicImage::ImageRgba8(upscaled))
+            .at_position(region.position)
+            .build()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{GenericImageView, Rgba};
+
+    use super::*;
+
+    #[test]
+    fn test_upscale() {
+        let red = Rgba([255, 0, 0, 255]);
+        let tile = Tile::builder()
+            .with_image(DynamicImage::ImageRgba8(RgbaImage::from_pixel(10, 10, red)))
+            .at_position(Vec2d { x: 10, y: 0 })
+            .build();
+        let region = Region {
+            position: Vec2d { x: 20, y: 0 },
+            size: Vec2d { x: 20, y: 20 },
+        };
+        let source = region.scale(Vec2d::square(80), Vec2d::square(40));
+        let upscaled = upscale(region, source, std::slice::from_ref(&tile)).unwrap();
+        assert_eq!(upscaled.position(), region.position);
+        assert_eq!(upscaled.size(), region.size);
+        assert_eq!(upscaled.image.get_pixel(5, 5), red);
+
+        let elsewhere = Region {
+            position: Vec2d { x: 0, y: 0 },
+            size: Vec2d::square(10),
+        };
+        assert!(upscale(elsewhere, elsewhere, &[tile]).is_none());
+    }
+}
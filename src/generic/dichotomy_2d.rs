@@ -5,14 +5,14 @@ pub struct Dichotomy {
 }
 
 impl Dichotomy {
-    fn best_guess(&self) -> u32 {
+    pub fn best_guess(&self) -> u32 {
         if let Some(max) = self.max {
             (max + self.min) / 2
         } else {
             self.min * 3 + 1
         }
     }
-    fn next(&mut self, previous_success: bool) -> Option<u32> {
+    pub fn next(&mut self, previous_success: bool) -> Option<u32> {
         let last_guess = self.best_guess();
         if previous_success {
             self.min = last_guess;
@@ -8,8 +8,9 @@ use crate::dezoomer::{
     Dezoomer, DezoomerError, DezoomerInput, TileFetchResult, TileProvider, TileReference,
     ZoomLevels, single_level,
 };
+use crate::xyz::is_xyz_template;
 
-mod dichotomy_2d;
+pub(crate) mod dichotomy_2d;
 
 /// A dezoomer that takes an image tile URL template like
 /// `http://example.com/image_{{X}}_{{Y}}.jpg`
@@ -23,7 +24,8 @@ impl Dezoomer for GenericDezoomer {
     }
 
     fn zoom_levels(&mut self, data: &DezoomerInput) -> Result<ZoomLevels, DezoomerError> {
-        self.assert(TEMPLATE_RE.is_match(&data.uri))?;
+        // Templates with a zoom level are handled by the xyz dezoomer
+        self.assert(TEMPLATE_RE.is_match(&data.uri) && !is_xyz_template(&data.uri))?;
         let dezoomer = ZoomLevel {
             url_template: data.uri.clone(),
             dichotomy: Default::default(),
@@ -48,6 +50,11 @@ lazy_static! {
     .unwrap();
 }
 
+/// Whether a URL contains the `{{X}}` or `{{Y}}` variables of a tile URL template
+pub fn is_generic_template(uri: &str) -> bool {
+    TEMPLATE_RE.is_match(uri)
+}
+
 struct ZoomLevel {
     url_template: String,
     dichotomy: dichotomy_2d::Dichotomy2d,
@@ -0,0 +1,277 @@
+use custom_error::custom_error;
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use crate::dezoomer::*;
+use crate::network::resolve_relative;
+
+custom_error! {pub HtmlPageError
+    NoZoomableImage = "The page does not reference any zoomable image",
+}
+
+impl From<HtmlPageError> for DezoomerError {
+    fn from(err: HtmlPageError) -> Self {
+        DezoomerError::Other { source: err.into() }
+    }
+}
+
+/// A dezoomer for the web pages that display a zoomable image in a viewer.
+/// It finds the metadata files of the images in the page,
+/// and returns them as ZoomableImageUrl objects, to be processed by the other dezoomers.
+#[derive(Default)]
+pub struct HtmlPageDezoomer;
+
+impl Dezoomer for HtmlPageDezoomer {
+    fn name(&self) -> &'static str {
+        "html"
+    }
+
+    fn zoom_levels(&mut self, _data: &DezoomerInput) -> Result<ZoomLevels, DezoomerError> {
+        // The images of a page have to be processed by other dezoomers
+        Err(self.wrong_dezoomer())
+    }
+
+    fn dezoomer_result(&mut self, data: &DezoomerInput) -> Result<DezoomerResult, DezoomerError> {
+        let DezoomerInputWithContents { uri, contents } = data.with_contents()?;
+        let page = String::from_utf8_lossy(contents);
+        self.assert(is_html(&page))?;
+        let urls = find_image_urls(uri, &page);
+        if urls.is_empty() {
+            return Err(HtmlPageError::NoZoomableImage.into());
+        }
+        Ok(dezoomer_result_from_urls(urls))
+    }
+}
+
+lazy_static! {
+    static ref TITLE: Regex = Regex::new(r"(?is)<title[^>]*>([^<]*)</title>").unwrap();
+    static ref QUOTED: Regex = Regex::new(r#""([^"]*)"|'([^']*)'"#).unwrap();
+    static ref ATTRIBUTE: Regex =
+        Regex::new(r#"(?is)([a-z-]+)\s*=\s*(?:"([^"]*)"|'([^']*)')"#).unwrap();
+    /// The tile sources of an OpenSeadragon viewer: a single one, or an array
+    static ref OSD_TILE_SOURCES: Regex =
+        Regex::new(r#"(?s)tileSources\s*[:=]\s*(\[[^\]]*\]|"[^"]*"|'[^']*')"#).unwrap();
+    /// The files an OpenSeadragon tile source can be described by
+    static ref TILE_SOURCE_FILE: Regex = Regex::new(r"(?i)\.(dzi|xml|json)(\?.*)?$").unwrap();
+    /// `<link rel="alternate" type="application/ld+json" href="manifest.json">`
+    static ref LINK_TAG: Regex = Regex::new(r"(?is)<link\b[^>]*>").unwrap();
+    /// Image information files, and manifests. The manifests of web applications are not IIIF,
+    /// so only the manifest URLs that mention IIIF are kept.
+    static ref IIIF_URL: Regex = Regex::new(
+        r#"(?i)["']([^"'\s<>]*(?:/info\.json|iiif[^"'\s<>]*/manifest(?:\.json)?))["']"#
+    )
+    .unwrap();
+    /// Viewers and IIIF drag-and-drop links often take the manifest as a query parameter
+    static ref IIIF_MANIFEST_PARAMETER: Regex =
+        Regex::new(r#"[?&](manifest=[^"'&\s<>]+)"#).unwrap();
+    static ref ZOOMIFY_IMAGE_PATH: Regex =
+        Regex::new(r#"zoomifyImagePath\s*[=:]\s*["']?([^"'&\s,;)]+)"#).unwrap();
+    /// `Z.showImage("viewerDiv", "images/image_folder")`, in Zoomify HTML5 viewers
+    static ref ZOOMIFY_SHOW_IMAGE: Regex =
+        Regex::new(r#"Z\.showImage\(\s*["'][^"']*["']\s*,\s*["']([^"']+)["']"#).unwrap();
+    static ref KRPANO_EMBEDPANO: Regex =
+        Regex::new(r#"embedpano\s*\(\s*\{[^}]*?\bxml\s*:\s*["']([^"']+)["']"#).unwrap();
+    static ref DZI_URL: Regex = Regex::new(r#"(?i)["']([^"'\s<>]+\.dzi)["']"#).unwrap();
+}
+
+/// Whether the contents of a file are an HTML page
+fn is_html(contents: &str) -> bool {
+    let start: String = contents
+        .chars()
+        .take(1024)
+        .collect::<String>()
+        .to_lowercase();
+    ["<!doctype html", "<html", "<head", "<body", "<script"]
+        .iter()
+        .any(|tag| start.contains(tag))
+}
+
+/// Finds the metadata files of the zoomable images referenced in an HTML page,
+/// in the order in which they appear in the page
+fn find_image_urls(page_url: &str, page: &str) -> Vec<ZoomableImageUrl> {
+    // Positions in the page, and paths relative to the page
+    let mut found: Vec<(usize, String)> = vec![];
+
+    for tile_sources in OSD_TILE_SOURCES.captures_iter(page) {
+        let sources = tile_sources.get(1).unwrap();
+        for quoted in QUOTED.captures_iter(sources.as_str()) {
+            let source = quoted.get(1).or_else(|| quoted.get(2)).unwrap();
+            if TILE_SOURCE_FILE.is_match(source.as_str()) {
+                found.push((sources.start() + source.start(), source.as_str().into()));
+            }
+        }
+    }
+    for link in LINK_TAG.find_iter(page) {
+        let (mut rel, mut media_type, mut href) = ("", "", None);
+        for attribute in ATTRIBUTE.captures_iter(link.as_str()) {
+            let value = attribute
+                .get(2)
+                .or_else(|| attribute.get(3))
+                .unwrap()
+                .as_str();
+            match attribute[1].to_lowercase().as_str() {
+                "rel" => rel = value,
+                "type" => media_type = value,
+                "href" => href = Some(value),
+                _ => {}
+            }
+        }
+        // Other json alternates, such as oEmbed descriptions, are not images
+        if let Some(href) = href
+            && rel.eq_ignore_ascii_case("alternate")
+            && media_type.contains("ld+json")
+        {
+            found.push((link.start(), href.into()));
+        }
+    }
+    for url in IIIF_URL.captures_iter(page) {
+        let url = url.get(1).unwrap();
+        found.push((url.start(), url.as_str().into()));
+    }
+    for parameter in IIIF_MANIFEST_PARAMETER.captures_iter(page) {
+        let parameter = parameter.get(1).unwrap();
+        let query = parameter.as_str().replace("&amp;", "&");
+        if let Some((_, manifest)) = url::form_urlencoded::parse(query.as_bytes()).next() {
+            found.push((parameter.start(), manifest.into_owned()));
+        }
+    }
+    for regex in [&*ZOOMIFY_IMAGE_PATH, &*ZOOMIFY_SHOW_IMAGE] {
+        for path in regex.captures_iter(page) {
+            let path = path.get(1).unwrap();
+            let folder = path.as_str().trim_end_matches('/');
+            let properties = if folder.ends_with("ImageProperties.xml") {
+                folder.to_string()
+            } else {
+                format!("{folder}/ImageProperties.xml")
+            };
+            found.push((path.start(), properties));
+        }
+    }
+    for regex in [&*KRPANO_EMBEDPANO, &*DZI_URL] {
+        for url in regex.captures_iter(page) {
+            let url = url.get(1).unwrap();
+            found.push((url.start(), url.as_str().into()));
+        }
+    }
+
+    found.sort_by_key(|&(position, _)| position);
+    let mut urls: Vec<String> = vec![];
+    for (_, path) in found {
+        // Paths can be escaped in javascript strings and in html attributes
+        let path = path.replace("\\/", "/").replace("&amp;", "&");
+        let url = resolve_relative(page_url, &path);
+        if !urls.contains(&url) {
+            urls.push(url);
+        }
+    }
+
+    // The title of the page is the title of its image, when there is only one
+    let title = TITLE
+        .captures(page)
+        .map(|c| c[1].trim().to_string())
+        .filter(|t| !t.is_empty() && urls.len() == 1);
+    urls.into_iter()
+        .map(|url| ZoomableImageUrl {
+            url,
+            title: title.clone(),
+            document: None,
+            page_size: None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn urls(page: &str) -> Vec<String> {
+        find_image_urls("https://example.com/viewer/page.html", page)
+            .into_iter()
+            .map(|u| u.url)
+            .collect()
+    }
+
+    #[test]
+    fn test_openseadragon_tile_sources() {
+        let page = r#"<script>
+            OpenSeadragon({
+                id: "viewer",
+                prefixUrl: "/openseadragon/images/",
+                tileSources: ["/images/a.dzi", 'https://iiif.example.org/b/info.json', {type: "image", url: "c.jpg"}]
+            });
+            </script>"#;
+        assert_eq!(
+            urls(page),
+            [
+                "https://example.com/images/a.dzi",
+                "https://iiif.example.org/b/info.json"
+            ]
+        );
+        let page =
+            r#"<script>var viewer = OpenSeadragon({tileSources: "tiles\/image.xml"});</script>"#;
+        assert_eq!(urls(page), ["https://example.com/viewer/tiles/image.xml"]);
+    }
+
+    #[test]
+    fn test_iiif_references() {
+        let page = r#"<html><head>
+            <link rel="alternate" type="application/ld+json" href="/iiif/book/manifest">
+            <link rel="stylesheet" href="style.css">
+            <link rel="manifest" href="/manifest.json">
+            <link rel="alternate" type="application/json+oembed" href="/oembed?url=page">
+            </head><body>
+            <a href="https://viewer.example.org/?manifest=https%3A%2F%2Fexample.org%2Fother%2Fmanifest.json&amp;page=2">View</a>
+            </body></html>"#;
+        assert_eq!(
+            urls(page),
+            [
+                "https://example.com/iiif/book/manifest",
+                "https://example.org/other/manifest.json"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_zoomify_and_krpano() {
+        let page = r#"<body>
+            <param name="FlashVars" value="zoomifyImagePath=images/painting/&zoomifyNavigator=0">
+            <script>Z.showImage("myContainer", "/zoomify/drawing");</script>
+            <script>embedpano({swf: "tour.swf", xml: "tour.xml", target: "pano"});</script>
+            </body>"#;
+        assert_eq!(
+            urls(page),
+            [
+                "https://example.com/viewer/images/painting/ImageProperties.xml",
+                "https://example.com/zoomify/drawing/ImageProperties.xml",
+                "https://example.com/viewer/tour.xml"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_title_and_duplicates() {
+        let page = r#"<html><title> The Garden </title>
+            <a href="big.dzi">big</a><img data-dzi='big.dzi'></html>"#;
+        let found = find_image_urls("https://example.com/page.html", page);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].url, "https://example.com/big.dzi");
+        assert_eq!(found[0].title.as_deref(), Some("The Garden"));
+    }
+
+    #[test]
+    fn test_not_html() {
+        let mut dezoomer = HtmlPageDezoomer;
+        let input = DezoomerInput {
+            uri: "https://example.com/info.json".into(),
+            contents: PageContents::Success(
+                br#"{"@id": "https://example.com/info.json"}"#.to_vec(),
+            ),
+        };
+        assert!(dezoomer.dezoomer_result(&input).is_err());
+        let input = DezoomerInput {
+            uri: "https://example.com/page.html".into(),
+            contents: PageContents::Success(b"<html><body>Nothing here</body></html>".to_vec()),
+        };
+        assert!(dezoomer.dezoomer_result(&input).is_err());
+    }
+}
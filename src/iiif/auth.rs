@@ -0,0 +1,100 @@
+//! Support for the IIIF Authentication API (versions 1 and 2).
+//! See https://iiif.io/api/auth/
+//!
+//! The access token service of an image is called with the user's cookies,
+//! and the token it returns is sent as a bearer token with subsequent requests.
+
+use serde::Deserialize;
+use url::Url;
+
+use crate::json_utils::all_json;
+use crate::network::resolve_relative;
+
+use super::IIIFError;
+use super::tile_info::Service;
+
+/// The URL at which an access token can be requested from the given token service
+pub fn token_request_url(service: &Service, info_url: &str) -> String {
+    let token_url = resolve_relative(info_url, &service.id);
+    if !service.is_auth2() {
+        return token_url;
+    }
+    // Version 2 of the API requires a message id and the origin of the requesting page
+    match (Url::parse(&token_url), Url::parse(info_url)) {
+        (Ok(mut url), Ok(info_url)) => {
+            url.query_pairs_mut()
+                .append_pair("messageId", "1")
+                .append_pair("origin", &info_url.origin().ascii_serialization());
+            url.to_string()
+        }
+        _ => token_url,
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TokenResponse {
+    access_token: Option<String>,
+    #[serde(alias = "profile")]
+    error: Option<String>,
+}
+
+/// Extracts the access token from the response of a token service.
+/// The response is either plain JSON, or an HTML page that posts the JSON message to its parent
+pub fn parse_token_response(contents: &[u8]) -> Result<String, IIIFError> {
+    let mut error = None;
+    for response in all_json::<TokenResponse>(contents) {
+        match response {
+            TokenResponse {
+                access_token: Some(token),
+                ..
+            } if !token.is_empty() => return Ok(token),
+            TokenResponse { error: Some(e), .. } => error = Some(e),
+            _ => {}
+        }
+    }
+    Err(IIIFError::AuthError {
+        description: error.unwrap_or_else(|| "no access token in the response".into()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn service(service_type: &str, id: &str) -> Service {
+        Service {
+            id: id.into(),
+            service_type: service_type.into(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_token_request_url() {
+        let info = "https://example.com/iiif/img/info.json";
+        assert_eq!(
+            token_request_url(&service("AuthTokenService1", "/auth/token"), info),
+            "https://example.com/auth/token"
+        );
+        assert_eq!(
+            token_request_url(&service("AuthAccessTokenService2", "/auth/token"), info),
+            "https://example.com/auth/token?messageId=1&origin=https%3A%2F%2Fexample.com"
+        );
+    }
+
+    #[test]
+    fn test_parse_token_response() {
+        let json = br#"{"accessToken": "abc", "expiresIn": 3600}"#;
+        assert_eq!(parse_token_response(json).unwrap(), "abc");
+        let html = br#"<html><body><script>
+            window.parent.postMessage({"messageId": "1", "accessToken": "xyz", "expiresIn": 60}, "*");
+        </script></body></html>"#;
+        assert_eq!(parse_token_response(html).unwrap(), "xyz");
+        let error = br#"{"error": "missingCredentials", "description": "Not logged in"}"#;
+        assert_eq!(
+            parse_token_response(error).unwrap_err().to_string(),
+            "IIIF authentication failed: missingCredentials"
+        );
+    }
+}
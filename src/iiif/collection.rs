@@ -0,0 +1,354 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use log::{debug, info};
+use regex::Regex;
+use url::Url;
+
+use crate::dezoomer::ZoomableImageUrl;
+use crate::network::resolve_relative;
+
+use super::manifest_types::{Collection, CollectionChild, CollectionMember};
+use super::{IIIFError, manifest_image_urls, parse_iiif_manifest_from_bytes};
+
+/// User-configurable behavior of the IIIF dezoomer
+#[derive(Debug, Clone)]
+pub struct IiifOptions {
+    /// How many levels of nested collections to traverse below the initial collection.
+    /// Manifests listed directly in the initial collection are always processed.
+    pub collection_depth: usize,
+    /// When set, only the manifests whose label or URL matches this pattern are processed
+    pub collection_filter: Option<Regex>,
+    /// Download images in a single request each, at one of the sizes listed by the server,
+    /// instead of tile by tile
+    pub full_images: bool,
+    /// Bearer token sent with the requests for images protected by the IIIF Authentication API.
+    /// When it is not set, it is requested from the access token service of the image.
+    pub access_token: Option<String>,
+    /// The origin of the image service that issued the access token.
+    /// The token is only sent to this origin. Tokens given by the user have no origin.
+    pub token_origin: Option<String>,
+    /// Cookie sent to the access token service, and with image requests
+    pub cookie: Option<String>,
+    /// The origin of the manifest or image information file the dezoomer was given.
+    /// The cookie and the tokens given by the user are only sent to this origin.
+    pub credentials_origin: Option<String>,
+}
+
+impl Default for IiifOptions {
+    fn default() -> Self {
+        IiifOptions {
+            collection_depth: 5,
+            collection_filter: None,
+            full_images: false,
+            access_token: None,
+            token_origin: None,
+            cookie: None,
+            credentials_origin: None,
+        }
+    }
+}
+
+impl IiifOptions {
+    /// Authentication headers to send with a request for image information or tiles
+    pub fn http_headers(&self, uri: &str) -> HashMap<String, String> {
+        let mut headers = HashMap::new();
+        let origin = url_origin(uri);
+        let is_sent_to = |allowed: Option<&String>| allowed.is_some() && allowed == origin.as_ref();
+        let token_origin = self.token_origin.as_ref();
+        if let Some(token) = &self.access_token
+            && is_sent_to(token_origin.or(self.credentials_origin.as_ref()))
+        {
+            headers.insert("Authorization".into(), format!("Bearer {token}"));
+        }
+        if let Some(cookie) = &self.cookie
+            && is_sent_to(self.credentials_origin.as_ref())
+        {
+            headers.insert("Cookie".into(), cookie.clone());
+        }
+        headers
+    }
+
+    fn accepts_manifest(&self, manifest: &CollectionMember) -> bool {
+        self.collection_filter.as_ref().is_none_or(|filter| {
+            filter.is_match(&manifest.id)
+                || manifest
+                    .label
+                    .get_english_or_first()
+                    .is_some_and(|label| filter.is_match(&label))
+        })
+    }
+}
+
+/// The scheme, host and port of a URL
+pub(crate) fn url_origin(uri: &str) -> Option<String> {
+    Some(Url::parse(uri).ok()?.origin().ascii_serialization())
+}
+
+struct PendingMember {
+    uri: String,
+    depth: usize,
+}
+
+/// State of the traversal of a IIIF collection.
+/// The traversal spans several calls to the dezoomer, because each member of the collection
+/// has to be requested separately from the network.
+#[derive(Default)]
+pub struct CollectionTraversal {
+    pending: VecDeque<PendingMember>,
+    current: Option<PendingMember>,
+    visited: HashSet<String>,
+    images: Vec<ZoomableImageUrl>,
+}
+
+impl CollectionTraversal {
+    pub fn new(
+        collection_uri: &str,
+        contents: &[u8],
+        options: &IiifOptions,
+    ) -> Result<Self, IIIFError> {
+        let collection: Collection =
+            serde_json::from_slice(contents).map_err(|source| IIIFError::JsonError { source })?;
+        let mut traversal = CollectionTraversal::default();
+        traversal.visited.insert(collection_uri.to_string());
+        traversal.add_collection(&collection, collection_uri, 0, options);
+        Ok(traversal)
+    }
+
+    /// The URI of the member the traversal is currently waiting for
+    pub fn current_uri(&self) -> Option<&str> {
+        self.current.as_ref().map(|m| m.uri.as_str())
+    }
+
+    /// Selects the next member to fetch, and returns its URI
+    pub fn next_uri(&mut self) -> Option<String> {
+        self.current = None;
+        while let Some(member) = self.pending.pop_front() {
+            if self.visited.insert(member.uri.clone()) {
+                let uri = member.uri.clone();
+                self.current = Some(member);
+                return Some(uri);
+            }
+            debug!(
+                "Skipping already visited IIIF collection member {}",
+                member.uri
+            );
+        }
+        None
+    }
+
+    /// Handles the contents of the member the traversal is currently waiting for,
+    /// which can be either a manifest or a nested collection
+    pub fn add_current_member(
+        &mut self,
+        contents: &[u8],
+        options: &IiifOptions,
+    ) -> Result<(), IIIFError> {
+        let Some(PendingMember { uri, depth }) = self.current.take() else {
+            return Ok(());
+        };
+        let collection: Collection =
+            serde_json::from_slice(contents).map_err(|source| IIIFError::JsonError { source })?;
+        if collection.is_collection() {
+            self.add_collection(&collection, &uri, depth, options);
+            Ok(())
+        } else {
+            let image_infos = parse_iiif_manifest_from_bytes(contents, &uri)?;
+            info!(
+                "Found {} images in IIIF manifest {}",
+                image_infos.len(),
+                uri
+            );
+            self.images.extend(manifest_image_urls(image_infos));
+            Ok(())
+        }
+    }
+
+    fn add_collection(
+        &mut self,
+        collection: &Collection,
+        uri: &str,
+        depth: usize,
+        options: &IiifOptions,
+    ) {
+        let mut members = Vec::new();
+        for child in collection.children() {
+            match child {
+                CollectionChild::Manifest(manifest) if !options.accepts_manifest(manifest) => {
+                    debug!(
+                        "Manifest {} does not match the collection filter",
+                        manifest.id
+                    );
+                }
+                CollectionChild::Collection(sub) if depth >= options.collection_depth => {
+                    info!(
+                        "Not traversing the IIIF collection {}: maximum depth of {} reached",
+                        sub.id, options.collection_depth
+                    );
+                }
+                CollectionChild::Manifest(member) | CollectionChild::Collection(member) => {
+                    members.push(PendingMember {
+                        uri: resolve_relative(uri, &member.id),
+                        depth: depth + 1,
+                    });
+                }
+            }
+        }
+        debug!("Found {} members in IIIF collection {}", members.len(), uri);
+        // Traverse depth-first, keeping the order of the collection
+        for member in members.into_iter().rev() {
+            self.pending.push_front(member);
+        }
+    }
+
+    pub fn into_images(self) -> Vec<ZoomableImageUrl> {
+        self.images
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROOT: &str = r#"{
+      "id": "https://example.org/root", "type": "Collection",
+      "items": [
+        { "id": "m1.json", "type": "Manifest", "label": { "en": ["Letter 1"] } },
+        { "id": "series.json", "type": "Collection" },
+        { "id": "m2.json", "type": "Manifest", "label": { "en": ["Map"] } }
+      ]
+    }"#;
+
+    const SERIES: &str = r#"{
+      "@id": "https://example.org/series.json", "@type": "sc:Collection",
+      "manifests": [ { "@id": "m3.json", "label": "Letter 3" } ],
+      "collections": [ { "@id": "root" } ]
+    }"#;
+
+    fn manifest(service: &str) -> Vec<u8> {
+        format!(
+            r#"{{ "type": "Manifest", "label": "{service}", "items": [{{ "type": "Canvas", "items": [{{ "items": [{{
+                "body": {{ "id": "x.jpg", "type": "Image", "service": [{{ "id": "{service}", "type": "ImageService3" }}] }}
+            }}] }}] }}] }}"#
+        )
+        .into_bytes()
+    }
+
+    fn traverse(options: &IiifOptions) -> (Vec<String>, Vec<String>) {
+        let mut traversal =
+            CollectionTraversal::new("https://example.org/root", ROOT.as_bytes(), options).unwrap();
+        let mut fetched = vec![];
+        while let Some(uri) = traversal.next_uri() {
+            let contents = if uri.ends_with("series.json") {
+                SERIES.as_bytes().to_vec()
+            } else if uri.ends_with("root") {
+                ROOT.as_bytes().to_vec()
+            } else {
+                manifest(uri.trim_end_matches(".json"))
+            };
+            traversal.add_current_member(&contents, options).unwrap();
+            fetched.push(uri);
+        }
+        let images = traversal.into_images().into_iter().map(|i| i.url).collect();
+        (fetched, images)
+    }
+
+    #[test]
+    fn test_depth_first_traversal() {
+        let (fetched, images) = traverse(&IiifOptions::default());
+        assert_eq!(
+            fetched,
+            vec![
+                "https://example.org/m1.json",
+                "https://example.org/series.json",
+                "https://example.org/m3.json",
+                "https://example.org/m2.json",
+            ]
+        );
+        assert_eq!(
+            images,
+            vec![
+                "https://example.org/m1/info.json",
+                "https://example.org/m3/info.json",
+                "https://example.org/m2/info.json",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_max_depth() {
+        let options = IiifOptions {
+            collection_depth: 0,
+            ..Default::default()
+        };
+        let (fetched, _) = traverse(&options);
+        assert_eq!(
+            fetched,
+            vec!["https://example.org/m1.json", "https://example.org/m2.json"]
+        );
+    }
+
+    #[test]
+    fn test_filter() {
+        let options = IiifOptions {
+            collection_filter: Some(Regex::new("(?i)letter").unwrap()),
+            ..Default::default()
+        };
+        let (_, images) = traverse(&options);
+        assert_eq!(
+            images,
+            vec![
+                "https://example.org/m1/info.json",
+                "https://example.org/m3/info.json",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_token_sent_to_its_origin_only() {
+        let options = IiifOptions {
+            access_token: Some("abc".into()),
+            token_origin: url_origin("https://iiif.example.org/img/info.json"),
+            ..Default::default()
+        };
+        let tile = "https://iiif.example.org/img/0,0,512,512/512,/0/default.jpg";
+        assert_eq!(
+            options
+                .http_headers(tile)
+                .get("Authorization")
+                .map(String::as_str),
+            Some("Bearer abc")
+        );
+        assert!(
+            options
+                .http_headers("https://cdn.example.com/img.jpg")
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_user_credentials_sent_to_input_origin_only() {
+        let options = IiifOptions {
+            access_token: Some("abc".into()),
+            cookie: Some("session=secret".into()),
+            credentials_origin: url_origin("https://library.example.org/iiif/manifest.json"),
+            ..Default::default()
+        };
+        let headers = options.http_headers("https://library.example.org/iiif/img/info.json");
+        assert_eq!(headers.get("Authorization").unwrap(), "Bearer abc");
+        assert_eq!(headers.get("Cookie").unwrap(), "session=secret");
+        assert!(
+            options
+                .http_headers("https://tracker.example.com/img/info.json")
+                .is_empty()
+        );
+        let without_origin = IiifOptions {
+            credentials_origin: None,
+            ..options
+        };
+        assert!(
+            without_origin
+                .http_headers("https://library.example.org/iiif/img/info.json")
+                .is_empty()
+        );
+    }
+}
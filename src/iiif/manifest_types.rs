@@ -1,6 +1,7 @@
-use serde::Deserialize;
+use serde::{Deserialize, Deserializer};
 use std::collections::HashMap;
 
+use crate::Vec2d;
 use crate::network::resolve_relative;
 
 // Helper for potentially multilingual labels
@@ -10,10 +11,22 @@ use crate::network::resolve_relative;
 pub enum IiifLabel {
     String(String),
     Map(HashMap<String, Vec<String>>),
+    /// Presentation API 2 list of strings or language-tagged values
+    List(Vec<IiifLabel>),
+    /// Presentation API 2 language-tagged value: `{"@value": "...", "@language": "en"}`
+    Value(LanguageValue),
     #[default]
     None, // Represents JSON null or a deliberately empty label
 }
 
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
+pub struct LanguageValue {
+    #[serde(rename = "@value")]
+    pub value: String,
+    #[serde(rename = "@language", default)]
+    pub language: Option<String>,
+}
+
 impl IiifLabel {
     /// Returns the English label if present, otherwise the first label found for any language,
     /// or the string itself if it's a simple string label. Returns None if the label is empty or explicitly None.
@@ -31,6 +44,12 @@ impl IiifLabel {
                 map.values()
                     .find_map(|labels| labels.first().filter(|s| !s.is_empty()).cloned())
             }
+            IiifLabel::Value(v) => Some(v.value.clone()).filter(|s| !s.is_empty()),
+            IiifLabel::List(labels) => labels
+                .iter()
+                .filter(|l| matches!(l, IiifLabel::Value(v) if v.language.as_deref() == Some("en")))
+                .chain(labels.iter())
+                .find_map(|l| l.get_english_or_first()),
             IiifLabel::None => None,
         }
     }
@@ -54,6 +73,13 @@ impl MetadataEntry {
         }
         None
     }
+
+    /// The label and the value of this entry, if neither is empty
+    pub fn label_and_value(&self) -> Option<(String, String)> {
+        let label = self.label.get_english_or_first()?;
+        let value = self.value.get_english_or_first()?;
+        Some((label, value))
+    }
 }
 
 // Default implementation to handle missing labels gracefully via #[serde(default)]
@@ -62,31 +88,52 @@ impl MetadataEntry {
 pub struct Manifest {
     #[serde(default, rename = "@context")]
     pub context: Option<String>, // Can be string or array of strings
-    #[serde(default)] // If ID is missing, we might use the fetch URL as a fallback later
+    #[serde(default, alias = "@id")]
+    // If ID is missing, we might use the fetch URL as a fallback later
     pub id: String,
-    #[serde(default, rename = "type")]
-    pub manifest_type: String, // Should be "Manifest"
+    #[serde(default, rename = "type", alias = "@type")]
+    pub manifest_type: String, // Should be "Manifest" (or "sc:Manifest" in Presentation API 2)
     #[serde(default)]
     pub label: IiifLabel,
     #[serde(default)]
     pub items: Vec<Canvas>,
+    /// Presentation API 2 manifests list their canvases in sequences instead of `items`
+    #[serde(default)]
+    pub sequences: Vec<Sequence>,
     #[serde(default)]
     pub metadata: Option<Vec<MetadataEntry>>,
+    /// Called `license` in Presentation API 2
+    #[serde(default, alias = "license")]
+    pub rights: IiifLabel,
+    #[serde(default, rename = "requiredStatement")]
+    pub required_statement: Option<MetadataEntry>,
+    /// Presentation API 2 equivalent of the required statement
+    #[serde(default)]
+    pub attribution: IiifLabel,
     // Other potentially useful fields:
     // pub summary: Option<IiifLabel>,
     // pub thumbnail: Option<Vec<Thumbnail>>,
 }
 
 #[derive(Debug, Deserialize, Clone, PartialEq, Eq, Default)]
-pub struct Canvas {
+pub struct Sequence {
     #[serde(default)]
+    pub canvases: Vec<Canvas>,
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq, Default)]
+pub struct Canvas {
+    #[serde(default, alias = "@id")]
     pub id: String,
-    #[serde(default, rename = "type")]
-    pub canvas_type: String, // Should be "Canvas"
+    #[serde(default, rename = "type", alias = "@type")]
+    pub canvas_type: String, // Should be "Canvas" (or "sc:Canvas")
     #[serde(default)]
     pub label: IiifLabel,
     #[serde(default)]
     pub items: Vec<AnnotationPage>,
+    /// Presentation API 2 painting annotations, found directly on the canvas
+    #[serde(default)]
+    pub images: Vec<ImageAnnotation>,
     pub width: Option<u32>,
     pub height: Option<u32>,
 }
@@ -112,6 +159,14 @@ pub struct Annotation {
     // target: Option<Target>, // Target is usually the canvas; can be string or object
 }
 
+/// Presentation API 2 equivalent of [Annotation]: the image is in `resource` instead of `body`
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq, Default)]
+pub struct ImageAnnotation {
+    pub motivation: Option<String>, // "sc:painting"
+    #[serde(default)]
+    pub resource: AnnotationBody,
+}
+
 #[derive(Debug, Deserialize, Clone, PartialEq, Eq, Default)]
 #[serde(untagged)] // AnnotationBody can be a single ImageBody or potentially an array or other types.
 // For "painting" motivation, it's typically a single ImageBody object.
@@ -125,13 +180,13 @@ pub enum AnnotationBody {
 
 #[derive(Debug, Deserialize, Clone, PartialEq, Eq, Default)]
 pub struct ImageBody {
-    #[serde(default)]
+    #[serde(default, alias = "@id")]
     pub id: String, // This can be the direct image URL or the base URI for an Image Service
-    #[serde(default, rename = "type")]
-    pub image_type: String, // Should be "Image"
+    #[serde(default, rename = "type", alias = "@type")]
+    pub image_type: String, // Should be "Image" (or "dctypes:Image" in Presentation API 2)
     pub format: Option<String>, // e.g., "image/jpeg"
-    #[serde(default)]
-    pub service: Vec<ImageService>, // Array of services, can be empty
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub service: Vec<ImageService>, // Array of services (a single object in Presentation API 2), can be empty
     pub width: Option<u32>,
     pub height: Option<u32>,
 }
@@ -144,11 +199,165 @@ pub struct ImageService {
     #[serde(alias = "@type")] // IIIF Image API 2 uses "@type"
     #[serde(default, rename = "type")]
     pub service_type: String, // e.g. "ImageService2", "ImageService3"
-    pub profile: Option<String>,
+    #[serde(default, rename = "@context", deserialize_with = "first_string")]
+    pub context: Option<String>, // e.g. "http://iiif.io/api/image/2/context.json"
+    #[serde(default, deserialize_with = "first_string")]
+    pub profile: Option<String>, // Can be a string or an array starting with the compliance level
     pub width: Option<u32>,
     pub height: Option<u32>,
 }
 
+impl ImageService {
+    /// Image API 2 services embedded in Presentation API 2 manifests often have no type,
+    /// and are only recognizable by their context or profile
+    fn is_image_service(&self) -> bool {
+        self.service_type.contains("ImageService")
+            || [&self.context, &self.profile]
+                .into_iter()
+                .flatten()
+                .any(|s| s.contains("iiif.io/api/image"))
+    }
+}
+
+impl ImageBody {
+    /// The URI to dezoomify for this image: the `info.json` of its image service if it has one,
+    /// or the direct image link otherwise.
+    fn image_uri(&self, manifest_url: &str) -> Option<String> {
+        // Prioritize ImageService3, then ImageService2, then any other image service
+        let services = &self.service;
+        let chosen_service = services
+            .iter()
+            .find(|s| s.service_type == "ImageService3")
+            .or_else(|| services.iter().find(|s| s.service_type == "ImageService2"))
+            .or_else(|| services.iter().find(|s| s.is_image_service()))
+            .filter(|s| !s.id.is_empty());
+
+        if let Some(service) = chosen_service {
+            let mut resolved_uri = resolve_relative(manifest_url, &service.id);
+            // Ensure it points to info.json if it's a service ID
+            if !resolved_uri.ends_with("/info.json") {
+                if !resolved_uri.ends_with('/') {
+                    resolved_uri.push('/');
+                }
+                resolved_uri.push_str("info.json");
+            }
+            Some(resolved_uri)
+        } else if !self.id.is_empty()
+            && (self.image_type == "Image" || self.image_type == "dctypes:Image")
+        {
+            // If no suitable service, and body.id is present and type is Image, use it directly.
+            // This covers cases where 'body' is a direct image link without a service.
+            Some(resolve_relative(manifest_url, &self.id))
+        } else {
+            None
+        }
+    }
+}
+
+/// Accepts either a single value or an array of values
+fn one_or_many<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany<T> {
+        Many(Vec<T>),
+        One(T),
+    }
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::Many(values) => values,
+        OneOrMany::One(value) => vec![value],
+    })
+}
+
+/// Accepts either a string or an array, in which case its first string is kept
+fn first_string<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<String>, D::Error> {
+    use serde_json::Value;
+    Ok(match Option::<Value>::deserialize(deserializer)? {
+        Some(Value::String(s)) => Some(s),
+        Some(Value::Array(values)) => values.into_iter().find_map(|v| match v {
+            Value::String(s) => Some(s),
+            _ => None,
+        }),
+        _ => None,
+    })
+}
+
+/// A IIIF collection: a list of manifests and of other collections
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq, Default)]
+pub struct Collection {
+    #[serde(default, alias = "@id")]
+    pub id: String,
+    #[serde(default, rename = "type", alias = "@type")]
+    pub collection_type: String, // Should be "Collection" (or "sc:Collection" in Presentation API 2)
+    #[serde(default)]
+    pub label: IiifLabel,
+    /// Presentation API 3 members, with their type
+    #[serde(default)]
+    pub items: Vec<CollectionMember>,
+    /// Presentation API 2.1 members, with their type
+    #[serde(default)]
+    pub members: Vec<CollectionMember>,
+    /// Presentation API 2 sub-collections
+    #[serde(default)]
+    pub collections: Vec<CollectionMember>,
+    /// Presentation API 2 manifests
+    #[serde(default)]
+    pub manifests: Vec<CollectionMember>,
+}
+
+/// A reference to a manifest or a collection, listed in a collection
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq, Default)]
+pub struct CollectionMember {
+    #[serde(default, alias = "@id")]
+    pub id: String,
+    #[serde(default, rename = "type", alias = "@type")]
+    pub member_type: String,
+    #[serde(default)]
+    pub label: IiifLabel,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CollectionChild<'a> {
+    Manifest(&'a CollectionMember),
+    Collection(&'a CollectionMember),
+}
+
+impl Collection {
+    /// Whether this is a Presentation API 3 ("Collection") or 2 ("sc:Collection") collection
+    pub fn is_collection(&self) -> bool {
+        is_collection_type(&self.collection_type)
+    }
+
+    /// The manifests and sub-collections of this collection, in document order.
+    /// Members of an unknown type are ignored.
+    pub fn children(&self) -> Vec<CollectionChild<'_>> {
+        let typed = self.items.iter().chain(&self.members).filter_map(|m| {
+            if is_collection_type(&m.member_type) {
+                Some(CollectionChild::Collection(m))
+            } else if matches!(m.member_type.as_str(), "Manifest" | "sc:Manifest") {
+                Some(CollectionChild::Manifest(m))
+            } else {
+                None
+            }
+        });
+        // In Presentation API 2, the type is given by the list the member is in
+        typed
+            .chain(self.collections.iter().map(CollectionChild::Collection))
+            .chain(self.manifests.iter().map(CollectionChild::Manifest))
+            .filter(|child| match child {
+                CollectionChild::Manifest(m) | CollectionChild::Collection(m) => !m.id.is_empty(),
+            })
+            .collect()
+    }
+}
+
+fn is_collection_type(t: &str) -> bool {
+    matches!(t, "Collection" | "sc:Collection")
+}
+
 /// Holds information extracted from a manifest for a single image to be dezoomified.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ExtractedImageInfo {
@@ -160,11 +369,36 @@ pub struct ExtractedImageInfo {
     pub metadata_title: Option<String>,
     /// The label of the canvas this image belongs to, if available.
     pub canvas_label: Option<String>,
-    /// The 0-based index of the canvas in the manifest's `items` array.
+    /// The 0-based index of the canvas in the manifest's `items` array
+    /// (or in its sequences, for Presentation API 2 manifests).
     pub canvas_index: usize,
+    /// The width and height of the canvas, if available
+    pub canvas_size: Option<Vec2d>,
+    /// The descriptive metadata of the manifest, as label and value pairs
+    pub manifest_metadata: Vec<(String, String)>,
+    /// The license of the manifest, usually as a URL
+    pub rights: Option<String>,
+    /// Text that must be displayed with the images of the manifest
+    pub required_statement: Option<String>,
 }
 
 impl Manifest {
+    /// Whether this is a Presentation API 3 ("Manifest") or 2 ("sc:Manifest") manifest
+    pub fn is_manifest(&self) -> bool {
+        matches!(self.manifest_type.as_str(), "Manifest" | "sc:Manifest")
+    }
+
+    /// The required statement as a single string, such as "Attribution: Provided by ..."
+    pub fn get_required_statement(&self) -> Option<String> {
+        match &self.required_statement {
+            Some(statement) => match statement.label_and_value() {
+                Some((label, value)) => Some(format!("{label}: {value}")),
+                None => statement.value.get_english_or_first(),
+            },
+            None => self.attribution.get_english_or_first(),
+        }
+    }
+
     /// Get the title from metadata if available
     pub fn get_metadata_title(&self) -> Option<String> {
         self.metadata
@@ -173,85 +407,65 @@ impl Manifest {
             .find_map(|entry| entry.get_title())
     }
 
+    /// All the canvases of the manifest, whether they are listed directly in `items` (Presentation API 3)
+    /// or inside `sequences` (Presentation API 2)
+    pub fn canvases(&self) -> impl Iterator<Item = &Canvas> {
+        self.items
+            .iter()
+            .chain(self.sequences.iter().flat_map(|s| s.canvases.iter()))
+    }
+
     /// Extracts all relevant image URIs (info.json or direct image links) from the manifest.
     ///
     /// It traverses Canvases, AnnotationPages, and Annotations to find "painting"
-    /// motivations where the body is an Image. It prioritizes Image Services
-    /// (ImageService2 or ImageService3) and constructs `info.json` URIs.
+    /// motivations where the body is an Image. For Presentation API 2 manifests,
+    /// it traverses Sequences, Canvases, and their `images` annotations instead.
+    /// It prioritizes Image Services (ImageService2 or ImageService3) and constructs `info.json` URIs.
     /// If no service is found, it uses the direct image `id` from the annotation body.
     /// All extracted URIs are resolved relative to `manifest_url`.
     pub fn extract_image_infos(&self, manifest_url: &str) -> Vec<ExtractedImageInfo> {
         let mut infos = Vec::new();
         let manifest_label = self.label.get_english_or_first();
         let metadata_title = self.get_metadata_title();
+        let manifest_metadata: Vec<(String, String)> = self
+            .metadata
+            .iter()
+            .flatten()
+            .filter_map(MetadataEntry::label_and_value)
+            .collect();
+        let rights = self.rights.get_english_or_first();
+        let required_statement = self.get_required_statement();
 
-        for (canvas_index, canvas) in self.items.iter().enumerate() {
+        for (canvas_index, canvas) in self.canvases().enumerate() {
             // We expect "Canvas" type, but proceed even if it's different,
             // as long as it contains annotation pages with painting annotations.
             // if canvas.canvas_type != "Canvas" { continue; }
 
             let canvas_label = canvas.label.get_english_or_first();
+            let canvas_size = canvas.width.zip(canvas.height).map(Vec2d::from);
 
-            for annotation_page in &canvas.items {
-                // Expect "AnnotationPage", but proceed if structure matches.
-                // if annotation_page.annotation_page_type != "AnnotationPage" { continue; }
-
-                for annotation in &annotation_page.items {
-                    if let AnnotationBody::Image(image_body) = &annotation.body {
-                        // Expect "Image" type for the body, but rely on service presence.
-                        // if image_body.image_type != "Image" { continue; }
-
-                        let mut final_image_uri: Option<String> = None;
-                        let mut chosen_original_service_id: Option<&str> = None;
-
-                        // Prioritize ImageService3, then ImageService2, then any other ImageService
-                        let services = &image_body.service;
-                        if let Some(service3) =
-                            services.iter().find(|s| s.service_type == "ImageService3")
-                        {
-                            if !service3.id.is_empty() {
-                                chosen_original_service_id = Some(&service3.id);
-                            }
-                        } else if let Some(service2) =
-                            services.iter().find(|s| s.service_type == "ImageService2")
-                        {
-                            if !service2.id.is_empty() {
-                                chosen_original_service_id = Some(&service2.id);
-                            }
-                        } else if let Some(any_service) = services
-                            .iter()
-                            .find(|s| s.service_type.contains("ImageService") && !s.id.is_empty())
-                        {
-                            chosen_original_service_id = Some(&any_service.id);
-                        }
-
-                        if let Some(original_service_id) = chosen_original_service_id {
-                            let mut resolved_uri =
-                                resolve_relative(manifest_url, original_service_id);
-                            // Ensure it points to info.json if it's a service ID
-                            if !resolved_uri.ends_with("/info.json") {
-                                if !resolved_uri.ends_with('/') {
-                                    resolved_uri.push('/');
-                                }
-                                resolved_uri.push_str("info.json");
-                            }
-                            final_image_uri = Some(resolved_uri);
-                        } else if !image_body.id.is_empty() && image_body.image_type == "Image" {
-                            // If no suitable service, and body.id is present and type is Image, use it directly.
-                            // This covers cases where 'body' is a direct image link without a service.
-                            final_image_uri = Some(resolve_relative(manifest_url, &image_body.id));
-                        }
+            let bodies = canvas
+                .items
+                .iter()
+                .flat_map(|annotation_page| &annotation_page.items)
+                .map(|annotation| &annotation.body)
+                .chain(canvas.images.iter().map(|image| &image.resource));
 
-                        if let Some(uri_to_add) = final_image_uri {
-                            infos.push(ExtractedImageInfo {
-                                image_uri: uri_to_add,
-                                manifest_label: manifest_label.clone(),
-                                metadata_title: metadata_title.clone(),
-                                canvas_label: canvas_label.clone(),
-                                canvas_index,
-                            });
-                        }
-                    }
+            for body in bodies {
+                if let AnnotationBody::Image(image_body) = body
+                    && let Some(image_uri) = image_body.image_uri(manifest_url)
+                {
+                    infos.push(ExtractedImageInfo {
+                        image_uri,
+                        manifest_label: manifest_label.clone(),
+                        metadata_title: metadata_title.clone(),
+                        canvas_label: canvas_label.clone(),
+                        canvas_index,
+                        canvas_size,
+                        manifest_metadata: manifest_metadata.clone(),
+                        rights: rights.clone(),
+                        required_statement: required_statement.clone(),
+                    });
                 }
             }
         }
@@ -367,6 +581,7 @@ mod tests {
         assert_eq!(info.manifest_label, Some("Book 1".to_string()));
         assert_eq!(info.canvas_label, Some("Page 1".to_string()));
         assert_eq!(info.canvas_index, 0);
+        assert_eq!(info.canvas_size, Some(Vec2d { x: 1000, y: 1500 }));
     }
 
     #[test]
@@ -671,4 +886,168 @@ mod tests {
             Some("Canvas 5 Rel Svc No Slash".to_string())
         );
     }
+
+    #[test]
+    fn test_presentation_2_label_extraction() {
+        let label: IiifLabel =
+            serde_json::from_str(r#"{"@value": "Page", "@language": "en"}"#).unwrap();
+        assert_eq!(label.get_english_or_first(), Some("Page".to_string()));
+
+        let label: IiifLabel = serde_json::from_str(
+            r#"[{"@value": "Seite", "@language": "de"}, {"@value": "Page", "@language": "en"}]"#,
+        )
+        .unwrap();
+        assert_eq!(label.get_english_or_first(), Some("Page".to_string()));
+
+        let label: IiifLabel = serde_json::from_str(r#"["", "First"]"#).unwrap();
+        assert_eq!(label.get_english_or_first(), Some("First".to_string()));
+    }
+
+    #[test]
+    fn test_presentation_2_manifest() {
+        let json_data = r#"
+        {
+          "@context": "http://iiif.io/api/presentation/2/context.json",
+          "@id": "https://example.org/iiif/book2/manifest",
+          "@type": "sc:Manifest",
+          "label": "Book 2",
+          "metadata": [
+            { "label": "Title", "value": [{"@value": "Livre 2", "@language": "fr"}] }
+          ],
+          "license": "https://creativecommons.org/licenses/by/4.0/",
+          "attribution": "Provided by Example Library",
+          "sequences": [{
+            "@type": "sc:Sequence",
+            "canvases": [
+              {
+                "@id": "https://example.org/iiif/book2/canvas/p1",
+                "@type": "sc:Canvas",
+                "label": "p. 1",
+                "width": 1000, "height": 1500,
+                "images": [{
+                  "@type": "oa:Annotation",
+                  "motivation": "sc:painting",
+                  "resource": {
+                    "@id": "https://example.org/iiif/book2/p1/full/full/0/default.jpg",
+                    "@type": "dctypes:Image",
+                    "service": {
+                      "@context": "http://iiif.io/api/image/2/context.json",
+                      "@id": "https://example.org/iiif/book2/p1",
+                      "profile": ["http://iiif.io/api/image/2/level2.json", {"formats": ["png"]}]
+                    }
+                  },
+                  "on": "https://example.org/iiif/book2/canvas/p1"
+                }]
+              },
+              {
+                "@id": "https://example.org/iiif/book2/canvas/p2",
+                "@type": "sc:Canvas",
+                "label": "p. 2",
+                "images": [{
+                  "motivation": "sc:painting",
+                  "resource": { "@id": "p2.jpg", "@type": "dctypes:Image" }
+                }]
+              }
+            ]
+          }]
+        }
+        "#;
+        let manifest: Manifest = serde_json::from_str(json_data).unwrap();
+        assert!(manifest.is_manifest());
+        assert_eq!(manifest.id, "https://example.org/iiif/book2/manifest");
+
+        let infos = manifest.extract_image_infos("https://example.org/iiif/book2/manifest");
+        assert_eq!(
+            infos,
+            vec![
+                ExtractedImageInfo {
+                    image_uri: "https://example.org/iiif/book2/p1/info.json".to_string(),
+                    manifest_label: Some("Book 2".to_string()),
+                    metadata_title: Some("Livre 2".to_string()),
+                    canvas_label: Some("p. 1".to_string()),
+                    canvas_index: 0,
+                    canvas_size: Some(Vec2d { x: 1000, y: 1500 }),
+                    manifest_metadata: vec![("Title".into(), "Livre 2".into())],
+                    rights: Some("https://creativecommons.org/licenses/by/4.0/".into()),
+                    required_statement: Some("Provided by Example Library".into()),
+                },
+                ExtractedImageInfo {
+                    image_uri: "https://example.org/iiif/book2/p2.jpg".to_string(),
+                    manifest_label: Some("Book 2".to_string()),
+                    metadata_title: Some("Livre 2".to_string()),
+                    canvas_label: Some("p. 2".to_string()),
+                    canvas_index: 1,
+                    canvas_size: None,
+                    manifest_metadata: vec![("Title".into(), "Livre 2".into())],
+                    rights: Some("https://creativecommons.org/licenses/by/4.0/".into()),
+                    required_statement: Some("Provided by Example Library".into()),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rights_and_required_statement() {
+        let manifest: Manifest = serde_json::from_str(
+            r#"{
+              "type": "Manifest",
+              "rights": "http://rightsstatements.org/vocab/NoC-NC/1.0/",
+              "requiredStatement": {
+                "label": { "en": ["Attribution"] },
+                "value": { "en": ["Provided courtesy of Example Institution"] }
+              }
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            manifest.rights.get_english_or_first().as_deref(),
+            Some("http://rightsstatements.org/vocab/NoC-NC/1.0/")
+        );
+        assert_eq!(
+            manifest.get_required_statement().as_deref(),
+            Some("Attribution: Provided courtesy of Example Institution")
+        );
+    }
+
+    #[test]
+    fn test_collection_children() {
+        let v3: Collection = serde_json::from_str(
+            r#"{
+              "id": "https://example.org/collection", "type": "Collection",
+              "items": [
+                { "id": "https://example.org/m1", "type": "Manifest", "label": { "en": ["M1"] } },
+                { "id": "https://example.org/c1", "type": "Collection" },
+                { "id": "https://example.org/other", "type": "Canvas" }
+              ]
+            }"#,
+        )
+        .unwrap();
+        assert!(v3.is_collection());
+        let children = v3.children();
+        assert_eq!(children.len(), 2);
+        assert!(
+            matches!(children[0], CollectionChild::Manifest(m) if m.label.get_english_or_first().as_deref() == Some("M1"))
+        );
+        assert!(
+            matches!(children[1], CollectionChild::Collection(c) if c.id == "https://example.org/c1")
+        );
+
+        let v2: Collection = serde_json::from_str(
+            r#"{
+              "@id": "https://example.org/collection", "@type": "sc:Collection",
+              "collections": [ { "@id": "https://example.org/c1", "label": "Series 1" } ],
+              "manifests": [ { "@id": "https://example.org/m1", "@type": "sc:Manifest" } ]
+            }"#,
+        )
+        .unwrap();
+        assert!(v2.is_collection());
+        let children = v2.children();
+        assert_eq!(children.len(), 2);
+        assert!(
+            matches!(children[0], CollectionChild::Collection(c) if c.id == "https://example.org/c1")
+        );
+        assert!(
+            matches!(children[1], CollectionChild::Manifest(m) if m.id == "https://example.org/m1")
+        );
+    }
 }
@@ -1,8 +1,10 @@
+use std::collections::HashMap;
 use std::sync::Arc;
 
 use custom_error::custom_error;
-use log::{debug, warn};
+use log::{debug, info, warn};
 
+use collection::CollectionTraversal;
 use tile_info::ImageInfo;
 
 use crate::dezoomer::*;
@@ -10,13 +12,175 @@ use crate::iiif::tile_info::TileSizeFormat;
 use crate::json_utils::all_json;
 use crate::max_size_in_rect;
 
+pub mod auth;
+pub mod collection;
 pub mod manifest_types;
 pub mod tile_info;
 
+pub use collection::IiifOptions;
+
 /// Dezoomer for the International Image Interoperability Framework.
 /// See https://iiif.io/
 #[derive(Default)]
-pub struct IIIF;
+pub struct IIIF {
+    options: IiifOptions,
+    /// Set while the members of a IIIF collection are being fetched
+    collection: Option<CollectionTraversal>,
+    /// Set while an access token is being requested for an image that requires authentication
+    auth: Option<PendingAuth>,
+    /// Access tokens are requested only once
+    auth_attempted: bool,
+}
+
+/// An image information file that lists an access token service
+struct PendingAuth {
+    info_uri: String,
+    info_contents: Vec<u8>,
+    token_uri: String,
+    token_received: bool,
+}
+
+impl IIIF {
+    pub fn new(options: IiifOptions) -> Self {
+        IIIF {
+            options,
+            ..Default::default()
+        }
+    }
+
+    /// Reads an image information file. If the image is protected by the IIIF Authentication API,
+    /// an access token is requested first, and the file is requested again using the token.
+    fn image_levels(&mut self, uri: &str, contents: &[u8]) -> Result<ZoomLevels, DezoomerError> {
+        if self.options.access_token.is_none()
+            && !self.auth_attempted
+            && let Ok(info) = serde_json::from_slice::<ImageInfo>(contents)
+            && let Some(service) = info.auth_token_service()
+        {
+            self.auth_attempted = true;
+            let token_uri = auth::token_request_url(service, uri);
+            info!("The IIIF image {uri} requires authentication. Requesting an access token.");
+            self.auth = Some(PendingAuth {
+                info_uri: uri.to_string(),
+                info_contents: contents.to_vec(),
+                token_uri: token_uri.clone(),
+                token_received: false,
+            });
+            return Err(DezoomerError::NeedsData { uri: token_uri });
+        }
+        Ok(zoom_levels(uri, contents, &self.options)?)
+    }
+
+    /// The credentials given by the user are only sent to the origin of the first file read
+    fn set_credentials_origin(&mut self, uri: &str) {
+        if self.options.credentials_origin.is_none() {
+            self.options.credentials_origin = collection::url_origin(uri);
+        }
+    }
+
+    fn continue_auth(&mut self, data: &DezoomerInput) -> Result<ZoomLevels, DezoomerError> {
+        let pending = self.auth.as_mut().expect("authentication in progress");
+        let expected_uri = if pending.token_received {
+            &pending.info_uri
+        } else {
+            &pending.token_uri
+        };
+        // Other dezoomers may be requesting different files at the same time
+        if *expected_uri != data.uri {
+            return Err(DezoomerError::NeedsData {
+                uri: expected_uri.clone(),
+            });
+        }
+        if pending.token_received {
+            self.auth = None;
+            let with_contents = data.with_contents()?;
+            return Ok(zoom_levels(
+                with_contents.uri,
+                with_contents.contents,
+                &self.options,
+            )?);
+        }
+        let token = data
+            .with_contents()
+            .and_then(|d| Ok(auth::parse_token_response(d.contents)?));
+        match token {
+            Ok(token) => {
+                debug!("Received an access token from {}", data.uri);
+                self.options.access_token = Some(token);
+                self.options.token_origin = collection::url_origin(&pending.info_uri);
+                pending.token_received = true;
+                Err(DezoomerError::NeedsData {
+                    uri: pending.info_uri.clone(),
+                })
+            }
+            Err(e) => {
+                warn!(
+                    "Could not get an access token from {}: {}. \
+                    Try logging in with a browser and passing its cookie with --iiif-cookie.",
+                    data.uri, e
+                );
+                let pending = self.auth.take().expect("authentication in progress");
+                Ok(zoom_levels(
+                    &pending.info_uri,
+                    &pending.info_contents,
+                    &self.options,
+                )?)
+            }
+        }
+    }
+
+    fn start_collection(
+        &mut self,
+        uri: &str,
+        contents: &[u8],
+    ) -> Result<DezoomerResult, DezoomerError> {
+        self.collection = Some(CollectionTraversal::new(uri, contents, &self.options)?);
+        self.next_collection_member()
+    }
+
+    fn continue_collection(
+        &mut self,
+        data: &DezoomerInput,
+    ) -> Result<DezoomerResult, DezoomerError> {
+        let traversal = self
+            .collection
+            .as_mut()
+            .expect("collection traversal in progress");
+        // Other dezoomers may be requesting different files at the same time
+        if let Some(current) = traversal.current_uri()
+            && current != data.uri
+        {
+            return Err(DezoomerError::NeedsData {
+                uri: current.to_string(),
+            });
+        }
+        let added = data
+            .with_contents()
+            .and_then(|d| Ok(traversal.add_current_member(d.contents, &self.options)?));
+        if let Err(e) = added {
+            warn!("Skipping IIIF collection member {}: {}", data.uri, e);
+        }
+        self.next_collection_member()
+    }
+
+    fn next_collection_member(&mut self) -> Result<DezoomerResult, DezoomerError> {
+        let traversal = self
+            .collection
+            .as_mut()
+            .expect("collection traversal in progress");
+        if let Some(uri) = traversal.next_uri() {
+            return Err(DezoomerError::NeedsData { uri });
+        }
+        let images = self
+            .collection
+            .take()
+            .map(|t| t.into_images())
+            .unwrap_or_default();
+        if images.is_empty() {
+            return Err(IIIFError::EmptyCollection.into());
+        }
+        Ok(dezoomer_result_from_urls(images))
+    }
+}
 
 /// Represents a single IIIF image with metadata from a manifest or info.json
 #[derive(Debug)]
@@ -71,6 +235,8 @@ pub fn determine_title(image_info: &manifest_types::ExtractedImageInfo) -> Optio
 custom_error! {pub IIIFError
     JsonError{source: serde_json::Error} = "Invalid IIIF info.json file: {source}",
     ManifestParseError{description: String} = "Could not parse IIIF manifest: {description}",
+    EmptyCollection = "No image was found in the manifests of the IIIF collection",
+    AuthError{description: String} = "IIIF authentication failed: {description}",
 }
 
 impl From<IIIFError> for DezoomerError {
@@ -85,13 +251,38 @@ impl Dezoomer for IIIF {
     }
 
     fn zoom_levels(&mut self, data: &DezoomerInput) -> Result<ZoomLevels, DezoomerError> {
+        self.set_credentials_origin(&data.uri);
+        if self.auth.is_some() {
+            return self.continue_auth(data);
+        }
         let with_contents = data.with_contents()?;
         let contents = with_contents.contents;
         let uri = with_contents.uri;
-        Ok(zoom_levels(uri, contents)?)
+        self.image_levels(uri, contents)
+    }
+
+    fn http_headers(&self, uri: &str) -> HashMap<String, String> {
+        let mut headers = self.options.http_headers(uri);
+        // The access token service can be on another server than the image
+        if let Some(pending) = &self.auth
+            && pending.token_uri == uri
+            && let Some(cookie) = &self.options.cookie
+        {
+            headers.insert("Cookie".into(), cookie.clone());
+        }
+        headers
     }
 
     fn dezoomer_result(&mut self, data: &DezoomerInput) -> Result<DezoomerResult, DezoomerError> {
+        self.set_credentials_origin(&data.uri);
+        if self.collection.is_some() {
+            return self.continue_collection(data);
+        }
+        if self.auth.is_some() {
+            let levels = self.continue_auth(data)?;
+            let image = IIIFZoomableImage::new(levels, None);
+            return Ok(dezoomer_result_from_single_image(image));
+        }
         let with_contents = data.with_contents()?;
         let contents = with_contents.contents;
         let uri = with_contents.uri;
@@ -105,29 +296,18 @@ impl Dezoomer for IIIF {
             match type_str {
                 "ImageService2" | "ImageService3" | "iiif:ImageProfile" => {
                     // This is clearly an Image Service info.json, try parsing it directly
-                    match zoom_levels(uri, contents) {
-                        Ok(levels) => {
-                            let image = IIIFZoomableImage::new(levels, None);
-                            return Ok(dezoomer_result_from_single_image(image));
-                        }
-                        Err(e) => return Err(e.into()),
-                    }
+                    let levels = self.image_levels(uri, contents)?;
+                    let image = IIIFZoomableImage::new(levels, None);
+                    return Ok(dezoomer_result_from_single_image(image));
+                }
+                "Collection" | "sc:Collection" => {
+                    return self.start_collection(uri, contents);
                 }
-                "Manifest" => {
-                    // This is clearly a manifest, try parsing it as such
+                "Manifest" | "sc:Manifest" => {
+                    // This is clearly a manifest (Presentation API 3 or 2), try parsing it as such
                     match parse_iiif_manifest_from_bytes(contents, uri) {
                         Ok(image_infos) if !image_infos.is_empty() => {
-                            let image_urls: Vec<ZoomableImageUrl> = image_infos
-                                .into_iter()
-                                .map(|image_info| {
-                                    let title = determine_title(&image_info);
-                                    ZoomableImageUrl {
-                                        url: image_info.image_uri,
-                                        title,
-                                    }
-                                })
-                                .collect();
-
+                            let image_urls = manifest_image_urls(image_infos);
                             return Ok(dezoomer_result_from_urls(image_urls));
                         }
                         Ok(_) => {
@@ -146,11 +326,12 @@ impl Dezoomer for IIIF {
         // Check if URL suggests it's an info.json file
         if uri.ends_with("/info.json") {
             // Likely an Image Service, try parsing as info.json first
-            match zoom_levels(uri, contents) {
+            match self.image_levels(uri, contents) {
                 Ok(levels) => {
                     let image = IIIFZoomableImage::new(levels, None);
                     return Ok(dezoomer_result_from_single_image(image));
                 }
+                Err(e @ DezoomerError::NeedsData { .. }) => return Err(e),
                 Err(_) => {
                     // Fall through to try as manifest
                 }
@@ -161,36 +342,22 @@ impl Dezoomer for IIIF {
         match parse_iiif_manifest_from_bytes(contents, uri) {
             Ok(image_infos) if !image_infos.is_empty() => {
                 // Successfully parsed as manifest with images
-                let image_urls: Vec<ZoomableImageUrl> = image_infos
-                    .into_iter()
-                    .map(|image_info| {
-                        let title = determine_title(&image_info);
-                        ZoomableImageUrl {
-                            url: image_info.image_uri,
-                            title,
-                        }
-                    })
-                    .collect();
-
+                let image_urls = manifest_image_urls(image_infos);
                 Ok(dezoomer_result_from_urls(image_urls))
             }
             _ => {
                 // Not a manifest or failed to parse as manifest, try as info.json
-                match zoom_levels(uri, contents) {
-                    Ok(levels) => {
-                        let image = IIIFZoomableImage::new(levels, None);
-                        Ok(dezoomer_result_from_single_image(image))
-                    }
-                    Err(e) => Err(e.into()),
-                }
+                let levels = self.image_levels(uri, contents)?;
+                let image = IIIFZoomableImage::new(levels, None);
+                Ok(dezoomer_result_from_single_image(image))
             }
         }
     }
 }
 
-fn zoom_levels(url: &str, raw_info: &[u8]) -> Result<ZoomLevels, IIIFError> {
+fn zoom_levels(url: &str, raw_info: &[u8], options: &IiifOptions) -> Result<ZoomLevels, IIIFError> {
     match serde_json::from_slice(raw_info) {
-        Ok(info) => Ok(zoom_levels_from_info(url, info)),
+        Ok(info) => Ok(zoom_levels_from_info(url, info, options)),
         Err(e) => {
             // Due to the very fault-tolerant way we parse iiif manifests, a single javascript
             // object with a 'width' and a 'height' field is enough to be detected as an IIIF level
@@ -207,7 +374,7 @@ fn zoom_levels(url: &str, raw_info: &[u8]) -> Result<ZoomLevels, IIIFError> {
                     }
                     keep
                 })
-                .flat_map(|info| zoom_levels_from_info(url, info))
+                .flat_map(|info| zoom_levels_from_info(url, info, options))
                 .collect();
             if levels.is_empty() {
                 Err(e.into())
@@ -224,12 +391,28 @@ fn zoom_levels(url: &str, raw_info: &[u8]) -> Result<ZoomLevels, IIIFError> {
     }
 }
 
-fn zoom_levels_from_info(url: &str, mut image_info: ImageInfo) -> ZoomLevels {
+fn zoom_levels_from_info(
+    url: &str,
+    mut image_info: ImageInfo,
+    options: &IiifOptions,
+) -> ZoomLevels {
     image_info.remove_test_id();
     image_info.resolve_relative_urls(url);
     let img = Arc::new(image_info);
+    let base_url: &Arc<str> = &Arc::from(url.replace("/info.json", ""));
+    // The tiles are requested from the image service
+    let tiles_url = img.id.as_deref().unwrap_or(base_url);
+    let auth_headers = &Arc::new(options.http_headers(tiles_url));
+    if options.full_images || img.rejects_regions() {
+        if !options.full_images {
+            info!(
+                "The IIIF server does not support requesting image regions. \
+                Downloading the full image at the sizes it lists instead."
+            );
+        }
+        return full_image_levels(img, base_url, auth_headers);
+    }
     let tiles = img.tiles();
-    let base_url = &Arc::from(url.replace("/info.json", ""));
 
     tiles
         .iter()
@@ -251,6 +434,7 @@ fn zoom_levels_from_info(url: &str, mut image_info: ImageInfo) -> ZoomLevels {
                     quality: Arc::clone(&quality),
                     format: Arc::clone(&format),
                     size_format,
+                    auth_headers: Arc::clone(auth_headers),
                 };
                 debug!("Found zoom level {zoom_level:?}: page_info: {page_info:?}, tile_size: {tile_size:?}, scale_factor: {scale_factor}, base_url: {base_url}, quality: {quality}, format: {format}, size_format: {size_format:?}");
                 zoom_level
@@ -259,6 +443,29 @@ fn zoom_levels_from_info(url: &str, mut image_info: ImageInfo) -> ZoomLevels {
         .into_zoom_levels()
 }
 
+/// Levels that each download the whole image in a single request
+fn full_image_levels(
+    img: Arc<ImageInfo>,
+    base_url: &Arc<str>,
+    auth_headers: &Arc<HashMap<String, String>>,
+) -> ZoomLevels {
+    let quality = Arc::from(img.best_quality());
+    let format = Arc::from(img.best_format());
+    let size_format = img.full_image_size_format();
+    img.full_image_sizes()
+        .into_iter()
+        .map(|size| IIIFFullImageLevel {
+            size,
+            page_info: Arc::clone(&img),
+            base_url: Arc::clone(base_url),
+            quality: Arc::clone(&quality),
+            format: Arc::clone(&format),
+            size_format,
+            auth_headers: Arc::clone(auth_headers),
+        })
+        .into_zoom_levels()
+}
+
 struct IIIFZoomLevel {
     scale_factor: u32,
     tile_size: Vec2d,
@@ -267,6 +474,7 @@ struct IIIFZoomLevel {
     quality: Arc<str>,
     format: Arc<str>,
     size_format: TileSizeFormat,
+    auth_headers: Arc<HashMap<String, String>>,
 }
 
 impl TilesRect for IIIFZoomLevel {
@@ -304,6 +512,70 @@ impl TilesRect for IIIFZoomLevel {
             format = self.format,
         )
     }
+
+    fn http_headers(&self) -> HashMap<String, String> {
+        let mut headers = HashMap::clone(&self.auth_headers);
+        headers.insert("Referer".into(), self.tile_url(Vec2d::default()));
+        headers
+    }
+}
+
+/// A level made of a single tile: a rendition of the full image at one of the sizes listed by the server
+struct IIIFFullImageLevel {
+    size: Vec2d,
+    page_info: Arc<ImageInfo>,
+    base_url: Arc<str>,
+    quality: Arc<str>,
+    format: Arc<str>,
+    size_format: TileSizeFormat,
+    auth_headers: Arc<HashMap<String, String>>,
+}
+
+impl TilesRect for IIIFFullImageLevel {
+    fn size(&self) -> Vec2d {
+        self.size
+    }
+
+    fn tile_size(&self) -> Vec2d {
+        self.size
+    }
+
+    fn tile_url(&self, _col_and_row_pos: Vec2d) -> String {
+        let size = if self.size != self.page_info.size() {
+            TileSizeFormatter {
+                w: self.size.x,
+                h: self.size.y,
+                format: self.size_format,
+            }
+            .to_string()
+        } else if self.page_info.is_version_3() {
+            "max".to_string()
+        } else {
+            "full".to_string()
+        };
+        format!(
+            "{base}/full/{size}/0/{quality}.{format}",
+            base = self
+                .page_info
+                .id
+                .as_deref()
+                .unwrap_or_else(|| self.base_url.as_ref()),
+            quality = self.quality,
+            format = self.format,
+        )
+    }
+
+    fn http_headers(&self) -> HashMap<String, String> {
+        let mut headers = HashMap::clone(&self.auth_headers);
+        headers.insert("Referer".into(), self.tile_url(Vec2d::default()));
+        headers
+    }
+}
+
+impl std::fmt::Debug for IIIFFullImageLevel {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "IIIF full image ({})", self.size)
+    }
 }
 
 struct TileSizeFormatter {
@@ -317,6 +589,7 @@ impl std::fmt::Display for TileSizeFormatter {
         match self.format {
             TileSizeFormat::WidthHeight => write!(f, "{},{}", self.w, self.h),
             TileSizeFormat::Width => write!(f, "{},", self.w),
+            TileSizeFormat::Confined => write!(f, "!{},{}", self.w, self.h),
         }
     }
 }
@@ -356,13 +629,14 @@ pub fn parse_iiif_manifest_from_bytes(
     let manifest: manifest_types::Manifest =
         serde_json::from_slice(bytes).map_err(|e| IIIFError::JsonError { source: e })?;
 
-    if manifest.manifest_type != "Manifest" {
+    if !manifest.is_manifest() {
         // Don't warn for known IIIF Image Service types, as these are valid but not manifests
         if !matches!(
             manifest.manifest_type.as_str(),
             "ImageService2" | "ImageService3" | "iiif:ImageProfile"
         ) {
-            // While we could be more lenient, the Presentation API spec says this should be "Manifest".
+            // While we could be more lenient, the Presentation API spec says this should be "Manifest"
+            // ("sc:Manifest" in version 2).
             // If it's something else, it's likely not what we expect, or a different IIIF spec.
             warn!(
                 "Attempted to parse IIIF manifest from {} but 'type' field was '{}' instead of 'Manifest'. Proceeding, but this may indicate an incorrect file type.",
@@ -374,6 +648,29 @@ pub fn parse_iiif_manifest_from_bytes(
     Ok(manifest.extract_image_infos(manifest_url))
 }
 
+/// The images of a manifest, titled after the manifest and canvas labels
+fn manifest_image_urls(
+    image_infos: Vec<manifest_types::ExtractedImageInfo>,
+) -> Vec<ZoomableImageUrl> {
+    image_infos
+        .into_iter()
+        .map(|image_info| {
+            let title = determine_title(&image_info);
+            ZoomableImageUrl {
+                url: image_info.image_uri,
+                title,
+                document: Some(DocumentInfo {
+                    title: image_info.manifest_label,
+                    metadata: image_info.manifest_metadata,
+                    rights: image_info.rights,
+                    required_statement: image_info.required_statement,
+                }),
+                page_size: image_info.canvas_size,
+            }
+        })
+        .collect()
+}
+
 #[test]
 fn test_tiles() {
     let data = br#"{
@@ -406,7 +703,7 @@ fn test_tiles() {
            "supports" : ["regionByPct","sizeByForcedWh","sizeByWh","sizeAboveFull","rotationBy90s","mirroring","gray"] }
       ]
     }"#;
-    let mut levels = zoom_levels("test.com", data).unwrap();
+    let mut levels = zoom_levels("test.com", data, &IiifOptions::default()).unwrap();
     let tiles: Vec<String> = levels[6]
         .next_tiles(None)
         .into_iter()
@@ -431,7 +728,8 @@ fn test_tiles_max_area_filter() {
       "tiles" : [{ "width" : 1024, "scaleFactors" : [ 1 ] }],
       "profile" :  [ { "maxArea": 262144 } ]
     }"#;
-    let mut levels = zoom_levels("http://ophir.dev/info.json", data).unwrap();
+    let mut levels =
+        zoom_levels("http://ophir.dev/info.json", data, &IiifOptions::default()).unwrap();
     let tiles: Vec<String> = levels[0]
         .next_tiles(None)
         .into_iter()
@@ -454,7 +752,8 @@ fn test_missing_id() {
       "width" : 600,
       "height" : 350
     }"#;
-    let mut levels = zoom_levels("http://test.com/info.json", data).unwrap();
+    let mut levels =
+        zoom_levels("http://test.com/info.json", data, &IiifOptions::default()).unwrap();
     let tiles: Vec<String> = levels[0]
         .next_tiles(None)
         .into_iter()
@@ -469,6 +768,104 @@ fn test_missing_id() {
     )
 }
 
+#[test]
+fn test_level0_sizes_without_tiles() {
+    let data = br#"{
+      "@context": "http://iiif.io/api/image/2/context.json",
+      "@id": "http://test.com/img",
+      "profile": ["http://iiif.io/api/image/2/level0.json"],
+      "width": 1000,
+      "height": 800,
+      "sizes": [ { "width": 250, "height": 200 }, { "width": 500, "height": 400 } ]
+    }"#;
+    let levels = zoom_levels(
+        "http://test.com/img/info.json",
+        data,
+        &IiifOptions::default(),
+    )
+    .unwrap();
+    let tiles: Vec<(Vec2d, Vec<String>)> = levels
+        .into_iter()
+        .map(|mut level| {
+            let size = level.size_hint().unwrap();
+            let urls = level.next_tiles(None).into_iter().map(|t| t.url).collect();
+            (size, urls)
+        })
+        .collect();
+    assert_eq!(
+        tiles,
+        vec![
+            (
+                Vec2d { x: 250, y: 200 },
+                vec!["http://test.com/img/full/250,200/0/default.jpg".to_string()]
+            ),
+            (
+                Vec2d { x: 500, y: 400 },
+                vec!["http://test.com/img/full/500,400/0/default.jpg".to_string()]
+            ),
+            (
+                Vec2d { x: 1000, y: 800 },
+                vec!["http://test.com/img/full/full/0/default.jpg".to_string()]
+            ),
+        ]
+    )
+}
+
+#[test]
+fn test_level1_full_image_sizes() {
+    let data = br#"{
+      "@context": "http://iiif.io/api/image/2/context.json",
+      "@id": "http://test.com/img",
+      "profile": ["http://iiif.io/api/image/2/level1.json"],
+      "width": 1000,
+      "height": 800,
+      "sizes": [ { "width": 250, "height": 200 } ]
+    }"#;
+    let options = IiifOptions {
+        full_images: true,
+        ..Default::default()
+    };
+    let levels = zoom_levels("http://test.com/img/info.json", data, &options).unwrap();
+    let urls: Vec<String> = levels
+        .into_iter()
+        .flat_map(|mut level| level.next_tiles(None))
+        .map(|t| t.url)
+        .collect();
+    // Level 1 servers only support width-only sizes, besides the listed ones
+    assert_eq!(
+        urls,
+        vec![
+            "http://test.com/img/full/250,/0/default.jpg",
+            "http://test.com/img/full/full/0/default.jpg"
+        ]
+    );
+}
+
+#[test]
+fn test_full_images_option() {
+    let data = br#"{
+      "@context": "http://iiif.io/api/image/3/context.json",
+      "id": "http://test.com/img",
+      "type": "ImageService3",
+      "profile": "level2",
+      "width": 600,
+      "height": 350,
+      "tiles": [{ "width": 512, "scaleFactors": [1, 2] }]
+    }"#;
+    let options = IiifOptions {
+        full_images: true,
+        ..Default::default()
+    };
+    let mut levels = zoom_levels("http://test.com/img/info.json", data, &options).unwrap();
+    assert_eq!(levels.len(), 1);
+    let tiles: Vec<String> = levels[0]
+        .next_tiles(None)
+        .into_iter()
+        .map(|t| t.url)
+        .collect();
+    assert_eq!(tiles, vec!["http://test.com/img/full/max/0/default.png"]);
+}
+
 #[test]
 fn test_false_positive() {
     let data = br#"
@@ -479,7 +876,11 @@ fn test_false_positive() {
         tilesUrl:   "./ORIONFINAL/"
     };
     "#;
-    let res = zoom_levels("https://orion2020v5b.spaceforeverybody.com/", data);
+    let res = zoom_levels(
+        "https://orion2020v5b.spaceforeverybody.com/",
+        data,
+        &IiifOptions::default(),
+    );
     assert!(
         res.is_err(),
         "openseadragon zoomify image should not be misdetected"
@@ -500,7 +901,7 @@ fn test_qualities() {
         "formats" : [ "png", "zorglub" ],
         "scale_factors": [ 10 ]
     }"#;
-    let mut levels = zoom_levels("test.com", data).unwrap();
+    let mut levels = zoom_levels("test.com", data, &IiifOptions::default()).unwrap();
     let level = &mut levels[0];
     assert_eq!(level.size_hint(), Some(Vec2d { x: 515, y: 381 })); // 5156/10, 3816/10
     let tiles: Vec<String> = level.next_tiles(None).into_iter().map(|t| t.url).collect();
@@ -512,6 +913,33 @@ fn test_qualities() {
     )
 }
 
+#[test]
+fn test_cookie_sent_to_token_service() {
+    let options = IiifOptions {
+        cookie: Some("session=secret".into()),
+        ..Default::default()
+    };
+    let mut dezoomer = IIIF::new(options);
+    let info_uri = "https://images.example.com/img/info.json";
+    let info = r#"{"@id": "https://images.example.com/img", "width": 10, "height": 10,
+        "service": {"@id": "https://auth.example.com/login",
+            "profile": "http://iiif.io/api/auth/1/login",
+            "service": [{"@id": "https://auth.example.com/token",
+                "profile": "http://iiif.io/api/auth/1/token"}]}}"#;
+    let input = DezoomerInput {
+        uri: info_uri.into(),
+        contents: PageContents::Success(info.as_bytes().to_vec()),
+    };
+    let token_uri = match dezoomer.zoom_levels(&input) {
+        Err(DezoomerError::NeedsData { uri }) => uri,
+        other => panic!("Expected a token request, got {other:?}"),
+    };
+    let cookie = |uri: &str| dezoomer.http_headers(uri).get("Cookie").cloned();
+    assert_eq!(cookie(&token_uri).as_deref(), Some("session=secret"));
+    assert_eq!(cookie(info_uri).as_deref(), Some("session=secret"));
+    assert_eq!(cookie("https://auth.example.com/other"), None);
+}
+
 #[cfg(test)]
 mod manifest_parsing_tests {
     use super::*;
@@ -570,6 +998,10 @@ mod manifest_parsing_tests {
                 metadata_title: None,
                 canvas_label: Some("Page 1".to_string()),
                 canvas_index: 0,
+                canvas_size: None,
+                manifest_metadata: vec![],
+                rights: None,
+                required_statement: None,
             }
         );
     }
@@ -630,6 +1062,109 @@ mod manifest_parsing_tests {
         assert_eq!(infos[2].canvas_label, Some("C3 Direct Rel Img".to_string()));
     }
 
+    #[test]
+    fn test_dezoomer_result_with_presentation_2_manifest() {
+        let mut dezoomer = IIIF::default();
+        let manifest_data = br#"
+        {
+          "@context": "http://iiif.io/api/presentation/2/context.json",
+          "@id": "https://example.org/iiif/book1/manifest",
+          "@type": "sc:Manifest",
+          "label": "Test Book",
+          "sequences": [{
+            "canvases": [
+              {
+                "@type": "sc:Canvas", "label": "Page 1",
+                "images": [{ "motivation": "sc:painting", "resource": {
+                  "@id": "page1.jpg", "@type": "dctypes:Image",
+                  "service": { "@context": "http://iiif.io/api/image/2/context.json", "@id": "iiif/page1" }
+                }}]
+              },
+              {
+                "@type": "sc:Canvas", "label": "Page 2",
+                "images": [{ "motivation": "sc:painting", "resource": {
+                  "@id": "page2.jpg", "@type": "dctypes:Image",
+                  "service": { "@id": "iiif/page2", "profile": "http://iiif.io/api/image/2/level1.json" }
+                }}]
+              }
+            ]
+          }]
+        }
+        "#;
+
+        let input = DezoomerInput {
+            uri: "https://example.com/manifest.json".to_string(),
+            contents: PageContents::Success(manifest_data.to_vec()),
+        };
+
+        let result = dezoomer.dezoomer_result(&input).unwrap();
+        let urls: Vec<_> = result
+            .iter()
+            .map(|image| match image {
+                ZoomableImage::ImageUrl(url) => (url.url.as_str(), url.title.as_deref()),
+                _ => panic!("Expected ZoomableImage::ImageUrl"),
+            })
+            .collect();
+        assert_eq!(
+            urls,
+            vec![
+                (
+                    "https://example.com/iiif/page1/info.json",
+                    Some("Test Book - Page 1")
+                ),
+                (
+                    "https://example.com/iiif/page2/info.json",
+                    Some("Test Book - Page 2")
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_dezoomer_result_with_collection() {
+        let mut dezoomer = IIIF::default();
+        let collection = br#"{
+          "@context": "http://iiif.io/api/presentation/2/context.json",
+          "@id": "https://example.com/collection.json", "@type": "sc:Collection",
+          "manifests": [
+            { "@id": "https://example.com/missing.json", "label": "Missing" },
+            { "@id": "https://example.com/book.json", "label": "Book" }
+          ]
+        }"#;
+        let book = br#"{
+          "@type": "sc:Manifest", "label": "Book",
+          "sequences": [{ "canvases": [{ "label": "Cover", "images": [{ "resource": {
+            "@id": "cover.jpg", "@type": "dctypes:Image",
+            "service": { "@context": "http://iiif.io/api/image/2/context.json", "@id": "iiif/cover" }
+          }}]}]}]
+        }"#;
+
+        let mut input = DezoomerInput {
+            uri: "https://example.com/collection.json".to_string(),
+            contents: PageContents::Success(collection.to_vec()),
+        };
+        let result = loop {
+            match dezoomer.dezoomer_result(&input) {
+                Err(DezoomerError::NeedsData { uri }) => {
+                    input.contents = if uri.ends_with("book.json") {
+                        PageContents::Success(book.to_vec())
+                    } else {
+                        PageContents::Error(crate::ZoomError::NoTile)
+                    };
+                    input.uri = uri;
+                }
+                other => break other.unwrap(),
+            }
+        };
+        assert_eq!(result.len(), 1);
+        if let ZoomableImage::ImageUrl(ref url) = result[0] {
+            assert_eq!(url.url, "https://example.com/iiif/cover/info.json");
+            assert_eq!(url.title, Some("Book - Cover".to_string()));
+        } else {
+            panic!("Expected ZoomableImage::ImageUrl");
+        }
+    }
+
     #[test]
     fn test_parse_invalid_json_manifest() {
         let manifest_url = "https://example.com/invalid.json";
@@ -659,7 +1194,7 @@ mod manifest_parsing_tests {
 
     #[test]
     fn test_dezoomer_result_with_manifest() {
-        let mut dezoomer = IIIF;
+        let mut dezoomer = IIIF::default();
         let manifest_data = r#"
         {
           "@context": "http://iiif.io/api/presentation/3/context.json",
@@ -718,7 +1253,7 @@ mod manifest_parsing_tests {
 
     #[test]
     fn test_dezoomer_result_with_info_json() {
-        let mut dezoomer = IIIF;
+        let mut dezoomer = IIIF::default();
         let info_data = r#"{
           "@context" : "http://iiif.io/api/image/2/context.json",
           "@id" : "https://example.com/image",
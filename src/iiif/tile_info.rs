@@ -6,7 +6,7 @@ use log::debug;
 use log::info;
 use log::warn;
 use regex::Regex;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 
 use crate::Vec2d;
 use crate::network::resolve_relative;
@@ -37,6 +37,18 @@ pub struct ImageInfo {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub tiles: Option<Vec<TileInfo>>,
 
+    /// Sizes at which the full image can be requested, used by level 0 servers
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sizes: Option<Vec<SizeInfo>>,
+
+    /// Related services, such as the IIIF Authentication API services protecting the image
+    #[serde(
+        default,
+        skip_serializing_if = "Vec::is_empty",
+        deserialize_with = "lenient_services"
+    )]
+    pub service: Vec<Service>,
+
     // Used in IIIF version 1 :
     #[serde(skip_serializing_if = "Option::is_none")]
     pub scale_factors: Option<Vec<u32>>,
@@ -57,6 +69,8 @@ static FORMAT_ORDER: [&str; 7] = ["webp", "gif", "bmp", "tif", "jpg", "jpeg", "p
 pub enum TileSizeFormat {
     WidthHeight,
     Width,
+    /// `!w,h`: the largest size that fits in the given width and height
+    Confined,
 }
 
 impl ImageInfo {
@@ -117,6 +131,84 @@ impl ImageInfo {
         }
     }
 
+    /// The size syntax to request the full image at one of the sizes it is listed at.
+    /// Servers that do not support arbitrary sizes reject the ones they do not support.
+    pub fn full_image_size_format(&self) -> TileSizeFormat {
+        // In version 3, listed sizes can always be requested with their canonical w,h form
+        if self.is_version_3() {
+            return TileSizeFormat::WidthHeight;
+        }
+        let pinfo = self.profile_info();
+        let s: HashSet<&str> = pinfo
+            .supports
+            .iter()
+            .flat_map(|x| x.iter())
+            .map(|s| s.as_str())
+            .collect();
+        if s.contains("sizeByWh") || s.contains("sizeByForcedWh") {
+            TileSizeFormat::WidthHeight
+        } else if s.contains("sizeByW") {
+            TileSizeFormat::Width
+        } else if s.contains("sizeByWhListed") {
+            TileSizeFormat::WidthHeight
+        } else if s.contains("sizeByConfinedWh") {
+            TileSizeFormat::Confined
+        } else {
+            // The canonical form of version 2, used by static exports
+            TileSizeFormat::Width
+        }
+    }
+
+    /// Whether the server is known to reject requests for arbitrary image regions.
+    /// This is the case of static level 0 exports that do not list any tile:
+    /// they can only serve the full image, at the sizes they list.
+    pub fn rejects_regions(&self) -> bool {
+        let no_tiles = self.tiles.as_ref().is_none_or(|t| t.is_empty())
+            && self.tile_width.is_none()
+            && self.scale_factors.is_none();
+        let supports_regions = self
+            .profile_info()
+            .supports
+            .as_ref()
+            .map(|features| features.iter().any(|f| f == "regionByPx"));
+        no_tiles && supports_regions == Some(false)
+    }
+
+    /// Sizes at which the full image can be downloaded in a single request,
+    /// from the smallest to the largest. The full-resolution image is always included.
+    pub fn full_image_sizes(&self) -> Vec<Vec2d> {
+        let mut sizes: Vec<Vec2d> = self
+            .sizes
+            .iter()
+            .flatten()
+            .map(|s| Vec2d {
+                x: s.width,
+                y: s.height,
+            })
+            .filter(|s| s.x > 0 && s.y > 0 && s.fits_inside(self.size()))
+            .collect();
+        sizes.push(self.size());
+        sizes.sort_by_key(|s| s.area());
+        sizes.dedup();
+        sizes
+    }
+
+    /// The access token service of the IIIF Authentication API (version 1 or 2)
+    /// protecting this image, if any
+    pub fn auth_token_service(&self) -> Option<&Service> {
+        self.service.iter().find_map(Service::find_token_service)
+    }
+
+    /// Whether this is a IIIF Image API version 3 image, in which the full-resolution image
+    /// is requested with the "max" size instead of "full"
+    pub fn is_version_3(&self) -> bool {
+        self.iiif_type.as_deref() == Some("ImageService3")
+            || self
+                .context
+                .as_deref()
+                .is_some_and(|c| c.contains("iiif.io/api/image/3"))
+    }
+
     pub fn tiles(&self) -> Vec<TileInfo> {
         let profile_info = self.profile_info();
         let mut tiles: Vec<_> = self
@@ -198,6 +290,67 @@ pub struct TileInfo {
     pub scale_factors: Vec<u32>,
 }
 
+/// A service attached to an image. Services can be nested:
+/// an authentication service contains its access token service
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Default)]
+pub struct Service {
+    #[serde(default, alias = "@id")]
+    pub id: String,
+    #[serde(default, rename = "type", alias = "@type")]
+    pub service_type: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+    #[serde(
+        default,
+        skip_serializing_if = "Vec::is_empty",
+        deserialize_with = "lenient_services"
+    )]
+    pub service: Vec<Service>,
+}
+
+impl Service {
+    /// Whether this is an access token service from version 2 of the IIIF Authentication API
+    pub fn is_auth2(&self) -> bool {
+        self.service_type == "AuthAccessTokenService2"
+    }
+
+    fn is_token_service(&self) -> bool {
+        self.is_auth2()
+            || self.service_type == "AuthTokenService1"
+            || self
+                .profile
+                .as_deref()
+                .is_some_and(|p| p.ends_with("auth/1/token"))
+    }
+
+    fn find_token_service(&self) -> Option<&Service> {
+        if self.is_token_service() && !self.id.is_empty() {
+            Some(self)
+        } else {
+            self.service.iter().find_map(Service::find_token_service)
+        }
+    }
+}
+
+/// Services can be a single object or a list, and can contain anything:
+/// the ones we cannot parse are ignored instead of making the whole image info invalid
+fn lenient_services<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Service>, D::Error> {
+    let values = match serde_json::Value::deserialize(deserializer)? {
+        serde_json::Value::Array(values) => values,
+        value => vec![value],
+    };
+    Ok(values
+        .into_iter()
+        .filter_map(|v| serde_json::from_value(v).ok())
+        .collect())
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Copy)]
+pub struct SizeInfo {
+    pub width: u32,
+    pub height: u32,
+}
+
 impl TileInfo {
     pub fn size(&self) -> Vec2d {
         Vec2d {
@@ -282,6 +435,7 @@ impl Profile {
                 let mut formats = vec![];
                 let mut qualities = vec![];
                 let mut supports = vec![];
+                let mut supports_known = false;
                 let mut max_width = None;
                 let mut max_height = None;
                 let mut max_area = None;
@@ -303,7 +457,8 @@ impl Profile {
                         qualities.extend_from_slice(x)
                     }
                     if let Some(x) = &p.supports {
-                        supports.extend_from_slice(x)
+                        supports.extend_from_slice(x);
+                        supports_known = true;
                     }
                     update_max(&mut max_width, p.max_width);
                     update_max(&mut max_height, p.max_height);
@@ -312,7 +467,8 @@ impl Profile {
                 Cow::Owned(ProfileInfo {
                     formats: Some(formats),
                     qualities: Some(qualities),
-                    supports: Some(supports),
+                    // Unknown profile references should not hide features
+                    supports: supports_known.then_some(supports),
                     max_width,
                     max_height,
                     max_area,
@@ -418,3 +574,64 @@ fn test_best_quality() {
         assert_eq!(info.best_quality(), expected_best_quality);
     }
 }
+
+#[test]
+fn test_rejects_regions() {
+    let level0: ImageInfo = serde_json::from_str(
+        r#"{"width": 10, "height": 10, "profile": "http://iiif.io/api/image/2/level0.json"}"#,
+    )
+    .unwrap();
+    assert!(level0.rejects_regions());
+
+    let level0_with_tiles: ImageInfo = serde_json::from_str(
+        r#"{"width": 10, "height": 10, "profile": "level0", "tiles": [{"width": 5, "scaleFactors": [1]}]}"#,
+    )
+    .unwrap();
+    assert!(!level0_with_tiles.rejects_regions());
+
+    let level1: ImageInfo = serde_json::from_str(
+        r#"{"width": 10, "height": 10, "profile": ["http://iiif.io/api/image/2/level1.json", {}]}"#,
+    )
+    .unwrap();
+    assert!(!level1.rejects_regions());
+
+    let unknown: ImageInfo = serde_json::from_str(
+        r#"{"width": 10, "height": 10, "profile": ["http://example.com/custom-profile", {}]}"#,
+    )
+    .unwrap();
+    assert!(!unknown.rejects_regions());
+}
+
+#[test]
+fn test_auth_token_service() {
+    let auth1: ImageInfo = serde_json::from_str(
+        r#"{"width": 10, "height": 10, "service": {
+            "@id": "https://example.com/login", "profile": "http://iiif.io/api/auth/1/login",
+            "service": [
+                {"@id": "https://example.com/logout", "profile": "http://iiif.io/api/auth/1/logout"},
+                {"@id": "https://example.com/token", "profile": "http://iiif.io/api/auth/1/token"}
+            ]
+        }}"#,
+    )
+    .unwrap();
+    let token = auth1.auth_token_service().unwrap();
+    assert_eq!(token.id, "https://example.com/token");
+    assert!(!token.is_auth2());
+
+    let auth2: ImageInfo = serde_json::from_str(
+        r#"{"width": 10, "height": 10, "service": [
+            "not a service",
+            {"id": "https://example.com/probe", "type": "AuthProbeService2", "service": [{
+                "id": "https://example.com/access", "type": "AuthAccessService2", "profile": "active",
+                "service": [{"id": "https://example.com/token2", "type": "AuthAccessTokenService2"}]
+            }]}
+        ]}"#,
+    )
+    .unwrap();
+    let token = auth2.auth_token_service().unwrap();
+    assert_eq!(token.id, "https://example.com/token2");
+    assert!(token.is_auth2());
+
+    let public: ImageInfo = serde_json::from_str(r#"{"width": 10, "height": 10}"#).unwrap();
+    assert_eq!(public.auth_token_service(), None);
+}
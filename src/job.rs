@@ -0,0 +1,327 @@
+//! A job state file, recording the progress of a download while it happens,
+//! so that running the same command again after an interruption continues where it stopped.
+//! The downloaded tiles are kept on disk until the job is complete.
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use fixedbitset::FixedBitSet;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::dezoomer::{TileReference, ZoomLevel};
+use crate::{Vec2d, ZoomError};
+
+/// Minimal delay between two writes of the job file during a download
+const SAVE_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Job {
+    /// The URI given by the user, or the bulk file
+    input_uri: String,
+    /// The images to download, in the order in which they were found
+    images: Vec<ImageJob>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct ImageJob {
+    /// The format of the zoomable image, such as "Zoomify Image"
+    dezoomer: String,
+    /// The chosen zoom level, as it is described to the user
+    level: String,
+    size: Option<Vec2d>,
+    /// The file the image is saved to
+    output: Option<PathBuf>,
+    /// All the tiles of the chosen zoom level that are known so far
+    tiles: Vec<TileReference>,
+    /// Which of the tiles were downloaded
+    #[serde(with = "bitmap")]
+    completed: FixedBitSet,
+    /// Whether the image was entirely downloaded and saved
+    finished: bool,
+    /// Index of the tiles by position
+    #[serde(skip)]
+    positions: HashMap<Vec2d, usize>,
+}
+
+impl ImageJob {
+    fn new(zoom_level: &ZoomLevel) -> Self {
+        ImageJob {
+            dezoomer: format!("{zoom_level:?}"),
+            level: zoom_level.name(),
+            size: zoom_level.size_hint(),
+            ..Default::default()
+        }
+    }
+
+    fn is_same_level(&self, zoom_level: &ZoomLevel) -> bool {
+        self.dezoomer == format!("{zoom_level:?}") && self.level == zoom_level.name()
+    }
+}
+
+/// A job, and the file it is saved to
+pub struct JobFile {
+    path: PathBuf,
+    job: Job,
+    /// Whether the job was loaded from an existing file
+    resumed: bool,
+    /// The index of the image being downloaded
+    current: usize,
+    last_save: Instant,
+}
+
+impl JobFile {
+    /// Loads the job saved at the given path, or starts a new one if there is none
+    pub fn open(path: &Path, input_uri: &str) -> Result<Self, ZoomError> {
+        let resumed = path.exists();
+        let job = if resumed {
+            let reader = BufReader::new(File::open(path)?);
+            let job: Job = serde_json::from_reader(reader).map_err(io::Error::from)?;
+            if job.input_uri != input_uri {
+                return Err(ZoomError::CannotResume {
+                    path: path.to_string_lossy().to_string(),
+                    reason: format!("this job is the download of {}", job.input_uri),
+                });
+            }
+            let finished = job.images.iter().filter(|i| i.finished).count();
+            info!(
+                "Resuming the job saved in {}: {} images were already downloaded",
+                path.display(),
+                finished
+            );
+            job
+        } else {
+            Job {
+                input_uri: input_uri.to_string(),
+                images: vec![],
+            }
+        };
+        Ok(JobFile {
+            path: path.to_path_buf(),
+            job,
+            resumed,
+            current: 0,
+            last_save: Instant::now(),
+        })
+    }
+
+    /// Whether the job was started by a previous run
+    pub fn is_resumed(&self) -> bool {
+        self.resumed
+    }
+
+    /// The folder where the tiles are stored until the job is complete
+    pub fn tile_folder(&self) -> io::Result<PathBuf> {
+        let mut folder = self.path.as_os_str().to_owned();
+        folder.push(".tiles");
+        let folder = PathBuf::from(folder);
+        fs::create_dir_all(&folder)?;
+        Ok(folder)
+    }
+
+    /// Starts or continues the download of the image at the given index.
+    /// Returns the file the image was being saved to,
+    /// if the same zoom level of the image was already being downloaded.
+    pub fn start_image(&mut self, index: usize, zoom_level: &ZoomLevel) -> Option<PathBuf> {
+        self.current = index;
+        if self.job.images.len() <= index {
+            self.job.images.resize_with(index + 1, Default::default);
+        }
+        let image = &mut self.job.images[index];
+        if image.is_same_level(zoom_level) {
+            image.positions = (image.tiles.iter().enumerate())
+                .map(|(i, tile)| (tile.position, i))
+                .collect();
+            image.completed.grow(image.tiles.len());
+            image.output.clone()
+        } else {
+            if image.output.is_some() {
+                warn!(
+                    "The zoom level of image {} is not the one of the saved job. \
+                    Downloading it again.",
+                    index + 1
+                );
+            }
+            *image = ImageJob::new(zoom_level);
+            None
+        }
+    }
+
+    /// The file the image at the given index was saved to, if it was entirely downloaded
+    pub fn finished_output(&self, index: usize) -> Option<&Path> {
+        let image = self.job.images.get(index).filter(|i| i.finished)?;
+        image.output.as_deref()
+    }
+
+    pub fn set_output(&mut self, path: &Path) -> io::Result<()> {
+        self.image_mut().output = Some(path.to_path_buf());
+        self.save()
+    }
+
+    /// Adds tiles to the list of tiles of the current image,
+    /// and returns how many of them were already downloaded
+    pub fn add_tiles(&mut self, tile_refs: &[TileReference]) -> usize {
+        let image = self.image_mut();
+        let mut completed = 0;
+        for tile_ref in tile_refs {
+            match image.positions.get(&tile_ref.position) {
+                Some(&i) if image.tiles[i] == *tile_ref => {
+                    completed += usize::from(image.completed.contains(i));
+                }
+                _ => {
+                    image.positions.insert(tile_ref.position, image.tiles.len());
+                    image.tiles.push(tile_ref.clone());
+                    image.completed.grow(image.tiles.len());
+                }
+            }
+        }
+        completed
+    }
+
+    /// Marks the tile of the current image at the given position as downloaded
+    pub fn record_success(&mut self, position: Vec2d) {
+        let image = self.image_mut();
+        if let Some(&i) = image.positions.get(&position) {
+            image.completed.insert(i);
+        }
+        if self.last_save.elapsed() >= SAVE_INTERVAL {
+            self.checkpoint();
+        }
+    }
+
+    /// Marks the current image as entirely downloaded
+    pub fn finish_image(&mut self) -> io::Result<()> {
+        self.image_mut().finished = true;
+        self.save()
+    }
+
+    /// Whether all the images of a job with the given number of images were downloaded
+    pub fn is_complete(&self, image_count: usize) -> bool {
+        self.job.images.len() >= image_count && self.job.images.iter().all(|i| i.finished)
+    }
+
+    /// Saves the job, logging failures instead of interrupting the download
+    pub fn checkpoint(&mut self) {
+        if let Err(e) = self.save() {
+            warn!("Unable to save the job to {}: {}", self.path.display(), e);
+        }
+    }
+
+    pub fn save(&mut self) -> io::Result<()> {
+        // The job is written to a temporary file first, so that it is never left half-written
+        let mut tmp_path = self.path.as_os_str().to_owned();
+        tmp_path.push(".tmp");
+        let mut writer = BufWriter::new(File::create(&tmp_path)?);
+        serde_json::to_writer(&mut writer, &self.job)?;
+        writer.flush()?;
+        drop(writer);
+        fs::rename(&tmp_path, &self.path)?;
+        self.last_save = Instant::now();
+        Ok(())
+    }
+
+    /// Removes the job file and the tiles it stored, once the job is complete
+    pub fn remove(self) -> io::Result<()> {
+        let tile_folder = self.tile_folder()?;
+        fs::remove_dir_all(tile_folder)?;
+        if self.path.exists() {
+            fs::remove_file(&self.path)?;
+        }
+        Ok(())
+    }
+
+    fn image_mut(&mut self) -> &mut ImageJob {
+        &mut self.job.images[self.current]
+    }
+}
+
+/// Bitmaps are saved in base64, eight bits per byte
+mod bitmap {
+    use base64::{Engine, engine::general_purpose::STANDARD};
+    use fixedbitset::FixedBitSet;
+    use serde::{Deserialize, Deserializer, Serializer, de::Error};
+
+    pub fn serialize<S: Serializer>(bits: &FixedBitSet, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut bytes = vec![0u8; bits.len().div_ceil(8)];
+        for i in bits.ones() {
+            bytes[i / 8] |= 1 << (i % 8);
+        }
+        serializer.serialize_str(&STANDARD.encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<FixedBitSet, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        let bytes = STANDARD.decode(encoded).map_err(D::Error::custom)?;
+        let mut bits = FixedBitSet::with_capacity(bytes.len() * 8);
+        for i in 0..bits.len() {
+            bits.set(i, bytes[i / 8] & (1 << (i % 8)) != 0);
+        }
+        Ok(bits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dezoomer::TilesRect;
+
+    #[derive(Debug)]
+    struct FakeLvl;
+
+    impl TilesRect for FakeLvl {
+        fn size(&self) -> Vec2d {
+            Vec2d { x: 100, y: 100 }
+        }
+
+        fn tile_size(&self) -> Vec2d {
+            Vec2d { x: 60, y: 60 }
+        }
+
+        fn tile_url(&self, pos: Vec2d) -> String {
+            format!("{},{}", pos.x, pos.y)
+        }
+    }
+
+    fn tile_ref(x: u32, y: u32) -> TileReference {
+        TileReference {
+            url: format!("https://example.com/{x}_{y}.jpg"),
+            position: Vec2d { x, y },
+        }
+    }
+
+    #[test]
+    fn test_job_file() {
+        let dir = tempdir::TempDir::new("dezoomify-rs-job").unwrap();
+        let path = dir.path().join("download.job");
+        let level: ZoomLevel = Box::new(FakeLvl);
+
+        let mut job = JobFile::open(&path, "https://example.com/image").unwrap();
+        assert!(!job.is_resumed());
+        assert_eq!(job.start_image(0, &level), None);
+        job.set_output(Path::new("image.jpg")).unwrap();
+        assert_eq!(job.add_tiles(&[tile_ref(0, 0), tile_ref(1, 0)]), 0);
+        job.record_success(Vec2d { x: 1, y: 0 });
+        job.save().unwrap();
+
+        let mut job = JobFile::open(&path, "https://example.com/image").unwrap();
+        assert!(job.is_resumed());
+        assert_eq!(job.start_image(0, &level), Some(PathBuf::from("image.jpg")));
+        assert_eq!(job.add_tiles(&[tile_ref(0, 0), tile_ref(1, 0)]), 1);
+        assert_eq!(job.finished_output(0), None);
+        assert!(!job.is_complete(1));
+        job.finish_image().unwrap();
+        assert_eq!(job.finished_output(0), Some(Path::new("image.jpg")));
+        assert!(job.is_complete(1));
+        assert!(!job.is_complete(2));
+
+        assert!(JobFile::open(&path, "https://example.com/other").is_err());
+        let job = JobFile::open(&path, "https://example.com/image").unwrap();
+        job.remove().unwrap();
+        assert!(!path.exists());
+    }
+}
@@ -4,6 +4,7 @@ use std::env::current_dir;
 
 use std::io::BufRead;
 use std::path::{Path, PathBuf};
+use std::time::Instant;
 use std::{fs, io};
 
 use log::{debug, error, info};
@@ -14,26 +15,44 @@ pub use binary_display::{BinaryDisplay, display_bytes};
 use dezoomer::TileReference;
 use dezoomer::{Dezoomer, DezoomerError, DezoomerInput};
 use dezoomer::{ZoomLevel, ZoomLevelIter};
+pub use dry_run::{
+    DryRunFormat, ImageDescription, LevelDescription, describe_bulk, describe_image, dry_run,
+};
 pub use errors::ZoomError;
-use network::{client, fetch_uri};
-use output_file::get_outname;
+use network::{client, fetch_uri_with_headers};
+use output_file::{find_partial_output, get_outname};
 use tile::Tile;
 pub use vec2d::Vec2d;
 
-use crate::dezoomer::{DezoomerResult, PageContents, ZoomableImage};
+use crate::dezoomer::{DezoomerResult, DocumentInfo, PageContents, ZoomableImage};
+use crate::encoder::EncoderOptions;
+use crate::encoder::cbz_writer::{CbzWriter, ComicInfo};
+use crate::encoder::pdf_writer::PdfWriter;
 use crate::encoder::tile_buffer::TileBuffer;
 
+use crate::job::JobFile;
 use crate::output_file::reserve_output_file;
+use crate::provenance::Provenance;
+use crate::region::Region;
+use crate::resume::PreviousDownload;
+use crate::sidecar::Sidecar;
 
 mod arguments;
 mod binary_display;
 
 pub mod dezoomer;
 pub(crate) mod download_state;
+mod dry_run;
 mod encoder;
 mod errors;
+mod fill_missing;
+mod job;
 mod network;
 mod output_file;
+mod provenance;
+mod region;
+mod resume;
+mod sidecar;
 pub mod tile;
 mod vec2d;
 
@@ -43,13 +62,18 @@ pub mod custom_yaml;
 pub mod dzi;
 pub mod generic;
 pub mod google_arts_and_culture;
+pub mod html_page;
 pub mod iiif;
 pub mod iipimage;
 mod json_utils;
 pub mod krpano;
 pub mod nypl;
+pub mod openseadragon;
 pub mod pff;
 mod throttler;
+pub mod wmts;
+mod xml_utils;
+pub mod xyz;
 pub mod zoomify;
 
 fn stdin_line() -> Result<String, ZoomError> {
@@ -62,11 +86,13 @@ fn stdin_line() -> Result<String, ZoomError> {
     Ok(first_line?)
 }
 
-/// Process a single dezoomer to get a result, handling the NeedsData loop
+/// Process a single dezoomer to get a result, handling the NeedsData loop.
+/// The URLs of the fetched metadata files are added to `metadata_urls`.
 async fn get_dezoomer_result(
     dezoomer: &mut dyn Dezoomer,
     http: &Client,
     uri: &str,
+    metadata_urls: &mut Vec<String>,
 ) -> Result<DezoomerResult, ZoomError> {
     let mut i = DezoomerInput {
         uri: String::from(uri),
@@ -76,7 +102,9 @@ async fn get_dezoomer_result(
         match dezoomer.dezoomer_result(&i) {
             Ok(result) => return Ok(result),
             Err(DezoomerError::NeedsData { uri }) => {
-                let contents = fetch_uri(&uri, http).await.into();
+                metadata_urls.push(uri.clone());
+                let headers = dezoomer.http_headers(&uri);
+                let contents = fetch_uri_with_headers(&uri, http, &headers).await.into();
                 debug!("Response for metadata file '{}': {:?}", uri, &contents);
                 i.uri = uri;
                 i.contents = contents;
@@ -91,9 +119,10 @@ async fn get_images_from_uri(
     args: &Arguments,
     http: &Client,
     uri: &str,
+    metadata_urls: &mut Vec<String>,
 ) -> Result<Vec<ZoomableImage>, ZoomError> {
     let mut dezoomer = args.find_dezoomer()?;
-    let zoomable_images = get_dezoomer_result(dezoomer.as_mut(), http, uri).await?;
+    let zoomable_images = get_dezoomer_result(dezoomer.as_mut(), http, uri, metadata_urls).await?;
     Ok(zoomable_images)
 }
 
@@ -128,7 +157,7 @@ fn find_level_with_size(levels: &[ZoomLevel], target_size: Vec2d) -> Option<usiz
 }
 
 /// An interactive level picker
-fn level_picker(mut levels: Vec<ZoomLevel>) -> Result<ZoomLevel, ZoomError> {
+fn level_picker(levels: &mut Vec<ZoomLevel>) -> Result<ZoomLevel, ZoomError> {
     println!("Found the following zoom levels:");
     for (i, level) in levels.iter().enumerate() {
         println!("{: >2}. {}", i, level.name());
@@ -143,10 +172,11 @@ fn level_picker(mut levels: Vec<ZoomLevel>) -> Result<ZoomLevel, ZoomError> {
     }
 }
 
-fn choose_level(mut levels: Vec<ZoomLevel>, args: &Arguments) -> Result<ZoomLevel, ZoomError> {
+/// The index of the level to download, if the arguments are enough to choose it
+fn select_level_index(levels: &[ZoomLevel], args: &Arguments) -> Option<usize> {
     match levels.len() {
-        0 => Err(ZoomError::NoLevels),
-        1 => Ok(levels.swap_remove(0)),
+        0 => None,
+        1 => Some(0),
         _ => {
             if let Some(requested_level) = args.zoom_level {
                 let actual_level = resolve_level_index(requested_level, levels.len());
@@ -158,20 +188,41 @@ fn choose_level(mut levels: Vec<ZoomLevel>, args: &Arguments) -> Result<ZoomLeve
                         requested_level, actual_level
                     );
                 }
-                return Ok(levels.swap_remove(actual_level));
-            }
-
-            if let Some(best_size) = args.best_size(levels.iter().filter_map(|l| l.size_hint()))
-                && let Some(pos) = find_level_with_size(&levels, best_size)
-            {
-                return Ok(levels.swap_remove(pos));
+                return Some(actual_level);
             }
-
-            level_picker(levels)
+            let best_size = args.best_size(levels.iter().filter_map(|l| l.size_hint()))?;
+            find_level_with_size(levels, best_size)
         }
     }
 }
 
+/// Removes the level to download from the list of levels, and returns it
+fn choose_level(levels: &mut Vec<ZoomLevel>, args: &Arguments) -> Result<ZoomLevel, ZoomError> {
+    if levels.is_empty() {
+        return Err(ZoomError::NoLevels);
+    }
+    match select_level_index(levels, args) {
+        Some(index) => Ok(levels.swap_remove(index)),
+        None => level_picker(levels),
+    }
+}
+
+/// With `--fill-missing`, the level from which the missing tiles of the chosen level are taken:
+/// the largest one that is smaller than the chosen level
+fn fallback_level(
+    levels: Vec<ZoomLevel>,
+    chosen: &ZoomLevel,
+    args: &Arguments,
+) -> Option<ZoomLevel> {
+    let chosen_size = chosen.size_hint().filter(|_| args.fill_missing)?;
+    levels
+        .into_iter()
+        .filter_map(|level| Some((level.size_hint()?, level)))
+        .filter(|(size, _)| size.x < chosen_size.x && size.y < chosen_size.y)
+        .max_by_key(|(size, _)| size.area())
+        .map(|(_, level)| level)
+}
+
 /// An interactive image picker for when multiple images are available
 fn image_picker(mut images: Vec<ZoomableImage>) -> Result<ZoomableImage, ZoomError> {
     println!("Found the following images:");
@@ -225,29 +276,77 @@ fn choose_image(
     }
 }
 
-/// Finds the appropriate zoomlevel for a given size if one is specified,
-async fn find_zoomlevel(args: &Arguments) -> Result<ZoomLevel, ZoomError> {
+/// A zoomable image found from the input of the user, with all its zoom levels
+struct FoundImage {
+    /// The URI given by the user
+    uri: String,
+    /// The URL of the image, or of the page it was found in
+    source_url: String,
+    title: Option<String>,
+    document: Option<DocumentInfo>,
+    zoom_levels: Vec<ZoomLevel>,
+}
+
+/// Finds the image to download, asking the user to choose one if there are several
+async fn find_image(
+    args: &Arguments,
+    metadata_urls: &mut Vec<String>,
+) -> Result<FoundImage, ZoomError> {
     let uri = args.choose_input_uri()?;
     let http_client = client(args.headers(), args, Some(&uri))?;
     debug!("Trying to locate a zoomable image...");
 
     // Use the new unified processing pipeline
-    let images = get_images_from_uri(args, &http_client, &uri).await?;
+    let images = get_images_from_uri(args, &http_client, &uri, metadata_urls).await?;
     debug!("Found {} zoomable images", images.len());
 
     // Select an image from the available options (before resolving)
     let selected_image = choose_image(images, args)?;
     debug!("Selected image: {:?}", selected_image.title());
+    let source_url = selected_image.url().unwrap_or(&uri).to_string();
+    let title = selected_image.title().map(String::from);
+    let document = selected_image.document().cloned();
 
     // NOW resolve the selected image to get its zoom levels
     let zoom_levels = selected_image
-        .into_zoom_levels(&http_client)
+        .into_zoom_levels(&http_client, &args.iiif_options(), metadata_urls)
         .await
         .map_err(|e| ZoomError::Dezoomer { source: e })?;
     debug!("Extracted {} zoom levels", zoom_levels.len());
+    Ok(FoundImage {
+        uri,
+        source_url,
+        title,
+        document,
+        zoom_levels,
+    })
+}
+
+/// Finds the appropriate zoomlevel for a given size if one is specified,
+/// the level its missing tiles can be taken from, and where it comes from
+async fn find_zoomlevel(
+    args: &Arguments,
+    metadata_urls: &mut Vec<String>,
+) -> Result<(ZoomLevel, Option<ZoomLevel>, Provenance), ZoomError> {
+    let FoundImage {
+        uri,
+        source_url,
+        title,
+        document,
+        mut zoom_levels,
+    } = find_image(args, metadata_urls).await?;
 
     // Select a zoom level from the available options
-    choose_level(zoom_levels, args)
+    let zoom_level = choose_level(&mut zoom_levels, args)?;
+    let fallback = fallback_level(zoom_levels, &zoom_level, args);
+    let provenance = Provenance::new(
+        &uri,
+        &source_url,
+        format!("{zoom_level:?}"),
+        zoom_level.title().or(title),
+        document.as_ref(),
+    );
+    Ok((zoom_level, fallback, provenance))
 }
 
 /// Prepares the output file path for saving
@@ -259,30 +358,113 @@ fn prepare_output_path(
 ) -> Result<PathBuf, ZoomError> {
     let outname = get_outname(outfile_arg, title, base_dir, size_hint);
     let save_as = fs::canonicalize(outname.as_path()).unwrap_or_else(|_e| outname.clone());
+    if let Some(size) = size_hint {
+        encoder::check_output_size(&save_as, size)?;
+    }
     reserve_output_file(&save_as)?;
     Ok(save_as)
 }
 
 /// Creates a tile buffer for the given output path
-async fn create_tile_buffer(save_as: PathBuf, compression: u8) -> Result<TileBuffer, ZoomError> {
-    TileBuffer::new(save_as, compression).await
+async fn create_tile_buffer(
+    save_as: PathBuf,
+    options: EncoderOptions,
+) -> Result<TileBuffer, ZoomError> {
+    TileBuffer::new(save_as, options).await
 }
 
 pub async fn dezoomify(args: &Arguments) -> Result<PathBuf, ZoomError> {
-    let zoom_level = find_zoomlevel(args).await?;
+    let start = Instant::now();
+    let mut metadata_urls = vec![];
+    let (zoom_level, fallback, provenance) = find_zoomlevel(args, &mut metadata_urls).await?;
+    let metadata_duration = start.elapsed();
     let base_dir = current_dir()?;
-    let save_as = prepare_output_path(
-        &args.outfile,
-        &zoom_level.title(),
-        &base_dir,
-        zoom_level.size_hint(),
-    )?;
-    let tile_buffer = create_tile_buffer(save_as.clone(), args.compression).await?;
-    info!("Dezooming {}", zoom_level.name());
-    dezoomify_level(args, zoom_level, tile_buffer).await?;
+    let mut job = match &args.job_file {
+        Some(path) => Some(JobFile::open(path, &provenance.input_uri)?),
+        None => None,
+    };
+    let region = resolve_region(args, &zoom_level)?;
+    let output_size = region.map(|r| r.size).or(zoom_level.size_hint());
+    let job_output = job.as_mut().and_then(|job| job.start_image(0, &zoom_level));
+    let partial_output = if args.resume && job_output.is_none() {
+        find_partial_output(&args.outfile, &zoom_level.title(), &base_dir, output_size)
+    } else {
+        None
+    };
+    let (save_as, previous) = match (job_output, partial_output) {
+        (Some(path), _) => {
+            info!("Continuing the download to {}", path.display());
+            (path, None)
+        }
+        (None, Some(path)) => {
+            info!("Resuming the download of {}", path.display());
+            let previous = PreviousDownload::load(&path, zoom_level.size_hint(), region)?;
+            (path, Some(previous))
+        }
+        (None, None) => {
+            if args.resume {
+                info!("There is no incomplete download to resume. Starting a new download.");
+            }
+            let save_as =
+                prepare_output_path(&args.outfile, &zoom_level.title(), &base_dir, output_size)?;
+            (save_as, None)
+        }
+    };
+    if let Some(job) = &mut job {
+        job.set_output(&save_as)?;
+    }
+    let options = EncoderOptions {
+        provenance: Some(provenance.clone()),
+        ..args.encoder_options()
+    };
+    let tile_buffer = create_tile_buffer(save_as.clone(), options).await?;
+    let level_name = zoom_level.name();
+    info!("Dezooming {level_name}");
+    let start = Instant::now();
+    let mut state = download_state::DownloadState::new();
+    let result = download_level(
+        args,
+        zoom_level,
+        tile_buffer,
+        &mut state,
+        previous,
+        job.as_mut(),
+        fallback,
+    )
+    .await;
+    if args.sidecar && downloaded_image(&result) {
+        let sidecar = Sidecar::new(
+            provenance,
+            level_name,
+            metadata_urls,
+            &state,
+            metadata_duration,
+            start.elapsed(),
+        );
+        save_sidecar(&sidecar, &save_as)?;
+    }
+    resume::update_state(&save_as, &result, &state)?;
+    result?;
+    if let Some(job) = job {
+        job.remove()?;
+    }
     Ok(save_as)
 }
 
+/// Whether an image file was written, even if some of its tiles are missing
+fn downloaded_image(result: &Result<(), ZoomError>) -> bool {
+    matches!(result, Ok(()) | Err(ZoomError::PartialDownload { .. }))
+}
+
+fn save_sidecar(sidecar: &Sidecar, image_path: &Path) -> Result<(), ZoomError> {
+    let path = sidecar.save(image_path)?;
+    info!(
+        "Saved the description of the download to {}",
+        path.display()
+    );
+    Ok(())
+}
+
 /// Statistics for bulk processing
 #[derive(Debug, Default)]
 pub struct BulkStats {
@@ -314,6 +496,92 @@ impl BulkStats {
     }
 }
 
+/// Documents in which all the images of a bulk download are assembled
+#[derive(Default)]
+struct BulkDocuments {
+    pdf: Option<PdfWriter<io::BufWriter<fs::File>>>,
+    cbz: Option<CbzWriter>,
+    /// Folder where the images are saved before being moved to the cbz archive
+    cbz_pages_dir: Option<PathBuf>,
+}
+
+impl BulkDocuments {
+    /// Creates the documents. When `overwrite` is false, they must not already exist.
+    fn create(args: &Arguments, image_count: usize, overwrite: bool) -> Result<Self, ZoomError> {
+        let mut documents = BulkDocuments::default();
+        if let Some(path) = &args.pdf {
+            if !overwrite {
+                reserve_output_file(path)?;
+            }
+            let file = io::BufWriter::new(fs::File::create(path)?);
+            documents.pdf = Some(PdfWriter::new(file)?);
+        }
+        if let Some(path) = &args.cbz {
+            if !overwrite {
+                reserve_output_file(path)?;
+            }
+            documents.cbz = Some(CbzWriter::new(path, image_count)?);
+            let mut pages_dir = path.clone().into_os_string();
+            pages_dir.push(".pages");
+            let pages_dir = PathBuf::from(pages_dir);
+            fs::create_dir_all(&pages_dir)?;
+            documents.cbz_pages_dir = Some(pages_dir);
+        }
+        Ok(documents)
+    }
+
+    /// Adds a successfully downloaded image to all the documents
+    fn add_image(&mut self, path: &Path, page_size: Option<Vec2d>) {
+        if let Some(pdf) = &mut self.pdf
+            && let Err(e) = pdf.add_page(path, page_size)
+        {
+            log::warn!(
+                "Failed to add {} to the PDF document: {}",
+                path.display(),
+                e
+            );
+        }
+        if let Some(cbz) = &mut self.cbz {
+            match cbz.add_page(path) {
+                Ok(()) => {
+                    let sidecar = sidecar::sidecar_path(path);
+                    let added_files = [path, &sidecar];
+                    for file in added_files.into_iter().filter(|f| f.exists()) {
+                        fs::remove_file(file).unwrap_or_else(|e| {
+                            log::warn!("Unable to remove {}: {}", file.display(), e);
+                        });
+                    }
+                }
+                Err(e) => log::warn!("Failed to add {} to the archive: {}", path.display(), e),
+            }
+        }
+    }
+
+    fn finish(self, args: &Arguments, document: &DocumentInfo) -> Result<(), ZoomError> {
+        let title = document.title.as_deref();
+        if let (Some(pdf), Some(path)) = (self.pdf, &args.pdf) {
+            let page_count = pdf.page_count();
+            pdf.finish(title)?;
+            info!("Saved {} pages to {}", page_count, path.display());
+        }
+        if let (Some(cbz), Some(path)) = (self.cbz, &args.cbz) {
+            let page_count = cbz.page_count();
+            cbz.finish(&ComicInfo::from_metadata(title, &document.metadata))?;
+            info!("Saved {} pages to {}", page_count, path.display());
+        }
+        // Only the images that could not be added to the archive remain in the folder
+        if let Some(dir) = self.cbz_pages_dir
+            && fs::remove_dir(&dir).is_err()
+        {
+            log::warn!(
+                "Some images were not added to the archive. They were kept in {}",
+                dir.display()
+            );
+        }
+        Ok(())
+    }
+}
+
 /// Process multiple images in bulk mode using the new unified architecture
 pub async fn process_bulk(args: &Arguments) -> Result<BulkStats, ZoomError> {
     use log::{debug, trace};
@@ -322,16 +590,22 @@ pub async fn process_bulk(args: &Arguments) -> Result<BulkStats, ZoomError> {
     trace!("Bulk processing arguments: {:?}", args);
 
     // Get the bulk file/URI from arguments
-    let bulk_uri = args.bulk.as_ref().ok_or_else(|| ZoomError::NoBulkUrl {
-        bulk_file_path: "No bulk source specified".to_string(),
-    })?;
+    let bulk_uri = &args.bulk_source()?;
 
     debug!("Bulk source: {}", bulk_uri);
+    if args.resume {
+        return Err(ZoomError::UnsupportedInBulkMode {
+            option: "--resume".into(),
+            reason: "use --job to continue an interrupted bulk download".into(),
+        });
+    }
 
     // Get dezoomer result from the bulk source
     let http = client(std::iter::empty(), args, None)?;
     let mut dezoomer = args.find_dezoomer()?;
-    let dezoomer_result = get_dezoomer_result(dezoomer.as_mut(), &http, bulk_uri).await?;
+    let mut metadata_urls = vec![];
+    let dezoomer_result =
+        get_dezoomer_result(dezoomer.as_mut(), &http, bulk_uri, &mut metadata_urls).await?;
 
     let mut stats = BulkStats::new();
     let base_dir = current_dir()?;
@@ -350,7 +624,52 @@ pub async fn process_bulk(args: &Arguments) -> Result<BulkStats, ZoomError> {
             .collect::<Vec<_>>()
     );
 
-    process_bulk_zoomable_images(dezoomer_result, args, &http, &mut stats, &base_dir).await?;
+    let document = dezoomer_result
+        .iter()
+        .find_map(|img| img.document())
+        .cloned()
+        .unwrap_or_default();
+    let image_count = dezoomer_result.len();
+    let mut job = match &args.job_file {
+        Some(path) => Some(JobFile::open(path, bulk_uri)?),
+        None => None,
+    };
+    // The documents of a previous run of the job are replaced
+    let overwrite = job.as_ref().is_some_and(JobFile::is_resumed);
+    let mut documents = BulkDocuments::create(args, image_count, overwrite)?;
+    // When the images go to an archive, they are saved to a temporary folder first
+    let (base_dir, outfile) = match &documents.cbz_pages_dir {
+        Some(dir) => (
+            dir.clone(),
+            args.outfile
+                .as_ref()
+                .and_then(|f| f.file_name())
+                .map(|name| dir.join(name)),
+        ),
+        None => (base_dir, args.outfile.clone()),
+    };
+
+    process_bulk_zoomable_images(
+        dezoomer_result,
+        args,
+        &http,
+        &mut stats,
+        &base_dir,
+        &outfile,
+        &mut documents,
+        &metadata_urls,
+        job.as_mut(),
+    )
+    .await?;
+
+    documents.finish(args, &document)?;
+    if let Some(job) = job {
+        if job.is_complete(image_count) {
+            job.remove()?;
+        } else {
+            info!("Run the same command again to retry the images that could not be downloaded");
+        }
+    }
 
     // Log final statistics
     info!("Bulk processing complete!");
@@ -364,13 +683,20 @@ pub async fn process_bulk(args: &Arguments) -> Result<BulkStats, ZoomError> {
     Ok(stats)
 }
 
-/// Process a list of ZoomableImage objects in bulk - resolve each one to zoom levels as needed
+/// Process a list of ZoomableImage objects in bulk - resolve each one to zoom levels as needed.
+/// `bulk_metadata_urls` are the metadata files that were fetched to list the images.
+/// The progress of the downloads is recorded in the `job`, if there is one.
+#[allow(clippy::too_many_arguments)]
 async fn process_bulk_zoomable_images(
     images: Vec<ZoomableImage>,
     args: &Arguments,
     http: &Client,
     stats: &mut BulkStats,
     base_dir: &Path,
+    outfile: &Option<PathBuf>,
+    documents: &mut BulkDocuments,
+    bulk_metadata_urls: &[String],
+    mut job: Option<&mut JobFile>,
 ) -> Result<(), ZoomError> {
     use log::{debug, trace, warn};
 
@@ -380,6 +706,16 @@ async fn process_bulk_zoomable_images(
             .title()
             .unwrap_or_else(|| format!("Image_{}", index + 1).into())
             .to_string();
+        let page_size = zoomable_image.page_size();
+        let source_url = zoomable_image
+            .url()
+            .or(args.bulk.as_deref())
+            .or(args.input_uri.as_deref())
+            .unwrap_or_default()
+            .to_string();
+        let document = zoomable_image.document().cloned();
+        let start = Instant::now();
+        let mut metadata_urls = bulk_metadata_urls.to_vec();
         debug!(
             "Preparing image {}/{}: {}",
             index + 1,
@@ -388,7 +724,10 @@ async fn process_bulk_zoomable_images(
         );
 
         // Resolve the ZoomableImage to get zoom levels
-        let zoom_levels = match zoomable_image.into_zoom_levels(http).await {
+        let mut zoom_levels = match zoomable_image
+            .into_zoom_levels(http, &args.iiif_options(), &mut metadata_urls)
+            .await
+        {
             Ok(levels) => levels,
             Err(e) => {
                 warn!(
@@ -409,7 +748,7 @@ async fn process_bulk_zoomable_images(
         );
 
         // Choose the appropriate zoom level using existing logic
-        let zoom_level = match choose_level(zoom_levels, args) {
+        let zoom_level = match choose_level(&mut zoom_levels, args) {
             Ok(level) => level,
             Err(e) => {
                 warn!(
@@ -423,6 +762,7 @@ async fn process_bulk_zoomable_images(
             }
         };
 
+        let fallback = fallback_level(zoom_levels, &zoom_level, args);
         debug!(
             "Selected zoom level for image {}: {} ({}x{})",
             index + 1,
@@ -431,15 +771,43 @@ async fn process_bulk_zoomable_images(
             zoom_level.size_hint().map(|s| s.y).unwrap_or(0)
         );
 
+        let output_size = match resolve_region(args, &zoom_level) {
+            Ok(region) => region.map(|r| r.size).or(zoom_level.size_hint()),
+            Err(e) => {
+                warn!("Unable to download image {}: {}", index + 1, e);
+                stats.record_failure();
+                continue;
+            }
+        };
+        let job_output = job
+            .as_deref_mut()
+            .and_then(|job| job.start_image(index, &zoom_level));
+        if let Some(job) = job.as_deref()
+            && let Some(output) = job.finished_output(index).filter(|o| o.exists())
+        {
+            info!(
+                "Image {} was already downloaded to {}",
+                index + 1,
+                output.display()
+            );
+            stats.record_success();
+            documents.add_image(output, page_size);
+            continue;
+        }
+
         // Use get_outname to handle file collision properly, without args.outfile override
-        let save_as = if let Some(ref base_outfile) = args.outfile {
+        let continuing = job_output.is_some();
+        let save_as = if let Some(output) = job_output {
+            // Continue the download of the image started by a previous run of the job
+            output
+        } else if let Some(base_outfile) = outfile {
             // In bulk mode with specified outfile, use index-based naming with collision handling
             let base_path = generate_bulk_output_name(base_outfile, index);
             get_outname(
                 &Some(base_path),
                 &zoom_level.title().or_else(|| Some(image_title.clone())),
                 base_dir,
-                zoom_level.size_hint(),
+                output_size,
             )
         } else {
             // Use the zoom level title if present, fallback to image title
@@ -447,12 +815,27 @@ async fn process_bulk_zoomable_images(
                 &None,
                 &zoom_level.title().or_else(|| Some(image_title.clone())),
                 base_dir,
-                zoom_level.size_hint(),
+                output_size,
             )
         };
 
-        // Reserve the output file to avoid collisions
-        if let Err(e) = reserve_output_file(&save_as) {
+        // Check that the output format can hold the image before reserving its file
+        if let Some(size) = output_size
+            && let Err(e) = encoder::check_output_size(&save_as, size)
+        {
+            warn!(
+                "Unable to save image {} ('{}'): {}",
+                index + 1,
+                image_title,
+                e
+            );
+            stats.record_failure();
+            continue;
+        }
+
+        // Reserve the output file to avoid collisions,
+        // unless it was already created by a previous run of the job
+        if !continuing && let Err(e) = reserve_output_file(&save_as) {
             let file_name = save_as
                 .file_name()
                 .map(|n| n.to_string_lossy())
@@ -467,8 +850,25 @@ async fn process_bulk_zoomable_images(
             stats.record_failure();
             continue;
         };
+        if let Some(job) = job.as_deref_mut()
+            && let Err(e) = job.set_output(&save_as)
+        {
+            warn!("Failed to save the job of image {}: {}", index + 1, e);
+        }
 
-        let tile_buffer = match create_tile_buffer(save_as.clone(), args.compression).await {
+        let metadata_duration = start.elapsed();
+        let provenance = Provenance::new(
+            args.bulk.as_deref().unwrap_or_default(),
+            &source_url,
+            format!("{zoom_level:?}"),
+            zoom_level.title().or_else(|| Some(image_title.clone())),
+            document.as_ref(),
+        );
+        let options = EncoderOptions {
+            provenance: Some(provenance.clone()),
+            ..args.encoder_options()
+        };
+        let tile_buffer = match create_tile_buffer(save_as.clone(), options).await {
             Ok(buffer) => buffer,
             Err(e) => {
                 let file_name = save_as
@@ -496,7 +896,38 @@ async fn process_bulk_zoomable_images(
             save_as.file_name().unwrap_or_default().to_string_lossy()
         );
 
-        match dezoomify_level(args, zoom_level, tile_buffer).await {
+        let level_name = zoom_level.name();
+        let start = Instant::now();
+        let mut state = download_state::DownloadState::new();
+        let result = download_level(
+            args,
+            zoom_level,
+            tile_buffer,
+            &mut state,
+            None,
+            job.as_deref_mut(),
+            fallback,
+        )
+        .await;
+        if args.sidecar && downloaded_image(&result) {
+            let sidecar = Sidecar::new(
+                provenance,
+                level_name,
+                metadata_urls,
+                &state,
+                metadata_duration,
+                start.elapsed(),
+            );
+            if let Err(e) = save_sidecar(&sidecar, &save_as) {
+                warn!(
+                    "Failed to save the description of image {}: {}",
+                    index + 1,
+                    e
+                );
+            }
+        }
+
+        match result {
             Ok(()) => {
                 info!(
                     "Successfully saved image {} to {}",
@@ -504,6 +935,12 @@ async fn process_bulk_zoomable_images(
                     save_as.display()
                 );
                 stats.record_success();
+                if let Some(job) = job.as_deref_mut()
+                    && let Err(e) = job.finish_image()
+                {
+                    warn!("Failed to save the job of image {}: {}", index + 1, e);
+                }
+                documents.add_image(&save_as, page_size);
             }
             Err(ZoomError::PartialDownload {
                 successful_tiles,
@@ -584,14 +1021,33 @@ fn determine_final_result(
 }
 
 pub async fn dezoomify_level(
+    args: &Arguments,
+    zoom_level: ZoomLevel,
+    tile_buffer: TileBuffer,
+) -> Result<(), ZoomError> {
+    let mut state = download_state::DownloadState::new();
+    download_level(args, zoom_level, tile_buffer, &mut state, None, None, None).await
+}
+
+/// Downloads all the tiles of a level, recording the progress of the download in `state`.
+/// When completing a `previous` download, only its missing tiles are requested.
+/// The downloaded tiles are recorded in the `job`, if there is one.
+/// With `--fill-missing`, the missing tiles are taken from the `fallback` level.
+async fn download_level(
     args: &Arguments,
     mut zoom_level: ZoomLevel,
     tile_buffer: TileBuffer,
+    state: &mut download_state::DownloadState,
+    previous: Option<PreviousDownload>,
+    job: Option<&mut JobFile>,
+    fallback: Option<ZoomLevel>,
 ) -> Result<(), ZoomError> {
     debug!("Starting to dezoomify {zoom_level:?}");
+    state.region = resolve_region(args, &zoom_level)?;
     let mut canvas = tile_buffer;
-    let mut coordinator = download_state::TileDownloadCoordinator::new(&zoom_level, args)?;
-    let mut state = download_state::DownloadState::new();
+    let mut coordinator = download_state::TileDownloadCoordinator::new(&zoom_level, args)?
+        .resuming(previous)
+        .recording_to(job)?;
     let progress = download_state::ProgressManager::new();
 
     progress.set_computing_urls();
@@ -600,26 +1056,78 @@ pub async fn dezoomify_level(
 
     while let Some(tile_refs) = zoom_level_iter.next_tile_references() {
         coordinator
-            .download_batch(
-                tile_refs,
-                &mut canvas,
-                &mut state,
-                &progress,
-                &zoom_level_iter,
-            )
+            .download_batch(tile_refs, &mut canvas, state, &progress, &zoom_level_iter)
             .await?;
 
         zoom_level_iter.set_fetch_result(state.create_fetch_result());
     }
 
-    validate_download_success(&state)?;
+    if args.fill_missing {
+        fill_missing_tiles(args, fallback, &mut canvas, state).await;
+    }
+
+    validate_download_success(state)?;
 
     progress.set_finalizing();
     canvas.finalize().await?;
     progress.finish();
 
     let destination = canvas.destination().to_string_lossy().to_string();
-    determine_final_result(&state, destination)
+    determine_final_result(state, destination)
+}
+
+/// Fills the holes left by the tiles that could not be downloaded with
+/// the same parts of the image, taken from the `fallback` level and upscaled
+async fn fill_missing_tiles(
+    args: &Arguments,
+    fallback: Option<ZoomLevel>,
+    canvas: &mut TileBuffer,
+    state: &mut download_state::DownloadState,
+) {
+    let regions = fill_missing::missing_regions(state);
+    let Some(image_size) = state.image_size.filter(|_| !regions.is_empty()) else {
+        return;
+    };
+    let mut tiles = vec![None; regions.len()];
+    if let Some(level) = fallback {
+        let level_name = level.name();
+        info!(
+            "Filling {} missing tiles from the lower level {}",
+            regions.len(),
+            level_name
+        );
+        match fill_missing::upscale_from_level(level, args, &regions, image_size).await {
+            Ok(upscaled) => tiles = upscaled,
+            Err(e) => log::warn!("Unable to fill the missing tiles: {e}"),
+        }
+    } else {
+        log::warn!("There is no lower zoom level to fill the missing tiles from");
+    }
+    for (region, tile) in regions.into_iter().zip(tiles) {
+        match tile {
+            Some(tile) => {
+                info!(
+                    "Filled the missing tile at {} with an upscaled image of {}x{} pixels",
+                    region.position, region.size.x, region.size.y
+                );
+                state.upscaled_regions.push(region);
+                if let Some(tile) = state.visible_part(tile) {
+                    canvas.add_tile(tile).await;
+                }
+            }
+            None => {
+                if let Some(tile) = state.visible_part(Tile::empty(region.position, region.size)) {
+                    canvas.add_tile(tile).await;
+                }
+            }
+        }
+    }
+}
+
+/// The part of the zoom level to download, when `--region` is used
+fn resolve_region(args: &Arguments, zoom_level: &ZoomLevel) -> Result<Option<Region>, ZoomError> {
+    let region = args.region.map(|spec| spec.resolve(zoom_level.size_hint()));
+    region.transpose()
 }
 
 /// Returns the maximal size a tile can have in order to fit in a canvas of the given size
@@ -879,6 +1387,10 @@ mod iiif_title_tests {
             metadata_title: Some("Metadata Title".to_string()),
             canvas_label: Some("Canvas Label".to_string()),
             canvas_index: 0,
+            canvas_size: None,
+            manifest_metadata: vec![],
+            rights: None,
+            required_statement: None,
         };
 
         let result = determine_title(&image_info);
@@ -896,6 +1408,10 @@ mod iiif_title_tests {
             metadata_title: None,
             canvas_label: Some("Page 1".to_string()),
             canvas_index: 0,
+            canvas_size: None,
+            manifest_metadata: vec![],
+            rights: None,
+            required_statement: None,
         };
 
         let result = determine_title(&image_info);
@@ -910,6 +1426,10 @@ mod iiif_title_tests {
             metadata_title: None,
             canvas_label: Some("Single Page".to_string()),
             canvas_index: 0,
+            canvas_size: None,
+            manifest_metadata: vec![],
+            rights: None,
+            required_statement: None,
         };
 
         let result = determine_title(&image_info);
@@ -925,6 +1445,10 @@ mod iiif_title_tests {
             metadata_title: Some("Same Title".to_string()), // Duplicate
             canvas_label: Some("Different Label".to_string()),
             canvas_index: 0,
+            canvas_size: None,
+            manifest_metadata: vec![],
+            rights: None,
+            required_statement: None,
         };
 
         let result = determine_title(&image_info);
@@ -939,6 +1463,10 @@ mod iiif_title_tests {
             metadata_title: None,
             canvas_label: None,
             canvas_index: 0,
+            canvas_size: None,
+            manifest_metadata: vec![],
+            rights: None,
+            required_statement: None,
         };
 
         let result = determine_title(&image_info);
@@ -953,6 +1481,10 @@ mod iiif_title_tests {
             metadata_title: Some("Metadata Only".to_string()),
             canvas_label: None,
             canvas_index: 0,
+            canvas_size: None,
+            manifest_metadata: vec![],
+            rights: None,
+            required_statement: None,
         };
 
         let result = determine_title(&image_info);
@@ -967,6 +1499,10 @@ mod iiif_title_tests {
             metadata_title: None,
             canvas_label: Some("Page #1: Introduction/Overview".to_string()),
             canvas_index: 0,
+            canvas_size: None,
+            manifest_metadata: vec![],
+            rights: None,
+            required_statement: None,
         };
 
         let result = determine_title(&image_info);
@@ -990,6 +1526,10 @@ mod iiif_title_tests {
             metadata_title: None,
             canvas_label: Some(long_canvas.clone()),
             canvas_index: 0,
+            canvas_size: None,
+            manifest_metadata: vec![],
+            rights: None,
+            required_statement: None,
         };
 
         let result = determine_title(&image_info);
@@ -1005,6 +1545,10 @@ mod iiif_title_tests {
             metadata_title: Some("詩經選讀".to_string()),
             canvas_label: Some("第一章：關雎".to_string()),
             canvas_index: 0,
+            canvas_size: None,
+            manifest_metadata: vec![],
+            rights: None,
+            required_statement: None,
         };
 
         let result = determine_title(&image_info);
@@ -1022,6 +1566,10 @@ mod iiif_title_tests {
             metadata_title: Some("\tTabbed metadata\t".to_string()),
             canvas_label: Some("Canvas\nwith\nnewlines".to_string()),
             canvas_index: 0,
+            canvas_size: None,
+            manifest_metadata: vec![],
+            rights: None,
+            required_statement: None,
         };
 
         let result = determine_title(&image_info);
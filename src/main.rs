@@ -1,7 +1,7 @@
 use env_logger::TimestampPrecision;
 use human_panic::setup_panic;
 
-use dezoomify_rs::{Arguments, ZoomError, dezoomify, process_bulk};
+use dezoomify_rs::{Arguments, ZoomError, dezoomify, dry_run, process_bulk};
 use log::{error, info, warn};
 
 #[tokio::main]
@@ -12,7 +12,13 @@ async fn main() {
     let args: Arguments = clap::Parser::parse();
     init_log(&args);
 
-    if args.is_bulk_mode() {
+    if let Some(format) = args.dry_run {
+        // Describe the image, or every image of the bulk input, without downloading it
+        if let Err(err) = dry_run(&args, format).await {
+            error!("{err}");
+            has_errors = true;
+        }
+    } else if args.is_bulk_mode() {
         // Bulk processing mode
         match process_bulk(&args).await {
             Ok(stats) => {
@@ -22,8 +22,21 @@ use crate::tile::{Tile, load_image_with_metadata};
 /// to a local file
 // TODO: return Bytes
 pub async fn fetch_uri(uri: &str, http: &Client) -> Result<Vec<u8>, ZoomError> {
+    fetch_uri_with_headers(uri, http, &HashMap::new()).await
+}
+
+/// Same as [fetch_uri], sending additional http headers with the request
+pub async fn fetch_uri_with_headers(
+    uri: &str,
+    http: &Client,
+    headers: &HashMap<String, String>,
+) -> Result<Vec<u8>, ZoomError> {
     if uri.starts_with("http://") || uri.starts_with("https://") {
-        let req = http.get(uri).build()?;
+        let mut req = http.get(uri);
+        for (name, value) in headers {
+            req = req.header(name, value);
+        }
+        let req = req.build()?;
         debug!(
             "Making http request to {uri} with headers '{:?}'",
             req.headers()
@@ -0,0 +1,258 @@
+use std::sync::Arc;
+
+use custom_error::custom_error;
+use log::debug;
+
+use tile_source::{CustomTileSource, TileSource, fill_template, normalize_javascript};
+
+use crate::dezoomer::*;
+use crate::dzi::load_from_dzi;
+use crate::json_utils::all_json;
+use crate::network::resolve_relative;
+
+mod tile_source;
+
+/// A dezoomer for the tile sources that are defined inline,
+/// in the javascript code that creates an OpenSeadragon viewer
+/// See https://openseadragon.github.io/examples/tilesource-custom/
+#[derive(Default)]
+pub struct OpenSeadragonDezoomer;
+
+impl Dezoomer for OpenSeadragonDezoomer {
+    fn name(&self) -> &'static str {
+        "openseadragon"
+    }
+
+    fn zoom_levels(&mut self, data: &DezoomerInput) -> Result<ZoomLevels, DezoomerError> {
+        let DezoomerInputWithContents { uri, contents } = data.with_contents()?;
+        let levels = load_from_code(uri, contents)?;
+        Ok(levels)
+    }
+}
+
+custom_error! {pub OpenSeadragonError
+    NoTileSource = "No inline OpenSeadragon tile source was found",
+}
+
+impl From<OpenSeadragonError> for DezoomerError {
+    fn from(err: OpenSeadragonError) -> Self {
+        DezoomerError::Other { source: err.into() }
+    }
+}
+
+fn load_from_code(url: &str, contents: &[u8]) -> Result<ZoomLevels, OpenSeadragonError> {
+    let code = String::from_utf8_lossy(contents);
+    let code = normalize_javascript(&code);
+    let levels: ZoomLevels = all_json::<TileSource>(code.as_bytes())
+        .flat_map(|source| {
+            debug!("Found an inline OpenSeadragon tile source: {source:?}");
+            load_from_tile_source(url, source)
+        })
+        .collect();
+    if levels.is_empty() {
+        Err(OpenSeadragonError::NoTileSource)
+    } else {
+        Ok(levels)
+    }
+}
+
+fn load_from_tile_source(url: &str, source: TileSource) -> ZoomLevels {
+    match source {
+        TileSource::LegacyImagePyramid(pyramid) => pyramid
+            .levels
+            .into_iter()
+            .filter(|level| level.width > 0 && level.height > 0)
+            .map(|level| LegacyLevel {
+                url: resolve_relative(url, &level.url),
+                size: Vec2d {
+                    x: level.width,
+                    y: level.height,
+                },
+            })
+            .into_zoom_levels(),
+        TileSource::Custom(source) => load_from_custom(url, source),
+        TileSource::DeepZoom { image } => load_from_dzi(url, image).unwrap_or_else(|e| {
+            debug!("Invalid inline deep zoom tile source: {e}");
+            vec![]
+        }),
+    }
+}
+
+fn load_from_custom(url: &str, source: CustomTileSource) -> ZoomLevels {
+    let Some(tile_size) = source.tile_size() else {
+        debug!("The tile source has no tile size");
+        return vec![];
+    };
+    let page_url: Arc<str> = Arc::from(url);
+    let template: Arc<str> = Arc::from(source.get_tile_url.as_str());
+    let size = source.size();
+    let max_level = source.max_level();
+    (source.min_level..=max_level)
+        .rev()
+        // Each level is half the size of the next one
+        .map_while(|level| {
+            let scale = 1u32.checked_shl(max_level - level)?;
+            Some(CustomLevel {
+                page_url: Arc::clone(&page_url),
+                template: Arc::clone(&template),
+                size: size.ceil_div(Vec2d::square(scale)),
+                tile_size,
+                overlap: source.tile_overlap,
+                level,
+            })
+        })
+        .into_zoom_levels()
+}
+
+/// A level of a legacy image pyramid, which is made of a single image
+struct LegacyLevel {
+    url: String,
+    size: Vec2d,
+}
+
+impl TilesRect for LegacyLevel {
+    fn size(&self) -> Vec2d {
+        self.size
+    }
+
+    fn tile_size(&self) -> Vec2d {
+        self.size
+    }
+
+    fn tile_url(&self, _pos: Vec2d) -> String {
+        self.url.clone()
+    }
+}
+
+impl std::fmt::Debug for LegacyLevel {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "OpenSeadragon legacy image pyramid")
+    }
+}
+
+/// A level of a tile source with a custom `getTileUrl` function
+struct CustomLevel {
+    page_url: Arc<str>,
+    template: Arc<str>,
+    size: Vec2d,
+    tile_size: Vec2d,
+    overlap: u32,
+    level: u32,
+}
+
+impl TilesRect for CustomLevel {
+    fn size(&self) -> Vec2d {
+        self.size
+    }
+
+    fn tile_size(&self) -> Vec2d {
+        self.tile_size
+    }
+
+    fn tile_url(&self, pos: Vec2d) -> String {
+        let path = fill_template(&self.template, self.level, pos);
+        resolve_relative(&self.page_url, &path)
+    }
+
+    fn tile_ref(&self, pos: Vec2d) -> TileReference {
+        let delta = Vec2d {
+            x: if pos.x == 0 { 0 } else { self.overlap },
+            y: if pos.y == 0 { 0 } else { self.overlap },
+        };
+        TileReference {
+            url: self.tile_url(pos),
+            position: self.tile_size() * pos - delta,
+        }
+    }
+}
+
+impl std::fmt::Debug for CustomLevel {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "OpenSeadragon custom tile source")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tile_urls(level: &mut ZoomLevel) -> Vec<String> {
+        level.next_tiles(None).into_iter().map(|t| t.url).collect()
+    }
+
+    #[test]
+    fn test_legacy_image_pyramid() {
+        let code = br#"OpenSeadragon({
+            id: "viewer",
+            tileSources: {
+                type: 'legacy-image-pyramid',
+                levels: [
+                    {url: '/images/small.jpg', width: 500, height: 300},
+                    {url: 'https://example.org/large.jpg', width: 2000, height: 1200},
+                ]
+            }
+        });"#;
+        let mut levels = load_from_code("https://example.com/page.html", code).unwrap();
+        assert_eq!(levels.len(), 2);
+        assert_eq!(levels[1].size_hint(), Some(Vec2d { x: 2000, y: 1200 }));
+        assert_eq!(
+            tile_urls(&mut levels[0]),
+            ["https://example.com/images/small.jpg"]
+        );
+        assert_eq!(tile_urls(&mut levels[1]), ["https://example.org/large.jpg"]);
+    }
+
+    #[test]
+    fn test_custom_tile_source() {
+        let code = br#"<script>
+        var viewer = OpenSeadragon({
+            id: "viewer",
+            tileSources: {
+                height: 1000, width: 1500, tileSize: 512, tileOverlap: 1, minLevel: 9,
+                getTileUrl: function( level, x, y ){
+                    return "tiles/" + (level - 9) + "/" + x + "-" + y + ".jpg";
+                }
+            }
+        });
+        </script>"#;
+        let mut levels = load_from_code("https://example.com/viewer/page.html", code).unwrap();
+        assert_eq!(levels.len(), 3);
+        let largest = &mut levels[0];
+        assert_eq!(largest.size_hint(), Some(Vec2d { x: 1500, y: 1000 }));
+        let tiles = largest.next_tiles(None);
+        assert_eq!(tiles.len(), 6);
+        assert_eq!(tiles[1].url, "https://example.com/viewer/tiles/2/1-0.jpg");
+        assert_eq!(tiles[1].position, Vec2d { x: 511, y: 0 });
+        assert_eq!(levels[2].size_hint(), Some(Vec2d { x: 375, y: 250 }));
+        assert_eq!(
+            tile_urls(&mut levels[2]),
+            ["https://example.com/viewer/tiles/0/0-0.jpg"]
+        );
+    }
+
+    #[test]
+    fn test_inline_deep_zoom() {
+        let code = br#"tileSources: {
+            Image: {
+                xmlns: "http://schemas.microsoft.com/deepzoom/2008",
+                Url: "/images/highsmith_files/", Format: "jpg", Overlap: "2", TileSize: "256",
+                Size: { Height: "9221", Width: "7026" }
+            }
+        }"#;
+        let mut levels = load_from_code("https://example.com/page.html", code).unwrap();
+        assert_eq!(levels[0].size_hint(), Some(Vec2d { x: 7026, y: 9221 }));
+        assert_eq!(
+            tile_urls(&mut levels[0])[0],
+            "https://example.com/images/highsmith_files/14/0_0.jpg"
+        );
+    }
+
+    #[test]
+    fn test_no_tile_source() {
+        let code = br#"OpenSeadragon({id: "viewer", tileSources: "/images/a.dzi"});"#;
+        assert!(load_from_code("https://example.com/page.html", code).is_err());
+        // The levels of a legacy pyramid are not tile sources on their own
+        let code = br#"{url: 'a.jpg', width: 500, height: 300}"#;
+        assert!(load_from_code("https://example.com/page.html", code).is_err());
+    }
+}
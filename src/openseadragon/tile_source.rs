@@ -0,0 +1,273 @@
+use std::borrow::Cow;
+
+use lazy_static::lazy_static;
+use regex::{Captures, Regex};
+use serde::Deserialize;
+
+use crate::Vec2d;
+use crate::dzi::DziFile;
+
+/// A tile source written inline in the configuration of an OpenSeadragon viewer
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum TileSource {
+    LegacyImagePyramid(LegacyImagePyramid),
+    Custom(CustomTileSource),
+    /// `{Image: {Url, Format, Overlap, TileSize, Size: {Width, Height}}}`
+    DeepZoom {
+        #[serde(rename = "Image")]
+        image: DziFile,
+    },
+}
+
+#[derive(Debug, Deserialize)]
+enum LegacyType {
+    #[serde(rename = "legacy-image-pyramid")]
+    LegacyImagePyramid,
+}
+
+/// `{type: 'legacy-image-pyramid', levels: [{url, width, height}, ...]}`
+#[derive(Debug, Deserialize)]
+pub struct LegacyImagePyramid {
+    #[serde(rename = "type")]
+    _type: LegacyType,
+    pub levels: Vec<LegacyLevel>,
+}
+
+/// A level of a legacy image pyramid, which is a single image
+#[derive(Debug, Deserialize)]
+pub struct LegacyLevel {
+    pub url: String,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// `{width, height, tileSize, getTileUrl: function(level, x, y) {...}}`
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CustomTileSource {
+    pub width: u32,
+    pub height: u32,
+    tile_size: Option<u32>,
+    tile_width: Option<u32>,
+    tile_height: Option<u32>,
+    #[serde(default)]
+    pub tile_overlap: u32,
+    #[serde(default)]
+    pub min_level: u32,
+    max_level: Option<u32>,
+    /// The URL template that [normalize_javascript] made from the `getTileUrl` function
+    pub get_tile_url: String,
+}
+
+impl CustomTileSource {
+    pub fn size(&self) -> Vec2d {
+        Vec2d {
+            x: self.width,
+            y: self.height,
+        }
+    }
+
+    pub fn tile_size(&self) -> Option<Vec2d> {
+        let x = self.tile_width.or(self.tile_size)?;
+        let y = self.tile_height.or(self.tile_size)?;
+        (x > 0 && y > 0).then_some(Vec2d { x, y })
+    }
+
+    /// The level at which the image is displayed at its full size.
+    /// OpenSeadragon numbers levels like deep zoom images: level 0 is a single pixel.
+    pub fn max_level(&self) -> u32 {
+        self.max_level.unwrap_or_else(|| {
+            let largest = self.width.max(self.height).max(1);
+            largest
+                .checked_next_power_of_two()
+                .map_or(u32::BITS, u32::trailing_zeros)
+        })
+    }
+}
+
+/// A javascript expression, until the end of the statement or of the object property
+const EXPRESSION: &str = r#"((?:`[^`]*`|"[^"]*"|'[^']*'|[^;{},"'`])+)"#;
+
+lazy_static! {
+    /// `getTileUrl: function(level, x, y) { return ...; }`, `getTileUrl(level, x, y) { ... }`
+    /// and `getTileUrl: (level, x, y) => ...`
+    static ref GET_TILE_URL: Regex = Regex::new(&format!(
+        concat!(
+            r"getTileUrl\s*(?::\s*(?:function\b\s*\w*\s*)?)?\(([\w\s,]*)\)\s*(?:=>\s*)?",
+            r"(?:\{{\s*return\s+{expression}\s*;?\s*\}}|{expression})"
+        ),
+        expression = EXPRESSION
+    ))
+    .unwrap();
+    /// Sizes are often written as products, such as `width: 512 * 256`
+    static ref PRODUCT: Regex = Regex::new(r"(:\s*)(\d+)\s*\*\s*(\d+)\b").unwrap();
+    /// A variable, possibly with an offset, such as `level - 8`
+    static ref VARIABLE: Regex = Regex::new(r"^(\w+)\s*(?:([+-])\s*(\d+))?$").unwrap();
+    static ref TEMPLATE_SUBSTITUTION: Regex = Regex::new(r"\$\{([^}]*)\}").unwrap();
+    static ref PLACEHOLDER: Regex = Regex::new(r"\{(level|x|y)(?:([+-])(\d+))?\}").unwrap();
+}
+
+/// Rewrites the parts of inline tile sources that are javascript but not JSON5,
+/// so that the tile sources can be parsed:
+/// products of numbers are computed,
+/// and `getTileUrl` functions that only concatenate strings and their arguments
+/// are replaced by URL templates such as `"/tiles/{level-8}/{x}_{y}.jpg"`.
+pub fn normalize_javascript(code: &str) -> Cow<'_, str> {
+    let mut code = GET_TILE_URL.replace_all(code, |c: &Captures| {
+        let expression = c.get(2).or_else(|| c.get(3)).unwrap().as_str();
+        match url_template(&c[1], expression) {
+            Some(template) => format!("getTileUrl: {}", serde_json::Value::from(template)),
+            None => c[0].to_string(),
+        }
+    });
+    while PRODUCT.is_match(&code) {
+        code = Cow::Owned(
+            PRODUCT
+                .replace_all(&code, |c: &Captures| {
+                    let a: u64 = c[2].parse().unwrap_or(u64::MAX);
+                    let b: u64 = c[3].parse().unwrap_or(u64::MAX);
+                    format!("{}{}", &c[1], a.saturating_mul(b))
+                })
+                .into_owned(),
+        );
+    }
+    code
+}
+
+/// Converts the expression a `getTileUrl` function returns into a URL template.
+/// Returns None if the expression is not a simple concatenation.
+fn url_template(params: &str, expression: &str) -> Option<String> {
+    let params: Vec<&str> = params.split(',').map(str::trim).collect();
+    let variable = |expression: &str| -> Option<String> {
+        let c = VARIABLE.captures(expression.trim())?;
+        let Some(index) = params.iter().position(|&p| p == &c[1]) else {
+            // Numbers are the only other values a simple expression can contain
+            let is_number = c.get(2).is_none() && c[1].bytes().all(|d| d.is_ascii_digit());
+            return is_number.then(|| c[1].to_string());
+        };
+        let name = ["level", "x", "y"].get(index)?;
+        Some(match (c.get(2), c.get(3)) {
+            (Some(sign), Some(offset)) => format!("{{{name}{}{}}}", sign.as_str(), offset.as_str()),
+            _ => format!("{{{name}}}"),
+        })
+    };
+    let mut template = String::new();
+    for term in split_concatenation(expression) {
+        let term = term.trim();
+        let quote = term.chars().next()?;
+        if term.len() >= 2 && matches!(quote, '"' | '\'') && term.ends_with(quote) {
+            template.push_str(&term[1..term.len() - 1].replace("\\/", "/"));
+        } else if term.len() >= 2 && quote == '`' && term.ends_with('`') {
+            let mut valid = true;
+            let text =
+                TEMPLATE_SUBSTITUTION.replace_all(&term[1..term.len() - 1], |c: &Captures| {
+                    variable(&c[1]).unwrap_or_else(|| {
+                        valid = false;
+                        String::new()
+                    })
+                });
+            template.push_str(&text);
+            if !valid {
+                return None;
+            }
+        } else {
+            let unparenthesized = term.trim_start_matches('(').trim_end_matches(')');
+            template.push_str(&variable(unparenthesized)?);
+        }
+    }
+    Some(template)
+}
+
+/// Splits a javascript expression on the `+` operators that are not in strings or parentheses
+fn split_concatenation(expression: &str) -> Vec<&str> {
+    let mut terms = vec![];
+    let (mut start, mut depth, mut quote) = (0, 0, None);
+    for (i, c) in expression.char_indices() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'' | '`') => quote = Some(c),
+            (None, '(') => depth += 1,
+            (None, ')') => depth -= 1,
+            (None, '+') if depth == 0 => {
+                terms.push(&expression[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    terms.push(&expression[start..]);
+    terms
+}
+
+/// The URL of a tile, from a template made by [normalize_javascript]
+pub fn fill_template(template: &str, level: u32, position: Vec2d) -> String {
+    PLACEHOLDER
+        .replace_all(template, |c: &Captures| {
+            let value = i64::from(match &c[1] {
+                "level" => level,
+                "x" => position.x,
+                _ => position.y,
+            });
+            let offset: i64 = c.get(3).map_or(0, |n| n.as_str().parse().unwrap_or(0));
+            match c.get(2).map(|sign| sign.as_str()) {
+                Some("-") => value - offset,
+                _ => value + offset,
+            }
+            .to_string()
+        })
+        .into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_url_template() {
+        let template = |expression| url_template("level, x, y", expression);
+        assert_eq!(
+            template(r#""/tiles/" + (level - 8) + "-r" + y + "-c" + x + ".jpg""#).as_deref(),
+            Some("/tiles/{level-8}-r{y}-c{x}.jpg")
+        );
+        assert_eq!(
+            template("`https://example.com/${level}/${x}_${y}.png`").as_deref(),
+            Some("https://example.com/{level}/{x}_{y}.png")
+        );
+        assert_eq!(
+            url_template("z,col,row", "'t/' + z + '/' + col + '/' + row").as_deref(),
+            Some("t/{level}/{x}/{y}")
+        );
+        assert_eq!(template("this.baseUrl + level"), None);
+        assert_eq!(template("`${base}/${level}`"), None);
+        assert_eq!(template("'a' + Math.pow(2, level)"), None);
+    }
+
+    #[test]
+    fn test_normalize_javascript() {
+        let code = r#"{
+            height: 512 * 256, width: 512*256, tileSize: 256, minLevel: 8,
+            getTileUrl: function( level, x, y ){
+                return "http://example.com/bluemarble/" +
+                    (level-8) + "-r" + y + "-c" + x + ".jpg";
+            }
+        }"#;
+        let source: CustomTileSource = json5::from_str(&normalize_javascript(code)).unwrap();
+        assert_eq!(source.size(), Vec2d::square(131072));
+        assert_eq!(source.max_level(), 17);
+        assert_eq!(
+            fill_template(&source.get_tile_url, 9, Vec2d { x: 1, y: 0 }),
+            "http://example.com/bluemarble/1-r0-c1.jpg"
+        );
+
+        let code = "{getTileUrl: (z, x, y) => `/t/${z}/${x}/${y}.png`, width: 10}";
+        assert_eq!(
+            normalize_javascript(code),
+            r#"{getTileUrl: "/t/{level}/{x}/{y}.png", width: 10}"#
+        );
+        // Functions that do more than concatenating strings are kept as they are
+        let code = "{getTileUrl(l, x, y) { return this.url + l; }}";
+        assert_eq!(normalize_javascript(code), code);
+    }
+}
@@ -6,6 +6,7 @@ use std::path::{Path, PathBuf};
 use log::debug;
 use sanitize_filename_reader_friendly::sanitize;
 
+use crate::resume::state_path;
 use crate::{Vec2d, ZoomError};
 
 pub fn reserve_output_file(path: &Path) -> Result<(), ZoomError> {
@@ -13,19 +14,27 @@ pub fn reserve_output_file(path: &Path) -> Result<(), ZoomError> {
     Ok(())
 }
 
+/// An image can be encoded as JPEG only if both its dimensions can be encoded as u16
+fn fits_in_jpg(size: Option<Vec2d>) -> Option<bool> {
+    size.map(|Vec2d { x, y }| u16::try_from(x.max(y)).is_ok())
+}
+
+fn default_extension(size: Option<Vec2d>) -> &'static str {
+    if fits_in_jpg(size) == Some(true) {
+        "jpg"
+    } else {
+        "png"
+    }
+}
+
 pub fn get_outname(
     outfile: &Option<PathBuf>,
     zoom_name: &Option<String>,
     base_dir: &Path,
     size: Option<Vec2d>,
 ) -> PathBuf {
-    // An image can be encoded as JPEG only if both its dimensions can be encoded as u16
-    let fits_in_jpg = size.map(|Vec2d { x, y }| u16::try_from(x.max(y)).is_ok());
-    let extension = if fits_in_jpg == Some(true) {
-        "jpg"
-    } else {
-        "png"
-    };
+    let fits_in_jpg = fits_in_jpg(size);
+    let extension = default_extension(size);
     if let Some(path) = outfile {
         if let Some(forced_extension) = path.extension() {
             if fits_in_jpg == Some(false)
@@ -38,34 +47,57 @@ pub fn get_outname(
             path.with_extension(extension)
         }
     } else {
-        let base = zoom_name
-            .as_ref()
-            .map(|s| sanitize(s))
-            .filter(|s| !s.is_empty())
-            .unwrap_or_else(|| "dezoomified".into());
-        let mut path = base_dir.to_path_buf();
-        let mut base_with_ext = OsString::from(&base);
-        base_with_ext.push(".");
-        base_with_ext.push(extension);
-        path = path.join(base_with_ext);
-
         // append a suffix (_1,_2,..) to `outname` if  the file already exists
-        let filename = path.file_stem().map(OsString::from).unwrap_or_default();
-        let ext = path.extension().map(OsString::from).unwrap_or_default();
-        for i in 1.. {
-            if !path.exists() {
-                break;
-            }
-            debug!(
-                "File {:?} already exists. Trying another file name...",
-                &path
-            );
-            let mut name = OsString::from(&filename);
-            name.push(format!("_{i:04}."));
-            name.push(&ext);
-            path.set_file_name(name);
-        }
-        path
+        candidate_names(zoom_name, base_dir, extension)
+            .find(|path| {
+                let exists = path.exists();
+                if exists {
+                    debug!(
+                        "File {:?} already exists. Trying another file name...",
+                        path
+                    );
+                }
+                !exists
+            })
+            .expect("There is always a free file name")
+    }
+}
+
+/// The names an image can be saved as, by order of preference
+fn candidate_names(
+    zoom_name: &Option<String>,
+    base_dir: &Path,
+    extension: &str,
+) -> impl Iterator<Item = PathBuf> {
+    let base = zoom_name
+        .as_ref()
+        .map(|s| sanitize(s))
+        .filter(|s| !s.is_empty())
+        .unwrap_or_else(|| "dezoomified".into());
+    let mut base_with_ext = OsString::from(&base);
+    base_with_ext.push(".");
+    base_with_ext.push(extension);
+    let path = base_dir.join(base_with_ext);
+    let numbered = (1..).map(move |i| base_dir.join(format!("{base}_{i:04}.{extension}")));
+    std::iter::once(path).chain(numbered)
+}
+
+/// Finds the image left incomplete by a previous download with the same arguments,
+/// among the files that [get_outname] would have chosen
+pub fn find_partial_output(
+    outfile: &Option<PathBuf>,
+    zoom_name: &Option<String>,
+    base_dir: &Path,
+    size: Option<Vec2d>,
+) -> Option<PathBuf> {
+    let is_partial = |path: &PathBuf| state_path(path).exists();
+    if outfile.is_some() {
+        Some(get_outname(outfile, zoom_name, base_dir, size)).filter(is_partial)
+    } else {
+        let extension = default_extension(size);
+        candidate_names(zoom_name, base_dir, extension)
+            .take_while(|path| path.exists())
+            .find(is_partial)
     }
 }
 
@@ -169,4 +201,32 @@ mod tests {
             assert_eq!(outname, expected_result);
         }
     }
+
+    #[test]
+    fn find_partial_output_among_numbered_names() {
+        let base_dir = TempDir::new("dezoomify-rs-test-partial").unwrap();
+        let base = |s| base_dir.as_ref().join(s);
+        let name = Some("hello".to_string());
+        let size = Some(Vec2d { x: 1000, y: 1000 });
+        assert_eq!(
+            find_partial_output(&None, &name, base_dir.as_ref(), size),
+            None
+        );
+        for file in ["hello.jpg", "hello_0001.jpg", "hello_0001.jpg.partial.json"] {
+            File::create(base(file)).unwrap();
+        }
+        assert_eq!(
+            find_partial_output(&None, &name, base_dir.as_ref(), size),
+            Some(base("hello_0001.jpg"))
+        );
+        let outfile = Some(base("hello.jpg"));
+        assert_eq!(
+            find_partial_output(&outfile, &name, base_dir.as_ref(), size),
+            None
+        );
+        assert_eq!(
+            get_outname(&None, &name, base_dir.as_ref(), size),
+            base("hello_0002.jpg")
+        );
+    }
 }
@@ -0,0 +1,189 @@
+//! Where a downloaded image comes from, saved in the output files
+//! so that archived images remain traceable.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+
+use crate::dezoomer::DocumentInfo;
+use crate::xml_utils::escape_xml;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Provenance {
+    /// The URI given by the user, or the bulk file the image was listed in
+    pub input_uri: String,
+    /// The URL of the image, or of the page it was found in
+    pub source_url: String,
+    /// The format of the zoomable image, such as "Zoomify Image"
+    pub dezoomer: String,
+    pub title: Option<String>,
+    /// When the image was downloaded, in ISO 8601 format
+    pub download_date: String,
+    /// The document the image is a part of, such as a IIIF manifest
+    pub document: DocumentInfo,
+}
+
+impl Provenance {
+    /// The provenance of an image downloaded now
+    pub fn new(
+        input_uri: &str,
+        source_url: &str,
+        dezoomer: String,
+        title: Option<String>,
+        document: Option<&DocumentInfo>,
+    ) -> Self {
+        let document = document.cloned().unwrap_or_default();
+        Provenance {
+            input_uri: input_uri.to_string(),
+            source_url: source_url.to_string(),
+            dezoomer,
+            title: title.or_else(|| document.title.clone()),
+            download_date: iso_8601_date(SystemTime::now()),
+            document,
+        }
+    }
+
+    /// An XMP packet describing the image, using the Dublin Core and XMP rights vocabularies
+    /// where possible. See https://developer.adobe.com/xmp/docs/XMPSpecifications/
+    pub fn to_xmp(&self) -> String {
+        let mut properties = String::new();
+        let mut add = |name: &str, value: &str| {
+            properties.push_str(&format!("   <{name}>{value}</{name}>\n"));
+        };
+        if let Some(title) = &self.title {
+            add("dc:title", &language_alternative(title));
+        }
+        add("dc:source", &escape_xml(&self.source_url));
+        if let Some(statement) = &self.document.required_statement {
+            add("dc:rights", &language_alternative(statement));
+        }
+        if let Some(rights) = &self.document.rights {
+            if rights.starts_with("http://") || rights.starts_with("https://") {
+                add("xmpRights:WebStatement", &escape_xml(rights));
+            } else {
+                add("xmpRights:UsageTerms", &language_alternative(rights));
+            }
+        }
+        let creator_tool = concat!("dezoomify-rs ", env!("CARGO_PKG_VERSION"));
+        add("xmp:CreatorTool", creator_tool);
+        add("xmp:CreateDate", &self.download_date);
+        add("dezoomify:dezoomer", &escape_xml(&self.dezoomer));
+        if let Some(title) = &self.document.title {
+            add("dezoomify:documentTitle", &escape_xml(title));
+        }
+        if !self.document.metadata.is_empty() {
+            let entries: String = self
+                .document
+                .metadata
+                .iter()
+                .map(|(label, value)| {
+                    format!(
+                        "\n     <rdf:li rdf:parseType=\"Resource\">\
+                        <dezoomify:label>{}</dezoomify:label>\
+                        <dezoomify:value>{}</dezoomify:value></rdf:li>",
+                        escape_xml(label),
+                        escape_xml(value)
+                    )
+                })
+                .collect();
+            add(
+                "dezoomify:metadata",
+                &format!("<rdf:Bag>{entries}</rdf:Bag>"),
+            );
+        }
+        format!(
+            "<?xpacket begin=\"\u{FEFF}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>\n\
+            <x:xmpmeta xmlns:x=\"adobe:ns:meta/\">\n \
+            <rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">\n  \
+            <rdf:Description rdf:about=\"\" \
+            xmlns:dc=\"http://purl.org/dc/elements/1.1/\" \
+            xmlns:xmp=\"http://ns.adobe.com/xap/1.0/\" \
+            xmlns:xmpRights=\"http://ns.adobe.com/xap/1.0/rights/\" \
+            xmlns:dezoomify=\"https://github.com/lovasoa/dezoomify-rs#\">\n\
+            {properties}  </rdf:Description>\n </rdf:RDF>\n</x:xmpmeta>\n\
+            <?xpacket end=\"w\"?>"
+        )
+    }
+}
+
+/// An XMP text value that could be translated, with a single default language
+fn language_alternative(text: &str) -> String {
+    format!(
+        "<rdf:Alt><rdf:li xml:lang=\"x-default\">{}</rdf:li></rdf:Alt>",
+        escape_xml(text)
+    )
+}
+
+/// Formats a date as `YYYY-MM-DDThh:mm:ssZ`, in UTC
+fn iso_8601_date(time: SystemTime) -> String {
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let (days, seconds_of_day) = (seconds / 86400, seconds % 86400);
+    // Converts a number of days since 1970 to a civil date.
+    // See http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719468;
+    let era = z / 146097;
+    let day_of_era = z % 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        seconds_of_day / 3600,
+        seconds_of_day / 60 % 60,
+        seconds_of_day % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_iso_8601_date() {
+        let date = |seconds| iso_8601_date(UNIX_EPOCH + Duration::from_secs(seconds));
+        assert_eq!(date(0), "1970-01-01T00:00:00Z");
+        assert_eq!(date(951_782_400), "2000-02-29T00:00:00Z");
+        assert_eq!(date(1_792_234_567), "2026-10-17T10:56:07Z");
+    }
+
+    #[test]
+    fn test_xmp() {
+        let document = DocumentInfo {
+            title: Some("Book of Hours".into()),
+            metadata: vec![("Author".into(), "Jean <Bourdichon>".into())],
+            rights: Some("https://creativecommons.org/publicdomain/mark/1.0/".into()),
+            required_statement: Some("Attribution: Bibliothèque nationale de France".into()),
+        };
+        let provenance = Provenance {
+            input_uri: "https://example.com/book.html".into(),
+            source_url: "https://example.com/manifest.json?a=1&b=2".into(),
+            dezoomer: "IIIF Image".into(),
+            title: Some("Folio 1".into()),
+            download_date: "2026-10-17T10:56:07Z".into(),
+            document,
+        };
+        let xmp = provenance.to_xmp();
+        for expected in [
+            "<dc:source>https://example.com/manifest.json?a=1&amp;b=2</dc:source>",
+            "<dc:title><rdf:Alt><rdf:li xml:lang=\"x-default\">Folio 1</rdf:li></rdf:Alt></dc:title>",
+            "<xmpRights:WebStatement>https://creativecommons.org/publicdomain/mark/1.0/</xmpRights:WebStatement>",
+            "Attribution: Bibliothèque nationale de France</rdf:li>",
+            "<xmp:CreateDate>2026-10-17T10:56:07Z</xmp:CreateDate>",
+            "<dezoomify:dezoomer>IIIF Image</dezoomify:dezoomer>",
+            "<dezoomify:documentTitle>Book of Hours</dezoomify:documentTitle>",
+            "<dezoomify:value>Jean &lt;Bourdichon&gt;</dezoomify:value>",
+        ] {
+            assert!(xmp.contains(expected), "{expected} not in {xmp}");
+        }
+        assert!(xmp.ends_with("<?xpacket end=\"w\"?>"));
+    }
+}
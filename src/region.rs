@@ -0,0 +1,303 @@
+//! Rectangular parts of an image, used to download only a detail of a large image
+//! and to fill missing tiles.
+
+use std::collections::BTreeSet;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::dezoomer::TileReference;
+use crate::tile::Tile;
+use crate::{Vec2d, ZoomError};
+
+/// A rectangular part of an image
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Region {
+    pub position: Vec2d,
+    pub size: Vec2d,
+}
+
+impl Region {
+    pub fn end(&self) -> Vec2d {
+        self.position + self.size
+    }
+
+    pub fn intersects(&self, other: &Region) -> bool {
+        self.intersection(other).is_some()
+    }
+
+    /// The part of the image covered by both regions, if they overlap
+    pub fn intersection(&self, other: &Region) -> Option<Region> {
+        let position = self.position.max(other.position);
+        let end = self.end().min(other.end());
+        (position.x < end.x && position.y < end.y).then(|| Region {
+            position,
+            size: end - position,
+        })
+    }
+
+    /// The same region, in a version of the image of size `to` instead of `from`
+    pub fn scale(&self, from: Vec2d, to: Vec2d) -> Region {
+        let scale = |value: u32, from: u32, to: u32, round_up: bool| {
+            let (value, from, to) = (u64::from(value), u64::from(from), u64::from(to));
+            let scaled = if round_up {
+                (value * to).div_ceil(from)
+            } else {
+                value * to / from
+            };
+            u32::try_from(scaled).unwrap_or(u32::MAX)
+        };
+        let (start, end) = (self.position, self.end());
+        let position = Vec2d {
+            x: scale(start.x, from.x, to.x, false),
+            y: scale(start.y, from.y, to.y, false),
+        };
+        let end = Vec2d {
+            x: scale(end.x, from.x, to.x, true),
+            y: scale(end.y, from.y, to.y, true),
+        };
+        Region {
+            position,
+            size: end - position,
+        }
+    }
+
+    /// The part of a tile that is inside the region,
+    /// positioned relatively to the top left corner of the region
+    pub fn crop(&self, tile: Tile) -> Option<Tile> {
+        let tile_region = Region {
+            position: tile.position(),
+            size: tile.size(),
+        };
+        let visible = self.intersection(&tile_region)?;
+        let Vec2d { x, y } = visible.position - tile.position();
+        Some(Tile {
+            image: tile.image.crop_imm(x, y, visible.size.x, visible.size.y),
+            position: visible.position - self.position,
+            ..tile
+        })
+    }
+}
+
+/// The tiles of a batch, with the part of the image each of them covers.
+/// The tiles are assumed to form a grid, in which each tile extends until the next one.
+pub fn grid_cells(
+    tile_refs: Vec<TileReference>,
+    level_size: Vec2d,
+) -> impl Iterator<Item = (TileReference, Region)> {
+    let xs: BTreeSet<u32> = tile_refs.iter().map(|t| t.position.x).collect();
+    let ys: BTreeSet<u32> = tile_refs.iter().map(|t| t.position.y).collect();
+    let next = |values: &BTreeSet<u32>, value: u32, max: u32| {
+        values.range(value + 1..).next().copied().unwrap_or(max)
+    };
+    tile_refs.into_iter().map(move |tile_ref| {
+        let position = tile_ref.position;
+        let end = Vec2d {
+            x: next(&xs, position.x, level_size.x),
+            y: next(&ys, position.y, level_size.y),
+        };
+        let size = end - position;
+        (tile_ref, Region { position, size })
+    })
+}
+
+/// A length given by the user, in pixels or in percents of the size of the image
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Length {
+    Pixels(u32),
+    Percent(f64),
+}
+
+impl Length {
+    fn resolve(self, image_length: Option<u32>) -> Option<u32> {
+        match self {
+            Length::Pixels(pixels) => Some(pixels),
+            Length::Percent(percent) => {
+                image_length.map(|length| (f64::from(length) * percent / 100.).round() as u32)
+            }
+        }
+    }
+}
+
+/// The region of the image the user wants to download, as given with `--region x,y,w,h`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RegionSpec {
+    x: Length,
+    y: Length,
+    width: Length,
+    height: Length,
+}
+
+impl FromStr for RegionSpec {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err_msg = "Invalid region. A region is 'x,y,width,height', \
+                       in pixels or in percents of the size of the image, such as '25%,0%,50%,50%'";
+        let lengths = s
+            .split(',')
+            .map(|part| {
+                let part = part.trim();
+                match part.strip_suffix('%') {
+                    Some(percent) => percent
+                        .trim()
+                        .parse::<f64>()
+                        .ok()
+                        .filter(|p| (0. ..=100.).contains(p))
+                        .map(Length::Percent),
+                    None => part.parse().ok().map(Length::Pixels),
+                }
+            })
+            .collect::<Option<Vec<Length>>>()
+            .ok_or(err_msg)?;
+        if let [x, y, width, height] = lengths[..] {
+            Ok(RegionSpec {
+                x,
+                y,
+                width,
+                height,
+            })
+        } else {
+            Err(err_msg)
+        }
+    }
+}
+
+impl RegionSpec {
+    /// The region in pixels, inside an image of the given size
+    pub fn resolve(&self, image_size: Option<Vec2d>) -> Result<Region, ZoomError> {
+        let invalid = |reason: &str| ZoomError::InvalidRegion {
+            reason: reason.to_string(),
+        };
+        let resolve = |length: Length, image_length: Option<u32>| {
+            length.resolve(image_length).ok_or_else(|| {
+                invalid("the size of the image is not known in advance, so it cannot be expressed in percents")
+            })
+        };
+        let region = Region {
+            position: Vec2d {
+                x: resolve(self.x, image_size.map(|s| s.x))?,
+                y: resolve(self.y, image_size.map(|s| s.y))?,
+            },
+            size: Vec2d {
+                x: resolve(self.width, image_size.map(|s| s.x))?,
+                y: resolve(self.height, image_size.map(|s| s.y))?,
+            },
+        };
+        let Some(size) = image_size else {
+            return Ok(region);
+        };
+        let image = Region {
+            position: Vec2d::default(),
+            size,
+        };
+        region.intersection(&image).ok_or_else(|| {
+            invalid(&format!(
+                "{region:?} does not cover any part of the image, of size {size}"
+            ))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::DynamicImage;
+
+    use super::*;
+
+    #[test]
+    fn test_scale_region() {
+        let region = Region {
+            position: Vec2d { x: 256, y: 512 },
+            size: Vec2d { x: 256, y: 100 },
+        };
+        let scaled = region.scale(Vec2d { x: 1000, y: 612 }, Vec2d { x: 500, y: 306 });
+        assert_eq!(
+            scaled,
+            Region {
+                position: Vec2d { x: 128, y: 256 },
+                size: Vec2d { x: 128, y: 50 },
+            }
+        );
+    }
+
+    #[test]
+    fn test_grid_cells() {
+        let tile_refs = [(0, 0), (100, 0), (0, 100), (100, 100)]
+            .into_iter()
+            .map(|(x, y)| TileReference {
+                url: format!("{x},{y}"),
+                position: Vec2d { x, y },
+            })
+            .collect();
+        let cells: Vec<Region> = grid_cells(tile_refs, Vec2d { x: 150, y: 120 })
+            .map(|(_, cell)| cell)
+            .collect();
+        assert_eq!(cells[0].size, Vec2d { x: 100, y: 100 });
+        assert_eq!(cells[3].size, Vec2d { x: 50, y: 20 });
+    }
+
+    #[test]
+    fn test_crop() {
+        let region = Region {
+            position: Vec2d { x: 50, y: 50 },
+            size: Vec2d { x: 100, y: 100 },
+        };
+        let tile = Tile::empty(Vec2d { x: 0, y: 100 }, Vec2d::square(100));
+        let cropped = region.crop(tile).unwrap();
+        assert_eq!(cropped.position(), Vec2d { x: 0, y: 50 });
+        assert_eq!(cropped.size(), Vec2d::square(50));
+
+        let outside = Tile::empty(Vec2d { x: 150, y: 0 }, Vec2d::square(100));
+        assert!(region.crop(outside).is_none());
+        let image = DynamicImage::new_rgb8(10, 10);
+        let inside = Tile::builder()
+            .with_image(image)
+            .at_position(Vec2d::square(60))
+            .build();
+        assert_eq!(region.crop(inside).unwrap().position(), Vec2d::square(10));
+    }
+
+    #[test]
+    fn test_region_spec() {
+        let size = Some(Vec2d { x: 1000, y: 500 });
+        let spec: RegionSpec = "100,50,200,100".parse().unwrap();
+        let expected = Region {
+            position: Vec2d { x: 100, y: 50 },
+            size: Vec2d { x: 200, y: 100 },
+        };
+        assert_eq!(spec.resolve(size).unwrap(), expected);
+        assert_eq!(spec.resolve(None).unwrap(), expected);
+
+        let spec: RegionSpec = "10%, 10%, 20%, 20.5%".parse().unwrap();
+        let expected = Region {
+            size: Vec2d { x: 200, y: 103 },
+            ..expected
+        };
+        assert_eq!(spec.resolve(size).unwrap(), expected);
+        assert!(spec.resolve(None).is_err());
+
+        // The region is clipped to the image
+        let spec: RegionSpec = "900,0,500,1000".parse().unwrap();
+        assert_eq!(
+            spec.resolve(size).unwrap(),
+            Region {
+                position: Vec2d { x: 900, y: 0 },
+                size: Vec2d { x: 100, y: 500 },
+            }
+        );
+        let outside: RegionSpec = "1000,0,10,10".parse().unwrap();
+        assert!(outside.resolve(size).is_err());
+
+        for invalid in [
+            "",
+            "1,2,3",
+            "1,2,3,4,5",
+            "a,b,c,d",
+            "10%,0,120%,1",
+            "-1,0,1,1",
+        ] {
+            assert!(invalid.parse::<RegionSpec>().is_err(), "{invalid}");
+        }
+    }
+}
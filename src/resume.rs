@@ -0,0 +1,267 @@
+//! Completing a partial download.
+//! When some tiles cannot be downloaded, they are listed in a state file next to the image,
+//! so that a later run with `--resume` requests only them again,
+//! and takes all the other tiles from the existing image.
+
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use image::{DynamicImage, GenericImageView};
+use log::{debug, info};
+use serde::{Deserialize, Serialize};
+
+use crate::dezoomer::TileReference;
+use crate::download_state::DownloadState;
+use crate::region::Region;
+use crate::tile::Tile;
+use crate::{Vec2d, ZoomError};
+
+/// What remains to be downloaded in an incomplete image
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PartialDownloadState {
+    /// Size of the image
+    pub size: Vec2d,
+    /// Size of the largest tile of the image
+    pub tile_size: Vec2d,
+    pub failed_tiles: Vec<TileReference>,
+    /// The part of the image that was downloaded, with `--region`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub region: Option<Region>,
+}
+
+impl PartialDownloadState {
+    /// The state of a download, if enough is known about it to resume it
+    pub fn from_download(state: &DownloadState) -> Option<Self> {
+        Some(PartialDownloadState {
+            size: state.image_size?,
+            tile_size: state.largest_tile_size?,
+            failed_tiles: state.failed_tiles.clone(),
+            region: state.region,
+        })
+    }
+
+    /// Writes the state next to the image saved at the given path, and returns its path
+    pub fn save(&self, image_path: &Path) -> io::Result<PathBuf> {
+        let path = state_path(image_path);
+        let mut writer = BufWriter::new(File::create(&path)?);
+        serde_json::to_writer_pretty(&mut writer, self)?;
+        writer.write_all(b"\n")?;
+        writer.flush()?;
+        Ok(path)
+    }
+
+    pub fn load(image_path: &Path) -> io::Result<Self> {
+        let reader = BufReader::new(File::open(state_path(image_path))?);
+        Ok(serde_json::from_reader(reader)?)
+    }
+}
+
+/// The path of the state file of an incomplete image: the name of the image followed by `.partial.json`
+pub fn state_path(image_path: &Path) -> PathBuf {
+    let mut path = image_path.as_os_str().to_owned();
+    path.push(".partial.json");
+    PathBuf::from(path)
+}
+
+/// Saves the list of missing tiles after a partial download,
+/// and removes the state file of a previous download once the image is complete.
+pub fn update_state(
+    image_path: &Path,
+    result: &Result<(), ZoomError>,
+    state: &DownloadState,
+) -> io::Result<()> {
+    let path = state_path(image_path);
+    match result {
+        Err(ZoomError::PartialDownload { .. }) => {
+            if let Some(partial) = PartialDownloadState::from_download(state) {
+                partial.save(image_path)?;
+                info!(
+                    "The {} missing tiles are listed in {}. \
+                    Run the same command again with --resume to download them.",
+                    partial.failed_tiles.len(),
+                    path.display()
+                );
+            }
+        }
+        Ok(()) if path.exists() => fs::remove_file(&path)?,
+        _ => {}
+    }
+    Ok(())
+}
+
+/// An incomplete image, from which the tiles that were successfully downloaded can be reused
+pub struct PreviousDownload {
+    image: DynamicImage,
+    /// Position of the image in the zoom level, when only a region of it was downloaded
+    offset: Vec2d,
+    tile_size: Vec2d,
+    failed_positions: HashSet<Vec2d>,
+}
+
+impl PreviousDownload {
+    /// Loads the image saved at the given path, and the list of its missing tiles.
+    /// The image must be the same `region` of a zoom level of the same size.
+    pub fn load(
+        image_path: &Path,
+        expected_size: Option<Vec2d>,
+        region: Option<Region>,
+    ) -> Result<Self, ZoomError> {
+        let cannot_resume = |reason: String| ZoomError::CannotResume {
+            path: image_path.to_string_lossy().to_string(),
+            reason,
+        };
+        let state = PartialDownloadState::load(image_path)
+            .map_err(|e| cannot_resume(format!("invalid state file: {e}")))?;
+        if let Some(size) = expected_size.filter(|&s| s != state.size) {
+            return Err(cannot_resume(format!(
+                "the image is now {size} pixels, but it was {} pixels",
+                state.size
+            )));
+        }
+        if state.region != region {
+            return Err(cannot_resume(format!(
+                "the region of the image is now {region:?}, but it was {:?}",
+                state.region
+            )));
+        }
+        let image = image::open(image_path)
+            .map_err(|e| cannot_resume(format!("unable to read the image: {e}")))?;
+        let image_size = region.map_or(state.size, |r| r.size);
+        if Vec2d::from(image.dimensions()) != image_size {
+            return Err(cannot_resume(format!(
+                "the image should be {image_size} pixels"
+            )));
+        }
+        debug!(
+            "Resuming the download of {:?}, with {} missing tiles",
+            image_path,
+            state.failed_tiles.len()
+        );
+        Ok(PreviousDownload {
+            image,
+            offset: region.map_or_else(Vec2d::default, |r| r.position),
+            tile_size: state.tile_size,
+            failed_positions: state.failed_tiles.iter().map(|t| t.position).collect(),
+        })
+    }
+
+    /// Splits tiles between those that have to be downloaded,
+    /// and those that can be taken from the previous image
+    pub fn reuse_tiles(&self, tile_refs: Vec<TileReference>) -> (Vec<TileReference>, Vec<Tile>) {
+        let image = Region {
+            position: self.offset,
+            size: Vec2d::from(self.image.dimensions()),
+        };
+        let visible = |tile_ref: &TileReference| {
+            let cell = Region {
+                position: tile_ref.position,
+                size: self.tile_size,
+            };
+            image.intersection(&cell)
+        };
+        let (to_download, to_reuse): (Vec<_>, Vec<_>) =
+            tile_refs.into_iter().partition(|tile_ref| {
+                self.failed_positions.contains(&tile_ref.position) || visible(tile_ref).is_none()
+            });
+        let reused = to_reuse
+            .iter()
+            .filter_map(visible)
+            .map(|part| {
+                let Vec2d { x, y } = part.position - self.offset;
+                Tile::builder()
+                    .with_image(self.image.crop_imm(x, y, part.size.x, part.size.y))
+                    .at_position(part.position)
+                    .build()
+            })
+            .collect();
+        (to_download, reused)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tile_ref(x: u32, y: u32) -> TileReference {
+        TileReference {
+            url: format!("https://example.com/{x}_{y}.jpg"),
+            position: Vec2d { x, y },
+        }
+    }
+
+    #[test]
+    fn test_reuse_tiles() {
+        let previous = PreviousDownload {
+            image: DynamicImage::new_rgb8(150, 100),
+            offset: Vec2d::default(),
+            tile_size: Vec2d::square(64),
+            failed_positions: [Vec2d { x: 64, y: 0 }].into_iter().collect(),
+        };
+        let tiles = vec![
+            tile_ref(0, 0),
+            tile_ref(64, 0),
+            tile_ref(128, 64),
+            tile_ref(192, 0),
+        ];
+        let (to_download, reused) = previous.reuse_tiles(tiles);
+        assert_eq!(to_download, vec![tile_ref(64, 0), tile_ref(192, 0)]);
+        let reused: Vec<_> = reused.iter().map(|t| (t.position(), t.size())).collect();
+        assert_eq!(
+            reused,
+            vec![
+                (Vec2d { x: 0, y: 0 }, Vec2d { x: 64, y: 64 }),
+                (Vec2d { x: 128, y: 64 }, Vec2d { x: 22, y: 36 }),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_reuse_tiles_in_region() {
+        // The previous image is the region from (100, 50) to (200, 150) of the zoom level
+        let previous = PreviousDownload {
+            image: DynamicImage::new_rgb8(100, 100),
+            offset: Vec2d { x: 100, y: 50 },
+            tile_size: Vec2d::square(64),
+            failed_positions: HashSet::new(),
+        };
+        let (to_download, reused) = previous.reuse_tiles(vec![tile_ref(64, 128), tile_ref(0, 0)]);
+        assert_eq!(to_download, vec![tile_ref(0, 0)]);
+        assert_eq!(reused[0].position(), Vec2d { x: 100, y: 128 });
+        assert_eq!(reused[0].size(), Vec2d { x: 28, y: 22 });
+    }
+
+    #[test]
+    fn test_state_file() {
+        let dir = tempdir::TempDir::new("dezoomify-rs-resume").unwrap();
+        let image_path = dir.path().join("image.png");
+        let state = DownloadState {
+            image_size: Some(Vec2d { x: 150, y: 100 }),
+            largest_tile_size: Some(Vec2d::square(64)),
+            failed_tiles: vec![tile_ref(64, 0)],
+            ..Default::default()
+        };
+        let partial = Err(ZoomError::PartialDownload {
+            successful_tiles: 5,
+            total_tiles: 6,
+            destination: "image.png".into(),
+        });
+        update_state(&image_path, &partial, &state).unwrap();
+        assert_eq!(
+            state_path(&image_path),
+            dir.path().join("image.png.partial.json")
+        );
+        assert_eq!(
+            PartialDownloadState::load(&image_path).unwrap(),
+            PartialDownloadState {
+                size: Vec2d { x: 150, y: 100 },
+                tile_size: Vec2d::square(64),
+                failed_tiles: vec![tile_ref(64, 0)],
+                region: None,
+            }
+        );
+        update_state(&image_path, &Ok(()), &state).unwrap();
+        assert!(!state_path(&image_path).exists());
+    }
+}
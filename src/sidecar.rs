@@ -0,0 +1,137 @@
+//! A machine-readable record of the download of an image, saved in a JSON file next to it
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::Serialize;
+
+use crate::Vec2d;
+use crate::dezoomer::TileReference;
+use crate::download_state::DownloadState;
+use crate::provenance::Provenance;
+use crate::region::Region;
+
+#[derive(Debug, Serialize)]
+pub struct Sidecar {
+    #[serde(flatten)]
+    pub provenance: Provenance,
+    /// The chosen zoom level, as it is described to the user
+    pub level: String,
+    /// The metadata files that were fetched to find the image, in order
+    pub metadata_urls: Vec<String>,
+    /// Size of the chosen zoom level
+    pub size: Option<Vec2d>,
+    pub tile_size: Option<Vec2d>,
+    pub total_tiles: u64,
+    pub successful_tiles: u64,
+    pub failed_tiles: Vec<TileReference>,
+    /// The part of the zoom level that was downloaded, if only a part of it was
+    pub region: Option<Region>,
+    /// The parts of the image that were filled with an upscaled lower zoom level
+    pub upscaled_regions: Vec<Region>,
+    pub timings: Timings,
+}
+
+/// Durations of the steps of the download, in seconds
+#[derive(Debug, Default, Serialize)]
+pub struct Timings {
+    /// Finding the image and its zoom levels
+    pub metadata: f64,
+    /// Downloading the tiles and writing the image
+    pub download: f64,
+}
+
+impl Sidecar {
+    pub fn new(
+        provenance: Provenance,
+        level: String,
+        metadata_urls: Vec<String>,
+        state: &DownloadState,
+        metadata_duration: Duration,
+        download_duration: Duration,
+    ) -> Self {
+        Sidecar {
+            provenance,
+            level,
+            metadata_urls,
+            size: state.image_size,
+            tile_size: state.tile_size,
+            total_tiles: state.total_tiles,
+            successful_tiles: state.successful_tiles,
+            failed_tiles: state.failed_tiles.clone(),
+            region: state.region,
+            upscaled_regions: state.upscaled_regions.clone(),
+            timings: Timings {
+                metadata: metadata_duration.as_secs_f64(),
+                download: download_duration.as_secs_f64(),
+            },
+        }
+    }
+
+    /// Writes the record next to the image saved at the given path, and returns its path
+    pub fn save(&self, image_path: &Path) -> io::Result<PathBuf> {
+        let path = sidecar_path(image_path);
+        let mut writer = BufWriter::new(File::create(&path)?);
+        serde_json::to_writer_pretty(&mut writer, self)?;
+        writer.write_all(b"\n")?;
+        writer.flush()?;
+        Ok(path)
+    }
+}
+
+/// The path of the sidecar file of an image: the name of the image followed by `.json`
+pub fn sidecar_path(image_path: &Path) -> PathBuf {
+    let mut path = image_path.as_os_str().to_owned();
+    path.push(".json");
+    PathBuf::from(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sidecar_json() {
+        let state = DownloadState {
+            total_tiles: 4,
+            successful_tiles: 3,
+            tile_size: Some(Vec2d::square(256)),
+            image_size: Some(Vec2d { x: 500, y: 300 }),
+            failed_tiles: vec![TileReference {
+                url: "https://example.com/1_1.jpg".into(),
+                position: Vec2d { x: 256, y: 256 },
+            }],
+            ..Default::default()
+        };
+        let provenance = Provenance {
+            input_uri: "https://example.com/page.html".into(),
+            source_url: "https://example.com/info.json".into(),
+            dezoomer: "IIIF".into(),
+            ..Default::default()
+        };
+        let sidecar = Sidecar::new(
+            provenance,
+            "IIIF (500 x 300 pixels)".into(),
+            vec!["https://example.com/info.json".into()],
+            &state,
+            Duration::from_millis(1500),
+            Duration::from_secs(3),
+        );
+        let json = serde_json::to_value(&sidecar).unwrap();
+        assert_eq!(json["input_uri"], "https://example.com/page.html");
+        assert_eq!(json["source_url"], "https://example.com/info.json");
+        assert_eq!(json["size"], serde_json::json!({"x": 500, "y": 300}));
+        assert_eq!(json["successful_tiles"], 3);
+        assert_eq!(
+            json["failed_tiles"],
+            serde_json::json!([{"url": "https://example.com/1_1.jpg", "position": {"x": 256, "y": 256}}])
+        );
+        assert_eq!(json["timings"]["metadata"], 1.5);
+        assert_eq!(
+            sidecar_path(Path::new("dir/image.jpg")),
+            PathBuf::from("dir/image.jpg.json")
+        );
+    }
+}
@@ -0,0 +1,282 @@
+use serde::Deserialize;
+
+use crate::Vec2d;
+
+/// The response to a WMTS GetCapabilities request
+/// See http://schemas.opengis.net/wmts/1.0/wmtsGetCapabilities_response.xsd
+#[derive(Debug, Deserialize)]
+pub struct Capabilities {
+    #[serde(rename = "ows:OperationsMetadata", default)]
+    operations_metadata: OperationsMetadata,
+    #[serde(rename = "Contents")]
+    pub contents: Contents,
+}
+
+impl Capabilities {
+    /// The URL GetTile requests can be sent to with key-value pair parameters
+    pub fn get_tile_kvp_url(&self) -> Option<&str> {
+        self.operations_metadata
+            .operations
+            .iter()
+            .filter(|operation| operation.name == "GetTile")
+            .flat_map(|operation| &operation.dcp)
+            .flat_map(|dcp| &dcp.http.get)
+            .find(|get| get.allows_encoding("KVP"))
+            .map(|get| get.href.as_str())
+    }
+}
+
+#[derive(Debug, Deserialize, Default)]
+struct OperationsMetadata {
+    #[serde(rename = "ows:Operation", default)]
+    operations: Vec<Operation>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Operation {
+    #[serde(rename = "@name")]
+    name: String,
+    #[serde(rename = "ows:DCP", default)]
+    dcp: Vec<Dcp>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Dcp {
+    #[serde(rename = "ows:HTTP")]
+    http: Http,
+}
+
+#[derive(Debug, Deserialize)]
+struct Http {
+    #[serde(rename = "ows:Get", default)]
+    get: Vec<Get>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Get {
+    #[serde(rename = "@xlink:href")]
+    href: String,
+    #[serde(rename = "ows:Constraint", default)]
+    constraints: Vec<Constraint>,
+}
+
+impl Get {
+    /// Endpoints that do not restrict their encoding accept all of them
+    fn allows_encoding(&self, encoding: &str) -> bool {
+        let mut encodings = self
+            .constraints
+            .iter()
+            .filter(|c| c.name == "GetEncoding")
+            .flat_map(|c| &c.allowed_values.values)
+            .peekable();
+        encodings.peek().is_none() || encodings.any(|e| e.trim().eq_ignore_ascii_case(encoding))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Constraint {
+    #[serde(rename = "@name")]
+    name: String,
+    #[serde(rename = "ows:AllowedValues", default)]
+    allowed_values: AllowedValues,
+}
+
+#[derive(Debug, Deserialize, Default)]
+struct AllowedValues {
+    #[serde(rename = "ows:Value", default)]
+    values: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Contents {
+    #[serde(rename = "Layer", default)]
+    pub layers: Vec<Layer>,
+    #[serde(rename = "TileMatrixSet", default)]
+    pub tile_matrix_sets: Vec<TileMatrixSet>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Layer {
+    #[serde(rename = "ows:Title", default)]
+    titles: Vec<String>,
+    #[serde(rename = "ows:Identifier")]
+    pub identifier: String,
+    #[serde(rename = "Style", default)]
+    styles: Vec<Style>,
+    #[serde(rename = "Format", default)]
+    formats: Vec<String>,
+    #[serde(rename = "Dimension", default)]
+    pub dimensions: Vec<Dimension>,
+    #[serde(rename = "TileMatrixSetLink", default)]
+    pub tile_matrix_set_links: Vec<TileMatrixSetLink>,
+    #[serde(rename = "ResourceURL", default)]
+    resource_urls: Vec<ResourceUrl>,
+}
+
+impl Layer {
+    pub fn title(&self) -> &str {
+        self.titles.first().unwrap_or(&self.identifier)
+    }
+
+    /// The identifier of the default style of the layer
+    pub fn style(&self) -> &str {
+        self.styles
+            .iter()
+            .find(|style| style.is_default)
+            .or_else(|| self.styles.first())
+            .map_or("default", |style| &style.identifier)
+    }
+
+    pub fn format(&self) -> Option<&str> {
+        self.formats.first().map(String::as_str)
+    }
+
+    /// The template of the RESTful URLs of the tiles, if the server supports them
+    pub fn tile_url_template(&self) -> Option<&str> {
+        self.resource_urls
+            .iter()
+            .find(|url| url.resource_type == "tile")
+            .map(|url| url.template.as_str())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Style {
+    #[serde(rename = "@isDefault", default)]
+    is_default: bool,
+    #[serde(rename = "ows:Identifier")]
+    identifier: String,
+}
+
+/// An additional parameter of the tiles, such as a date
+#[derive(Debug, Deserialize)]
+pub struct Dimension {
+    #[serde(rename = "ows:Identifier")]
+    pub identifier: String,
+    #[serde(rename = "Default")]
+    pub default: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ResourceUrl {
+    #[serde(rename = "@resourceType")]
+    resource_type: String,
+    #[serde(rename = "@template")]
+    template: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TileMatrixSetLink {
+    #[serde(rename = "TileMatrixSet")]
+    pub tile_matrix_set: String,
+    #[serde(rename = "TileMatrixSetLimits")]
+    limits: Option<TileMatrixSetLimits>,
+}
+
+impl TileMatrixSetLink {
+    /// The tiles of a tile matrix that the layer covers, as the first tile and the number of tiles.
+    /// When the link has limits, the tile matrices it does not list are not available.
+    pub fn covered_tiles(&self, matrix: &TileMatrix) -> Option<(Vec2d, Vec2d)> {
+        let Some(limits) = &self.limits else {
+            return Some((Vec2d::default(), matrix.matrix_size()));
+        };
+        let l = limits
+            .tile_matrix_limits
+            .iter()
+            .find(|l| l.tile_matrix == matrix.identifier)?;
+        let start = Vec2d {
+            x: l.min_tile_col,
+            y: l.min_tile_row,
+        };
+        let end = Vec2d {
+            x: l.max_tile_col.saturating_add(1),
+            y: l.max_tile_row.saturating_add(1),
+        }
+        .min(matrix.matrix_size());
+        let count = end - start;
+        (count.x > 0 && count.y > 0).then_some((start, count))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct TileMatrixSetLimits {
+    #[serde(rename = "TileMatrixLimits", default)]
+    tile_matrix_limits: Vec<TileMatrixLimits>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct TileMatrixLimits {
+    tile_matrix: String,
+    min_tile_row: u32,
+    max_tile_row: u32,
+    min_tile_col: u32,
+    max_tile_col: u32,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TileMatrixSet {
+    #[serde(rename = "ows:Identifier")]
+    pub identifier: String,
+    #[serde(rename = "TileMatrix", default)]
+    pub tile_matrices: Vec<TileMatrix>,
+}
+
+/// A zoom level of a tile matrix set
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct TileMatrix {
+    #[serde(rename = "ows:Identifier")]
+    pub identifier: String,
+    tile_width: u32,
+    tile_height: u32,
+    matrix_width: u32,
+    matrix_height: u32,
+}
+
+impl TileMatrix {
+    pub fn tile_size(&self) -> Vec2d {
+        Vec2d {
+            x: self.tile_width,
+            y: self.tile_height,
+        }
+    }
+
+    /// The number of tiles in the matrix, horizontally and vertically
+    pub fn matrix_size(&self) -> Vec2d {
+        Vec2d {
+            x: self.matrix_width,
+            y: self.matrix_height,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_capabilities() {
+        let capabilities: Capabilities =
+            serde_xml_rs::from_str(include_str!("../../testdata/wmts/WMTSCapabilities.xml"))
+                .unwrap();
+        assert_eq!(capabilities.get_tile_kvp_url(), None);
+        let layer = &capabilities.contents.layers[0];
+        assert_eq!(layer.title(), "Map of the world");
+        assert_eq!(layer.style(), "default");
+        assert_eq!(layer.format(), Some("image/jpeg"));
+        assert_eq!(
+            layer.tile_url_template(),
+            Some("../generic/map_{TileCol}_{TileRow}.jpg")
+        );
+        let set = &capabilities.contents.tile_matrix_sets[0];
+        assert_eq!(set.identifier, "GoogleMapsCompatible");
+        let matrix = &set.tile_matrices[1];
+        assert_eq!(matrix.tile_size(), Vec2d::square(256));
+        assert_eq!(matrix.matrix_size(), Vec2d::square(4));
+        assert_eq!(
+            layer.tile_matrix_set_links[0].covered_tiles(matrix),
+            Some((Vec2d::default(), Vec2d::square(2)))
+        );
+    }
+}
@@ -0,0 +1,434 @@
+use std::sync::Arc;
+
+use custom_error::custom_error;
+use lazy_static::lazy_static;
+use regex::{Captures, Regex};
+
+use capabilities::{Capabilities, Layer};
+
+use crate::dezoomer::*;
+use crate::network::resolve_relative;
+
+mod capabilities;
+
+/// A dezoomer for the maps published with the OGC Web Map Tile Service standard.
+/// It reads the answer to a GetCapabilities request, and returns each layer as an image.
+/// A `LAYER` parameter in the URL of the capabilities selects a single layer.
+/// See https://www.ogc.org/standards/wmts/
+#[derive(Default)]
+pub struct WmtsDezoomer;
+
+impl Dezoomer for WmtsDezoomer {
+    fn name(&self) -> &'static str {
+        "wmts"
+    }
+
+    fn zoom_levels(&mut self, data: &DezoomerInput) -> Result<ZoomLevels, DezoomerError> {
+        let DezoomerInputWithContents { uri, contents } = data.with_contents()?;
+        self.assert(is_capabilities(contents))?;
+        // The levels of different layers cannot be mixed, so only one layer is returned
+        let layer = load_layers(uri, contents)?.swap_remove(0);
+        Ok(layer.levels)
+    }
+
+    fn dezoomer_result(&mut self, data: &DezoomerInput) -> Result<DezoomerResult, DezoomerError> {
+        let DezoomerInputWithContents { uri, contents } = data.with_contents()?;
+        self.assert(is_capabilities(contents))?;
+        let images = load_layers(uri, contents)?
+            .into_iter()
+            .map(|layer| {
+                let image = SimpleZoomableImage::new(layer.levels, Some(layer.title));
+                Box::new(image) as Box<dyn ZoomableImageWithLevels>
+            })
+            .collect();
+        Ok(dezoomer_result_from_images(images))
+    }
+}
+
+custom_error! {pub WmtsError
+    XmlError{source: serde_xml_rs::Error} = "Unable to parse the WMTS capabilities: {source}",
+    NoLayer = "The WMTS capabilities do not contain any layer that can be downloaded",
+    NoSuchLayer{name: String} = "The WMTS capabilities do not contain any layer named '{name}'",
+}
+
+impl From<WmtsError> for DezoomerError {
+    fn from(err: WmtsError) -> Self {
+        DezoomerError::Other { source: err.into() }
+    }
+}
+
+fn is_capabilities(contents: &[u8]) -> bool {
+    let contents = String::from_utf8_lossy(contents);
+    contents.contains("Capabilities") && contents.contains("TileMatrixSet")
+}
+
+/// The zoom levels of a layer of the map
+struct LayerLevels {
+    title: String,
+    levels: ZoomLevels,
+}
+
+/// The value of the `LAYER` parameter of the query string of a URL
+fn requested_layer(url: &str) -> Option<String> {
+    let (_, query) = url.split_once('?')?;
+    url::form_urlencoded::parse(query.as_bytes())
+        .find(|(name, _)| name.eq_ignore_ascii_case("layer"))
+        .map(|(_, value)| value.into_owned())
+}
+
+/// The layers that can be downloaded, or only the one requested in the URL
+fn load_layers(url: &str, contents: &[u8]) -> Result<Vec<LayerLevels>, WmtsError> {
+    let capabilities: Capabilities = serde_xml_rs::from_reader(contents)?;
+    // Without a GetTile endpoint, the capabilities are usually served by the same URL
+    let kvp_url = capabilities.get_tile_kvp_url().map_or_else(
+        || url.split('?').next().unwrap_or(url).to_string(),
+        |href| resolve_relative(url, href),
+    );
+    let requested = requested_layer(url);
+    let layers: Vec<LayerLevels> = capabilities
+        .contents
+        .layers
+        .iter()
+        .filter(|layer| {
+            requested
+                .as_ref()
+                .is_none_or(|name| *name == layer.identifier)
+        })
+        .map(|layer| LayerLevels {
+            title: layer.title().to_string(),
+            levels: load_layer(url, &kvp_url, layer, &capabilities),
+        })
+        .filter(|layer| !layer.levels.is_empty())
+        .collect();
+    match requested {
+        _ if !layers.is_empty() => Ok(layers),
+        Some(name) => Err(WmtsError::NoSuchLayer { name }),
+        None => Err(WmtsError::NoLayer),
+    }
+}
+
+fn load_layer(url: &str, kvp_url: &str, layer: &Layer, capabilities: &Capabilities) -> ZoomLevels {
+    let tiles = Arc::new(match layer.tile_url_template() {
+        Some(template) => LayerTiles::Rest {
+            base_url: url.to_string(),
+            template: template.to_string(),
+            style: layer.style().to_string(),
+            dimensions: layer
+                .dimensions
+                .iter()
+                .map(|d| (d.identifier.to_lowercase(), d.default.clone()))
+                .collect(),
+        },
+        None => LayerTiles::Kvp {
+            url: with_parameters(
+                kvp_url,
+                &[
+                    ("SERVICE", "WMTS"),
+                    ("REQUEST", "GetTile"),
+                    ("VERSION", "1.0.0"),
+                    ("LAYER", &layer.identifier),
+                    ("STYLE", layer.style()),
+                    ("FORMAT", layer.format().unwrap_or("image/jpeg")),
+                ],
+            ),
+        },
+    });
+    let title: Arc<str> = Arc::from(layer.title());
+    layer
+        .tile_matrix_set_links
+        .iter()
+        .filter_map(|link| {
+            let set = capabilities
+                .contents
+                .tile_matrix_sets
+                .iter()
+                .find(|set| set.identifier == link.tile_matrix_set)?;
+            Some((link, set))
+        })
+        .flat_map(|(link, set)| {
+            let tile_matrix_set: Arc<str> = Arc::from(set.identifier.as_str());
+            let tiles = Arc::clone(&tiles);
+            let title = Arc::clone(&title);
+            set.tile_matrices.iter().filter_map(move |matrix| {
+                let (first_tile, tile_count) = link.covered_tiles(matrix)?;
+                let tile_size = matrix.tile_size();
+                (tile_size.x > 0 && tile_size.y > 0).then(|| WmtsLevel {
+                    tiles: Arc::clone(&tiles),
+                    title: Arc::clone(&title),
+                    tile_matrix_set: Arc::clone(&tile_matrix_set),
+                    tile_matrix: matrix.identifier.clone(),
+                    first_tile,
+                    size: tile_size * tile_count,
+                    tile_size,
+                })
+            })
+        })
+        .into_zoom_levels()
+}
+
+/// Appends query parameters to a URL that may already have some
+fn with_parameters(url: &str, parameters: &[(&str, &str)]) -> String {
+    let query = url::form_urlencoded::Serializer::new(String::new())
+        .extend_pairs(parameters)
+        .finish();
+    let separator = match url.chars().last() {
+        Some('?' | '&') => "",
+        _ if url.contains('?') => "&",
+        _ => "?",
+    };
+    format!("{url}{separator}{query}")
+}
+
+lazy_static! {
+    static ref PLACEHOLDER: Regex = Regex::new(r"\{(\w+)\}").unwrap();
+}
+
+/// How the tiles of a layer are requested
+#[derive(Debug)]
+enum LayerTiles {
+    /// With the template of a ResourceURL, such as `/{TileMatrix}/{TileRow}/{TileCol}.png`
+    Rest {
+        base_url: String,
+        template: String,
+        style: String,
+        /// The default values of the dimensions of the layer, by lowercase name
+        dimensions: Vec<(String, String)>,
+    },
+    /// With a GetTile request, with its parameters in the query string
+    Kvp { url: String },
+}
+
+/// A tile matrix of a layer, cropped to the tiles the layer covers
+struct WmtsLevel {
+    tiles: Arc<LayerTiles>,
+    title: Arc<str>,
+    tile_matrix_set: Arc<str>,
+    tile_matrix: String,
+    /// The column and row of the top left tile
+    first_tile: Vec2d,
+    size: Vec2d,
+    tile_size: Vec2d,
+}
+
+impl TilesRect for WmtsLevel {
+    fn size(&self) -> Vec2d {
+        self.size
+    }
+
+    fn tile_size(&self) -> Vec2d {
+        self.tile_size
+    }
+
+    fn tile_url(&self, pos: Vec2d) -> String {
+        let Vec2d { x: col, y: row } = self.first_tile + pos;
+        match &*self.tiles {
+            LayerTiles::Rest {
+                base_url,
+                template,
+                style,
+                dimensions,
+            } => {
+                // Placeholder names are case insensitive
+                let path = PLACEHOLDER.replace_all(template, |c: &Captures| {
+                    match c[1].to_lowercase().as_str() {
+                        "tilematrixset" => self.tile_matrix_set.to_string(),
+                        "tilematrix" => self.tile_matrix.clone(),
+                        "tilerow" => row.to_string(),
+                        "tilecol" => col.to_string(),
+                        "style" => style.clone(),
+                        name => dimensions
+                            .iter()
+                            .find(|(dimension, _)| dimension == name)
+                            .map_or_else(|| c[0].to_string(), |(_, value)| value.clone()),
+                    }
+                });
+                resolve_relative(base_url, &path)
+            }
+            LayerTiles::Kvp { url } => with_parameters(
+                url,
+                &[
+                    ("TILEMATRIXSET", &self.tile_matrix_set),
+                    ("TILEMATRIX", &self.tile_matrix),
+                    ("TILEROW", &row.to_string()),
+                    ("TILECOL", &col.to_string()),
+                ],
+            ),
+        }
+    }
+
+    fn title(&self) -> Option<String> {
+        Some(self.title.to_string())
+    }
+}
+
+impl std::fmt::Debug for WmtsLevel {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "WMTS tile matrix {}", self.tile_matrix)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tile_urls(level: &mut ZoomLevel) -> Vec<String> {
+        level.next_tiles(None).into_iter().map(|t| t.url).collect()
+    }
+
+    #[test]
+    fn test_rest_tiles() {
+        let xml = br#"<Capabilities xmlns="http://www.opengis.net/wmts/1.0" xmlns:ows="http://www.opengis.net/ows/1.1">
+            <Contents>
+                <Layer>
+                    <ows:Identifier>plan</ows:Identifier>
+                    <Style><ows:Identifier>sepia</ows:Identifier></Style>
+                    <Format>image/png</Format>
+                    <Dimension><ows:Identifier>Time</ows:Identifier><Default>1850</Default></Dimension>
+                    <TileMatrixSetLink><TileMatrixSet>scans</TileMatrixSet></TileMatrixSetLink>
+                    <ResourceURL format="image/png" resourceType="tile"
+                        template="/wmts/{Style}/{Time}/{TileMatrixSet}/{TileMatrix}/{TileRow}/{TileCol}.png"/>
+                </Layer>
+                <TileMatrixSet>
+                    <ows:Identifier>scans</ows:Identifier>
+                    <TileMatrix>
+                        <ows:Identifier>scans:0</ows:Identifier>
+                        <TileWidth>512</TileWidth><TileHeight>256</TileHeight>
+                        <MatrixWidth>3</MatrixWidth><MatrixHeight>2</MatrixHeight>
+                    </TileMatrix>
+                </TileMatrixSet>
+            </Contents>
+        </Capabilities>"#;
+        let mut layers = load_layers("https://example.com/maps/capabilities.xml", xml).unwrap();
+        assert_eq!(layers.len(), 1);
+        assert_eq!(layers[0].title, "plan");
+        let level = &mut layers[0].levels[0];
+        assert_eq!(level.size_hint(), Some(Vec2d { x: 1536, y: 512 }));
+        let urls = tile_urls(level);
+        assert_eq!(urls.len(), 6);
+        assert_eq!(
+            urls[5],
+            "https://example.com/wmts/sepia/1850/scans/scans:0/1/2.png"
+        );
+    }
+
+    #[test]
+    fn test_kvp_tiles_with_limits() {
+        let xml = br#"<Capabilities xmlns="http://www.opengis.net/wmts/1.0"
+            xmlns:ows="http://www.opengis.net/ows/1.1" xmlns:xlink="http://www.w3.org/1999/xlink">
+            <ows:OperationsMetadata>
+                <ows:Operation name="GetCapabilities">
+                    <ows:DCP><ows:HTTP><ows:Get xlink:href="https://example.com/capabilities?"/></ows:HTTP></ows:DCP>
+                </ows:Operation>
+                <ows:Operation name="GetTile">
+                    <ows:DCP><ows:HTTP>
+                        <ows:Get xlink:href="https://example.com/rest/">
+                            <ows:Constraint name="GetEncoding">
+                                <ows:AllowedValues><ows:Value>RESTful</ows:Value></ows:AllowedValues>
+                            </ows:Constraint>
+                        </ows:Get>
+                        <ows:Get xlink:href="https://example.com/service?map=atlas">
+                            <ows:Constraint name="GetEncoding">
+                                <ows:AllowedValues><ows:Value>KVP</ows:Value></ows:AllowedValues>
+                            </ows:Constraint>
+                        </ows:Get>
+                    </ows:HTTP></ows:DCP>
+                </ows:Operation>
+            </ows:OperationsMetadata>
+            <Contents>
+                <Layer>
+                    <ows:Title>Atlas</ows:Title>
+                    <ows:Identifier>atlas page</ows:Identifier>
+                    <Format>image/jpeg</Format>
+                    <TileMatrixSetLink>
+                        <TileMatrixSet>grid</TileMatrixSet>
+                        <TileMatrixSetLimits>
+                            <TileMatrixLimits>
+                                <TileMatrix>5</TileMatrix>
+                                <MinTileRow>10</MinTileRow><MaxTileRow>11</MaxTileRow>
+                                <MinTileCol>20</MinTileCol><MaxTileCol>22</MaxTileCol>
+                            </TileMatrixLimits>
+                        </TileMatrixSetLimits>
+                    </TileMatrixSetLink>
+                </Layer>
+                <TileMatrixSet>
+                    <ows:Identifier>grid</ows:Identifier>
+                    <TileMatrix>
+                        <ows:Identifier>4</ows:Identifier>
+                        <TileWidth>256</TileWidth><TileHeight>256</TileHeight>
+                        <MatrixWidth>16</MatrixWidth><MatrixHeight>16</MatrixHeight>
+                    </TileMatrix>
+                    <TileMatrix>
+                        <ows:Identifier>5</ows:Identifier>
+                        <TileWidth>256</TileWidth><TileHeight>256</TileHeight>
+                        <MatrixWidth>32</MatrixWidth><MatrixHeight>32</MatrixHeight>
+                    </TileMatrix>
+                </TileMatrixSet>
+            </Contents>
+        </Capabilities>"#;
+        let mut layers = load_layers("https://example.com/capabilities", xml).unwrap();
+        // The tile matrix that the limits do not list is not available
+        assert_eq!(layers[0].levels.len(), 1);
+        let level = &mut layers[0].levels[0];
+        assert_eq!(level.size_hint(), Some(Vec2d { x: 768, y: 512 }));
+        assert_eq!(
+            tile_urls(level)[0],
+            "https://example.com/service?map=atlas&SERVICE=WMTS&REQUEST=GetTile&VERSION=1.0.0\
+            &LAYER=atlas+page&STYLE=default&FORMAT=image%2Fjpeg\
+            &TILEMATRIXSET=grid&TILEMATRIX=5&TILEROW=10&TILECOL=20"
+        );
+    }
+
+    #[test]
+    fn test_layers_as_images() {
+        let xml = br#"<Capabilities xmlns:ows="http://www.opengis.net/ows/1.1"><Contents>
+            <Layer>
+                <ows:Title>North</ows:Title><ows:Identifier>n</ows:Identifier>
+                <TileMatrixSetLink><TileMatrixSet>s</TileMatrixSet></TileMatrixSetLink>
+            </Layer>
+            <Layer>
+                <ows:Title>Missing tile matrix set</ows:Title><ows:Identifier>m</ows:Identifier>
+                <TileMatrixSetLink><TileMatrixSet>unknown</TileMatrixSet></TileMatrixSetLink>
+            </Layer>
+            <Layer>
+                <ows:Title>South</ows:Title><ows:Identifier>s</ows:Identifier>
+                <TileMatrixSetLink><TileMatrixSet>s</TileMatrixSet></TileMatrixSetLink>
+            </Layer>
+            <TileMatrixSet><ows:Identifier>s</ows:Identifier><TileMatrix>
+                <ows:Identifier>0</ows:Identifier><TileWidth>256</TileWidth><TileHeight>256</TileHeight>
+                <MatrixWidth>1</MatrixWidth><MatrixHeight>1</MatrixHeight>
+            </TileMatrix></TileMatrixSet>
+        </Contents></Capabilities>"#;
+        let input = DezoomerInput {
+            uri: "https://example.com/wmts?SERVICE=WMTS&REQUEST=GetCapabilities".into(),
+            contents: PageContents::Success(xml.to_vec()),
+        };
+        let images = WmtsDezoomer.dezoomer_result(&input).unwrap();
+        let titles: Vec<_> = images.iter().map(|i| i.title().unwrap()).collect();
+        assert_eq!(titles, ["North", "South"]);
+        // Only the levels of the first layer are returned as zoom levels
+        let levels = WmtsDezoomer.zoom_levels(&input).unwrap();
+        assert_eq!(levels.len(), 1);
+        assert_eq!(levels[0].title().as_deref(), Some("North"));
+
+        // Unless the URL requests another one
+        let input = DezoomerInput {
+            uri: format!("{}&LAYER=s", input.uri),
+            ..input
+        };
+        let mut levels = WmtsDezoomer.zoom_levels(&input).unwrap();
+        assert_eq!(levels.len(), 1);
+        assert_eq!(
+            tile_urls(&mut levels[0])[0],
+            "https://example.com/wmts?SERVICE=WMTS&REQUEST=GetTile&VERSION=1.0.0\
+            &LAYER=s&STYLE=default&FORMAT=image%2Fjpeg\
+            &TILEMATRIXSET=s&TILEMATRIX=0&TILEROW=0&TILECOL=0"
+        );
+        let images = WmtsDezoomer.dezoomer_result(&input).unwrap();
+        assert_eq!(images.len(), 1);
+        let input = DezoomerInput {
+            uri: "https://example.com/wmts?REQUEST=GetCapabilities&layer=east".into(),
+            ..input
+        };
+        assert!(WmtsDezoomer.zoom_levels(&input).is_err());
+    }
+}
@@ -0,0 +1,15 @@
+/// Escapes the characters that cannot appear as is in the text content of an XML element
+pub(crate) fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+#[test]
+fn test_escape_xml() {
+    assert_eq!(
+        escape_xml("<b>Fish & chips</b>"),
+        "&lt;b&gt;Fish &amp; chips&lt;/b&gt;"
+    );
+    assert_eq!(escape_xml("&amp;"), "&amp;amp;");
+}
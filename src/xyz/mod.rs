@@ -0,0 +1,615 @@
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::sync::Arc;
+
+use custom_error::custom_error;
+use image::ImageReader;
+use lazy_static::lazy_static;
+use log::debug;
+use regex::{Captures, Regex};
+
+use crate::dezoomer::*;
+use crate::generic::dichotomy_2d::Dichotomy;
+
+/// Zoom levels are not probed beyond this one, where a world map is a billion tiles wide
+const MAX_ZOOM: u32 = 30;
+
+/// A dezoomer for the tile pyramids of slippy maps, such as the ones displayed by
+/// Leaflet or OpenLayers, given a tile URL template like
+/// `http://example.com/tiles/{{Z}}/{{X}}/{{Y}}.png`.
+/// `{{-Y}}` instead of `{{Y}}` denotes TMS tiles, in which rows are numbered from the bottom,
+/// and `{{QUADKEY}}` replaces the three numbers in Bing-style tile URLs.
+/// Tiles are requested to find the largest zoom level and the extent of the image in it,
+/// starting from the lowest zoom level, or from a tile given in the template,
+/// like in `{{Z=12}}/{{X=2200}}/{{Y=1343}}`.
+#[derive(Default)]
+pub struct XyzDezoomer {
+    template: Option<Arc<UrlTemplate>>,
+    /// The tiles that were already requested, with their size if they exist
+    probed: HashMap<String, Option<Vec2d>>,
+    pending: Option<String>,
+}
+
+impl Dezoomer for XyzDezoomer {
+    fn name(&self) -> &'static str {
+        "xyz"
+    }
+
+    fn zoom_levels(&mut self, data: &DezoomerInput) -> Result<ZoomLevels, DezoomerError> {
+        if let Some(template) = UrlTemplate::parse(&data.uri) {
+            if self
+                .template
+                .as_ref()
+                .is_none_or(|t| t.template != data.uri)
+            {
+                *self = XyzDezoomer {
+                    template: Some(Arc::new(template)),
+                    ..Default::default()
+                };
+            }
+        } else if self.pending.as_ref() == Some(&data.uri) {
+            let size = match &data.contents {
+                PageContents::Success(contents) => image_size(contents),
+                _ => None,
+            };
+            debug!("Probed tile {}: {size:?}", data.uri);
+            self.probed.insert(data.uri.clone(), size);
+            self.pending = None;
+        }
+        let Some(template) = self.template.clone() else {
+            return Err(self.wrong_dezoomer());
+        };
+        let result = self.probe_levels(&template);
+        if let Err(DezoomerError::NeedsData { uri }) = &result {
+            self.pending = Some(uri.clone());
+        }
+        result
+    }
+}
+
+custom_error! {pub XyzError
+    NoTile{url: String} = "No tile could be loaded at {url}",
+}
+
+impl From<XyzError> for DezoomerError {
+    fn from(err: XyzError) -> Self {
+        DezoomerError::Other { source: err.into() }
+    }
+}
+
+/// Whether a URL is a tile URL template with a zoom level, for the xyz dezoomer
+pub fn is_xyz_template(uri: &str) -> bool {
+    UrlTemplate::parse(uri).is_some()
+}
+
+impl XyzDezoomer {
+    /// The size of a tile, if it exists.
+    /// Tiles that were not requested yet are requested with [DezoomerError::NeedsData].
+    fn tile_size(
+        &self,
+        template: &UrlTemplate,
+        zoom: u32,
+        tile: Vec2d,
+    ) -> Result<Option<Vec2d>, DezoomerError> {
+        let uri = template.url(zoom, tile);
+        self.probed
+            .get(&uri)
+            .copied()
+            .ok_or(DezoomerError::NeedsData { uri })
+    }
+
+    /// Whether a tile exists. Tiles outside of the grid of the zoom level are not requested.
+    fn tile_exists(
+        &self,
+        template: &UrlTemplate,
+        zoom: u32,
+        tile: Option<Vec2d>,
+    ) -> Result<bool, DezoomerError> {
+        match tile {
+            Some(tile) if in_grid(zoom, tile) => {
+                Ok(self.tile_size(template, zoom, tile)?.is_some())
+            }
+            _ => Ok(false),
+        }
+    }
+
+    /// A tile of the image at the lowest zoom level it is found at,
+    /// and whether the levels below that one were searched too.
+    fn seed_tile(&self, template: &UrlTemplate) -> Result<(u32, Vec2d, bool), DezoomerError> {
+        if let Some((zoom, tile)) = template.seed {
+            return if self.tile_exists(template, zoom, Some(tile))? {
+                Ok((zoom, tile, false))
+            } else {
+                Err(XyzError::NoTile {
+                    url: template.url(zoom, tile),
+                }
+                .into())
+            };
+        }
+        // The lowest zoom level contains one tile, or four tiles with quadkeys
+        let zoom = template.min_zoom;
+        let side = 1 << zoom;
+        for y in 0..side {
+            for x in 0..side {
+                let tile = Vec2d { x, y };
+                if self.tile_exists(template, zoom, Some(tile))? {
+                    return Ok((zoom, tile, true));
+                }
+            }
+        }
+        Err(XyzError::NoTile {
+            url: template.url(zoom, Vec2d::default()),
+        }
+        .into())
+    }
+
+    /// Finds the levels of the image from the tiles that were probed so far.
+    /// The probes are replayed on each call, and the first missing tile is requested.
+    fn probe_levels(&self, template: &Arc<UrlTemplate>) -> Result<ZoomLevels, DezoomerError> {
+        let (seed_zoom, seed, lowest_searched) = self.seed_tile(template)?;
+
+        // Lower zoom levels contain the tiles that the seed tile is a part of
+        let min_zoom = if lowest_searched {
+            seed_zoom
+        } else {
+            let levels_below = seed_zoom - template.min_zoom;
+            seed_zoom
+                - largest_success(|k| {
+                    let tile = (k <= levels_below).then(|| seed / (1 << k));
+                    self.tile_exists(template, seed_zoom.saturating_sub(k), tile)
+                })?
+        };
+
+        // Follow the image in the higher zoom levels, where each tile is split in four
+        let (mut max_zoom, mut tile) = (seed_zoom, seed);
+        'zoom: while max_zoom < MAX_ZOOM {
+            for y in 0..2 {
+                for x in 0..2 {
+                    let child = tile * 2 + Vec2d { x, y };
+                    if self.tile_exists(template, max_zoom + 1, Some(child))? {
+                        max_zoom += 1;
+                        tile = child;
+                        continue 'zoom;
+                    }
+                }
+            }
+            break;
+        }
+        debug!("The zoom levels of {template:?} go from {min_zoom} to {max_zoom}");
+
+        // Search the first and last columns and rows of the image around the tile
+        let distance = |dx: i64, dy: i64| {
+            largest_success(|k| {
+                let x = u32::try_from(i64::from(tile.x) + dx * i64::from(k)).ok();
+                let y = u32::try_from(i64::from(tile.y) + dy * i64::from(k)).ok();
+                self.tile_exists(template, max_zoom, x.zip(y).map(Vec2d::from))
+            })
+        };
+        let first = tile
+            - Vec2d {
+                x: distance(-1, 0)?,
+                y: distance(0, -1)?,
+            };
+        let last = tile
+            + Vec2d {
+                x: distance(1, 0)?,
+                y: distance(0, 1)?,
+            };
+        // The tiles of the last row and column of the image can be smaller than the others
+        let tile_size =
+            self.tile_size(template, max_zoom, first)?
+                .ok_or_else(|| XyzError::NoTile {
+                    url: template.url(max_zoom, first),
+                })?;
+        let corner_size = self
+            .tile_size(template, max_zoom, last)?
+            .unwrap_or(tile_size);
+        debug!(
+            "At zoom level {max_zoom}, the image goes from tile {first} to tile {last}, \
+            with tiles of {tile_size} pixels"
+        );
+
+        // Each zoom level is half the size of the next one
+        Ok((min_zoom..=max_zoom)
+            .rev()
+            .map(|zoom| {
+                let scale = max_zoom - zoom;
+                let first_tile = first / (1 << scale);
+                // In the lower levels, the image can start and end in the middle of a tile
+                let end = |first: u32, last: u32, tile: u32, corner: u32| {
+                    let end = u64::from(last) * u64::from(tile) + u64::from(corner);
+                    let end = end.div_ceil(1 << scale);
+                    (end - u64::from(first) * u64::from(tile)) as u32
+                };
+                XyzLevel {
+                    template: Arc::clone(template),
+                    zoom,
+                    first_tile,
+                    size: Vec2d {
+                        x: end(first_tile.x, last.x, tile_size.x, corner_size.x),
+                        y: end(first_tile.y, last.y, tile_size.y, corner_size.y),
+                    },
+                    tile_size,
+                }
+            })
+            .into_zoom_levels())
+    }
+}
+
+/// Whether a tile is in the grid of a zoom level, which contains 2^zoom tiles in each direction
+fn in_grid(zoom: u32, tile: Vec2d) -> bool {
+    zoom <= MAX_ZOOM && tile.x >> zoom == 0 && tile.y >> zoom == 0
+}
+
+/// The largest number for which `success` is true, given that it is true for 0,
+/// and false for all the numbers after the first one for which it is false
+fn largest_success(
+    mut success: impl FnMut(u32) -> Result<bool, DezoomerError>,
+) -> Result<u32, DezoomerError> {
+    let mut dichotomy = Dichotomy::default();
+    let mut guess = dichotomy.best_guess();
+    while let Some(next) = dichotomy.next(success(guess)?) {
+        guess = next;
+    }
+    Ok(dichotomy.best_guess())
+}
+
+/// Reads the dimensions of an image without decoding it
+fn image_size(contents: &[u8]) -> Option<Vec2d> {
+    let reader = ImageReader::new(Cursor::new(contents))
+        .with_guessed_format()
+        .ok()?;
+    let (x, y) = reader.into_dimensions().ok()?;
+    Some(Vec2d { x, y })
+}
+
+lazy_static! {
+    static ref TEMPLATE_RE: Regex = Regex::new(
+        r"(?xi)
+    \{\{
+        (?P<dimension>z|x|-?y|quadkey)
+        (?::0(?P<zeroes>\d+))?
+        (?:=(?P<seed>\d+))?
+     \}\}
+    "
+    )
+    .unwrap();
+}
+
+#[derive(Debug)]
+struct UrlTemplate {
+    template: String,
+    /// Whether the rows are numbered from the bottom of the image
+    tms: bool,
+    /// Quadkeys have one digit per zoom level, so they start at zoom level 1
+    min_zoom: u32,
+    /// The zoom level and position of a tile of the image, when the template gives one
+    seed: Option<(u32, Vec2d)>,
+}
+
+impl UrlTemplate {
+    fn parse(uri: &str) -> Option<Self> {
+        let mut dimensions: HashMap<String, Option<&str>> = HashMap::new();
+        for c in TEMPLATE_RE.captures_iter(uri) {
+            let seed = c.name("seed").map(|m| m.as_str());
+            dimensions.insert(c["dimension"].to_ascii_lowercase(), seed);
+        }
+        let has = |dimension: &str| dimensions.contains_key(dimension);
+        let tms = has("-y");
+        let quadkey = has("quadkey");
+        if !(quadkey || has("z") && has("x") && (has("y") || tms)) {
+            return None;
+        }
+        let seed = if quadkey {
+            dimensions
+                .get("quadkey")
+                .copied()
+                .flatten()
+                .and_then(parse_quadkey)
+        } else {
+            parse_seed(&dimensions)
+        };
+        Some(UrlTemplate {
+            template: uri.to_string(),
+            tms,
+            min_zoom: u32::from(quadkey),
+            seed: seed.filter(|&(zoom, tile)| in_grid(zoom, tile)),
+        })
+    }
+
+    /// The URL of a tile. Rows are numbered from the top, and converted for TMS templates.
+    fn url(&self, zoom: u32, tile: Vec2d) -> String {
+        let Vec2d { x, y } = tile;
+        let row = if self.tms { (1 << zoom) - 1 - y } else { y };
+        TEMPLATE_RE
+            .replace_all(&self.template, |caps: &Captures| {
+                let num = match caps["dimension"].to_ascii_lowercase().as_str() {
+                    "quadkey" => return quadkey(zoom, x, y),
+                    "z" => zoom,
+                    "x" => x,
+                    _ => row,
+                };
+                let padding: usize = caps
+                    .name("zeroes")
+                    .and_then(|m| m.as_str().parse().ok())
+                    .unwrap_or(0);
+                format!("{num:0padding$}")
+            })
+            .into_owned()
+    }
+}
+
+/// The quadkey of a tile, as used by Bing maps: one digit per zoom level,
+/// made of the bits of the column and row numbers at that level.
+/// See https://learn.microsoft.com/en-us/bingmaps/articles/bing-maps-tile-system
+pub fn quadkey(zoom: u32, x: u32, y: u32) -> String {
+    (0..zoom)
+        .rev()
+        .map(|bit| {
+            let digit = ((x >> bit) & 1) + 2 * ((y >> bit) & 1);
+            char::from_digit(digit, 4).unwrap()
+        })
+        .collect()
+}
+
+/// The zoom level and position of the tile given by the numbers of a template
+fn parse_seed(dimensions: &HashMap<String, Option<&str>>) -> Option<(u32, Vec2d)> {
+    let number = |dimension: &str| dimensions.get(dimension).copied().flatten()?.parse().ok();
+    let zoom: u32 = number("z")?;
+    let x = number("x")?;
+    let y = match number("y") {
+        Some(y) => y,
+        // TMS rows are numbered from the bottom of the grid of the zoom level
+        None => (1u32 << zoom.min(MAX_ZOOM))
+            .checked_sub(1)?
+            .checked_sub(number("-y")?)?,
+    };
+    Some((zoom, Vec2d { x, y }))
+}
+
+/// The zoom level and position of the tile of a quadkey
+fn parse_quadkey(key: &str) -> Option<(u32, Vec2d)> {
+    let zoom = u32::try_from(key.len()).ok().filter(|&z| z <= MAX_ZOOM)?;
+    key.chars()
+        .try_fold((zoom, Vec2d::default()), |(zoom, tile), digit| {
+            let digit = digit.to_digit(4)?;
+            let bit = Vec2d {
+                x: digit & 1,
+                y: digit >> 1,
+            };
+            Some((zoom, tile * 2 + bit))
+        })
+}
+
+struct XyzLevel {
+    template: Arc<UrlTemplate>,
+    zoom: u32,
+    /// The position of the first tile of the image in the grid of the zoom level
+    first_tile: Vec2d,
+    size: Vec2d,
+    tile_size: Vec2d,
+}
+
+impl TilesRect for XyzLevel {
+    fn size(&self) -> Vec2d {
+        self.size
+    }
+
+    fn tile_size(&self) -> Vec2d {
+        self.tile_size
+    }
+
+    fn tile_url(&self, pos: Vec2d) -> String {
+        self.template.url(self.zoom, self.first_tile + pos)
+    }
+}
+
+impl std::fmt::Debug for XyzLevel {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Map tiles at zoom level {}", self.zoom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ops::RangeInclusive;
+
+    use super::*;
+
+    /// Runs the dezoomer on a template, serving the tiles of a pyramid with the given zoom levels.
+    /// In the largest level, the image starts at the `first` tile, and has the given number of tiles.
+    /// The tiles of the bottom row of the image are 100 pixels high.
+    fn dezoom(
+        template: &str,
+        zooms: RangeInclusive<u32>,
+        first: (u32, u32),
+        tiles: (u32, u32),
+    ) -> (ZoomLevels, usize) {
+        let max_zoom = *zooms.end();
+        let mut dezoomer = XyzDezoomer::default();
+        let mut data = DezoomerInput {
+            uri: template.to_string(),
+            contents: PageContents::Unknown,
+        };
+        let mut requests = 0;
+        loop {
+            match dezoomer.zoom_levels(&data) {
+                Ok(levels) => return (levels, requests),
+                Err(DezoomerError::NeedsData { uri }) => {
+                    requests += 1;
+                    assert!(requests < 100, "too many requests");
+                    let path = uri.trim_end_matches(".png");
+                    let (z, x, y) = if template.contains("QUADKEY") {
+                        let key = path.rsplit('/').next().unwrap();
+                        key.chars().fold((0, 0, 0), |(z, x, y), digit| {
+                            let digit = digit.to_digit(4).unwrap();
+                            (z + 1, 2 * x + (digit & 1), 2 * y + (digit >> 1))
+                        })
+                    } else {
+                        let numbers: Vec<u32> =
+                            path.split('/').filter_map(|n| n.parse().ok()).collect();
+                        let [z, x, y] = numbers[..] else {
+                            panic!("unexpected url {uri}")
+                        };
+                        (z, x, y)
+                    };
+                    let scale = 1 << (max_zoom.saturating_sub(z));
+                    let start = (first.0 / scale, first.1 / scale);
+                    let last = (
+                        (first.0 + tiles.0 - 1) / scale,
+                        (first.1 + tiles.1 - 1) / scale,
+                    );
+                    let exists = zooms.contains(&z)
+                        && (start.0..=last.0).contains(&x)
+                        && (start.1..=last.1).contains(&y);
+                    let bottom_row = if template.contains("-y") {
+                        start.1
+                    } else {
+                        last.1
+                    };
+                    let contents = if exists {
+                        let height = if y == bottom_row { 100 } else { 256 };
+                        let image = image::DynamicImage::new_rgb8(256, height);
+                        let mut bytes = vec![];
+                        image
+                            .write_to(&mut Cursor::new(&mut bytes), image::ImageFormat::Png)
+                            .unwrap();
+                        PageContents::Success(bytes)
+                    } else {
+                        PageContents::Error(std::io::Error::other("404").into())
+                    };
+                    data = DezoomerInput { uri, contents };
+                }
+                Err(e) => panic!("{e}"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_xyz_pyramid() {
+        let (mut levels, requests) = dezoom(
+            "https://example.com/{{Z}}/{{X}}/{{Y}}.png",
+            0..=5,
+            (0, 0),
+            (7, 3),
+        );
+        assert!(requests < 30, "{requests} requests");
+        assert_eq!(levels.len(), 6);
+        assert_eq!(
+            levels[0].size_hint(),
+            Some(Vec2d {
+                x: 7 * 256,
+                y: 2 * 256 + 100
+            })
+        );
+        assert_eq!(levels[0].tile_count_hint(), Some(21));
+        assert_eq!(levels[2].size_hint(), Some(Vec2d { x: 448, y: 153 }));
+        let tiles = levels[0].next_tiles(None);
+        assert_eq!(tiles[0].url, "https://example.com/5/0/0.png");
+        assert_eq!(tiles[20].url, "https://example.com/5/6/2.png");
+    }
+
+    #[test]
+    fn test_tms_pyramid() {
+        let (mut levels, _) = dezoom(
+            "https://example.com/{{z}}/{{x}}/{{-y}}.png",
+            0..=3,
+            (0, 0),
+            (2, 3),
+        );
+        assert_eq!(levels.len(), 4);
+        // In TMS, the bottom row of the image is the first one
+        assert_eq!(levels[0].size_hint(), Some(Vec2d { x: 512, y: 612 }));
+        let tiles = levels[0].next_tiles(None);
+        assert_eq!(tiles[0].url, "https://example.com/3/0/2.png");
+        assert_eq!(tiles[5].url, "https://example.com/3/1/0.png");
+    }
+
+    #[test]
+    fn test_quadkey_pyramid() {
+        let (mut levels, _) = dezoom("https://example.com/{{QUADKEY}}.png", 0..=3, (0, 0), (8, 8));
+        assert_eq!(levels.len(), 3);
+        assert_eq!(levels[0].size_hint(), Some(Vec2d { x: 2048, y: 1892 }));
+        assert_eq!(levels[2].size_hint(), Some(Vec2d { x: 512, y: 473 }));
+        let tiles = levels[2].next_tiles(None);
+        let urls: Vec<&str> = tiles.iter().map(|t| t.url.as_str()).collect();
+        assert_eq!(
+            urls,
+            [
+                "https://example.com/0.png",
+                "https://example.com/1.png",
+                "https://example.com/2.png",
+                "https://example.com/3.png"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_offset_pyramid() {
+        let template = "https://example.com/{{Z}}/{{X}}/{{Y}}.png";
+        let (mut levels, requests) = dezoom(template, 0..=6, (37, 20), (5, 3));
+        assert!(requests < 50, "{requests} requests");
+        assert_eq!(levels.len(), 7);
+        assert_eq!(
+            levels[0].size_hint(),
+            Some(Vec2d {
+                x: 5 * 256,
+                y: 2 * 256 + 100
+            })
+        );
+        let tiles = levels[0].next_tiles(None);
+        assert_eq!(tiles.len(), 15);
+        assert_eq!(tiles[0].url, "https://example.com/6/37/20.png");
+        assert_eq!(tiles[14].url, "https://example.com/6/41/22.png");
+        // The image starts in the middle of the first tile of the lower levels
+        assert_eq!(levels[1].size_hint(), Some(Vec2d { x: 768, y: 306 }));
+        let tiles = levels[1].next_tiles(None);
+        assert_eq!(tiles[0].url, "https://example.com/5/18/10.png");
+        assert_eq!(tiles.last().unwrap().url, "https://example.com/5/20/11.png");
+    }
+
+    #[test]
+    fn test_seed_tile() {
+        // The server does not have the lowest zoom levels, so the search starts from the given tile
+        let template = "https://example.com/{{z=12}}/{{x=1300}}/{{-y=3195}}.png";
+        let (mut levels, requests) = dezoom(template, 10..=14, (5203, 12780), (6, 4));
+        assert!(requests < 50, "{requests} requests");
+        assert_eq!(levels.len(), 5);
+        assert_eq!(
+            levels[0].size_hint(),
+            Some(Vec2d {
+                x: 6 * 256,
+                y: 3 * 256 + 100
+            })
+        );
+        let tiles = levels[0].next_tiles(None);
+        assert_eq!(tiles[0].url, "https://example.com/14/5203/12783.png");
+        assert_eq!(
+            tiles.last().unwrap().url,
+            "https://example.com/14/5208/12780.png"
+        );
+        let tiles = levels[4].next_tiles(None);
+        assert_eq!(tiles[0].url, "https://example.com/10/325/798.png");
+    }
+
+    #[test]
+    fn test_quadkey() {
+        assert_eq!(quadkey(3, 3, 5), "213");
+        assert_eq!(quadkey(1, 0, 0), "0");
+        assert_eq!(quadkey(0, 0, 0), "");
+    }
+
+    #[test]
+    fn test_url_template() {
+        assert!(UrlTemplate::parse("https://example.com/{{X}}/{{Y}}.png").is_none());
+        let template = UrlTemplate::parse("/t/{{Z:02}}/{{X}}_{{-Y}}.jpg").unwrap();
+        assert!(template.tms);
+        assert_eq!(template.seed, None);
+        assert_eq!(template.url(3, Vec2d { x: 4, y: 5 }), "/t/03/4_2.jpg");
+        let template = UrlTemplate::parse("/t/{{Z=3}}/{{X:02=4}}_{{-Y=2}}.jpg").unwrap();
+        assert_eq!(template.seed, Some((3, Vec2d { x: 4, y: 5 })));
+        assert_eq!(template.url(3, Vec2d { x: 4, y: 5 }), "/t/3/04_2.jpg");
+        let template = UrlTemplate::parse("/t/{{QUADKEY=213}}.jpg").unwrap();
+        assert_eq!(template.seed, Some((3, Vec2d { x: 3, y: 5 })));
+    }
+}
@@ -17,6 +17,27 @@ pub struct ImageProperties {
 }
 
 impl ImageProperties {
+    /// The properties of an image whose levels are the ones the first method of
+    /// [`levels`](Self::levels) computes
+    pub fn new(size: Vec2d, tile_size: u32) -> Self {
+        let tile_side = tile_size as f64;
+        let mut width = size.x as f64;
+        let mut height = size.y as f64;
+        let mut num_tiles = 0u64;
+        while width > tile_side || height > tile_side {
+            num_tiles += ((width / tile_side).ceil() * (height / tile_side).ceil()) as u64;
+            width /= 2.;
+            height /= 2.;
+        }
+        ImageProperties {
+            width: size.x,
+            height: size.y,
+            tile_size,
+            // An image that fits in a single tile has a single level
+            num_tiles: num_tiles.max(1).try_into().unwrap_or(u32::MAX),
+        }
+    }
+
     fn size(&self) -> Vec2d {
         Vec2d {
             x: self.width,
@@ -169,6 +190,21 @@ fn test_real_num_tiles() {
     );
 }
 
+#[test]
+fn test_new_matches_levels() {
+    let props = ImageProperties::new(Vec2d { x: 2052, y: 3185 }, 256);
+    assert_eq!(props.num_tiles, 4 + 12 + 35 + 117);
+    let levels = props.levels();
+    assert_eq!(levels.len(), 4);
+    assert_eq!(levels[0].size, Vec2d { x: 256, y: 398 });
+    assert_eq!(levels[1].size, Vec2d { x: 513, y: 796 });
+    assert_eq!(levels[3].tiles_before, 4 + 12 + 35);
+
+    let single_tile = ImageProperties::new(Vec2d { x: 100, y: 50 }, 256).levels();
+    assert_eq!(single_tile.len(), 1);
+    assert_eq!(single_tile[0].size, Vec2d { x: 100, y: 50 });
+}
+
 #[test]
 fn test_levels_recount() {
     // See: https://github.com/lovasoa/dezoomify-rs/issues/35
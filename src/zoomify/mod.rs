@@ -5,7 +5,7 @@ use image_properties::{ImageProperties, ZoomLevelInfo};
 
 use crate::dezoomer::*;
 
-mod image_properties;
+pub(crate) mod image_properties;
 
 /// Dezoomer for the zoomify image format.
 /// See: http://zoomify.com/
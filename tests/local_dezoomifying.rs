@@ -4,11 +4,14 @@ use std::fs::File;
 use std::hash::{Hash, Hasher};
 use std::io::Write;
 use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 
 use clap::Parser;
 use image::{self, DynamicImage, GenericImageView};
 use image_hasher::HasherConfig;
 use tempdir::TempDir;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
 
 use dezoomify_rs::{Arguments, ZoomError, dezoomify, process_bulk};
 
@@ -29,6 +32,38 @@ pub async fn custom_size_local_zoomify_tiles() {
     .unwrap()
 }
 
+/// The zoomable image displayed in a web page is found and downloaded
+#[tokio::test(flavor = "multi_thread")]
+pub async fn local_html_viewer_page() {
+    let workspace_root = get_workspace_root();
+    let input_path = workspace_root.join("testdata/html/zoomify_viewer.html");
+    let expected_path =
+        workspace_root.join("testdata/zoomify/test_custom_size/expected_result.jpg");
+
+    test_image(
+        input_path.to_str().unwrap(),
+        expected_path.to_str().unwrap(),
+    )
+    .await
+    .unwrap()
+}
+
+/// The tiles of an image are found from the `getTileUrl` function of an OpenSeadragon viewer
+#[tokio::test(flavor = "multi_thread")]
+pub async fn local_openseadragon_custom_tile_source() {
+    let workspace_root = get_workspace_root();
+    let input_path = workspace_root.join("testdata/html/openseadragon_custom.html");
+    let expected_path =
+        workspace_root.join("testdata/zoomify/test_custom_size/expected_result.jpg");
+
+    test_image(
+        input_path.to_str().unwrap(),
+        expected_path.to_str().unwrap(),
+    )
+    .await
+    .unwrap()
+}
+
 #[tokio::test(flavor = "multi_thread")]
 pub async fn local_generic_tiles() {
     // Get absolute path to avoid working directory issues
@@ -44,6 +79,355 @@ pub async fn local_generic_tiles() {
     .unwrap()
 }
 
+/// A slippy map tile pyramid, with TMS row numbers
+#[tokio::test(flavor = "multi_thread")]
+pub async fn local_tms_tiles() {
+    let workspace_root = get_workspace_root();
+    let input_path = workspace_root.join("testdata/xyz/{{Z}}/{{X}}/{{-Y}}.jpg");
+    let expected_path = workspace_root.join("testdata/generic/map_expected.png");
+
+    test_image(
+        input_path.to_str().unwrap(),
+        expected_path.to_str().unwrap(),
+    )
+    .await
+    .unwrap()
+}
+
+/// A WMTS layer whose tile matrix is cropped to the tiles the layer covers
+#[tokio::test(flavor = "multi_thread")]
+pub async fn local_wmts_capabilities() {
+    let workspace_root = get_workspace_root();
+    let input_path = workspace_root.join("testdata/wmts/WMTSCapabilities.xml");
+    let expected_path = workspace_root.join("testdata/generic/map_expected.png");
+
+    test_image(
+        input_path.to_str().unwrap(),
+        expected_path.to_str().unwrap(),
+    )
+    .await
+    .unwrap()
+}
+
+/// Save a local image as a deep zoom image, then dezoom the result
+#[tokio::test(flavor = "multi_thread")]
+#[allow(clippy::field_reassign_with_default)]
+pub async fn deep_zoom_output_round_trip() {
+    let workspace_root = get_workspace_root();
+    let input_path = workspace_root.join("testdata/zoomify/test_custom_size/ImageProperties.xml");
+    let expected_path =
+        workspace_root.join("testdata/zoomify/test_custom_size/expected_result.jpg");
+    let tmp_dir = TempDir::new("dezoomify-rs-dzi-output").unwrap();
+    let dzi_path = tmp_dir.path().join("image.dzi");
+
+    let mut args: Arguments = Default::default();
+    args.input_uri = Some(input_path.to_str().unwrap().into());
+    args.largest = true;
+    args.outfile = Some(dzi_path.clone());
+    args.dzi_tile_size = 100;
+    args.dzi_overlap = 2;
+    dezoomify(&args).await.expect("Saving as dzi failed");
+    assert!(tmp_dir.path().join("image_files/0/0_0.jpg").exists());
+    assert!(tmp_dir.path().join("image_viewer.html").exists());
+
+    let png_path = tmp_dir.path().join("image.png");
+    args.input_uri = Some(dzi_path.to_str().unwrap().into());
+    args.outfile = Some(png_path.clone());
+    dezoomify(&args).await.expect("Dezooming the dzi failed");
+    let actual = image::open(&png_path).unwrap();
+    let expected = image::open(&expected_path).unwrap();
+    assert_images_equal(actual, expected);
+}
+
+/// A missing tile is replaced with the same part of the lower level
+#[tokio::test(flavor = "multi_thread")]
+#[allow(clippy::field_reassign_with_default)]
+pub async fn fill_missing_tiles_from_lower_level() {
+    let workspace_root = get_workspace_root();
+    let input_path = workspace_root.join("testdata/zoomify/test_custom_size/ImageProperties.xml");
+    let expected_path =
+        workspace_root.join("testdata/zoomify/test_custom_size/expected_result.jpg");
+    let tmp_dir = TempDir::new("dezoomify-rs-fill-missing").unwrap();
+    let dzi_path = tmp_dir.path().join("image.dzi");
+
+    let mut args: Arguments = Default::default();
+    args.input_uri = Some(input_path.to_str().unwrap().into());
+    args.largest = true;
+    args.retries = 0;
+    args.outfile = Some(dzi_path.clone());
+    dezoomify(&args).await.expect("Saving as dzi failed");
+    std::fs::remove_file(tmp_dir.path().join("image_files/12/1_1.jpg")).unwrap();
+
+    let png_path = tmp_dir.path().join("image.png");
+    args.input_uri = Some(dzi_path.to_str().unwrap().into());
+    args.outfile = Some(png_path.clone());
+    args.fill_missing = true;
+    args.sidecar = true;
+    let err = dezoomify(&args).await.unwrap_err();
+    assert!(matches!(err, ZoomError::PartialDownload { .. }), "{err}");
+    let sidecar = std::fs::read_to_string(tmp_dir.path().join("image.png.json")).unwrap();
+    let sidecar: serde_json::Value = serde_json::from_str(&sidecar).unwrap();
+    assert_eq!(
+        sidecar["upscaled_regions"],
+        serde_json::json!([{"position": {"x": 253, "y": 253}, "size": {"x": 256, "y": 256}}])
+    );
+    let actual = image::open(&png_path).unwrap();
+    let expected = image::open(&expected_path).unwrap();
+    let (filled, original) = (actual.get_pixel(380, 380), expected.get_pixel(380, 380));
+    let difference: i32 = (0..3)
+        .map(|c| (i32::from(filled[c]) - i32::from(original[c])).abs())
+        .sum();
+    assert!(difference < 60, "{filled:?} is too far from {original:?}");
+    assert_images_equal(actual, expected);
+}
+
+/// Only the tiles covering the region are downloaded, and the image is cropped to it
+#[tokio::test(flavor = "multi_thread")]
+#[allow(clippy::field_reassign_with_default)]
+pub async fn download_region_of_interest() {
+    let workspace_root = get_workspace_root();
+    let input_path = workspace_root.join("testdata/zoomify/test_custom_size/ImageProperties.xml");
+    let expected_path =
+        workspace_root.join("testdata/zoomify/test_custom_size/expected_result.jpg");
+    let tmp_dir = TempDir::new("dezoomify-rs-region").unwrap();
+    let png_path = tmp_dir.path().join("detail.png");
+
+    let mut args: Arguments = Default::default();
+    args.input_uri = Some(input_path.to_str().unwrap().into());
+    args.largest = true;
+    args.outfile = Some(png_path.clone());
+    args.region = Some("300,500,50%,25%".parse().unwrap());
+    args.sidecar = true;
+    dezoomify(&args).await.expect("Dezooming a region failed");
+
+    let sidecar = std::fs::read_to_string(tmp_dir.path().join("detail.png.json")).unwrap();
+    let sidecar: serde_json::Value = serde_json::from_str(&sidecar).unwrap();
+    assert_eq!(
+        sidecar["region"],
+        serde_json::json!({"position": {"x": 300, "y": 500}, "size": {"x": 851, "y": 513}})
+    );
+    assert_eq!(sidecar["total_tiles"], 12);
+    let actual = image::open(&png_path).unwrap();
+    let expected = image::open(&expected_path)
+        .unwrap()
+        .crop_imm(300, 500, 851, 513);
+    assert_images_equal(actual, expected);
+}
+
+/// A dry run describes the zoom levels without downloading anything
+#[tokio::test(flavor = "multi_thread")]
+#[allow(clippy::field_reassign_with_default)]
+pub async fn dry_run_describes_levels() {
+    use dezoomify_rs::{Vec2d, describe_image};
+
+    let workspace_root = get_workspace_root();
+    let input_path = workspace_root.join("testdata/zoomify/test_custom_size/ImageProperties.xml");
+    let mut args: Arguments = Default::default();
+    args.input_uri = Some(input_path.to_str().unwrap().into());
+    args.largest = true;
+    let description = describe_image(&args).await.unwrap();
+    let chosen = description
+        .chosen_level
+        .expect("The largest level should be chosen");
+    let level = &description.levels[chosen];
+    assert_eq!(level.size, Some(Vec2d { x: 1702, y: 2052 }));
+    assert_eq!(level.tile_size, Some(Vec2d::square(256)));
+    assert_eq!(level.tile_count, Some(63));
+    assert!(level.sample_tile_urls[0].ends_with("TileGroup0/3-0-0.jpg"));
+    assert!(
+        description
+            .to_string()
+            .contains("Level 3 would be downloaded.")
+    );
+
+    args.largest = false;
+    let description = describe_image(&args).await.unwrap();
+    assert_eq!(description.chosen_level, None);
+    let json = serde_json::to_value(&description).unwrap();
+    assert_eq!(json["levels"].as_array().unwrap().len(), 4);
+}
+
+/// In bulk mode, a dry run describes every image of the bulk input
+#[tokio::test(flavor = "multi_thread")]
+#[allow(clippy::field_reassign_with_default)]
+pub async fn dry_run_describes_bulk_images() {
+    use dezoomify_rs::{Vec2d, describe_bulk};
+
+    let workspace_root = get_workspace_root();
+    let temp_dir = TempDir::new("dezoomify-rs-bulk-dry-run-test").unwrap();
+    let bulk_file_path = temp_dir.path().join("urls.txt");
+    let zoomify_path = workspace_root.join("testdata/zoomify/test_custom_size/ImageProperties.xml");
+    let generic_path = workspace_root.join("testdata/generic/map_{{X}}_{{Y}}.jpg");
+    let urls = format!("{}\n{}\n", zoomify_path.display(), generic_path.display());
+    std::fs::write(&bulk_file_path, urls).unwrap();
+
+    let mut args: Arguments = Default::default();
+    args.bulk = Some(bulk_file_path.to_string_lossy().to_string());
+    args.retries = 0;
+    let descriptions = describe_bulk(&args).await.unwrap();
+    assert_eq!(descriptions.len(), 2);
+    let zoomify = &descriptions[0];
+    assert_eq!(zoomify.source_url, zoomify_path.to_string_lossy());
+    let chosen = zoomify
+        .chosen_level
+        .expect("--largest is implied in bulk mode");
+    assert_eq!(
+        zoomify.levels[chosen].size,
+        Some(Vec2d { x: 1702, y: 2052 })
+    );
+    assert_eq!(descriptions[1].levels.len(), 1);
+    let files: Vec<_> = std::fs::read_dir(temp_dir.path()).unwrap().collect();
+    assert_eq!(files.len(), 1, "Nothing should be downloaded");
+}
+
+/// The source of the image is saved in the output file
+#[tokio::test(flavor = "multi_thread")]
+#[allow(clippy::field_reassign_with_default)]
+pub async fn provenance_metadata_in_output() {
+    use image::ImageDecoder;
+
+    let workspace_root = get_workspace_root();
+    let input_path = workspace_root.join("testdata/zoomify/test_custom_size/ImageProperties.xml");
+    let tmp_dir = TempDir::new("dezoomify-rs-provenance").unwrap();
+    let jpeg_path = tmp_dir.path().join("image.jpg");
+
+    let mut args: Arguments = Default::default();
+    args.input_uri = Some(input_path.to_str().unwrap().into());
+    args.largest = true;
+    args.outfile = Some(jpeg_path.clone());
+    dezoomify(&args).await.unwrap();
+
+    let file = std::io::BufReader::new(File::open(&jpeg_path).unwrap());
+    let mut decoder = image::codecs::jpeg::JpegDecoder::new(file).unwrap();
+    let xmp = decoder.xmp_metadata().unwrap().expect("no xmp metadata");
+    let xmp = String::from_utf8(xmp).unwrap();
+    assert!(xmp.contains(&format!("<dc:source>{}</dc:source>", input_path.display())));
+    assert!(xmp.contains("<dezoomify:dezoomer>Zoomify"), "{xmp}");
+    assert!(xmp.contains("<xmp:CreateDate>"), "{xmp}");
+}
+
+/// Describe the download in a JSON file next to the image
+#[tokio::test(flavor = "multi_thread")]
+#[allow(clippy::field_reassign_with_default)]
+pub async fn sidecar_json_output() {
+    let workspace_root = get_workspace_root();
+    let input_path = workspace_root.join("testdata/zoomify/test_custom_size/ImageProperties.xml");
+    let tmp_dir = TempDir::new("dezoomify-rs-sidecar").unwrap();
+
+    let mut args: Arguments = Default::default();
+    args.input_uri = Some(input_path.to_str().unwrap().into());
+    args.largest = true;
+    args.outfile = Some(tmp_dir.path().join("image.png"));
+    args.sidecar = true;
+    let saved = dezoomify(&args).await.unwrap();
+
+    let mut sidecar_path = saved.into_os_string();
+    sidecar_path.push(".json");
+    let json = std::fs::read_to_string(sidecar_path).unwrap();
+    let sidecar: serde_json::Value = serde_json::from_str(&json).unwrap();
+    let input_uri = input_path.to_str().unwrap();
+    assert_eq!(sidecar["input_uri"], input_uri);
+    assert_eq!(sidecar["metadata_urls"], serde_json::json!([input_uri]));
+    assert_eq!(sidecar["dezoomer"], "Zoomify Image");
+    assert!(sidecar["level"].as_str().unwrap().contains("pixels"));
+    assert!(sidecar["size"]["x"].as_u64().unwrap() > 0);
+    assert!(sidecar["total_tiles"].as_u64().unwrap() > 0);
+    assert_eq!(sidecar["total_tiles"], sidecar["successful_tiles"]);
+    assert_eq!(sidecar["failed_tiles"], serde_json::json!([]));
+    assert!(sidecar["timings"]["download"].as_f64().unwrap() > 0.);
+}
+
+/// Copies the zoomify test image to a temporary directory, without its tile 3-0-0.jpg,
+/// which is moved to missing.jpg. Returns the arguments to download the copy,
+/// and the path where the missing tile should be.
+#[allow(dead_code)]
+#[allow(clippy::field_reassign_with_default)]
+fn zoomify_copy_with_missing_tile(tmp_dir: &TempDir) -> (Arguments, PathBuf) {
+    let source_dir = get_workspace_root().join("testdata/zoomify/test_custom_size");
+    let tiles_dir = tmp_dir.path().join("TileGroup0");
+    std::fs::create_dir(&tiles_dir).unwrap();
+    std::fs::copy(
+        source_dir.join("ImageProperties.xml"),
+        tmp_dir.path().join("ImageProperties.xml"),
+    )
+    .unwrap();
+    for entry in std::fs::read_dir(source_dir.join("TileGroup0")).unwrap() {
+        let path = entry.unwrap().path();
+        std::fs::copy(&path, tiles_dir.join(path.file_name().unwrap())).unwrap();
+    }
+    let missing_tile = tiles_dir.join("3-0-0.jpg");
+    std::fs::rename(&missing_tile, tmp_dir.path().join("missing.jpg")).unwrap();
+
+    let mut args: Arguments = Default::default();
+    args.input_uri = Some(
+        tmp_dir
+            .path()
+            .join("ImageProperties.xml")
+            .to_str()
+            .unwrap()
+            .into(),
+    );
+    args.largest = true;
+    args.retries = 0;
+    args.outfile = Some(tmp_dir.path().join("image.png"));
+    (args, missing_tile)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+pub async fn resume_partial_download() {
+    let tmp_dir = TempDir::new("dezoomify-rs-resume").unwrap();
+    let (mut args, missing_tile) = zoomify_copy_with_missing_tile(&tmp_dir);
+    let err = dezoomify(&args).await.unwrap_err();
+    assert!(matches!(err, ZoomError::PartialDownload { .. }), "{err}");
+    let state_path = tmp_dir.path().join("image.png.partial.json");
+    let state: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&state_path).unwrap()).unwrap();
+    assert_eq!(state["failed_tiles"].as_array().unwrap().len(), 1);
+    assert_eq!(
+        state["failed_tiles"][0]["position"],
+        serde_json::json!({"x": 0, "y": 0})
+    );
+
+    // Only the missing tile should be requested again
+    std::fs::rename(tmp_dir.path().join("missing.jpg"), &missing_tile).unwrap();
+    std::fs::remove_file(tmp_dir.path().join("TileGroup0/3-5-3.jpg")).unwrap();
+    args.resume = true;
+    let saved = dezoomify(&args).await.unwrap();
+    assert_eq!(saved, tmp_dir.path().join("image.png"));
+    assert!(!state_path.exists());
+    let expected_path = "testdata/zoomify/test_custom_size/expected_result.jpg";
+    let expected = image::open(get_workspace_root().join(expected_path)).unwrap();
+    assert_images_equal(image::open(saved).unwrap(), expected);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+pub async fn job_file_continues_download() {
+    let tmp_dir = TempDir::new("dezoomify-rs-job").unwrap();
+    let (mut args, missing_tile) = zoomify_copy_with_missing_tile(&tmp_dir);
+    let job_path = tmp_dir.path().join("download.job");
+    args.job_file = Some(job_path.clone());
+    let err = dezoomify(&args).await.unwrap_err();
+    assert!(matches!(err, ZoomError::PartialDownload { .. }), "{err}");
+    let job: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&job_path).unwrap()).unwrap();
+    assert_eq!(job["images"][0]["dezoomer"], "Zoomify Image");
+    assert!(job["images"][0]["tiles"].as_array().unwrap().len() > 1);
+    assert_eq!(job["images"][0]["finished"], false);
+
+    // The tiles that were downloaded by the first run are not requested again
+    let tiles_dir = missing_tile.parent().unwrap();
+    std::fs::remove_dir_all(tiles_dir).unwrap();
+    std::fs::create_dir(tiles_dir).unwrap();
+    std::fs::rename(tmp_dir.path().join("missing.jpg"), &missing_tile).unwrap();
+    let saved = dezoomify(&args).await.unwrap();
+    assert_eq!(saved, tmp_dir.path().join("image.png"));
+    assert!(!job_path.exists());
+    assert!(!tmp_dir.path().join("download.job.tiles").exists());
+    let expected_path = "testdata/zoomify/test_custom_size/expected_result.jpg";
+    let expected = image::open(get_workspace_root().join(expected_path)).unwrap();
+    assert_images_equal(image::open(saved).unwrap(), expected);
+}
+
 #[tokio::test(flavor = "multi_thread")]
 pub async fn bulk_mode_local_tiles() {
     test_bulk_processing().await.unwrap()
@@ -54,6 +438,173 @@ pub async fn bulk_mode_end_to_end_cli() {
     test_bulk_mode_cli_end_to_end().await.unwrap()
 }
 
+/// Assemble the images of a bulk download into a pdf file
+#[tokio::test(flavor = "multi_thread")]
+#[allow(clippy::field_reassign_with_default)]
+pub async fn bulk_mode_pdf_output() {
+    let workspace_root = get_workspace_root();
+    let temp_dir = TempDir::new("dezoomify-rs-bulk-pdf-test").unwrap();
+    let bulk_file_path = temp_dir.path().join("urls.txt");
+    let zoomify_path = workspace_root.join("testdata/zoomify/test_custom_size/ImageProperties.xml");
+    let generic_path = workspace_root.join("testdata/generic/map_{{X}}_{{Y}}.jpg");
+    let urls = format!("{}\n{}\n", zoomify_path.display(), generic_path.display());
+    std::fs::write(&bulk_file_path, urls).unwrap();
+
+    let pdf_path = temp_dir.path().join("book.pdf");
+    let mut args: Arguments = Default::default();
+    args.bulk = Some(bulk_file_path.to_string_lossy().to_string());
+    args.outfile = Some(temp_dir.path().join("page.png"));
+    args.pdf = Some(pdf_path.clone());
+    args.logging = "error".into();
+    let stats = process_bulk(&args).await.unwrap();
+    assert_eq!(stats.successful_images, 2);
+
+    let pdf = std::fs::read(&pdf_path).unwrap();
+    assert!(pdf.starts_with(b"%PDF-"));
+    let text = String::from_utf8_lossy(&pdf);
+    assert!(text.contains("/Count 2"));
+    assert_eq!(text.matches("/Filter /FlateDecode").count(), 2);
+}
+
+/// Without --bulk, the input of a pdf download is processed in bulk mode
+#[tokio::test(flavor = "multi_thread")]
+#[allow(clippy::field_reassign_with_default)]
+pub async fn pdf_output_without_bulk() {
+    let workspace_root = get_workspace_root();
+    let temp_dir = TempDir::new("dezoomify-rs-pdf-input-test").unwrap();
+    let bulk_file_path = temp_dir.path().join("urls.txt");
+    let generic_path = workspace_root.join("testdata/generic/map_{{X}}_{{Y}}.jpg");
+    std::fs::write(
+        &bulk_file_path,
+        format!("{0}\n{0}\n", generic_path.display()),
+    )
+    .unwrap();
+
+    let pdf_path = temp_dir.path().join("book.pdf");
+    let mut args: Arguments = Default::default();
+    args.input_uri = Some(bulk_file_path.to_string_lossy().to_string());
+    args.outfile = Some(temp_dir.path().join("page.jpg"));
+    args.pdf = Some(pdf_path.clone());
+    args.logging = "error".into();
+    assert!(args.is_bulk_mode());
+    let stats = process_bulk(&args).await.unwrap();
+    assert_eq!(stats.successful_images, 2);
+    let pdf = std::fs::read(&pdf_path).unwrap();
+    assert!(String::from_utf8_lossy(&pdf).contains("/Count 2"));
+}
+
+/// Store the images of a bulk download in a cbz archive, without leaving them in the folder
+#[tokio::test(flavor = "multi_thread")]
+#[allow(clippy::field_reassign_with_default)]
+pub async fn bulk_mode_cbz_output() {
+    let workspace_root = get_workspace_root();
+    let temp_dir = TempDir::new("dezoomify-rs-bulk-cbz-test").unwrap();
+    let bulk_file_path = temp_dir.path().join("urls.txt");
+    let generic_path = workspace_root.join("testdata/generic/map_{{X}}_{{Y}}.jpg");
+    let urls = format!("{0}\n{0}\n", generic_path.display());
+    std::fs::write(&bulk_file_path, urls).unwrap();
+
+    let cbz_path = temp_dir.path().join("book.cbz");
+    let mut args: Arguments = Default::default();
+    args.bulk = Some(bulk_file_path.to_string_lossy().to_string());
+    args.outfile = Some(temp_dir.path().join("page.jpg"));
+    args.cbz = Some(cbz_path.clone());
+    args.logging = "error".into();
+    let stats = process_bulk(&args).await.unwrap();
+    assert_eq!(stats.successful_images, 2);
+
+    let mut files: Vec<String> = std::fs::read_dir(temp_dir.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into())
+        .collect();
+    files.sort();
+    assert_eq!(files, vec!["book.cbz", "urls.txt"]);
+    let archive = zip::ZipArchive::new(File::open(&cbz_path).unwrap()).unwrap();
+    let names: Vec<String> = archive.file_names().map(|n| n.unwrap().into()).collect();
+    assert_eq!(names, vec!["001.jpg", "002.jpg", "ComicInfo.xml"]);
+}
+
+/// Without --bulk, the input of a cbz download is processed in bulk mode
+#[tokio::test(flavor = "multi_thread")]
+#[allow(clippy::field_reassign_with_default)]
+pub async fn cbz_output_without_bulk() {
+    let workspace_root = get_workspace_root();
+    let temp_dir = TempDir::new("dezoomify-rs-cbz-input-test").unwrap();
+    let bulk_file_path = temp_dir.path().join("urls.txt");
+    let generic_path = workspace_root.join("testdata/generic/map_{{X}}_{{Y}}.jpg");
+    std::fs::write(
+        &bulk_file_path,
+        format!("{0}\n{0}\n", generic_path.display()),
+    )
+    .unwrap();
+
+    let cbz_path = temp_dir.path().join("book.cbz");
+    let mut args: Arguments = Default::default();
+    args.input_uri = Some(bulk_file_path.to_string_lossy().to_string());
+    args.outfile = Some(temp_dir.path().join("page.jpg"));
+    args.cbz = Some(cbz_path.clone());
+    args.logging = "error".into();
+    assert!(args.is_bulk_mode());
+    let stats = process_bulk(&args).await.unwrap();
+    assert_eq!(stats.successful_images, 2);
+    let archive = zip::ZipArchive::new(File::open(&cbz_path).unwrap()).unwrap();
+    assert_eq!(archive.len(), 3);
+}
+
+/// Images too large for the output format fail without stopping the bulk download
+#[tokio::test(flavor = "multi_thread")]
+#[allow(clippy::field_reassign_with_default)]
+pub async fn bulk_mode_image_too_large() {
+    let workspace_root = get_workspace_root();
+    let temp_dir = TempDir::new("dezoomify-rs-bulk-too-large-test").unwrap();
+    let generic_path = workspace_root.join("testdata/generic/map_{{X}}_{{Y}}.jpg");
+    let yaml_path = temp_dir.path().join("tiles.yaml");
+    let yaml = format!(
+        "url_template: \"{}\"\n\
+         x_template: \"x * 256\"\n\
+         y_template: \"0\"\n\
+         variables:\n  - {{ name: x, from: 0, to: 99 }}\n\
+         width: 25600\n\
+         height: 256\n",
+        workspace_root
+            .join("testdata/generic/map_0_0.jpg")
+            .display()
+    );
+    std::fs::write(&yaml_path, yaml).unwrap();
+    let bulk_file_path = temp_dir.path().join("urls.txt");
+    let urls = format!("{}\n{}\n", yaml_path.display(), generic_path.display());
+    std::fs::write(&bulk_file_path, urls).unwrap();
+
+    let mut args: Arguments = Default::default();
+    args.bulk = Some(bulk_file_path.to_string_lossy().to_string());
+    args.outfile = Some(temp_dir.path().join("page.webp"));
+    args.logging = "error".into();
+    let stats = process_bulk(&args).await.unwrap();
+    assert_eq!(stats.successful_images, 1);
+    assert_eq!(stats.failed_images, 1);
+
+    let mut files: Vec<String> = std::fs::read_dir(temp_dir.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into())
+        .collect();
+    files.sort();
+    assert_eq!(files, vec!["page_2.webp", "tiles.yaml", "urls.txt"]);
+}
+
+/// Interrupted bulk downloads are continued with --job, not --resume
+#[tokio::test(flavor = "multi_thread")]
+#[allow(clippy::field_reassign_with_default)]
+pub async fn bulk_mode_rejects_resume() {
+    let mut args: Arguments = Default::default();
+    args.bulk = Some("urls.txt".into());
+    args.resume = true;
+    let err = process_bulk(&args).await.unwrap_err();
+    assert!(
+        matches!(err, ZoomError::UnsupportedInBulkMode { .. }),
+        "{err}"
+    );
+}
+
 /// Get the workspace root directory (where Cargo.toml is located)
 fn get_workspace_root() -> PathBuf {
     let mut current_dir = std::env::current_dir().expect("Failed to get current directory");
@@ -575,3 +1126,98 @@ async fn test_google_arts_and_culture_url_validation() {
     let result = dezoomer2.zoom_levels(&invalid_input);
     assert!(matches!(result, Err(DezoomerError::WrongDezoomer { .. })));
 }
+
+/// Serves an image protected by the IIIF Authentication API:
+/// the token service requires a session cookie, and the tiles require the access token
+#[allow(dead_code)]
+async fn serve_iiif_auth_image(requests: Arc<Mutex<Vec<String>>>) -> std::net::SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let mut tile = Vec::new();
+    DynamicImage::ImageRgb8(image::RgbImage::from_pixel(4, 4, image::Rgb([255, 0, 0])))
+        .write_to(
+            &mut std::io::Cursor::new(&mut tile),
+            image::ImageFormat::Png,
+        )
+        .unwrap();
+    tokio::spawn(async move {
+        loop {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0; 1024];
+            while !request.ends_with(b"\r\n\r\n") {
+                let n = socket.read(&mut buf).await.unwrap();
+                if n == 0 {
+                    break;
+                }
+                request.extend_from_slice(&buf[..n]);
+            }
+            let request = String::from_utf8_lossy(&request).to_lowercase();
+            let path = request.split(' ').nth(1).unwrap_or_default().to_string();
+            let authorized = request.contains("authorization: bearer secret");
+            let (status, body) = if path == "/iiif/img/info.json" {
+                let info = r#"{
+                  "@context": "http://iiif.io/api/image/2/context.json",
+                  "@id": "/iiif/img", "protocol": "http://iiif.io/api/image",
+                  "width": 4, "height": 4, "profile": ["http://iiif.io/api/image/2/level2.json"],
+                  "tiles": [{ "width": 4, "scaleFactors": [1] }],
+                  "service": {
+                    "@id": "/auth/login", "profile": "http://iiif.io/api/auth/1/login",
+                    "service": [{ "@id": "/auth/token", "profile": "http://iiif.io/api/auth/1/token" }]
+                  }
+                }"#;
+                ("200 OK", info.as_bytes().to_vec())
+            } else if path == "/auth/token" && request.contains("cookie: session=letmein") {
+                (
+                    "200 OK",
+                    br#"{"accessToken": "secret", "expiresIn": 3600}"#.to_vec(),
+                )
+            } else if path.starts_with("/iiif/img/") && authorized {
+                ("200 OK", tile.clone())
+            } else {
+                ("401 Unauthorized", vec![])
+            };
+            requests
+                .lock()
+                .unwrap()
+                .push(format!("{path} authorized={authorized}"));
+            let header = format!(
+                "HTTP/1.1 {status}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                body.len()
+            );
+            socket.write_all(header.as_bytes()).await.unwrap();
+            socket.write_all(&body).await.unwrap();
+        }
+    });
+    addr
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[allow(clippy::field_reassign_with_default)]
+async fn test_iiif_auth_token_service() {
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    let addr = serve_iiif_auth_image(Arc::clone(&requests)).await;
+    let temp_dir = TempDir::new("dezoomify-rs-iiif-auth-test").unwrap();
+
+    let mut args: Arguments = Default::default();
+    args.input_uri = Some(format!("http://{addr}/iiif/img/info.json"));
+    args.largest = true;
+    args.retries = 0;
+    args.logging = "error".into();
+    args.iiif_cookie = Some("session=letmein".into());
+    args.outfile = Some(temp_dir.path().join("out.png"));
+    let saved = dezoomify(&args).await.expect("Dezooming failed");
+
+    let image = image::open(saved).unwrap();
+    assert_eq!(image.dimensions(), (4, 4));
+    assert_eq!(image.get_pixel(0, 0), image::Rgba([255, 0, 0, 255]));
+    let requests = requests.lock().unwrap().clone();
+    assert_eq!(
+        requests[..3],
+        [
+            "/iiif/img/info.json authorized=false",
+            "/auth/token authorized=false",
+            "/iiif/img/info.json authorized=true",
+        ]
+    );
+}